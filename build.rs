@@ -2,11 +2,22 @@ use std::{env, fs, io};
 
 include!("src/game.rs");
 
+#[path = "src/engine/gamelib/toolchain.rs"]
+mod toolchain;
+
 fn main() {
     let profile = env::var("PROFILE").unwrap();
 
     println!("cargo:rustc-cfg=build={:?}", profile);
 
+    // toolchain asks to be rerun when Cargo.lock changes, which stops Cargo rerunning this
+    // when anything else does, so everything else this reads has to be listed too
+    toolchain::emit();
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/game.rs");
+    println!("cargo:rerun-if-changed=src/platform/win32/purpl.rc");
+    println!("cargo:rerun-if-changed=src/engine/rendersystem/shaders");
+
     #[cfg(windows)]
     embed_resource::compile(
         "src/platform/win32/purpl.rc",
@@ -0,0 +1,252 @@
+use super::{Channel, Clip, Interpolation, Joint, JointPose, Skeleton, Track};
+use crate::engine::vfs;
+use log::{debug, error, warn};
+use nalgebra::{Matrix4, Quaternion, UnitQuaternion, Vector3};
+use std::{collections::HashMap, sync::Arc};
+
+// Reads a glTF file and its buffers through the VFS, without decoding any images it has.
+// Buffers in other files are relative to the glTF file.
+pub fn open(path: &str) -> Result<(gltf::Document, Vec<gltf::buffer::Data>), String> {
+    let data = vfs::open(path)?;
+    let gltf =
+        gltf::Gltf::from_slice(&data).map_err(|err| format!("failed to parse {path}: {err}"))?;
+
+    let is_external = |buffer: &gltf::Buffer| matches!(buffer.source(), gltf::buffer::Source::Uri(uri) if !uri.starts_with("data:"));
+    if !gltf.document.buffers().any(|buffer| is_external(&buffer)) {
+        let buffers = gltf::import_buffers(&gltf.document, None, gltf.blob)
+            .map_err(|err| format!("failed to read buffers for {path}: {err}"))?;
+        return Ok((gltf.document, buffers));
+    }
+
+    let directory = path.rsplit_once('/').map_or("", |(directory, _)| directory);
+    let mut buffers = Vec::new();
+    for buffer in gltf.document.buffers() {
+        let mut data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .ok_or_else(|| format!("{path} has no binary chunk"))?,
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                return Err(format!(
+                    "{path} mixes embedded and external buffers, which isn't supported"
+                ));
+            }
+            gltf::buffer::Source::Uri(uri) => vfs::open(&format!("{directory}/{uri}"))?,
+        };
+        if data.len() < buffer.length() {
+            return Err(format!(
+                "buffer {} in {path} is {} bytes, expected {}",
+                buffer.index(),
+                data.len(),
+                buffer.length()
+            ));
+        }
+        // Like import_buffers does
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        buffers.push(gltf::buffer::Data(data));
+    }
+    Ok((gltf.document, buffers))
+}
+
+fn joint_pose(node: &gltf::Node) -> JointPose {
+    let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+    JointPose {
+        translation: Vector3::from(translation),
+        rotation: UnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z)),
+        scale: Vector3::from(scale),
+    }
+}
+
+// The file's first skin. glTF doesn't keep parents before children, so the joints are
+// reordered, and the returned list maps each of the skin's joint indices to the skeleton's.
+pub fn skeleton(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+) -> Result<(Skeleton, Vec<usize>), String> {
+    let Some(skin) = document.skins().next() else {
+        return Err(String::from("no skin"));
+    };
+
+    let mut parents = HashMap::new();
+    for node in document.nodes() {
+        for child in node.children() {
+            parents.insert(child.index(), node.index());
+        }
+    }
+
+    let nodes: Vec<gltf::Node> = skin.joints().collect();
+    let inverse_binds: Vec<Matrix4<f32>> = skin
+        .reader(|buffer| Some(&buffers[buffer.index()]))
+        .read_inverse_bind_matrices()
+        .map(|matrices| matrices.map(Matrix4::from).collect())
+        .unwrap_or_else(|| vec![Matrix4::identity(); nodes.len()]);
+    if inverse_binds.len() != nodes.len() {
+        return Err(format!(
+            "{} inverse bind matrices for {} joints",
+            inverse_binds.len(),
+            nodes.len()
+        ));
+    }
+
+    // Joints can have nodes that aren't joints between them and their parent joint
+    let joint_index: HashMap<usize, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(joint, node)| (node.index(), joint))
+        .collect();
+    let parent_joint = |node: usize| {
+        let mut node = parents.get(&node).copied();
+        while let Some(parent) = node {
+            if let Some(joint) = joint_index.get(&parent) {
+                return Some(*joint);
+            }
+            node = parents.get(&parent).copied();
+        }
+        None
+    };
+    let skin_parents: Vec<Option<usize>> = nodes
+        .iter()
+        .map(|node| parent_joint(node.index()))
+        .collect();
+    let depth = |mut joint: usize| {
+        let mut depth = 0;
+        while let Some(parent) = skin_parents[joint] {
+            joint = parent;
+            depth += 1;
+            if depth > nodes.len() {
+                break;
+            }
+        }
+        depth
+    };
+
+    let mut order: Vec<usize> = (0..nodes.len()).collect();
+    order.sort_by_key(|joint| depth(*joint));
+    let mut remap = vec![0; nodes.len()];
+    for (index, joint) in order.iter().enumerate() {
+        remap[*joint] = index;
+    }
+
+    let joints = order
+        .iter()
+        .map(|joint| {
+            let node = &nodes[*joint];
+            Joint {
+                name: node
+                    .name()
+                    .map_or_else(|| format!("joint {joint}"), String::from),
+                parent: skin_parents[*joint].map(|parent| remap[parent]),
+                rest: joint_pose(node),
+                inverse_bind: inverse_binds[*joint],
+            }
+        })
+        .collect();
+
+    Ok((Skeleton::new(joints)?, remap))
+}
+
+fn interpolation(sampler: &gltf::animation::Sampler) -> Interpolation {
+    match sampler.interpolation() {
+        gltf::animation::Interpolation::Step => Interpolation::Step,
+        gltf::animation::Interpolation::Linear => Interpolation::Linear,
+        gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+    }
+}
+
+// Every animation in the file, with channels going to the skeleton's joints by name so clips
+// can come from a different file than the model. Channels for anything else are left out.
+pub fn clips(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+    skeleton: &Skeleton,
+) -> Vec<Clip> {
+    let mut clips = Vec::new();
+    for animation in document.animations() {
+        let name = animation
+            .name()
+            .map_or_else(|| format!("animation {}", animation.index()), String::from);
+        let mut channels: Vec<Channel> = Vec::new();
+        for channel in animation.channels() {
+            let node = channel.target().node();
+            let Some(joint) = node.name().and_then(|name| skeleton.find(name)) else {
+                debug!(
+                    "Skipping channel for node {} in clip {name} that isn't a joint",
+                    node.index()
+                );
+                continue;
+            };
+
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let (Some(inputs), Some(outputs)) = (reader.read_inputs(), reader.read_outputs())
+            else {
+                warn!("Skipping channel without keys in clip {name}");
+                continue;
+            };
+            let times: Vec<f32> = inputs.collect();
+            let interpolation = interpolation(&channel.sampler());
+
+            let index = match channels.iter().position(|channel| channel.joint == joint) {
+                Some(index) => index,
+                None => {
+                    channels.push(Channel {
+                        joint,
+                        translation: None,
+                        rotation: None,
+                        scale: None,
+                    });
+                    channels.len() - 1
+                }
+            };
+            let target = &mut channels[index];
+            let result = match outputs {
+                gltf::animation::util::ReadOutputs::Translations(values) => {
+                    Track::new(times, values.map(Vector3::from).collect(), interpolation)
+                        .map(|track| target.translation = Some(track))
+                }
+                // Spline tangents aren't unit length, but they're only used for their
+                // components
+                gltf::animation::util::ReadOutputs::Rotations(values) => Track::new(
+                    times,
+                    values
+                        .into_f32()
+                        .map(|[x, y, z, w]| {
+                            UnitQuaternion::new_unchecked(Quaternion::new(w, x, y, z))
+                        })
+                        .collect(),
+                    interpolation,
+                )
+                .map(|track| target.rotation = Some(track)),
+                gltf::animation::util::ReadOutputs::Scales(values) => {
+                    Track::new(times, values.map(Vector3::from).collect(), interpolation)
+                        .map(|track| target.scale = Some(track))
+                }
+                gltf::animation::util::ReadOutputs::MorphTargetWeights(_) => {
+                    debug!("Skipping morph target weights in clip {name}");
+                    Ok(())
+                }
+            };
+            if let Err(err) = result {
+                warn!("Skipping channel for joint {joint} in clip {name}: {err}");
+            }
+        }
+
+        debug!("Loaded clip {name} with {} channel(s)", channels.len());
+        clips.push(Clip::new(&name, channels));
+    }
+    clips
+}
+
+// Loads every clip in name.gltf from the game's model directory for skeleton
+pub fn load_clips(name: &str, skeleton: &Skeleton) -> Result<Vec<Arc<Clip>>, String> {
+    let path = format!("{}{name}.gltf", crate::engine::GameDirs::models());
+    let (document, buffers) = open(&path).map_err(|err| {
+        error!("Failed to load animations from {path}: {err}");
+        err
+    })?;
+    Ok(clips(&document, &buffers, skeleton)
+        .into_iter()
+        .map(Arc::new)
+        .collect())
+}
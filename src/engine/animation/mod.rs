@@ -0,0 +1,638 @@
+use nalgebra::{Matrix4, Quaternion, Translation3, UnitQuaternion, Vector3};
+use std::sync::Arc;
+
+pub mod import;
+
+pub use import::load_clips;
+
+// A joint's transform relative to its parent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JointPose {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for JointPose {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::repeat(1.0),
+        }
+    }
+}
+
+impl JointPose {
+    // Scales, then rotates, then translates, like components::Transform
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Translation3::from(self.translation).to_homogeneous()
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: slerp(&self.rotation, &other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+
+    // What has to be added to reference to get this, for additive clips
+    pub fn relative_to(&self, reference: &Self) -> Self {
+        Self {
+            translation: self.translation - reference.translation,
+            rotation: reference.rotation.inverse() * self.rotation,
+            scale: self.scale.component_div(&reference.scale),
+        }
+    }
+
+    // Adds weight of a difference from relative_to on top
+    pub fn add(&self, additive: &Self, weight: f32) -> Self {
+        let identity = Self::default();
+        let additive = identity.lerp(additive, weight);
+        Self {
+            translation: self.translation + additive.translation,
+            rotation: self.rotation * additive.rotation,
+            scale: self.scale.component_mul(&additive.scale),
+        }
+    }
+}
+
+// Always takes the short way around
+fn slerp(a: &UnitQuaternion<f32>, b: &UnitQuaternion<f32>, t: f32) -> UnitQuaternion<f32> {
+    let b = if a.coords.dot(&b.coords) < 0.0 {
+        UnitQuaternion::new_unchecked(-b.into_inner())
+    } else {
+        *b
+    };
+    a.try_slerp(&b, t, f32::EPSILON).unwrap_or(b)
+}
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    // Where the joint is when nothing's playing
+    pub rest: JointPose,
+    // From model space to the joint's space in the pose the mesh was bound in
+    pub inverse_bind: Matrix4<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    // Parents have to come before their children
+    pub fn new(joints: Vec<Joint>) -> Result<Self, String> {
+        for (index, joint) in joints.iter().enumerate() {
+            if joint.parent.is_some_and(|parent| parent >= index) {
+                return Err(format!("joint {} comes before its parent", joint.name));
+            }
+        }
+        Ok(Self { joints })
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|joint| joint.name == name)
+    }
+
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            joints: self.joints.iter().map(|joint| joint.rest).collect(),
+        }
+    }
+}
+
+// A transform for every joint in a skeleton, in the same order
+#[derive(Clone, Debug)]
+pub struct Pose {
+    pub joints: Vec<JointPose>,
+}
+
+impl Pose {
+    // Moves weight of the way to other
+    pub fn blend(&mut self, other: &Self, weight: f32) {
+        for (joint, target) in self.joints.iter_mut().zip(&other.joints) {
+            *joint = joint.lerp(target, weight);
+        }
+    }
+
+    // Adds weight of the difference between pose and reference on top
+    pub fn add(&mut self, pose: &Self, reference: &Self, weight: f32) {
+        for ((joint, pose), reference) in self
+            .joints
+            .iter_mut()
+            .zip(&pose.joints)
+            .zip(&reference.joints)
+        {
+            *joint = joint.add(&pose.relative_to(reference), weight);
+        }
+    }
+
+    // Each joint's transform in model space
+    pub fn model_matrices(&self, skeleton: &Skeleton) -> Vec<Matrix4<f32>> {
+        let mut matrices: Vec<Matrix4<f32>> = Vec::with_capacity(self.joints.len());
+        for (pose, joint) in self.joints.iter().zip(skeleton.joints()) {
+            let local = pose.matrix();
+            matrices.push(match joint.parent {
+                Some(parent) => matrices[parent] * local,
+                None => local,
+            });
+        }
+        matrices
+    }
+
+    // What the vertex shader moves bind pose vertices by
+    pub fn skinning_matrices(&self, skeleton: &Skeleton) -> Vec<Matrix4<f32>> {
+        self.model_matrices(skeleton)
+            .iter()
+            .zip(skeleton.joints())
+            .map(|(matrix, joint)| matrix * joint.inverse_bind)
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    // Hermite splines, with an in tangent, the value and an out tangent for each key
+    CubicSpline,
+}
+
+pub trait Keyframe: Copy {
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+    fn hermite(&self, out_tangent: &Self, other: &Self, in_tangent: &Self, t: f32, dt: f32)
+        -> Self;
+}
+
+// The basis functions for a Hermite spline from 0 to 1
+fn hermite_basis(t: f32) -> [f32; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        2.0 * t3 - 3.0 * t2 + 1.0,
+        t3 - 2.0 * t2 + t,
+        -2.0 * t3 + 3.0 * t2,
+        t3 - t2,
+    ]
+}
+
+impl Keyframe for Vector3<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Vector3::lerp(self, other, t)
+    }
+
+    fn hermite(
+        &self,
+        out_tangent: &Self,
+        other: &Self,
+        in_tangent: &Self,
+        t: f32,
+        dt: f32,
+    ) -> Self {
+        let [a, b, c, d] = hermite_basis(t);
+        self * a + out_tangent * (b * dt) + other * c + in_tangent * (d * dt)
+    }
+}
+
+impl Keyframe for UnitQuaternion<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        slerp(self, other, t)
+    }
+
+    // Done on the components and normalized after, which is what glTF says to do
+    fn hermite(
+        &self,
+        out_tangent: &Self,
+        other: &Self,
+        in_tangent: &Self,
+        t: f32,
+        dt: f32,
+    ) -> Self {
+        let [a, b, c, d] = hermite_basis(t);
+        let coords = self.coords * a
+            + out_tangent.coords * (b * dt)
+            + other.coords * c
+            + in_tangent.coords * (d * dt);
+        UnitQuaternion::from_quaternion(Quaternion::from_vector(coords))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    times: Vec<f32>,
+    values: Vec<T>,
+    interpolation: Interpolation,
+}
+
+impl<T: Keyframe> Track<T> {
+    // Times are in seconds and have to go up. Cubic splines need three values per time.
+    pub fn new(
+        times: Vec<f32>,
+        values: Vec<T>,
+        interpolation: Interpolation,
+    ) -> Result<Self, String> {
+        let per_key = match interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        };
+        if values.len() != times.len() * per_key {
+            return Err(format!(
+                "{} value(s) for {} key(s) with {interpolation:?} interpolation",
+                values.len(),
+                times.len()
+            ));
+        }
+        if times.windows(2).any(|pair| pair[1] < pair[0]) {
+            return Err(String::from("key times go backwards"));
+        }
+        Ok(Self {
+            times,
+            values,
+            interpolation,
+        })
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+
+    fn value(&self, key: usize) -> &T {
+        match self.interpolation {
+            Interpolation::CubicSpline => &self.values[key * 3 + 1],
+            _ => &self.values[key],
+        }
+    }
+
+    // Holds the first and last keys outside of the track
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let next = self.times.partition_point(|key| *key <= time);
+        if self.times.is_empty() {
+            return None;
+        } else if next == 0 {
+            return Some(*self.value(0));
+        } else if next == self.times.len() {
+            return Some(*self.value(next - 1));
+        }
+
+        let previous = next - 1;
+        let dt = self.times[next] - self.times[previous];
+        let t = if dt > 0.0 {
+            (time - self.times[previous]) / dt
+        } else {
+            0.0
+        };
+        Some(match self.interpolation {
+            Interpolation::Step => *self.value(previous),
+            Interpolation::Linear => self.value(previous).lerp(self.value(next), t),
+            Interpolation::CubicSpline => self.value(previous).hermite(
+                &self.values[previous * 3 + 2],
+                self.value(next),
+                &self.values[next * 3],
+                t,
+                dt,
+            ),
+        })
+    }
+}
+
+// Everything a clip does to one joint, parts without a track stay as they are
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub joint: usize,
+    pub translation: Option<Track<Vector3<f32>>>,
+    pub rotation: Option<Track<UnitQuaternion<f32>>>,
+    pub scale: Option<Track<Vector3<f32>>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Clip {
+    name: String,
+    duration: f32,
+    channels: Vec<Channel>,
+}
+
+impl Clip {
+    pub fn new(name: &str, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .flat_map(|channel| {
+                [
+                    channel.translation.as_ref().map(Track::duration),
+                    channel.rotation.as_ref().map(Track::duration),
+                    channel.scale.as_ref().map(Track::duration),
+                ]
+            })
+            .flatten()
+            .fold(0.0, f32::max);
+        Self {
+            name: String::from(name),
+            duration,
+            channels,
+        }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    // In seconds
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    // Overwrites the joints the clip moves, and leaves the rest of the pose alone
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        for channel in &self.channels {
+            let Some(joint) = pose.joints.get_mut(channel.joint) else {
+                continue;
+            };
+            if let Some(translation) = channel
+                .translation
+                .as_ref()
+                .and_then(|track| track.sample(time))
+            {
+                joint.translation = translation;
+            }
+            if let Some(rotation) = channel
+                .rotation
+                .as_ref()
+                .and_then(|track| track.sample(time))
+            {
+                joint.rotation = rotation;
+            }
+            if let Some(scale) = channel.scale.as_ref().and_then(|track| track.sample(time)) {
+                joint.scale = scale;
+            }
+        }
+    }
+
+    fn translation(&self, joint: usize, time: f32) -> Option<Vector3<f32>> {
+        self.channels
+            .iter()
+            .find(|channel| channel.joint == joint)
+            .and_then(|channel| channel.translation.as_ref())
+            .and_then(|track| track.sample(time))
+    }
+}
+
+struct PlayingClip {
+    clip: Arc<Clip>,
+    time: f32,
+    speed: f32,
+    looping: bool,
+    weight: f32,
+    // How much the weight changes per second, negative once it's fading out
+    fade_rate: f32,
+}
+
+impl PlayingClip {
+    // Moves the time along and returns how far the root moved on the ground while doing it
+    fn advance(&mut self, delta: f32, root: Option<usize>) -> Vector3<f32> {
+        let duration = self.clip.duration;
+        let previous = self.time;
+        self.time += delta * self.speed;
+        let wrapped = self.looping && duration > 0.0 && !(0.0..duration).contains(&self.time);
+        if wrapped {
+            self.time = self.time.rem_euclid(duration);
+        } else {
+            self.time = self.time.clamp(0.0, duration);
+        }
+
+        let Some(root) = root else {
+            return Vector3::zeros();
+        };
+        let at = |time| {
+            self.clip
+                .translation(root, time)
+                .unwrap_or_else(Vector3::zeros)
+        };
+        let moved = if !wrapped {
+            at(self.time) - at(previous)
+        } else if self.speed >= 0.0 {
+            at(duration) - at(previous) + at(self.time) - at(0.0)
+        } else {
+            at(0.0) - at(previous) + at(self.time) - at(duration)
+        };
+        Vector3::new(moved.x, 0.0, moved.z)
+    }
+}
+
+// An additive clip that plays on top of everything else, like breathing or flinching
+struct Layer {
+    clip: Arc<Clip>,
+    time: f32,
+    weight: f32,
+    // The clip's first frame, which is what counts as no change
+    reference: Pose,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayerId(usize);
+
+// Plays clips on a skeleton, fading between them when a new one starts and adding layers on
+// top. Each animated instance needs its own.
+pub struct Animator {
+    skeleton: Arc<Skeleton>,
+    // Newest last
+    playing: Vec<PlayingClip>,
+    layers: Vec<Option<Layer>>,
+    // The joint whose movement on the ground gets taken out of the pose and given to the
+    // game, so the character can be moved by it instead
+    root_motion_joint: Option<usize>,
+    root_motion: Vector3<f32>,
+    pose: Pose,
+    skinning: Vec<Matrix4<f32>>,
+}
+
+impl Animator {
+    pub fn new(skeleton: Arc<Skeleton>) -> Self {
+        let pose = skeleton.rest_pose();
+        let skinning = pose.skinning_matrices(&skeleton);
+        Self {
+            skeleton,
+            playing: Vec::new(),
+            layers: Vec::new(),
+            root_motion_joint: None,
+            root_motion: Vector3::zeros(),
+            pose,
+            skinning,
+        }
+    }
+
+    // Fades from whatever's playing to clip over fade seconds, 0 switches straight away
+    pub fn play(&mut self, clip: Arc<Clip>, looping: bool, fade: f32) {
+        let (weight, fade_rate) = if fade > 0.0 {
+            for playing in &mut self.playing {
+                playing.fade_rate = -1.0 / fade;
+            }
+            (0.0, 1.0 / fade)
+        } else {
+            self.playing.clear();
+            (1.0, 0.0)
+        };
+        self.playing.push(PlayingClip {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            looping,
+            weight,
+            fade_rate,
+        });
+    }
+
+    // For the clip that was played last, negative plays it backwards
+    pub fn set_speed(&mut self, speed: f32) {
+        if let Some(playing) = self.playing.last_mut() {
+            playing.speed = speed;
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.playing.clear();
+    }
+
+    // Whether the clip that was played last is still going
+    pub fn is_playing(&self) -> bool {
+        self.playing.last().is_some_and(|playing| {
+            playing.looping
+                || (playing.speed >= 0.0 && playing.time < playing.clip.duration)
+                || (playing.speed < 0.0 && playing.time > 0.0)
+        })
+    }
+
+    pub fn add_layer(&mut self, clip: Arc<Clip>, weight: f32) -> LayerId {
+        let mut reference = self.skeleton.rest_pose();
+        clip.sample(0.0, &mut reference);
+        let layer = Layer {
+            clip,
+            time: 0.0,
+            weight,
+            reference,
+        };
+        match self.layers.iter().position(Option::is_none) {
+            Some(index) => {
+                self.layers[index] = Some(layer);
+                LayerId(index)
+            }
+            None => {
+                self.layers.push(Some(layer));
+                LayerId(self.layers.len() - 1)
+            }
+        }
+    }
+
+    pub fn set_layer_weight(&mut self, layer: LayerId, weight: f32) {
+        if let Some(Some(layer)) = self.layers.get_mut(layer.0) {
+            layer.weight = weight;
+        }
+    }
+
+    pub fn remove_layer(&mut self, layer: LayerId) {
+        if let Some(layer) = self.layers.get_mut(layer.0) {
+            *layer = None;
+        }
+    }
+
+    // None leaves the root where the clips put it
+    pub fn set_root_motion(&mut self, joint: Option<usize>) {
+        self.root_motion_joint = joint;
+    }
+
+    // How far the root has moved on the ground in model space since the last call
+    pub fn take_root_motion(&mut self) -> Vector3<f32> {
+        std::mem::replace(&mut self.root_motion, Vector3::zeros())
+    }
+
+    // Delta is in seconds
+    pub fn update(&mut self, delta: f32) {
+        for playing in &mut self.playing {
+            playing.weight = (playing.weight + playing.fade_rate * delta).clamp(0.0, 1.0);
+        }
+        self.playing
+            .retain(|playing| playing.fade_rate >= 0.0 || playing.weight > 0.0);
+
+        // Each clip is blended in by its share of the weight so far, which gives every clip
+        // its share of the total at the end
+        let rest = self.skeleton.rest_pose();
+        let mut pose = rest.clone();
+        let mut sample = rest.clone();
+        let mut total = 0.0;
+        let mut root_motion = Vector3::zeros();
+        for playing in &mut self.playing {
+            let moved = playing.advance(delta, self.root_motion_joint);
+            if playing.weight <= 0.0 {
+                continue;
+            }
+
+            sample.joints.copy_from_slice(&rest.joints);
+            playing.clip.sample(playing.time, &mut sample);
+            if let Some(root) = self.root_motion_joint {
+                // The root stays where it starts on the ground, but can still go up and down
+                let start = playing
+                    .clip
+                    .translation(root, 0.0)
+                    .unwrap_or_else(Vector3::zeros);
+                if let Some(joint) = sample.joints.get_mut(root) {
+                    joint.translation.x = start.x;
+                    joint.translation.z = start.z;
+                }
+            }
+
+            total += playing.weight;
+            pose.blend(&sample, playing.weight / total);
+            root_motion += moved * playing.weight;
+        }
+        if total > 0.0 {
+            self.root_motion += root_motion / total;
+        }
+
+        for layer in self.layers.iter_mut().flatten() {
+            let duration = layer.clip.duration;
+            layer.time = if duration > 0.0 {
+                (layer.time + delta).rem_euclid(duration)
+            } else {
+                0.0
+            };
+            sample.joints.copy_from_slice(&rest.joints);
+            layer.clip.sample(layer.time, &mut sample);
+            pose.add(&sample, &layer.reference, layer.weight);
+        }
+
+        self.skinning = pose.skinning_matrices(&self.skeleton);
+        self.pose = pose;
+    }
+
+    pub fn skeleton(&self) -> &Arc<Skeleton> {
+        &self.skeleton
+    }
+
+    pub fn pose(&self) -> &Pose {
+        &self.pose
+    }
+
+    // From the last update, for SkinnedModel::render
+    pub fn skinning_matrices(&self) -> &[Matrix4<f32>] {
+        &self.skinning
+    }
+}
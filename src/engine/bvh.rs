@@ -0,0 +1,667 @@
+use crate::engine::rendersystem::bounds::{Aabb, Frustum};
+use nalgebra::{Point3, Vector3};
+
+// How much leaves are grown by, so things that move a little don't need to be reinserted
+const MARGIN: f32 = 0.1;
+
+// Only valid until the proxy is removed, after which the same ID can be reused
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ProxyId(usize);
+
+enum NodeKind<T> {
+    Leaf { tight: Aabb, data: T },
+    Branch { children: [usize; 2] },
+    Free,
+}
+
+struct Node<T> {
+    // Grown by MARGIN for leaves
+    aabb: Aabb,
+    parent: Option<usize>,
+    // Leaves are 0
+    height: u32,
+    kind: NodeKind<T>,
+}
+
+// A dynamic bounding volume hierarchy, the same idea as Box2D's dynamic tree. Leaves are
+// inserted next to whichever node makes the tree's surface area grow the least, and branches
+// get rotated on the way back up to keep it balanced.
+//
+// Queries check the grown boxes to skip parts of the tree and the exact ones for leaves, so
+// they find the same things as checking every box would.
+pub struct Bvh<T> {
+    nodes: Vec<Node<T>>,
+    free: Vec<usize>,
+    root: Option<usize>,
+    len: usize,
+}
+
+impl<T> Default for Bvh<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Bvh<T> {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = None;
+        self.len = 0;
+    }
+
+    pub fn insert(&mut self, aabb: Aabb, data: T) -> ProxyId {
+        let leaf = self.allocate(Node {
+            aabb: aabb.grow(MARGIN),
+            parent: None,
+            height: 0,
+            kind: NodeKind::Leaf { tight: aabb, data },
+        });
+        self.insert_leaf(leaf);
+        self.len += 1;
+        ProxyId(leaf)
+    }
+
+    pub fn remove(&mut self, proxy: ProxyId) -> Option<T> {
+        if !matches!(
+            self.nodes.get(proxy.0).map(|node| &node.kind),
+            Some(NodeKind::Leaf { .. })
+        ) {
+            return None;
+        }
+
+        self.remove_leaf(proxy.0);
+        self.len -= 1;
+        self.free.push(proxy.0);
+        match std::mem::replace(&mut self.nodes[proxy.0].kind, NodeKind::Free) {
+            NodeKind::Leaf { data, .. } => Some(data),
+            _ => None,
+        }
+    }
+
+    // Returns whether the proxy had to be reinserted, which only happens when it leaves its
+    // grown box
+    pub fn update(&mut self, proxy: ProxyId, aabb: Aabb) -> bool {
+        let Some(node) = self.nodes.get_mut(proxy.0) else {
+            return false;
+        };
+        let NodeKind::Leaf { tight, .. } = &mut node.kind else {
+            return false;
+        };
+        *tight = aabb;
+        if node.aabb.contains(&aabb) {
+            return false;
+        }
+
+        self.remove_leaf(proxy.0);
+        self.nodes[proxy.0].aabb = aabb.grow(MARGIN);
+        self.insert_leaf(proxy.0);
+        true
+    }
+
+    pub fn get(&self, proxy: ProxyId) -> Option<&T> {
+        match self.nodes.get(proxy.0).map(|node| &node.kind) {
+            Some(NodeKind::Leaf { data, .. }) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, proxy: ProxyId) -> Option<&mut T> {
+        match self.nodes.get_mut(proxy.0).map(|node| &mut node.kind) {
+            Some(NodeKind::Leaf { data, .. }) => Some(data),
+            _ => None,
+        }
+    }
+
+    // The box it was last inserted or updated with
+    pub fn aabb(&self, proxy: ProxyId) -> Option<Aabb> {
+        match self.nodes.get(proxy.0).map(|node| &node.kind) {
+            Some(NodeKind::Leaf { tight, .. }) => Some(*tight),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ProxyId, &T)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, node)| match &node.kind {
+                NodeKind::Leaf { data, .. } => Some((ProxyId(index), data)),
+                _ => None,
+            })
+    }
+
+    // How many levels there are, which is about log2(len) if the tree is balanced
+    pub fn height(&self) -> u32 {
+        self.root.map_or(0, |root| self.nodes[root].height + 1)
+    }
+
+    pub fn query_aabb<F>(&self, aabb: &Aabb, mut found: F)
+    where
+        F: FnMut(ProxyId, &T),
+    {
+        self.visit(
+            |node| aabb.intersects(node),
+            |proxy, _, data| found(proxy, data),
+        );
+    }
+
+    pub fn query_frustum<F>(&self, frustum: &Frustum, mut found: F)
+    where
+        F: FnMut(ProxyId, &T),
+    {
+        self.visit(
+            |node| frustum.intersects_aabb(node),
+            |proxy, _, data| found(proxy, data),
+        );
+    }
+
+    // Everything with a box within radius of center
+    pub fn query_radius<F>(&self, center: &Point3<f32>, radius: f32, mut found: F)
+    where
+        F: FnMut(ProxyId, &T),
+    {
+        let radius_squared = radius * radius;
+        self.visit(
+            |node| node.distance_squared(center) <= radius_squared,
+            |proxy, _, data| found(proxy, data),
+        );
+    }
+
+    // Everything the ray hits before max_distance, with how far along the ray the hit is. The
+    // direction doesn't have to be normalized, but distances are in multiples of it.
+    pub fn query_ray<F>(
+        &self,
+        origin: &Point3<f32>,
+        direction: &Vector3<f32>,
+        max_distance: f32,
+        mut found: F,
+    ) where
+        F: FnMut(ProxyId, &T, f32),
+    {
+        self.visit(
+            |node| node.ray_distance(origin, direction, max_distance).is_some(),
+            |proxy, tight, data| {
+                if let Some(distance) = tight.ray_distance(origin, direction, max_distance) {
+                    found(proxy, data, distance);
+                }
+            },
+        );
+    }
+
+    // The closest thing the ray hits, for picking
+    pub fn ray_cast(
+        &self,
+        origin: &Point3<f32>,
+        direction: &Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<(ProxyId, f32)> {
+        let mut closest: Option<(ProxyId, f32)> = None;
+        self.query_ray(origin, direction, max_distance, |proxy, _, distance| {
+            if closest.is_none_or(|(_, closest)| distance < closest) {
+                closest = Some((proxy, distance));
+            }
+        });
+        closest
+    }
+
+    // Calls found with every leaf whose exact box passes test, skipping branches that don't
+    fn visit<G, F>(&self, test: G, mut found: F)
+    where
+        G: Fn(&Aabb) -> bool,
+        F: FnMut(ProxyId, &Aabb, &T),
+    {
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !test(&node.aabb) {
+                continue;
+            }
+            match &node.kind {
+                NodeKind::Leaf { tight, data } => {
+                    if test(tight) {
+                        found(ProxyId(index), tight, data);
+                    }
+                }
+                NodeKind::Branch { children } => stack.extend_from_slice(children),
+                NodeKind::Free => {}
+            }
+        }
+    }
+
+    fn allocate(&mut self, node: Node<T>) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, index: usize) {
+        self.nodes[index].kind = NodeKind::Free;
+        self.nodes[index].parent = None;
+        self.free.push(index);
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        let Some(root) = self.root else {
+            self.nodes[leaf].parent = None;
+            self.root = Some(leaf);
+            return;
+        };
+
+        // Go down whichever side costs the least, until it's cheaper to stop
+        let leaf_aabb = self.nodes[leaf].aabb;
+        let mut index = root;
+        while let NodeKind::Branch { children } = self.nodes[index].kind {
+            let area = self.nodes[index].aabb.surface_area();
+            let combined_area = self.nodes[index].aabb.union(&leaf_aabb).surface_area();
+
+            // Making a new parent for this node and the leaf
+            let cost = 2.0 * combined_area;
+            // Every node above the leaf grows
+            let inherited_cost = 2.0 * (combined_area - area);
+            let child_cost = |child: usize| {
+                let node = &self.nodes[child];
+                let area = node.aabb.union(&leaf_aabb).surface_area();
+                match node.kind {
+                    NodeKind::Leaf { .. } => area + inherited_cost,
+                    _ => area - node.aabb.surface_area() + inherited_cost,
+                }
+            };
+            let costs = [child_cost(children[0]), child_cost(children[1])];
+            if cost < costs[0] && cost < costs[1] {
+                break;
+            }
+            index = if costs[0] < costs[1] {
+                children[0]
+            } else {
+                children[1]
+            };
+        }
+
+        let sibling = index;
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.allocate(Node {
+            aabb: self.nodes[sibling].aabb.union(&leaf_aabb),
+            parent: old_parent,
+            height: self.nodes[sibling].height + 1,
+            kind: NodeKind::Branch {
+                children: [sibling, leaf],
+            },
+        });
+        self.nodes[sibling].parent = Some(new_parent);
+        self.nodes[leaf].parent = Some(new_parent);
+        match old_parent {
+            Some(old_parent) => self.replace_child(old_parent, sibling, new_parent),
+            None => self.root = Some(new_parent),
+        }
+
+        self.refit(Some(new_parent));
+    }
+
+    // The leaf's sibling takes its parent's place
+    fn remove_leaf(&mut self, leaf: usize) {
+        let Some(parent) = self.nodes[leaf].parent else {
+            self.root = None;
+            return;
+        };
+        let NodeKind::Branch { children } = self.nodes[parent].kind else {
+            return;
+        };
+        let sibling = if children[0] == leaf {
+            children[1]
+        } else {
+            children[0]
+        };
+
+        let grandparent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grandparent;
+        self.nodes[leaf].parent = None;
+        self.release(parent);
+        match grandparent {
+            Some(grandparent) => {
+                self.replace_child(grandparent, parent, sibling);
+                self.refit(Some(grandparent));
+            }
+            None => self.root = Some(sibling),
+        }
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if let NodeKind::Branch { children } = &mut self.nodes[parent].kind {
+            for child in children {
+                if *child == old {
+                    *child = new;
+                }
+            }
+        }
+    }
+
+    // Fixes boxes and heights from index up to the root, balancing along the way
+    fn refit(&mut self, mut index: Option<usize>) {
+        while let Some(current) = index {
+            let current = self.balance(current);
+            self.fit(current);
+            index = self.nodes[current].parent;
+        }
+    }
+
+    fn fit(&mut self, index: usize) {
+        let NodeKind::Branch { children: [a, b] } = self.nodes[index].kind else {
+            return;
+        };
+        self.nodes[index].aabb = self.nodes[a].aabb.union(&self.nodes[b].aabb);
+        self.nodes[index].height = 1 + self.nodes[a].height.max(self.nodes[b].height);
+    }
+
+    // Rotates the taller child up if the children's heights differ by more than one, and
+    // returns whatever is in index's place afterwards
+    fn balance(&mut self, index: usize) -> usize {
+        let NodeKind::Branch { children: [b, c] } = self.nodes[index].kind else {
+            return index;
+        };
+        let difference = self.nodes[c].height as i64 - self.nodes[b].height as i64;
+        if difference > 1 {
+            self.rotate(index, c, b)
+        } else if difference < -1 {
+            self.rotate(index, b, c)
+        } else {
+            index
+        }
+    }
+
+    // Puts high in index's place, with index taking high's shorter child
+    fn rotate(&mut self, index: usize, high: usize, low: usize) -> usize {
+        let NodeKind::Branch { children: [f, g] } = self.nodes[high].kind else {
+            return index;
+        };
+
+        let parent = self.nodes[index].parent;
+        self.nodes[high].parent = parent;
+        self.nodes[index].parent = Some(high);
+        match parent {
+            Some(parent) => self.replace_child(parent, index, high),
+            None => self.root = Some(high),
+        }
+
+        let (keep, give) = if self.nodes[f].height > self.nodes[g].height {
+            (f, g)
+        } else {
+            (g, f)
+        };
+        self.nodes[high].kind = NodeKind::Branch {
+            children: [index, keep],
+        };
+        self.nodes[index].kind = NodeKind::Branch {
+            children: [low, give],
+        };
+        self.nodes[give].parent = Some(index);
+
+        self.fit(index);
+        self.fit(high);
+        high
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::rendersystem::shadow;
+    use nalgebra::Matrix4;
+    use std::collections::BTreeSet;
+
+    // xorshift64, so every run does the same things
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        // In [min, max)
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + (self.next() >> 40) as f32 / (1u64 << 24) as f32 * (max - min)
+        }
+
+        fn index(&mut self, len: usize) -> usize {
+            (self.next() % len as u64) as usize
+        }
+
+        fn point(&mut self, extent: f32) -> Point3<f32> {
+            Point3::new(
+                self.range(-extent, extent),
+                self.range(-extent, extent),
+                self.range(-extent, extent),
+            )
+        }
+
+        fn aabb(&mut self) -> Aabb {
+            let min = self.point(100.0);
+            let size = Vector3::new(
+                self.range(0.0, 5.0),
+                self.range(0.0, 5.0),
+                self.range(0.0, 5.0),
+            );
+            Aabb::new(min, min + size)
+        }
+    }
+
+    // Everything tight passes, by a linear scan
+    fn scan(proxies: &[(ProxyId, Aabb, u32)], test: impl Fn(&Aabb) -> bool) -> BTreeSet<u32> {
+        proxies
+            .iter()
+            .filter(|(_, tight, _)| test(tight))
+            .map(|(_, _, data)| *data)
+            .collect()
+    }
+
+    fn collect(query: impl FnOnce(&mut dyn FnMut(ProxyId, &u32))) -> BTreeSet<u32> {
+        let mut found = BTreeSet::new();
+        query(&mut |_, data| {
+            assert!(found.insert(*data), "{data} was found twice");
+        });
+        found
+    }
+
+    // Parents and children agree, branches hold their children, and heights add up
+    fn check_structure(bvh: &Bvh<u32>) {
+        let Some(root) = bvh.root else {
+            assert_eq!(bvh.len(), 0);
+            return;
+        };
+        assert_eq!(bvh.nodes[root].parent, None);
+
+        let mut leaves = 0;
+        let mut stack = vec![root];
+        while let Some(index) = stack.pop() {
+            let node = &bvh.nodes[index];
+            match &node.kind {
+                NodeKind::Leaf { tight, .. } => {
+                    assert!(node.aabb.contains(tight));
+                    assert_eq!(node.height, 0);
+                    leaves += 1;
+                }
+                NodeKind::Branch { children } => {
+                    for child in children {
+                        assert_eq!(bvh.nodes[*child].parent, Some(index));
+                        assert!(node.aabb.contains(&bvh.nodes[*child].aabb));
+                    }
+                    let [a, b] = children.map(|child| bvh.nodes[child].height);
+                    assert_eq!(node.height, 1 + a.max(b));
+                    assert!(a.abs_diff(b) <= 1, "unbalanced at {index}");
+                    stack.extend_from_slice(children);
+                }
+                NodeKind::Free => panic!("free node {index} is in the tree"),
+            }
+        }
+        assert_eq!(leaves, bvh.len());
+    }
+
+    fn check_queries(bvh: &Bvh<u32>, proxies: &[(ProxyId, Aabb, u32)], random: &mut Random) {
+        let aabb = random.aabb().grow(random.range(0.0, 20.0));
+        assert_eq!(
+            collect(|found| bvh.query_aabb(&aabb, found)),
+            scan(proxies, |tight| aabb.intersects(tight))
+        );
+
+        let center = random.point(100.0);
+        let radius = random.range(0.0, 40.0);
+        assert_eq!(
+            collect(|found| bvh.query_radius(&center, radius, found)),
+            scan(proxies, |tight| {
+                tight.distance_squared(&center) <= radius * radius
+            })
+        );
+
+        let eye = random.point(120.0);
+        let view = Matrix4::look_at_rh(&eye, &random.point(50.0), &Vector3::y());
+        let projection = shadow::perspective(random.range(0.3, 2.0), 16.0 / 9.0, 0.1, 150.0);
+        let frustum = Frustum::from_matrix(&(projection * view));
+        assert_eq!(
+            collect(|found| bvh.query_frustum(&frustum, found)),
+            scan(proxies, |tight| frustum.intersects_aabb(tight))
+        );
+
+        // Mostly aimed at something, since a random ray through sparse boxes rarely hits
+        let origin = random.point(120.0);
+        let target = match proxies.len() {
+            0 => random.point(100.0),
+            len if random.index(4) != 0 => proxies[random.index(len)].1.min,
+            _ => random.point(100.0),
+        };
+        let direction = (target - origin) * random.range(0.001, 0.02);
+        let max_distance = random.range(0.0, 300.0);
+        let mut hits = BTreeSet::new();
+        bvh.query_ray(
+            &origin,
+            &direction,
+            max_distance,
+            |proxy, data, distance| {
+                assert_eq!(
+                    bvh.aabb(proxy)
+                        .unwrap()
+                        .ray_distance(&origin, &direction, max_distance),
+                    Some(distance)
+                );
+                assert!(hits.insert(*data));
+            },
+        );
+        assert_eq!(
+            hits,
+            scan(proxies, |tight| {
+                tight
+                    .ray_distance(&origin, &direction, max_distance)
+                    .is_some()
+            })
+        );
+
+        let closest = proxies
+            .iter()
+            .filter_map(|(_, tight, _)| tight.ray_distance(&origin, &direction, max_distance))
+            .min_by(f32::total_cmp);
+        assert_eq!(
+            bvh.ray_cast(&origin, &direction, max_distance)
+                .map(|(_, distance)| distance),
+            closest
+        );
+    }
+
+    #[test]
+    fn queries_match_a_linear_scan() {
+        let mut random = Random(0x9e3779b97f4a7c15);
+        let mut bvh = Bvh::new();
+        let mut proxies: Vec<(ProxyId, Aabb, u32)> = Vec::new();
+        let mut next_data = 0;
+
+        for step in 0..5000 {
+            match random.index(10) {
+                // Grows to a few hundred and then hovers around there
+                0..=3 if proxies.len() < 400 => {
+                    let aabb = random.aabb();
+                    let proxy = bvh.insert(aabb, next_data);
+                    proxies.push((proxy, aabb, next_data));
+                    next_data += 1;
+                }
+                0..=4 if !proxies.is_empty() => {
+                    let (proxy, _, data) = proxies.swap_remove(random.index(proxies.len()));
+                    assert_eq!(bvh.remove(proxy), Some(data));
+                    assert_eq!(bvh.remove(proxy), None);
+                }
+                _ if !proxies.is_empty() => {
+                    let index = random.index(proxies.len());
+                    let (proxy, aabb, _) = &mut proxies[index];
+                    // Mostly small moves that stay in the grown box, sometimes a jump
+                    let offset = if random.index(4) == 0 {
+                        random.point(50.0).coords
+                    } else {
+                        random.point(MARGIN / 2.0).coords
+                    };
+                    *aabb = Aabb::new(aabb.min + offset, aabb.max + offset);
+                    bvh.update(*proxy, *aabb);
+                }
+                _ => {}
+            }
+
+            assert_eq!(bvh.len(), proxies.len());
+            if step % 25 == 0 {
+                check_structure(&bvh);
+                for (proxy, aabb, data) in &proxies {
+                    assert_eq!(bvh.get(*proxy), Some(data));
+                    assert_eq!(bvh.aabb(*proxy), Some(*aabb));
+                }
+                check_queries(&bvh, &proxies, &mut random);
+            }
+        }
+    }
+
+    #[test]
+    fn height_stays_logarithmic() {
+        // Sorted inserts along a line are the worst case for a tree that doesn't balance
+        let mut bvh = Bvh::new();
+        let mut proxies = Vec::new();
+        for i in 0..4096 {
+            let min = Point3::new(i as f32 * 2.0, 0.0, 0.0);
+            proxies.push(bvh.insert(Aabb::new(min, min + Vector3::repeat(1.0)), i));
+
+            // AVL trees are at most about 1.44 log2(n) high
+            let len = bvh.len() as f32;
+            let limit = (1.45 * (len + 2.0).log2()).ceil() as u32 + 1;
+            assert!(bvh.height() <= limit, "{} high with {len}", bvh.height());
+        }
+        check_structure(&bvh);
+
+        // Taking out every other one shouldn't leave it lopsided either
+        for proxy in proxies.iter().step_by(2) {
+            bvh.remove(*proxy);
+        }
+        check_structure(&bvh);
+        let len = bvh.len() as f32;
+        assert!(bvh.height() <= (1.45 * (len + 2.0).log2()).ceil() as u32 + 1);
+    }
+}
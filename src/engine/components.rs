@@ -0,0 +1,59 @@
+use crate::engine::rendersystem::bounds::Aabb;
+use nalgebra::{Matrix4, Translation3, UnitQuaternion, Vector3};
+use std::collections::BTreeMap;
+
+// What an entity is called in tools like the developer UI
+#[derive(Clone, Debug, PartialEq)]
+pub struct Name(pub String);
+
+// Around the entity before its Transform, entities with both are kept in the engine's BVH
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds(pub Aabb);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub position: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vector3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::repeat(1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn new(position: Vector3<f32>, rotation: UnitQuaternion<f32>, scale: Vector3<f32>) -> Self {
+        Self {
+            position,
+            rotation,
+            scale,
+        }
+    }
+
+    // Scales, then rotates, then translates
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Translation3::from(self.position).to_homogeneous()
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+// Which model an entity is drawn with, by name in the game's model directory. Scenes keep
+// these so loading one can say which models it needs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelRef(pub String);
+
+// Values the game reads and writes itself, which get saved along with the entity
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Properties(pub BTreeMap<String, String>);
+
+// Entities with one are sent to clients, and the server gives them out. Clients add it to the
+// entities they make from snapshots, so the same entity has the same one everywhere.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NetId(pub u32);
@@ -0,0 +1,163 @@
+// Reports for when the game dies, written to DataDirs::crashes.
+//
+// Panics get a full report from the panic hook: what happened and where, the version, a
+// backtrace, whatever other code filled in with set_section (the GPU, arguments, cvars and log
+// file), and the end of the log. A summary then goes to stderr and, if there's a window, a
+// message box.
+//
+// Fatal signals and exceptions are handled by the platform, which can't allocate or take locks
+// since whatever crashed might have been doing that. Their reports have the sections as they
+// were last formatted by set_section, raw stack addresses, and on Linux the memory map to match
+// them to symbols with.
+
+use super::{devui, DataDirs};
+use crate::platform;
+use chrono::Local;
+use once_cell::sync::Lazy;
+use std::{
+    backtrace::Backtrace,
+    collections::BTreeMap,
+    fmt::Write,
+    fs, panic, process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+// Log lines at the end of a report
+const LOG_LINES: usize = 200;
+
+static SECTIONS: Lazy<Mutex<BTreeMap<&'static str, String>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+static SHOW_MESSAGE_BOX: AtomicBool = AtomicBool::new(false);
+
+fn heading() -> String {
+    format!(
+        "{} {}.{}.{}",
+        crate::GAME_NAME,
+        crate::GAME_VERSION_MAJOR,
+        crate::GAME_VERSION_MINOR,
+        crate::GAME_VERSION_PATCH
+    )
+}
+
+fn platform_line() -> String {
+    format!(
+        "Platform: {} {}\n",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+fn write_sections(text: &mut String, sections: &BTreeMap<&'static str, String>) {
+    for (name, section) in sections {
+        let _ = write!(text, "\n{name}:\n{}\n", section.trim_end());
+    }
+}
+
+// Replaces a section of any report written after this. The platform's handler gets a new copy
+// of everything whenever something changes.
+pub fn set_section(name: &'static str, text: String) {
+    let Ok(mut sections) = SECTIONS.lock() else {
+        return;
+    };
+    if sections.get(name) == Some(&text) {
+        return;
+    }
+    sections.insert(name, text);
+
+    let mut text = platform_line();
+    write_sections(&mut text, &sections);
+    platform::set_crash_text(text);
+}
+
+// show_message_box is for when there's someone at a window to see it, and not for servers
+pub fn install(show_message_box: bool) {
+    SHOW_MESSAGE_BOX.store(show_message_box, Ordering::SeqCst);
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        let reason = match info.location() {
+            Some(location) => format!("panicked at {location}: {message}"),
+            None => format!("panicked: {message}"),
+        };
+        report_panic(&reason);
+    }));
+
+    // The handler can't get the time, so its report is named after when the game started
+    let _ = fs::create_dir_all(DataDirs::crashes());
+    let path = format!(
+        "{}{}-{}-{}.txt",
+        DataDirs::crashes(),
+        crate::GAME_EXECUTABLE_NAME,
+        Local::now().format("%Y-%m-%d_%H-%M-%S"),
+        process::id()
+    );
+    platform::install_crash_handler(&path, &heading());
+    platform::set_crash_text(platform_line());
+}
+
+fn report_panic(reason: &str) {
+    if !platform::begin_crash_report() {
+        return;
+    }
+
+    let current = thread::current();
+    let mut text = format!(
+        "{} crashed on thread {}: {reason}\n",
+        heading(),
+        current.name().unwrap_or("unnamed")
+    );
+    let _ = write!(
+        text,
+        "Time: {}\n{}",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        platform_line()
+    );
+
+    // Whatever panicked might have been holding the lock
+    if let Ok(sections) = SECTIONS.try_lock() {
+        write_sections(&mut text, &sections);
+    }
+    let _ = write!(text, "\nBacktrace:\n{}\n", Backtrace::force_capture());
+    let _ = write!(text, "\nLog:\n");
+    for line in devui::recent_log(LOG_LINES) {
+        let _ = writeln!(text, "{line}");
+    }
+
+    let date = Local::now().format("%Y-%m-%d_%H-%M-%S");
+    let path = format!(
+        "{}{}-{date}.txt",
+        DataDirs::crashes(),
+        crate::GAME_EXECUTABLE_NAME
+    );
+    let saved = fs::create_dir_all(DataDirs::crashes()).and_then(|_| fs::write(&path, &text));
+    let summary = match &saved {
+        Ok(()) => format!(
+            "{} crashed: {reason}\n\nA report was saved to {path}",
+            crate::GAME_NAME
+        ),
+        Err(err) => format!(
+            "{} crashed: {reason}\n\nThe report couldn't be saved to {path}: {err}",
+            crate::GAME_NAME
+        ),
+    };
+
+    eprintln!("{summary}");
+    if saved.is_err() {
+        eprintln!("{text}");
+    }
+    if SHOW_MESSAGE_BOX.load(Ordering::SeqCst) {
+        platform::show_error(&format!("{} crashed", crate::GAME_NAME), &summary);
+    }
+}
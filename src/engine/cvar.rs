@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CvarValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    String(String),
+}
+
+impl CvarValue {
+    // Parses text as the same type as this value
+    pub fn parse_as(&self, text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        match self {
+            Self::Bool(_) => match text.to_ascii_lowercase().as_str() {
+                "1" | "true" | "on" | "yes" => Ok(Self::Bool(true)),
+                "0" | "false" | "off" | "no" => Ok(Self::Bool(false)),
+                _ => Err(format!("{text} isn't a boolean")),
+            },
+            Self::Int(_) => text
+                .parse()
+                .map(Self::Int)
+                .map_err(|err| format!("{text} isn't an integer: {err}")),
+            Self::Float(_) => text
+                .parse()
+                .map(Self::Float)
+                .map_err(|err| format!("{text} isn't a number: {err}")),
+            Self::String(_) => Ok(Self::String(String::from(text))),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::Bool(_) => "boolean",
+            Self::Int(_) => "integer",
+            Self::Float(_) => "number",
+            Self::String(_) => "string",
+        }
+    }
+}
+
+impl fmt::Display for CvarValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Int(value) => write!(f, "{value}"),
+            Self::Float(value) => write!(f, "{value}"),
+            Self::String(value) => f.write_str(value),
+        }
+    }
+}
+
+pub struct Cvar {
+    pub value: CvarValue,
+    pub default: CvarValue,
+    pub description: String,
+}
+
+// Settings that can be changed by name at runtime, like from the developer UI. The type of
+// each one is fixed by its default.
+#[derive(Default)]
+pub struct Cvars {
+    vars: BTreeMap<String, Cvar>,
+    // Since take_changed was last called
+    changed: bool,
+}
+
+impl Cvars {
+    // Registering something that already exists keeps its value if the type is the same
+    pub fn register(&mut self, name: &str, default: CvarValue, description: &str) {
+        let value = match self.vars.get(name) {
+            Some(cvar) if cvar.value.type_name() == default.type_name() => cvar.value.clone(),
+            _ => default.clone(),
+        };
+        self.vars.insert(
+            String::from(name),
+            Cvar {
+                value,
+                default,
+                description: String::from(description),
+            },
+        );
+        self.changed = true;
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CvarValue> {
+        self.vars.get(name).map(|cvar| &cvar.value)
+    }
+
+    pub fn set(&mut self, name: &str, value: CvarValue) -> Result<(), String> {
+        let Some(cvar) = self.vars.get_mut(name) else {
+            return Err(format!("No cvar named {name}"));
+        };
+        if cvar.value.type_name() != value.type_name() {
+            return Err(format!(
+                "{name} is a {}, not a {}",
+                cvar.value.type_name(),
+                value.type_name()
+            ));
+        }
+        if cvar.value != value {
+            cvar.value = value;
+            self.changed = true;
+        }
+        Ok(())
+    }
+
+    pub fn set_from_str(&mut self, name: &str, text: &str) -> Result<(), String> {
+        let value = match self.vars.get(name) {
+            Some(cvar) => cvar.value.parse_as(text)?,
+            None => return Err(format!("No cvar named {name}")),
+        };
+        self.set(name, value)
+    }
+
+    // These give the type's default if the cvar doesn't exist or is a different type
+    pub fn get_bool(&self, name: &str) -> bool {
+        match self.get(name) {
+            Some(CvarValue::Bool(value)) => *value,
+            _ => false,
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> i64 {
+        match self.get(name) {
+            Some(CvarValue::Int(value)) => *value,
+            _ => 0,
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> f32 {
+        match self.get(name) {
+            Some(CvarValue::Float(value)) => *value,
+            _ => 0.0,
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> &str {
+        match self.get(name) {
+            Some(CvarValue::String(value)) => value,
+            _ => "",
+        }
+    }
+
+    // In name order
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Cvar)> {
+        self.vars.iter()
+    }
+
+    // Whatever changes values through this has to call mark_changed
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Cvar)> {
+        self.vars.iter_mut()
+    }
+
+    pub fn mark_changed(&mut self) {
+        self.changed = true;
+    }
+
+    // Whether anything was registered or set since the last call
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+}
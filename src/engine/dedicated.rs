@@ -0,0 +1,260 @@
+use super::{components, gamelib, input, mods, net, scene, script, vfs, GameDirs, State};
+use crate::{platform, player};
+use log::{debug, error, info, warn};
+use std::{
+    collections::HashMap,
+    io,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+// Falling further behind than this skips ticks instead of running them all at once
+const MAX_LAG: Duration = Duration::from_secs(1);
+
+// The game running as a server with --dedicated. There's no window, renderer or input, just
+// the world, scripts, the game library and a net::Server, ticking at --tick-rate. Every client
+// gets an entity named after it that its commands move with player::simulate.
+//
+// Admin commands are read from stdin, one per line:
+//
+//     status                   lists the connected clients
+//     kick <client> [reason]
+//     map <level>              replaces the world with a level from the level directory
+//     quit
+pub struct Server {
+    mods: Vec<mods::Mod>,
+    world: legion::World,
+    // Nothing is ever pressed, but scripts and the game library still want one
+    input: input::State,
+    scripts: script::Scripts,
+    game: Option<gamelib::GameLibrary>,
+    net: net::Server,
+    players: HashMap<u32, legion::Entity>,
+    console: mpsc::Receiver<String>,
+    tick_length: Duration,
+    running: bool,
+}
+
+// Lines from stdin, read on their own thread so waiting for them doesn't hold up ticks
+fn spawn_console() -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    let reader = thread::Builder::new()
+        .name(String::from("console"))
+        .spawn(move || {
+            platform::init_thread();
+            // Servers started without a terminal get EOF straight away and keep running
+            for line in io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+    if let Err(err) = reader {
+        warn!("Failed to start console: {err}");
+    }
+    receiver
+}
+
+impl Server {
+    pub fn init(args: crate::Args) -> Self {
+        let (game_dir, mods) = State::init_common(&args);
+
+        let address = format!("0.0.0.0:{}", args.port);
+        let net = match net::Server::bind(&address) {
+            Ok(net) => net.tick_rate(args.tick_rate).max_clients(args.max_clients),
+            Err(err) => panic!("Failed to start server: {err}"),
+        };
+
+        let mut self_ = Self {
+            mods,
+            world: legion::World::default(),
+            input: input::State::default(),
+            scripts: script::Scripts::new().hot_reload(args.reload_scripts),
+            game: None,
+            net,
+            players: HashMap::new(),
+            console: spawn_console(),
+            tick_length: Duration::from_secs_f64(1.0 / args.tick_rate.max(1) as f64),
+            running: true,
+        };
+
+        let mut host = gamelib::EngineHost {
+            world: &mut self_.world,
+            input: &self_.input,
+            delta: 0.0,
+        };
+        self_.game = gamelib::GameLibrary::load(&game_dir, &mut host);
+
+        if let Some(map) = &args.map {
+            if let Err(err) = self_.load_map(map) {
+                error!("Failed to load map {map}: {err}");
+            }
+        }
+
+        info!(
+            "Dedicated server started with {} mods, type help for commands",
+            self_.mods.len()
+        );
+        self_
+    }
+
+    // Ticks until quit
+    pub fn run(mut self) {
+        let delta = self.tick_length.as_secs_f64();
+        let mut next_tick = Instant::now();
+        while self.running {
+            self.update(delta);
+
+            next_tick += self.tick_length;
+            let now = Instant::now();
+            if next_tick > now {
+                thread::sleep(next_tick - now);
+            } else if now - next_tick > MAX_LAG {
+                warn!(
+                    "Server is {:.1} seconds behind, skipping ticks",
+                    (now - next_tick).as_secs_f64()
+                );
+                next_tick = now;
+            }
+        }
+        self.shutdown();
+    }
+
+    // The game runs before the network, so snapshots have this tick's changes
+    fn update(&mut self, delta: f64) {
+        while let Ok(line) = self.console.try_recv() {
+            self.command(&line);
+        }
+
+        self.scripts.update(&mut self.world, &mut self.input, delta);
+        if let Some(game) = &mut self.game {
+            let mut host = gamelib::EngineHost {
+                world: &mut self.world,
+                input: &self.input,
+                delta,
+            };
+            game.tick(&mut host, delta);
+        }
+
+        for event in self.net.update(&mut self.world, delta, player::simulate) {
+            match event {
+                net::ServerEvent::Connected { client, name } => self.spawn_player(client, &name),
+                net::ServerEvent::Disconnected { client, .. } => {
+                    if let Some(player) = self.players.remove(&client) {
+                        self.world.remove(player);
+                    }
+                }
+                net::ServerEvent::Message { client, data } => {
+                    debug!("Client {client} sent a {} byte message", data.len())
+                }
+            }
+        }
+    }
+
+    fn command(&mut self, line: &str) {
+        let mut parts = line.split_whitespace();
+        let Some(command) = parts.next() else {
+            return;
+        };
+
+        match command {
+            "help" => info!("Commands are status, kick <client> [reason], map <level> and quit"),
+            "status" => {
+                let clients: Vec<(u32, &str)> = self.net.clients().collect();
+                info!(
+                    "Tick {}, {} clients connected",
+                    self.net.tick(),
+                    clients.len()
+                );
+                for (client, name) in clients {
+                    info!("    {client}: {name}");
+                }
+            }
+            "kick" => {
+                let client = parts.next().and_then(|client| client.parse::<u32>().ok());
+                let Some(client) = client else {
+                    warn!("Usage: kick <client> [reason]");
+                    return;
+                };
+                if !self.net.clients().any(|(id, _)| id == client) {
+                    warn!("No client {client}");
+                    return;
+                }
+                let reason: Vec<&str> = parts.collect();
+                let reason = if reason.is_empty() {
+                    String::from("kicked")
+                } else {
+                    reason.join(" ")
+                };
+                self.net.kick(client, &reason);
+            }
+            "map" => {
+                let Some(name) = parts.next() else {
+                    warn!("Usage: map <level>");
+                    return;
+                };
+                if let Err(err) = self.load_map(name) {
+                    error!("Failed to load map {name}: {err}");
+                }
+            }
+            "quit" | "exit" => self.running = false,
+            _ => warn!("Unknown command {command}, try help"),
+        }
+    }
+
+    fn spawn_player(&mut self, client: u32, name: &str) {
+        let player = self.world.push((
+            components::Name(String::from(name)),
+            components::Transform::default(),
+        ));
+        self.net.replicate(&mut self.world, player);
+        self.net.set_player(client, Some(player));
+        self.players.insert(client, player);
+    }
+
+    // Every entity in the level is sent to clients, and players get new entities since
+    // theirs went with the old world
+    fn load_map(&mut self, name: &str) -> Result<(), String> {
+        let path = format!("{}{name}.{}", GameDirs::levels(), scene::LEVEL_EXTENSION);
+        let data = vfs::open(&path)?;
+        let level = scene::Scene::load(&data, &scene::Migrations::default())?;
+
+        self.world.clear();
+        self.players.clear();
+        let loaded = level.spawn(&mut self.world);
+        for entity in loaded.entities {
+            self.net.replicate(&mut self.world, entity);
+        }
+        let clients: Vec<(u32, String)> = self
+            .net
+            .clients()
+            .map(|(client, name)| (client, String::from(name)))
+            .collect();
+        for (client, name) in clients {
+            self.spawn_player(client, &name);
+        }
+
+        info!("Loaded map {name}");
+        Ok(())
+    }
+
+    fn shutdown(mut self) {
+        info!("Dedicated server shutdown started");
+
+        if let Some(game) = self.game.take() {
+            let mut host = gamelib::EngineHost {
+                world: &mut self.world,
+                input: &self.input,
+                delta: 0.0,
+            };
+            game.shutdown(&mut host);
+        }
+        self.net.shutdown();
+
+        info!("Dedicated server shutdown succeeded");
+    }
+}
@@ -0,0 +1,837 @@
+use super::{bvh, components, cvar, input, profiler, rendersystem};
+use crate::platform::input::{Event, Key, MouseButton};
+use legion::IntoQuery;
+use log::error;
+use nalgebra::UnitQuaternion;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Instant;
+
+const MAX_LOG_LINES: usize = 2000;
+// egui scrolls in points, and a wheel notch is about three lines
+const SCROLL_SPEED: f32 = 50.0;
+
+struct LogLine {
+    level: log::Level,
+    target: String,
+    message: String,
+}
+
+static LOG: Lazy<Mutex<VecDeque<LogLine>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+// Chained into the logger so the log window has something to show
+pub fn record_log(record: &log::Record) {
+    let Ok(mut log) = LOG.lock() else {
+        return;
+    };
+    if log.len() >= MAX_LOG_LINES {
+        log.pop_front();
+    }
+    log.push_back(LogLine {
+        level: record.level(),
+        target: String::from(record.target()),
+        message: record.args().to_string(),
+    });
+}
+
+// The newest lines, for crash reports. Doesn't wait for the lock, in case whatever crashed was
+// holding it.
+pub fn recent_log(count: usize) -> Vec<String> {
+    let Ok(log) = LOG.try_lock() else {
+        return Vec::new();
+    };
+    log.iter()
+        .skip(log.len().saturating_sub(count))
+        .map(|line| format!("[{} {}] {}", line.level, line.target, line.message))
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    pub fps: f64,
+    // In milliseconds
+    pub frame_time: i64,
+    pub draws: rendersystem::DrawStats,
+}
+
+// egui only sends the changed part of a texture, but the whole thing gets uploaded again
+struct UiTexture {
+    handle: rendersystem::TextureHandle,
+    size: [usize; 2],
+    pixels: Vec<egui::Color32>,
+}
+
+// Tools for looking at and changing what the engine is doing while it runs, toggled with F1.
+// It captures whatever input egui wants, so the game doesn't see clicks on a window.
+pub struct DevUi {
+    context: egui::Context,
+    visible: bool,
+    start_time: Instant,
+    modifiers: egui::Modifiers,
+    pointer: egui::Pos2,
+    textures: HashMap<egui::TextureId, UiTexture>,
+
+    show_stats: bool,
+    show_cvars: bool,
+    show_entities: bool,
+    show_log: bool,
+    show_profiler: bool,
+    show_memory: bool,
+    cvar_filter: String,
+    selected_entity: Option<legion::Entity>,
+    log_level: log::Level,
+}
+
+impl Default for DevUi {
+    fn default() -> Self {
+        Self {
+            context: egui::Context::default(),
+            visible: false,
+            start_time: Instant::now(),
+            modifiers: egui::Modifiers::default(),
+            pointer: egui::Pos2::ZERO,
+            textures: HashMap::new(),
+            show_stats: true,
+            show_cvars: false,
+            show_entities: false,
+            show_log: false,
+            show_profiler: false,
+            show_memory: false,
+            cvar_filter: String::new(),
+            selected_entity: None,
+            log_level: log::Level::Info,
+        }
+    }
+}
+
+fn key_to_egui(key: Key) -> Option<egui::Key> {
+    Some(match key {
+        Key::A => egui::Key::A,
+        Key::B => egui::Key::B,
+        Key::C => egui::Key::C,
+        Key::D => egui::Key::D,
+        Key::E => egui::Key::E,
+        Key::F => egui::Key::F,
+        Key::G => egui::Key::G,
+        Key::H => egui::Key::H,
+        Key::I => egui::Key::I,
+        Key::J => egui::Key::J,
+        Key::K => egui::Key::K,
+        Key::L => egui::Key::L,
+        Key::M => egui::Key::M,
+        Key::N => egui::Key::N,
+        Key::O => egui::Key::O,
+        Key::P => egui::Key::P,
+        Key::Q => egui::Key::Q,
+        Key::R => egui::Key::R,
+        Key::S => egui::Key::S,
+        Key::T => egui::Key::T,
+        Key::U => egui::Key::U,
+        Key::V => egui::Key::V,
+        Key::W => egui::Key::W,
+        Key::X => egui::Key::X,
+        Key::Y => egui::Key::Y,
+        Key::Z => egui::Key::Z,
+        Key::Num0 => egui::Key::Num0,
+        Key::Num1 => egui::Key::Num1,
+        Key::Num2 => egui::Key::Num2,
+        Key::Num3 => egui::Key::Num3,
+        Key::Num4 => egui::Key::Num4,
+        Key::Num5 => egui::Key::Num5,
+        Key::Num6 => egui::Key::Num6,
+        Key::Num7 => egui::Key::Num7,
+        Key::Num8 => egui::Key::Num8,
+        Key::Num9 => egui::Key::Num9,
+        Key::Escape => egui::Key::Escape,
+        Key::Enter => egui::Key::Enter,
+        Key::Tab => egui::Key::Tab,
+        Key::Backspace => egui::Key::Backspace,
+        Key::Space => egui::Key::Space,
+        Key::Insert => egui::Key::Insert,
+        Key::Delete => egui::Key::Delete,
+        Key::Home => egui::Key::Home,
+        Key::End => egui::Key::End,
+        Key::PageUp => egui::Key::PageUp,
+        Key::PageDown => egui::Key::PageDown,
+        Key::Left => egui::Key::ArrowLeft,
+        Key::Right => egui::Key::ArrowRight,
+        Key::Up => egui::Key::ArrowUp,
+        Key::Down => egui::Key::ArrowDown,
+        Key::Minus => egui::Key::Minus,
+        Key::Equals => egui::Key::PlusEquals,
+        _ => return None,
+    })
+}
+
+impl DevUi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn update(
+        &mut self,
+        input: &mut input::State,
+        cvars: &mut cvar::Cvars,
+        world: &mut legion::World,
+        render: &mut rendersystem::State,
+        spatial: &bvh::Bvh<legion::Entity>,
+        stats: FrameStats,
+    ) {
+        let events = self.translate_events(input.events());
+        if !self.visible {
+            return;
+        }
+
+        let (width, height) = render.backbuffer_size();
+        let raw_input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(width as f32, height as f32),
+            )),
+            time: Some(self.start_time.elapsed().as_secs_f64()),
+            modifiers: self.modifiers,
+            events,
+            ..Default::default()
+        };
+
+        let context = self.context.clone();
+        let output = context.run(raw_input, |context| {
+            self.windows(context, cvars, world, render, stats)
+        });
+        input.capture(
+            context.wants_keyboard_input(),
+            context.wants_pointer_input() || context.is_pointer_over_area(),
+        );
+
+        // Clicking on the world selects whatever's under the pointer
+        let clicked = input.events().iter().any(|event| {
+            matches!(
+                event,
+                Event::MouseButton {
+                    button: MouseButton::Left,
+                    pressed: true,
+                }
+            )
+        });
+        if clicked && !input.mouse_captured() {
+            if let Some(entity) = pick(render, spatial, self.pointer) {
+                self.selected_entity = Some(entity);
+                self.show_entities = true;
+            }
+        }
+
+        for (id, delta) in output.textures_delta.set {
+            self.update_texture(render, id, delta);
+        }
+        for primitive in context.tessellate(output.shapes) {
+            let egui::epaint::Primitive::Mesh(mesh) = primitive.primitive else {
+                continue;
+            };
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+            let clip = primitive.clip_rect;
+            render.queue_ui_overlay(rendersystem::ui::UiMesh {
+                vertices: mesh
+                    .vertices
+                    .iter()
+                    .map(|vertex| rendersystem::ui::UiVertex {
+                        position: [vertex.pos.x, vertex.pos.y],
+                        texture_coordinate: [vertex.uv.x, vertex.uv.y],
+                        color: vertex.color.to_array(),
+                    })
+                    .collect(),
+                indices: mesh.indices,
+                texture: texture.handle,
+                clip: [clip.min.x, clip.min.y, clip.max.x, clip.max.y],
+            });
+        }
+        for id in output.textures_delta.free {
+            if let Some(texture) = self.textures.remove(&id) {
+                render.destroy_texture(texture.handle);
+            }
+        }
+    }
+
+    pub fn shutdown(&mut self, render: &mut rendersystem::State) {
+        for (_, texture) in self.textures.drain() {
+            render.destroy_texture(texture.handle);
+        }
+    }
+
+    // Also handles the toggle, which works even when the UI is hidden
+    fn translate_events(&mut self, events: &[Event]) -> Vec<egui::Event> {
+        let mut translated = Vec::new();
+        for event in events {
+            match *event {
+                Event::Key {
+                    key: Key::F1,
+                    pressed: true,
+                } => self.visible = !self.visible,
+                Event::Key { key, pressed } => {
+                    match key {
+                        Key::Shift => self.modifiers.shift = pressed,
+                        Key::Control => {
+                            self.modifiers.ctrl = pressed;
+                            self.modifiers.command = pressed;
+                        }
+                        Key::Alt => self.modifiers.alt = pressed,
+                        _ => {}
+                    }
+                    if let Some(key) = key_to_egui(key) {
+                        translated.push(egui::Event::Key {
+                            key,
+                            pressed,
+                            repeat: false,
+                            modifiers: self.modifiers,
+                        });
+                    }
+                }
+                Event::Text(character) => {
+                    translated.push(egui::Event::Text(character.to_string()));
+                }
+                Event::MouseMoved { x, y } => {
+                    self.pointer = egui::pos2(x, y);
+                    translated.push(egui::Event::PointerMoved(self.pointer));
+                }
+                Event::MouseButton { button, pressed } => {
+                    translated.push(egui::Event::PointerButton {
+                        pos: self.pointer,
+                        button: match button {
+                            MouseButton::Left => egui::PointerButton::Primary,
+                            MouseButton::Right => egui::PointerButton::Secondary,
+                            MouseButton::Middle => egui::PointerButton::Middle,
+                        },
+                        pressed,
+                        modifiers: self.modifiers,
+                    });
+                }
+                Event::MouseWheel(notches) => {
+                    translated.push(egui::Event::Scroll(egui::vec2(0.0, notches * SCROLL_SPEED)));
+                }
+            }
+        }
+        translated
+    }
+
+    fn update_texture(
+        &mut self,
+        render: &mut rendersystem::State,
+        id: egui::TextureId,
+        delta: egui::epaint::ImageDelta,
+    ) {
+        let size = delta.image.size();
+        let pixels: Vec<egui::Color32> = match &delta.image {
+            egui::ImageData::Color(image) => image.pixels.clone(),
+            egui::ImageData::Font(image) => image.srgba_pixels(None).collect(),
+        };
+
+        let (size, pixels) = match (delta.pos, self.textures.remove(&id)) {
+            (Some(pos), Some(mut texture)) => {
+                for row in 0..size[1] {
+                    let start = (pos[1] + row) * texture.size[0] + pos[0];
+                    texture.pixels[start..start + size[0]]
+                        .copy_from_slice(&pixels[row * size[0]..(row + 1) * size[0]]);
+                }
+                render.destroy_texture(texture.handle);
+                (texture.size, texture.pixels)
+            }
+            (_, old_texture) => {
+                if let Some(old_texture) = old_texture {
+                    render.destroy_texture(old_texture.handle);
+                }
+                (size, pixels)
+            }
+        };
+
+        let bytes: Vec<u8> = pixels.iter().flat_map(|pixel| pixel.to_array()).collect();
+        match render.create_texture(size[0] as u32, size[1] as u32, &bytes, false) {
+            Ok(handle) => {
+                self.textures.insert(
+                    id,
+                    UiTexture {
+                        handle,
+                        size,
+                        pixels,
+                    },
+                );
+            }
+            Err(err) => error!("Failed to create UI texture {id:?}: {err}"),
+        }
+    }
+
+    fn windows(
+        &mut self,
+        context: &egui::Context,
+        cvars: &mut cvar::Cvars,
+        world: &mut legion::World,
+        render: &rendersystem::State,
+        stats: FrameStats,
+    ) {
+        egui::TopBottomPanel::top("developer menu").show(context, |ui| {
+            ui.horizontal(|ui| {
+                ui.toggle_value(&mut self.show_stats, "Stats");
+                ui.toggle_value(&mut self.show_cvars, "Cvars");
+                ui.toggle_value(&mut self.show_entities, "Entities");
+                ui.toggle_value(&mut self.show_log, "Log");
+                ui.toggle_value(&mut self.show_profiler, "Profiler");
+                ui.toggle_value(&mut self.show_memory, "Memory");
+            });
+        });
+
+        egui::Window::new("Stats")
+            .open(&mut self.show_stats)
+            .show(context, |ui| stats_window(ui, &stats, world));
+        egui::Window::new("Cvars")
+            .open(&mut self.show_cvars)
+            .show(context, |ui| cvars_window(ui, cvars, &mut self.cvar_filter));
+        egui::Window::new("Entities")
+            .open(&mut self.show_entities)
+            .show(context, |ui| {
+                entities_window(ui, world, &mut self.selected_entity)
+            });
+        egui::Window::new("Log")
+            .open(&mut self.show_log)
+            .default_width(600.0)
+            .show(context, |ui| log_window(ui, &mut self.log_level));
+        egui::Window::new("Profiler")
+            .open(&mut self.show_profiler)
+            .show(context, profiler_window);
+        egui::Window::new("Memory")
+            .open(&mut self.show_memory)
+            .show(context, |ui| memory_window(ui, render));
+    }
+}
+
+// The closest entity with bounds under the pointer
+fn pick(
+    render: &rendersystem::State,
+    spatial: &bvh::Bvh<legion::Entity>,
+    pointer: egui::Pos2,
+) -> Option<legion::Entity> {
+    let (origin, direction) = render.screen_ray(pointer.x, pointer.y)?;
+    let (proxy, _) = spatial.ray_cast(&origin, &direction, 1.0)?;
+    spatial.get(proxy).copied()
+}
+
+fn stats_window(ui: &mut egui::Ui, stats: &FrameStats, world: &legion::World) {
+    egui::Grid::new("stats").show(ui, |ui| {
+        ui.label("FPS");
+        ui.label(format!("{:.1}", stats.fps));
+        ui.end_row();
+        ui.label("Frame time");
+        ui.label(format!("{} ms", stats.frame_time));
+        ui.end_row();
+        ui.label("Instances");
+        ui.label(stats.draws.instances.to_string());
+        ui.end_row();
+        ui.label("Batches");
+        ui.label(stats.draws.batches.to_string());
+        ui.end_row();
+        ui.label("Culled");
+        ui.label(stats.draws.culled.to_string());
+        ui.end_row();
+        ui.label("Entities");
+        ui.label(world.len().to_string());
+        ui.end_row();
+    });
+}
+
+fn cvars_window(ui: &mut egui::Ui, cvars: &mut cvar::Cvars, filter: &mut String) {
+    ui.horizontal(|ui| {
+        ui.label("Filter");
+        ui.text_edit_singleline(filter);
+    });
+    ui.separator();
+
+    let mut changed = false;
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        egui::Grid::new("cvars").striped(true).show(ui, |ui| {
+            for (name, cvar) in cvars.iter_mut() {
+                if !name.contains(filter.as_str()) {
+                    continue;
+                }
+
+                ui.label(name.as_str())
+                    .on_hover_text(cvar.description.as_str());
+                let response = match &mut cvar.value {
+                    cvar::CvarValue::Bool(value) => ui.checkbox(value, ""),
+                    cvar::CvarValue::Int(value) => ui.add(egui::DragValue::new(value)),
+                    cvar::CvarValue::Float(value) => {
+                        ui.add(egui::DragValue::new(value).speed(0.01))
+                    }
+                    cvar::CvarValue::String(value) => ui.text_edit_singleline(value),
+                };
+                changed |= response.changed();
+                if ui
+                    .add_enabled(cvar.value != cvar.default, egui::Button::new("Reset"))
+                    .clicked()
+                {
+                    cvar.value = cvar.default.clone();
+                    changed = true;
+                }
+                ui.end_row();
+            }
+        });
+    });
+    if changed {
+        cvars.mark_changed();
+    }
+}
+
+fn entities_window(
+    ui: &mut egui::Ui,
+    world: &mut legion::World,
+    selected: &mut Option<legion::Entity>,
+) {
+    let mut entities: Vec<(legion::Entity, String)> =
+        <(legion::Entity, Option<&components::Name>)>::query()
+            .iter(&*world)
+            .map(|(entity, name)| {
+                let name = name.map_or_else(|| format!("{entity:?}"), |name| name.0.clone());
+                (*entity, name)
+            })
+            .collect();
+    entities.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+    egui::ScrollArea::vertical()
+        .id_source("entity list")
+        .max_height(200.0)
+        .show(ui, |ui| {
+            for (entity, name) in &entities {
+                if ui
+                    .selectable_label(*selected == Some(*entity), name.as_str())
+                    .clicked()
+                {
+                    *selected = Some(*entity);
+                }
+            }
+        });
+    ui.separator();
+
+    let Some(entity) = *selected else {
+        ui.label("Nothing selected");
+        return;
+    };
+    let Some(mut entry) = world.entry(entity) else {
+        *selected = None;
+        return;
+    };
+
+    ui.label(format!("{entity:?}"));
+    if let Ok(name) = entry.get_component_mut::<components::Name>() {
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut name.0);
+        });
+    }
+    if let Ok(transform) = entry.get_component_mut::<components::Transform>() {
+        // Rotation is edited as Euler angles in degrees
+        let (roll, pitch, yaw) = transform.rotation.euler_angles();
+        let mut angles = [roll.to_degrees(), pitch.to_degrees(), yaw.to_degrees()];
+        egui::Grid::new("transform").show(ui, |ui| {
+            ui.label("Position");
+            for axis in transform.position.iter_mut() {
+                ui.add(egui::DragValue::new(axis).speed(0.05));
+            }
+            ui.end_row();
+            ui.label("Rotation");
+            for angle in &mut angles {
+                ui.add(egui::DragValue::new(angle).speed(0.5));
+            }
+            ui.end_row();
+            ui.label("Scale");
+            for axis in transform.scale.iter_mut() {
+                ui.add(egui::DragValue::new(axis).speed(0.01));
+            }
+            ui.end_row();
+        });
+        let [roll, pitch, yaw] = angles.map(f32::to_radians);
+        let rotation = UnitQuaternion::from_euler_angles(roll, pitch, yaw);
+        if rotation.angle_to(&transform.rotation) > 1e-5 {
+            transform.rotation = rotation;
+        }
+    }
+
+    if ui.button("Delete").clicked() {
+        world.remove(entity);
+        *selected = None;
+    }
+}
+
+fn log_window(ui: &mut egui::Ui, max_level: &mut log::Level) {
+    egui::ComboBox::from_label("Level")
+        .selected_text(max_level.as_str())
+        .show_ui(ui, |ui| {
+            for level in [
+                log::Level::Error,
+                log::Level::Warn,
+                log::Level::Info,
+                log::Level::Debug,
+                log::Level::Trace,
+            ] {
+                ui.selectable_value(max_level, level, level.as_str());
+            }
+        });
+    ui.separator();
+
+    let Ok(log) = LOG.lock() else {
+        return;
+    };
+    let lines: Vec<&LogLine> = log.iter().filter(|line| line.level <= *max_level).collect();
+    let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+    egui::ScrollArea::vertical()
+        .stick_to_bottom(true)
+        .auto_shrink([false, false])
+        .show_rows(ui, row_height, lines.len(), |ui, rows| {
+            for line in &lines[rows] {
+                let color = match line.level {
+                    log::Level::Error => egui::Color32::LIGHT_RED,
+                    log::Level::Warn => egui::Color32::YELLOW,
+                    log::Level::Info => egui::Color32::LIGHT_GREEN,
+                    log::Level::Debug => egui::Color32::LIGHT_BLUE,
+                    log::Level::Trace => egui::Color32::GRAY,
+                };
+                ui.label(
+                    egui::RichText::new(format!(
+                        "[{} {}] {}",
+                        line.level, line.target, line.message
+                    ))
+                    .monospace()
+                    .color(color),
+                );
+            }
+        });
+}
+
+// Averages and peaks over the profiler's history, in milliseconds
+fn profiler_window(ui: &mut egui::Ui) {
+    let frames = profiler::frames();
+    if frames.is_empty() {
+        ui.label("No frames yet");
+        return;
+    }
+
+    let count = frames.len() as f64;
+    let average = frames.iter().map(|frame| frame.duration).sum::<f64>() / count;
+    let peak = frames
+        .iter()
+        .map(|frame| frame.duration)
+        .fold(0.0, f64::max);
+    let gpu_frames: Vec<f64> = frames
+        .iter()
+        .filter(|frame| !frame.gpu.is_empty())
+        .map(|frame| frame.gpu_time())
+        .collect();
+    egui::Grid::new("profiler frames").show(ui, |ui| {
+        ui.label("Frames");
+        ui.label(frames.len().to_string());
+        ui.end_row();
+        ui.label("Frame time");
+        ui.label(format!("{average:.2} ms average, {peak:.2} ms peak"));
+        ui.end_row();
+        if !gpu_frames.is_empty() {
+            ui.label("GPU time");
+            ui.label(format!(
+                "{:.2} ms average",
+                gpu_frames.iter().sum::<f64>() / gpu_frames.len() as f64
+            ));
+            ui.end_row();
+        }
+    });
+
+    // A bar for each frame, with a line at 60 FPS
+    let (response, painter) = ui.allocate_painter(egui::vec2(300.0, 60.0), egui::Sense::hover());
+    let rect = response.rect;
+    let scale = rect.height() / (peak.max(1000.0 / 30.0) as f32);
+    let width = rect.width() / profiler::HISTORY as f32;
+    for (index, frame) in frames.iter().enumerate() {
+        let height = frame.duration as f32 * scale;
+        let left = rect.left() + index as f32 * width;
+        let color = if frame.duration > 1000.0 / 30.0 {
+            egui::Color32::LIGHT_RED
+        } else if frame.duration > 1000.0 / 60.0 {
+            egui::Color32::YELLOW
+        } else {
+            egui::Color32::LIGHT_GREEN
+        };
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(left, rect.bottom() - height),
+                egui::pos2(left + width, rect.bottom()),
+            ),
+            0.0,
+            color,
+        );
+    }
+    painter.hline(
+        rect.x_range(),
+        rect.bottom() - (1000.0 / 60.0) * scale,
+        egui::Stroke::new(1.0, egui::Color32::GRAY),
+    );
+
+    // Scopes are listed in the order the newest frame ran them
+    let newest = frames.last().unwrap();
+    let mut cpu: Vec<(&str, u32, f64, f64)> = Vec::new();
+    for marker in newest.cpu.iter() {
+        if !cpu.iter().any(|(name, ..)| *name == marker.name) {
+            cpu.push((marker.name, marker.depth, 0.0, 0.0));
+        }
+    }
+    for frame in &frames {
+        for (name, _, total, peak) in &mut cpu {
+            let time: f64 = frame
+                .cpu
+                .iter()
+                .filter(|marker| marker.name == *name)
+                .map(|marker| marker.duration)
+                .sum();
+            *total += time;
+            *peak = peak.max(time);
+        }
+    }
+    let mut cpu_order: Vec<usize> = (0..cpu.len()).collect();
+    cpu_order.sort_by(|a, b| {
+        let start = |index: usize| {
+            newest
+                .cpu
+                .iter()
+                .find(|marker| marker.name == cpu[index].0)
+                .map_or(0.0, |marker| marker.start)
+        };
+        start(*a).total_cmp(&start(*b))
+    });
+
+    ui.separator();
+    egui::Grid::new("profiler cpu")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("CPU");
+            ui.strong("Average");
+            ui.strong("Peak");
+            ui.end_row();
+            for index in cpu_order {
+                let (name, depth, total, peak) = cpu[index];
+                ui.label(format!("{}{name}", "  ".repeat(depth as usize)));
+                ui.label(format!("{:.2} ms", total / count));
+                ui.label(format!("{peak:.2} ms"));
+                ui.end_row();
+            }
+        });
+
+    if let Some(timed) = frames.iter().rev().find(|frame| !frame.gpu.is_empty()) {
+        ui.separator();
+        egui::Grid::new("profiler gpu")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("GPU");
+                ui.strong("Average");
+                ui.strong("Peak");
+                ui.end_row();
+                for pass in &timed.gpu {
+                    let times: Vec<f64> = frames
+                        .iter()
+                        .flat_map(|frame| frame.gpu.iter())
+                        .filter(|other| other.name == pass.name)
+                        .map(|other| other.duration)
+                        .collect();
+                    ui.label(pass.name.as_str());
+                    ui.label(format!(
+                        "{:.2} ms",
+                        times.iter().sum::<f64>() / times.len() as f64
+                    ));
+                    ui.label(format!(
+                        "{:.2} ms",
+                        times.iter().copied().fold(0.0, f64::max)
+                    ));
+                    ui.end_row();
+                }
+            });
+    }
+
+    ui.separator();
+    if ui.button("Save capture").clicked() {
+        if let Err(err) = profiler::save_capture() {
+            error!("Failed to save profile: {err}");
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn memory_window(ui: &mut egui::Ui, render: &rendersystem::State) {
+    let stats = render.memory_stats();
+    egui::Grid::new("memory categories")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("Category");
+            ui.strong("Size");
+            ui.strong("Count");
+            ui.end_row();
+            for category in &stats.categories {
+                ui.label(category.name);
+                ui.label(format_bytes(category.bytes));
+                ui.label(category.count.to_string());
+                ui.end_row();
+            }
+        });
+
+    ui.separator();
+    egui::Grid::new("memory totals").show(ui, |ui| {
+        ui.label("Allocated");
+        ui.label(format!(
+            "{} of {} reserved",
+            format_bytes(stats.allocated),
+            format_bytes(stats.reserved)
+        ));
+        ui.end_row();
+        ui.label("Mesh heap");
+        ui.label(format!(
+            "{} of {}",
+            format_bytes(stats.mesh_heap_used),
+            format_bytes(stats.mesh_heap_capacity)
+        ));
+        ui.end_row();
+        ui.label("Staging ring");
+        ui.label(format_bytes(stats.staging_capacity));
+        ui.end_row();
+    });
+
+    ui.separator();
+    egui::Grid::new("memory heaps")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("Heap");
+            ui.strong("Usage");
+            ui.strong("Budget");
+            ui.strong("Size");
+            ui.end_row();
+            for (index, heap) in stats.heaps.iter().enumerate() {
+                let kind = if heap.device_local { "device" } else { "host" };
+                ui.label(format!("{index} ({kind})"));
+                let usage = format_bytes(heap.usage);
+                if heap.usage > heap.budget {
+                    ui.colored_label(egui::Color32::LIGHT_RED, usage);
+                } else {
+                    ui.label(usage);
+                }
+                ui.label(format_bytes(heap.budget));
+                ui.label(format_bytes(heap.size));
+                ui.end_row();
+            }
+        });
+
+    ui.separator();
+    if ui.button("Dump JSON").clicked() {
+        if let Err(err) = render.dump_memory_stats() {
+            error!("Failed to dump memory stats: {err}");
+        }
+    }
+}
@@ -0,0 +1,153 @@
+// The boundary between the engine and a game library. Game crates are a cdylib that includes
+// this file with #[path] and exports their game with export_game!, so it only depends on std,
+// log and legion. Both sides have to be built with the same compiler and the same legion
+// version, since trait objects and the world are passed straight through without a stable
+// layout, so libraries also have a build.rs that runs toolchain::emit and the engine refuses
+// ones where BUILD doesn't match its own.
+//
+// Memory goes back and forth too (the game itself, what serialize returns and any component
+// the game puts in the world), so export_game! makes the library's global allocator the
+// engine's, through the Allocator it's given before anything else is called.
+//
+//     #[path = "../../purpl/src/engine/gamelib/api.rs"]
+//     mod api;
+//
+//     #[derive(Default)]
+//     struct MyGame { score: u32 }
+//     impl api::Game for MyGame { ... }
+//
+//     api::export_game!(api, MyGame::default());
+
+// Bumped whenever Game, Host or the exported functions change, libraries built against another
+// version aren't loaded
+pub const API_VERSION: u32 = 3;
+// The compiler and legion this was built with, from toolchain. Nul terminated so the library
+// can hand it to the engine as a C string.
+pub const BUILD: &str = concat!(
+    env!("PURPL_RUSTC_VERSION"),
+    ", legion ",
+    env!("PURPL_LEGION_VERSION"),
+    "\0"
+);
+pub const VERSION_SYMBOL: &[u8] = b"purpl_game_api_version\0";
+pub const BUILD_SYMBOL: &[u8] = b"purpl_game_build\0";
+pub const ALLOCATOR_SYMBOL: &[u8] = b"purpl_game_set_allocator\0";
+pub const CREATE_SYMBOL: &[u8] = b"purpl_game_create\0";
+
+pub type VersionFn = extern "C" fn() -> u32;
+pub type BuildFn = extern "C" fn() -> *const std::ffi::c_char;
+// Has to be called before anything in the library allocates, which it aborts on otherwise
+pub type SetAllocatorFn = extern "C" fn(&'static Allocator);
+// Returns a Box<Box<dyn Game>> as a thin pointer, which the engine takes ownership of
+pub type CreateFn = extern "C" fn() -> *mut std::ffi::c_void;
+
+// The engine's global allocator, like GlobalAlloc with the layout split up
+#[repr(C)]
+pub struct Allocator {
+    pub alloc: unsafe extern "C" fn(size: usize, align: usize) -> *mut u8,
+    pub dealloc: unsafe extern "C" fn(ptr: *mut u8, size: usize, align: usize),
+    pub realloc:
+        unsafe extern "C" fn(ptr: *mut u8, size: usize, align: usize, new_size: usize) -> *mut u8,
+}
+
+// What the engine lets the game use
+pub trait Host {
+    fn world(&mut self) -> &mut legion::World;
+    // Seconds in a step, which is always the same
+    fn delta(&self) -> f64;
+    fn action_down(&self, action: &str) -> bool;
+    fn action_pressed(&self, action: &str) -> bool;
+    fn action_released(&self, action: &str) -> bool;
+    // The library has its own copy of log without a logger, so messages go through this
+    fn log(&self, level: log::Level, message: &str);
+}
+
+// The game's side. When the library changes on disk the engine calls serialize on the old
+// game, loads the new library and calls restore on its game instead of init, then unloads
+// the old one. Anything the game leaves in the world with code from the library in it (like
+// components that implement Drop) has to be taken out in serialize and put back in restore,
+// since that code goes away with the old library.
+pub trait Game {
+    fn init(&mut self, host: &mut dyn Host);
+    // Once every fixed step
+    fn tick(&mut self, host: &mut dyn Host);
+    fn shutdown(&mut self, host: &mut dyn Host);
+    fn serialize(&mut self, host: &mut dyn Host) -> Vec<u8>;
+    fn restore(&mut self, host: &mut dyn Host, data: &[u8]);
+}
+
+// Exports the functions the engine looks for. api is the path this file was included as, and
+// game is an expression that makes the game. The engine never uses it itself.
+#[allow(unused_macros)]
+macro_rules! export_game {
+    ($api:path, $game:expr) => {
+        #[no_mangle]
+        pub extern "C" fn purpl_game_api_version() -> u32 {
+            use $api as api;
+            api::API_VERSION
+        }
+
+        #[no_mangle]
+        pub extern "C" fn purpl_game_build() -> *const std::ffi::c_char {
+            use $api as api;
+            api::BUILD.as_ptr() as *const std::ffi::c_char
+        }
+
+        use $api as purpl_api;
+
+        // The engine's Allocator, null until purpl_game_set_allocator is called
+        static PURPL_ALLOCATOR: std::sync::atomic::AtomicPtr<std::ffi::c_void> =
+            std::sync::atomic::AtomicPtr::new(std::ptr::null_mut());
+
+        struct PurplAllocator;
+
+        impl PurplAllocator {
+            fn get(&self) -> &'static purpl_api::Allocator {
+                let allocator = PURPL_ALLOCATOR.load(std::sync::atomic::Ordering::Acquire);
+                if allocator.is_null() {
+                    std::process::abort();
+                }
+                unsafe { &*(allocator as *const purpl_api::Allocator) }
+            }
+        }
+
+        unsafe impl std::alloc::GlobalAlloc for PurplAllocator {
+            unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+                (self.get().alloc)(layout.size(), layout.align())
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+                (self.get().dealloc)(ptr, layout.size(), layout.align())
+            }
+
+            unsafe fn realloc(
+                &self,
+                ptr: *mut u8,
+                layout: std::alloc::Layout,
+                new_size: usize,
+            ) -> *mut u8 {
+                (self.get().realloc)(ptr, layout.size(), layout.align(), new_size)
+            }
+        }
+
+        #[global_allocator]
+        static PURPL_GLOBAL_ALLOCATOR: PurplAllocator = PurplAllocator;
+
+        #[no_mangle]
+        pub extern "C" fn purpl_game_set_allocator(allocator: &'static purpl_api::Allocator) {
+            PURPL_ALLOCATOR.store(
+                allocator as *const purpl_api::Allocator as *mut std::ffi::c_void,
+                std::sync::atomic::Ordering::Release,
+            );
+        }
+
+        #[no_mangle]
+        pub extern "C" fn purpl_game_create() -> *mut std::ffi::c_void {
+            use $api as api;
+            let game: Box<dyn api::Game> = Box::new($game);
+            Box::into_raw(Box::new(game)) as *mut std::ffi::c_void
+        }
+    };
+}
+#[allow(unused_imports)]
+pub(crate) use export_game;
@@ -0,0 +1,271 @@
+// Game code built as a separate library, like Quake's game module, so it can be rebuilt and
+// reloaded while the engine keeps running. See api for what the library implements.
+
+pub mod api;
+
+use crate::engine::{input, DataDirs};
+use log::{error, info, warn};
+use std::{
+    alloc::{self, Layout},
+    ffi::CStr,
+    fs,
+    time::SystemTime,
+};
+
+// How often the library is checked for changes, in seconds
+const RELOAD_INTERVAL: f64 = 1.0;
+
+// Game libraries allocate through this, so anything they make can be freed by the engine
+static ALLOCATOR: api::Allocator = api::Allocator {
+    alloc: engine_alloc,
+    dealloc: engine_dealloc,
+    realloc: engine_realloc,
+};
+
+unsafe extern "C" fn engine_alloc(size: usize, align: usize) -> *mut u8 {
+    alloc::alloc(Layout::from_size_align_unchecked(size, align))
+}
+
+unsafe extern "C" fn engine_dealloc(ptr: *mut u8, size: usize, align: usize) {
+    alloc::dealloc(ptr, Layout::from_size_align_unchecked(size, align))
+}
+
+unsafe extern "C" fn engine_realloc(
+    ptr: *mut u8,
+    size: usize,
+    align: usize,
+    new_size: usize,
+) -> *mut u8 {
+    alloc::realloc(
+        ptr,
+        Layout::from_size_align_unchecked(size, align),
+        new_size,
+    )
+}
+
+// What the engine gives the game for one call
+pub struct EngineHost<'a> {
+    pub world: &'a mut legion::World,
+    pub input: &'a input::State,
+    pub delta: f64,
+}
+
+impl api::Host for EngineHost<'_> {
+    fn world(&mut self) -> &mut legion::World {
+        self.world
+    }
+
+    fn delta(&self) -> f64 {
+        self.delta
+    }
+
+    fn action_down(&self, action: &str) -> bool {
+        self.input.action_down(action)
+    }
+
+    fn action_pressed(&self, action: &str) -> bool {
+        self.input.action_pressed(action)
+    }
+
+    fn action_released(&self, action: &str) -> bool {
+        self.input.action_released(action)
+    }
+
+    fn log(&self, level: log::Level, message: &str) {
+        log::log!(target: "game", level, "{message}");
+    }
+}
+
+// A loaded copy of the library. The game has to be dropped before the library is, since its
+// code is in it.
+struct Loaded {
+    game: Box<dyn api::Game>,
+    library: libloading::Library,
+    copy: String,
+}
+
+impl Loaded {
+    // The library is copied first, because Windows won't let the original be replaced while
+    // it's loaded and other systems hand back the old one when the same path is loaded again.
+    // The copy is named after the process too, since a client and a server on the same machine
+    // share DataDirs::gamelib.
+    fn load(path: &str, generation: u32) -> Result<Self, String> {
+        let name = libloading::library_filename(format!(
+            "{}-{}-{generation}",
+            crate::GAME_EXECUTABLE_NAME,
+            std::process::id()
+        ));
+        let copy = format!("{}{}", DataDirs::gamelib(), name.to_string_lossy());
+        fs::copy(path, &copy).map_err(|err| format!("failed to copy {path} to {copy}: {err}"))?;
+
+        let loaded = unsafe { Self::open(&copy) };
+        if loaded.is_err() {
+            let _ = fs::remove_file(&copy);
+        }
+        loaded
+    }
+
+    unsafe fn open(copy: &str) -> Result<Self, String> {
+        let library = libloading::Library::new(copy).map_err(|err| err.to_string())?;
+
+        let version = library
+            .get::<api::VersionFn>(api::VERSION_SYMBOL)
+            .map_err(|err| format!("no API version: {err}"))?();
+        if version != api::API_VERSION {
+            return Err(format!(
+                "library uses API version {version}, but the engine uses {}",
+                api::API_VERSION
+            ));
+        }
+
+        // Checked before anything passes a trait object or the world across
+        let build = library
+            .get::<api::BuildFn>(api::BUILD_SYMBOL)
+            .map_err(|err| format!("no build information: {err}"))?();
+        let build = CStr::from_ptr(build).to_string_lossy();
+        let engine_build = api::BUILD.trim_end_matches('\0');
+        if build != engine_build {
+            return Err(format!(
+                "library was built with {build}, but the engine was built with {engine_build}"
+            ));
+        }
+
+        library
+            .get::<api::SetAllocatorFn>(api::ALLOCATOR_SYMBOL)
+            .map_err(|err| format!("no allocator function: {err}"))?(&ALLOCATOR);
+
+        let create = library
+            .get::<api::CreateFn>(api::CREATE_SYMBOL)
+            .map_err(|err| format!("no create function: {err}"))?;
+        let game = create();
+        if game.is_null() {
+            return Err(String::from("create function returned null"));
+        }
+        let game = *Box::from_raw(game as *mut Box<dyn api::Game>);
+
+        Ok(Self {
+            game,
+            library,
+            copy: String::from(copy),
+        })
+    }
+
+    fn unload(self) {
+        let Self {
+            game,
+            library,
+            copy,
+        } = self;
+        drop(game);
+        if let Err(err) = library.close() {
+            warn!("Failed to unload game library {copy}: {err}");
+        }
+        let _ = fs::remove_file(&copy);
+    }
+}
+
+pub struct GameLibrary {
+    path: String,
+    loaded: Option<Loaded>,
+    modified: Option<SystemTime>,
+    generation: u32,
+    until_reload: f64,
+}
+
+impl GameLibrary {
+    // Where the library is for a game directory, like purplgame.dll or libpurplgame.so
+    pub fn path(game_dir: &str) -> String {
+        format!(
+            "{game_dir}/{}",
+            libloading::library_filename(crate::GAME_EXECUTABLE_NAME).to_string_lossy()
+        )
+    }
+
+    // None if the game directory doesn't have a library, in which case all the game code is
+    // in the executable
+    pub fn load(game_dir: &str, host: &mut dyn api::Host) -> Option<Self> {
+        let path = Self::path(game_dir);
+        let modified = fs::metadata(&path).and_then(|metadata| metadata.modified());
+        let Ok(modified) = modified else {
+            info!("No game library at {path}");
+            return None;
+        };
+
+        let mut loaded = match Loaded::load(&path, 0) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                error!("Failed to load game library {path}: {err}");
+                return None;
+            }
+        };
+        info!("Loaded game library {path}");
+        loaded.game.init(host);
+
+        Some(Self {
+            path,
+            loaded: Some(loaded),
+            modified: Some(modified),
+            generation: 0,
+            until_reload: RELOAD_INTERVAL,
+        })
+    }
+
+    // Reloads the library first if it changed. delta is in seconds.
+    pub fn tick(&mut self, host: &mut dyn api::Host, delta: f64) {
+        self.until_reload -= delta;
+        if self.until_reload <= 0.0 {
+            self.until_reload = RELOAD_INTERVAL;
+            self.reload_if_changed(host);
+        }
+
+        if let Some(loaded) = &mut self.loaded {
+            loaded.game.tick(host);
+        }
+    }
+
+    fn reload_if_changed(&mut self, host: &mut dyn api::Host) {
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        self.reload(host);
+    }
+
+    // Keeps the old library if the new one can't be loaded, which usually means the build
+    // hasn't finished writing it yet and it'll be tried again when it changes
+    pub fn reload(&mut self, host: &mut dyn api::Host) {
+        self.generation += 1;
+        let mut new = match Loaded::load(&self.path, self.generation) {
+            Ok(new) => new,
+            Err(err) => {
+                error!("Failed to reload game library {}: {err}", self.path);
+                return;
+            }
+        };
+
+        let data = match &mut self.loaded {
+            Some(old) => old.game.serialize(host),
+            None => Vec::new(),
+        };
+        info!(
+            "Reloading game library {} with {} bytes of state",
+            self.path,
+            data.len()
+        );
+        new.game.restore(host, &data);
+        if let Some(old) = self.loaded.replace(new) {
+            old.unload();
+        }
+    }
+
+    pub fn shutdown(mut self, host: &mut dyn api::Host) {
+        if let Some(mut loaded) = self.loaded.take() {
+            info!("Shutting down game library {}", self.path);
+            loaded.game.shutdown(host);
+            loaded.unload();
+        }
+    }
+}
@@ -0,0 +1,49 @@
+// Finds what api::BUILD says a crate was built with. The engine's build.rs and every game
+// library's include this and call emit:
+//
+//     #[path = "../purpl/src/engine/gamelib/toolchain.rs"]
+//     mod toolchain;
+//
+//     fn main() {
+//         toolchain::emit();
+//     }
+//
+// It only uses std, since build scripts don't have the crate's dependencies.
+
+use std::{env, fs, path::Path, process::Command};
+
+// Sets PURPL_RUSTC_VERSION and PURPL_LEGION_VERSION
+pub fn emit() {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let output = Command::new(&rustc).arg("--version").output().unwrap();
+    let rustc_version = String::from_utf8(output.stdout).unwrap();
+    println!(
+        "cargo:rustc-env=PURPL_RUSTC_VERSION={}",
+        rustc_version.trim()
+    );
+
+    // The lock file is in the workspace's root, which can be above the crate
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let lock = Path::new(&manifest_dir)
+        .ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| path.exists())
+        .expect("Cargo.lock wasn't found");
+    println!("cargo:rerun-if-changed={}", lock.display());
+    let lock = fs::read_to_string(&lock).unwrap();
+    let legion_version = legion_version(&lock).expect("legion isn't in Cargo.lock");
+    println!("cargo:rustc-env=PURPL_LEGION_VERSION={legion_version}");
+}
+
+fn legion_version(lock: &str) -> Option<&str> {
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line == "name = \"legion\"" {
+            return lines
+                .next()?
+                .strip_prefix("version = \"")?
+                .strip_suffix('"');
+        }
+    }
+    None
+}
@@ -0,0 +1,199 @@
+use crate::platform::input::{Event, Key, MouseButton};
+use std::collections::{HashMap, HashSet};
+
+// Something that can trigger an action
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(Key),
+    Button(MouseButton),
+}
+
+// What the keyboard and mouse are doing this frame. Things drawn over the game (like the
+// developer UI) can capture the keyboard or mouse, which makes the game see them as idle.
+#[derive(Default)]
+pub struct State {
+    events: Vec<Event>,
+    keys_down: HashSet<Key>,
+    keys_pressed: HashSet<Key>,
+    keys_released: HashSet<Key>,
+    buttons_down: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+    mouse_position: (f32, f32),
+    mouse_delta: (f32, f32),
+    wheel: f32,
+    text: String,
+
+    keyboard_captured: bool,
+    mouse_captured: bool,
+
+    // Named actions, which are active if any of their bindings are
+    actions: HashMap<String, Vec<Binding>>,
+}
+
+impl State {
+    // Starts a new frame with the events since the last one
+    pub fn update(&mut self, events: Vec<Event>) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.mouse_delta = (0.0, 0.0);
+        self.wheel = 0.0;
+        self.text.clear();
+        self.keyboard_captured = false;
+        self.mouse_captured = false;
+
+        for event in &events {
+            match *event {
+                Event::Key { key, pressed } => {
+                    if pressed {
+                        if self.keys_down.insert(key) {
+                            self.keys_pressed.insert(key);
+                        }
+                    } else if self.keys_down.remove(&key) {
+                        self.keys_released.insert(key);
+                    }
+                }
+                Event::Text(character) => self.text.push(character),
+                Event::MouseMoved { x, y } => {
+                    self.mouse_delta.0 += x - self.mouse_position.0;
+                    self.mouse_delta.1 += y - self.mouse_position.1;
+                    self.mouse_position = (x, y);
+                }
+                Event::MouseButton { button, pressed } => {
+                    if pressed {
+                        if self.buttons_down.insert(button) {
+                            self.buttons_pressed.insert(button);
+                        }
+                    } else if self.buttons_down.remove(&button) {
+                        self.buttons_released.insert(button);
+                    }
+                }
+                Event::MouseWheel(notches) => self.wheel += notches,
+            }
+        }
+        self.events = events;
+    }
+
+    // Keys and buttons don't get release events while the window isn't focused
+    pub fn release_all(&mut self) {
+        self.keys_released.extend(self.keys_down.drain());
+        self.buttons_released.extend(self.buttons_down.drain());
+    }
+
+    // Lasts until the next update
+    pub fn capture(&mut self, keyboard: bool, mouse: bool) {
+        self.keyboard_captured |= keyboard;
+        self.mouse_captured |= mouse;
+    }
+
+    pub fn keyboard_captured(&self) -> bool {
+        self.keyboard_captured
+    }
+
+    pub fn mouse_captured(&self) -> bool {
+        self.mouse_captured
+    }
+
+    // Everything that happened this frame, even if it's captured
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn key_down(&self, key: Key) -> bool {
+        !self.keyboard_captured && self.keys_down.contains(&key)
+    }
+
+    pub fn key_pressed(&self, key: Key) -> bool {
+        !self.keyboard_captured && self.keys_pressed.contains(&key)
+    }
+
+    pub fn key_released(&self, key: Key) -> bool {
+        !self.keyboard_captured && self.keys_released.contains(&key)
+    }
+
+    pub fn text(&self) -> &str {
+        if self.keyboard_captured {
+            ""
+        } else {
+            &self.text
+        }
+    }
+
+    pub fn button_down(&self, button: MouseButton) -> bool {
+        !self.mouse_captured && self.buttons_down.contains(&button)
+    }
+
+    pub fn button_pressed(&self, button: MouseButton) -> bool {
+        !self.mouse_captured && self.buttons_pressed.contains(&button)
+    }
+
+    pub fn button_released(&self, button: MouseButton) -> bool {
+        !self.mouse_captured && self.buttons_released.contains(&button)
+    }
+
+    // The position is always available, since the game might draw a cursor
+    pub fn mouse_position(&self) -> (f32, f32) {
+        self.mouse_position
+    }
+
+    pub fn mouse_delta(&self) -> (f32, f32) {
+        if self.mouse_captured {
+            (0.0, 0.0)
+        } else {
+            self.mouse_delta
+        }
+    }
+
+    pub fn wheel(&self) -> f32 {
+        if self.mouse_captured {
+            0.0
+        } else {
+            self.wheel
+        }
+    }
+
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.actions.entry(String::from(action)).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: &str) {
+        self.actions.remove(action);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map_or(&[], |bindings| bindings)
+    }
+
+    pub fn action_down(&self, action: &str) -> bool {
+        self.any_binding(action, |binding| match binding {
+            Binding::Key(key) => self.key_down(key),
+            Binding::Button(button) => self.button_down(button),
+        })
+    }
+
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.any_binding(action, |binding| match binding {
+            Binding::Key(key) => self.key_pressed(key),
+            Binding::Button(button) => self.button_pressed(button),
+        })
+    }
+
+    pub fn action_released(&self, action: &str) -> bool {
+        self.any_binding(action, |binding| match binding {
+            Binding::Key(key) => self.key_released(key),
+            Binding::Button(button) => self.button_released(button),
+        })
+    }
+
+    fn any_binding<F>(&self, action: &str, active: F) -> bool
+    where
+        F: Fn(Binding) -> bool,
+    {
+        self.bindings(action).iter().any(|binding| active(*binding))
+    }
+}
@@ -1,24 +1,71 @@
+pub mod animation;
+pub mod bvh;
+pub mod components;
+pub mod crash;
+pub mod cvar;
+pub mod dedicated;
+pub mod devui;
+pub mod gamelib;
+pub mod input;
+pub mod mods;
+pub mod net;
+pub mod profiler;
 pub mod rendersystem;
+pub mod replay;
+pub mod scene;
+pub mod script;
+pub mod ui;
+pub mod vfs;
 
 use crate::platform;
 use crate::platform::video::VideoBackend;
 use chrono::Local;
 use fern::colors::{Color, ColoredLevelConfig};
-use log::{debug, info};
-use std::{fs, io};
+use legion::IntoQuery;
+use log::{debug, error, info};
+use std::collections::{HashMap, HashSet};
+use std::{fs, io, mem};
 
 const FRAME_SMOOTHING: f64 = 0.9;
+// Seconds of game time in a step. Scripts, the game library and the network client run in
+// fixed steps, so the game and recordings of it don't depend on the frame rate.
+pub const STEP: f64 = 1.0 / 60.0;
+// After a long frame the game slows down rather than running more than this to catch up
+const MAX_STEPS: u32 = 5;
 
 pub struct State {
     game_dir: String,
+    mods: Vec<mods::Mod>,
     start_time: i64,
     last_time: i64,
     runtime: i64,
     fps: f64,
     delta: i64,
+    // Seconds of frame time that haven't been stepped yet
+    accumulator: f64,
 
     video: Box<dyn platform::video::VideoBackend>,
     render: rendersystem::State,
+    input: input::State,
+    cvars: cvar::Cvars,
+    world: legion::World,
+    scripts: script::Scripts,
+    game: Option<gamelib::GameLibrary>,
+    // Entities with a Transform and Bounds, in world space
+    spatial: bvh::Bvh<legion::Entity>,
+    spatial_proxies: HashMap<legion::Entity, bvh::ProxyId>,
+    devui: devui::DevUi,
+    // With --connect, the server the world comes from
+    net: Option<net::Client>,
+
+    // --record waits for the first frame to start, so it gets the world main set up
+    record_input: bool,
+    recorder: Option<replay::Recorder>,
+    replay: Option<replay::Player>,
+    // What the window sent since the last step, and whether it lost focus
+    pending_events: Vec<platform::input::Event>,
+    input_released: bool,
+    running: bool,
 }
 
 impl State {
@@ -32,7 +79,10 @@ impl State {
             .debug(Color::BrightCyan)
             .trace(Color::Cyan);
 
-        let dispatch = fern::Dispatch::new()
+        let log_path = DataDirs::logs() + crate::GAME_EXECUTABLE_NAME + "-" + &dt + ".log";
+        crash::set_section("Log file", log_path.clone());
+
+        let output = fern::Dispatch::new()
             .format(move |out, message, record| {
                 let dt = Local::now();
                 out.finish(format_args!(
@@ -44,25 +94,33 @@ impl State {
                     message
                 ))
             })
-            .chain(fern::log_file(
-                DataDirs::logs() + crate::GAME_EXECUTABLE_NAME + "-" + &dt + ".log",
-            )?);
+            .chain(fern::log_file(log_path)?);
+
+        #[cfg(any(build = "debug", all(not(build = "debug"), feature = "release_log")))]
+        let output = output.chain(io::stdout());
 
+        // The developer UI gets messages without the colors and time
+        let dispatch = fern::Dispatch::new()
+            .chain(output)
+            .chain(fern::Output::call(devui::record_log));
         #[cfg(build = "debug")]
         let dispatch = dispatch.level(log::LevelFilter::Debug);
         #[cfg(all(not(build = "debug"), feature = "release_log"))]
         let dispatch = dispatch.level(log::LevelFilter::Info);
         #[cfg(feature = "verbose_log")]
         let dispatch = dispatch.level(log::LevelFilter::Trace);
-        #[cfg(any(build = "debug", all(not(build = "debug"), feature = "release_log")))]
-        let dispatch = dispatch.chain(io::stdout());
 
         dispatch.apply()?;
 
         Ok(())
     }
 
-    pub fn init(args: crate::Args) -> Self {
+    // What the game and dedicated server both start with: the data directories, the logger,
+    // and the game directory and its mods in the VFS. Returns the game directory and mods.
+    fn init_common(args: &crate::Args) -> (String, Vec<mods::Mod>) {
+        crash::install(!args.dedicated && !args.headless);
+        crash::set_section("Arguments", format!("{args:#?}"));
+
         if args.wait_for_debugger {
             while !platform::have_debugger() {}
         }
@@ -81,7 +139,7 @@ impl State {
 
         info!("Engine initialization started");
 
-        let game_dir = fs::canonicalize(args.game)
+        let game_dir = fs::canonicalize(&args.game)
             .unwrap()
             .as_path()
             .to_str()
@@ -90,32 +148,198 @@ impl State {
             .replace("\\", "/");
         info!("Game directory is {}", game_dir);
         info!("Data directory is {}", DataDirs::base());
+        if let Err(err) = vfs::mount_root(&game_dir) {
+            panic!("Failed to mount game directory: {err}");
+        }
+        let mods = mods::load(&game_dir, &args.mods);
 
-        let video = platform::video::State::init();
-        let render = rendersystem::State::init(&video, args.render_api);
+        (game_dir, mods)
+    }
 
-        Self {
+    pub fn init(args: crate::Args) -> Self {
+        let (game_dir, mods) = Self::init_common(&args);
+
+        let video = platform::video::State::init();
+        let mut render = rendersystem::State::init(&video, args.render_api);
+        render.set_msaa_samples(args.msaa);
+
+        let mut cvars = cvar::Cvars::default();
+        Self::register_cvars(&mut cvars, &mut render);
+        Self::record_cvars(&mut cvars);
+        let mut input = input::State::default();
+        Self::bind_ui_actions(&mut input);
+        crate::player::bind_actions(&mut input);
+
+        let replay = args.replay.as_ref().and_then(|name| {
+            replay::Player::open(name, args.max_speed)
+                .map_err(|err| error!("Failed to open recording: {err}"))
+                .ok()
+        });
+        // Replays have their own world, so they don't connect
+        let net = args
+            .connect
+            .as_ref()
+            .filter(|_| replay.is_none())
+            .and_then(|address| {
+                net::Client::connect(address, &args.name)
+                    .map_err(|err| error!("Failed to connect to {address}: {err}"))
+                    .ok()
+            });
+
+        let mut self_ = Self {
             game_dir,
+            mods,
             start_time: 0,
             last_time: 0,
             runtime: 0,
             fps: 0.0,
             delta: 0,
+            accumulator: 0.0,
             video,
             render,
+            input,
+            cvars,
+            world: legion::World::default(),
+            scripts: script::Scripts::new().hot_reload(replay.is_none() || args.reload_scripts),
+            game: None,
+            spatial: bvh::Bvh::new(),
+            spatial_proxies: HashMap::new(),
+            devui: devui::DevUi::new(),
+            net,
+            record_input: args.record && replay.is_none(),
+            recorder: None,
+            replay,
+            pending_events: Vec::new(),
+            input_released: false,
+            running: true,
+        };
+        self_.render.load_builtin_resources(&GameDirs::shaders());
+
+        let mut host = gamelib::EngineHost {
+            world: &mut self_.world,
+            input: &self_.input,
+            delta: 0.0,
+        };
+        self_.game = gamelib::GameLibrary::load(&self_.game_dir, &mut host);
+
+        self_
+    }
+
+    // What ui::Ui uses to get around menus, which the game can rebind
+    fn bind_ui_actions(input: &mut input::State) {
+        use input::Binding;
+        use platform::input::Key;
+
+        input.bind("ui_up", Binding::Key(Key::Up));
+        input.bind("ui_down", Binding::Key(Key::Down));
+        input.bind("ui_left", Binding::Key(Key::Left));
+        input.bind("ui_right", Binding::Key(Key::Right));
+        input.bind("ui_accept", Binding::Key(Key::Enter));
+        input.bind("ui_accept", Binding::Key(Key::Space));
+        input.bind("ui_back", Binding::Key(Key::Escape));
+    }
+
+    // Render settings that can be changed at runtime start out as whatever the renderer uses
+    fn register_cvars(cvars: &mut cvar::Cvars, render: &mut rendersystem::State) {
+        let post = *render.post_settings();
+        cvars.register(
+            "r_bloom",
+            cvar::CvarValue::Bool(post.bloom),
+            "Whether bright parts of the scene glow",
+        );
+        cvars.register(
+            "r_bloom_intensity",
+            cvar::CvarValue::Float(post.bloom_intensity),
+            "How much bloom gets added to the scene",
+        );
+        cvars.register(
+            "r_auto_exposure",
+            cvar::CvarValue::Bool(post.auto_exposure),
+            "Whether exposure adapts to the brightness of the scene",
+        );
+        cvars.register(
+            "r_exposure",
+            cvar::CvarValue::Float(post.exposure),
+            "Exposure compensation in stops",
+        );
+        cvars.register(
+            "r_tone_mapper",
+            cvar::CvarValue::Int(post.tone_mapper as i64),
+            "0 is none, 1 is Reinhard, 2 is ACES and 3 is Uncharted 2",
+        );
+        cvars.register(
+            "r_fxaa",
+            cvar::CvarValue::Bool(post.fxaa),
+            "Whether FXAA is on",
+        );
+        cvars.register(
+            "r_cull",
+            cvar::CvarValue::Bool(true),
+            "Whether draws outside the camera and shadow maps are skipped",
+        );
+        cvars.register(
+            "r_lod_bias",
+            cvar::CvarValue::Float(0.0),
+            "Positive switches models to lower detail sooner, negative later",
+        );
+    }
+
+    // Crash reports get whatever the cvars were last set to. They're only formatted again
+    // when something changed.
+    fn record_cvars(cvars: &mut cvar::Cvars) {
+        if !cvars.take_changed() {
+            return;
         }
+        let cvars: Vec<String> = cvars
+            .iter()
+            .map(|(name, cvar)| format!("{name} = {}", cvar.value))
+            .collect();
+        crash::set_section("Cvars", cvars.join("\n"));
+    }
+
+    fn apply_cvars(&mut self) {
+        let post = self.render.post_settings();
+        post.bloom = self.cvars.get_bool("r_bloom");
+        post.bloom_intensity = self.cvars.get_float("r_bloom_intensity");
+        post.auto_exposure = self.cvars.get_bool("r_auto_exposure");
+        post.exposure = self.cvars.get_float("r_exposure");
+        post.tone_mapper = match self.cvars.get_int("r_tone_mapper") {
+            0 => rendersystem::post::ToneMapper::None,
+            1 => rendersystem::post::ToneMapper::Reinhard,
+            3 => rendersystem::post::ToneMapper::Uncharted2,
+            _ => rendersystem::post::ToneMapper::Aces,
+        };
+        post.fxaa = self.cvars.get_bool("r_fxaa");
+        self.render.set_culling(self.cvars.get_bool("r_cull"));
+        self.render.set_lod_bias(self.cvars.get_float("r_lod_bias"));
+        Self::record_cvars(&mut self.cvars);
     }
 
     pub fn update<F>(&mut self, in_render: Option<F>)
     where
         F: FnOnce(&mut Self),
     {
-        if !self.video.focused() || self.video.resized() {
+        let events = self.video.take_events();
+        // Replays keep going in the background
+        if self.video.resized() || (self.replay.is_none() && !self.video.focused()) {
+            self.input.release_all();
+            self.input_released = true;
             return;
         }
 
-        if self.last_time == 0 {
-            let now = chrono::Local::now().timestamp_millis();
+        if self.record_input {
+            self.record_input = false;
+            self.recorder = replay::Recorder::create(&self.world)
+                .map_err(|err| error!("Failed to start recording: {err}"))
+                .ok();
+        }
+        // Replays use what was recorded instead
+        if self.replay.is_none() {
+            self.pending_events.extend(events);
+        }
+
+        let now = chrono::Local::now().timestamp_millis();
+        if self.last_time != 0 {
             self.delta = now - self.last_time;
             self.runtime += self.delta;
             self.fps = if self.delta > 0 {
@@ -124,30 +348,243 @@ impl State {
             } else {
                 f64::INFINITY
             };
-
-            self.last_time = now;
         } else {
             self.start_time = chrono::Local::now().timestamp();
         }
+        self.last_time = now;
+        profiler::frame();
+
+        self.apply_cvars();
 
-        self.render.begin_commands(&self.video);
+        {
+            let _scope = profiler::scope("wait for GPU");
+            self.render.begin_commands(&self.video);
+        }
+
+        // The developer UI goes right before the last step, so the game doesn't see input it
+        // takes and the game's UI sees the same input the game did
+        let steps = self.take_steps();
+        let mut updated_devui = false;
+        for step in 0..steps {
+            let last = step + 1 == steps;
+            let Some(tick) = self.next_tick(last) else {
+                break;
+            };
+            tick.apply(&mut self.input);
+            if last {
+                self.update_devui();
+                updated_devui = true;
+            }
+            self.step(tick);
+        }
+        // Without a step the input waits for the next one
+        if !updated_devui {
+            self.input.update(Vec::new());
+            self.update_devui();
+        }
+
+        let scope = profiler::scope("spatial");
+        self.update_spatial();
+        drop(scope);
 
         if let Some(in_render) = in_render {
+            let _scope = profiler::scope("render");
             in_render(self);
         }
 
         self.render.present();
     }
 
+    // How many steps fit in the time since the last one
+    fn take_steps(&mut self) -> u32 {
+        if self
+            .replay
+            .as_ref()
+            .is_some_and(|replay| replay.max_speed())
+        {
+            return MAX_STEPS;
+        }
+
+        self.accumulator += self.delta as f64 / 1000.0;
+        let steps = ((self.accumulator / STEP) as u32).min(MAX_STEPS);
+        self.accumulator = (self.accumulator - steps as f64 * STEP).min(STEP);
+        steps
+    }
+
+    // The input for the next step, from the replay or from what the window sent. Losing focus
+    // goes to the first step after it, since input was already released then, and events go
+    // to the last one in a frame. The others only keep what was captured. None once the
+    // replay runs out.
+    fn next_tick(&mut self, last: bool) -> Option<replay::Tick> {
+        if let Some(replay) = &mut self.replay {
+            let tick = replay.next_tick(&mut self.world);
+            if tick.is_none() {
+                replay.report();
+                self.running = false;
+            }
+            return tick;
+        }
+
+        Some(replay::Tick {
+            released: mem::take(&mut self.input_released),
+            keyboard_captured: !last && self.input.keyboard_captured(),
+            mouse_captured: !last && self.input.mouse_captured(),
+            events: if last {
+                mem::take(&mut self.pending_events)
+            } else {
+                Vec::new()
+            },
+            checksum: 0,
+        })
+    }
+
+    fn update_devui(&mut self) {
+        let stats = devui::FrameStats {
+            fps: self.fps,
+            frame_time: self.delta,
+            draws: self.render.draw_stats(),
+        };
+        let _scope = profiler::scope("developer UI");
+        self.devui.update(
+            &mut self.input,
+            &mut self.cvars,
+            &mut self.world,
+            &mut self.render,
+            &self.spatial,
+            stats,
+        );
+    }
+
+    // Runs the scripts, game library and network client for one STEP with input already
+    // started for it, then checks or records the world
+    fn step(&mut self, tick: replay::Tick) {
+        let scope = profiler::scope("scripts");
+        self.scripts.update(&mut self.world, &mut self.input, STEP);
+        drop(scope);
+        if let Some(game) = &mut self.game {
+            let _scope = profiler::scope("game");
+            let mut host = gamelib::EngineHost {
+                world: &mut self.world,
+                input: &self.input,
+                delta: STEP,
+            };
+            game.tick(&mut host, STEP);
+        }
+        if let Some(client) = &mut self.net {
+            let _scope = profiler::scope("network");
+            let command =
+                net::Command::from_input(&self.input, crate::player::NET_ACTIONS, STEP as f32);
+            for event in client.update(
+                &mut self.world,
+                Some(command),
+                STEP,
+                crate::player::simulate,
+            ) {
+                if let net::ClientEvent::Message(data) = event {
+                    debug!("Server sent a {} byte message", data.len());
+                }
+            }
+            if client.disconnected() {
+                if let Some(client) = self.net.take() {
+                    client.disconnect(&mut self.world);
+                }
+            }
+        }
+
+        if let Some(replay) = &mut self.replay {
+            replay.check(&tick, &self.world);
+        }
+        if let Some(recorder) = &mut self.recorder {
+            // The developer UI might have captured input after the tick was started
+            let tick = replay::Tick {
+                keyboard_captured: self.input.keyboard_captured(),
+                mouse_captured: self.input.mouse_captured(),
+                checksum: replay::checksum(&self.world),
+                ..tick
+            };
+            if let Err(err) = recorder.record(&tick) {
+                error!("Stopping recording: {err}");
+                self.recorder = None;
+            }
+        }
+    }
+
+    // Changes the game makes show up in the BVH next frame
+    fn update_spatial(&mut self) {
+        let mut seen = HashSet::new();
+        let mut query = <(legion::Entity, &components::Transform, &components::Bounds)>::query();
+        for (entity, transform, bounds) in query.iter(&self.world) {
+            let aabb = bounds.0.transform(&transform.matrix());
+            match self.spatial_proxies.get(entity) {
+                Some(proxy) => {
+                    self.spatial.update(*proxy, aabb);
+                }
+                None => {
+                    let proxy = self.spatial.insert(aabb, *entity);
+                    self.spatial_proxies.insert(*entity, proxy);
+                }
+            }
+            seen.insert(*entity);
+        }
+
+        let spatial = &mut self.spatial;
+        self.spatial_proxies.retain(|entity, proxy| {
+            let alive = seen.contains(entity);
+            if !alive {
+                spatial.remove(*proxy);
+            }
+            alive
+        });
+    }
+
     pub fn shutdown(mut self) {
         info!("Engine shutdown started");
 
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish();
+        }
+        if let Some(client) = self.net.take() {
+            client.disconnect(&mut self.world);
+        }
+
+        if let Some(game) = self.game.take() {
+            let mut host = gamelib::EngineHost {
+                world: &mut self.world,
+                input: &self.input,
+                delta: 0.0,
+            };
+            game.shutdown(&mut host);
+        }
+
+        self.devui.shutdown(&mut self.render);
         self.render.shutdown();
         self.video.shutdown();
 
         info!("Engine shutdown succeeded");
     }
 
+    // False once a replay runs out
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    // Whether a replay didn't match what was recorded
+    pub fn replay_diverged(&self) -> bool {
+        self.replay
+            .as_ref()
+            .is_some_and(|replay| replay.diverged().is_some())
+    }
+
+    // The game directory on disk, everything in it should be read through vfs
+    pub fn game_dir(&self) -> &str {
+        &self.game_dir
+    }
+
+    // In load order, so later ones override earlier ones
+    pub fn mods(&self) -> &[mods::Mod] {
+        &self.mods
+    }
+
     pub fn video_state(&mut self) -> &mut Box<dyn platform::video::VideoBackend> {
         &mut self.video
     }
@@ -155,13 +592,58 @@ impl State {
     pub fn render_state(&mut self) -> &mut rendersystem::State {
         &mut self.render
     }
+
+    pub fn input_state(&self) -> &input::State {
+        &self.input
+    }
+
+    // For binding actions and letting the game's UI capture input
+    pub fn input_state_mut(&mut self) -> &mut input::State {
+        &mut self.input
+    }
+
+    pub fn cvars(&mut self) -> &mut cvar::Cvars {
+        &mut self.cvars
+    }
+
+    // Milliseconds the last frame took
+    pub fn delta(&self) -> i64 {
+        self.delta
+    }
+
+    pub fn scripts(&mut self) -> &mut script::Scripts {
+        &mut self.scripts
+    }
+
+    pub fn world(&mut self) -> &mut legion::World {
+        &mut self.world
+    }
+
+    // The connection to the server given to --connect, until it's lost
+    pub fn net_client(&self) -> Option<&net::Client> {
+        self.net.as_ref()
+    }
+
+    // For picking and anything else that needs to find entities by where they are. It's
+    // updated each frame after the game and scripts run.
+    pub fn spatial(&self) -> &bvh::Bvh<legion::Entity> {
+        &self.spatial
+    }
 }
 
 use crate::GAME_NAME;
 pub struct DataDirs;
 impl DataDirs {
     pub fn all() -> Vec<String> {
-        vec![Self::base(), Self::logs(), Self::saves()]
+        vec![
+            Self::base(),
+            Self::logs(),
+            Self::saves(),
+            Self::gamelib(),
+            Self::replays(),
+            Self::profiles(),
+            Self::crashes(),
+        ]
     }
 
     pub fn base() -> String {
@@ -177,23 +659,64 @@ impl DataDirs {
     pub fn saves() -> String {
         Self::base() + "saves/"
     }
+
+    // Copies of the game library, see gamelib
+    pub fn gamelib() -> String {
+        Self::base() + "gamelib/"
+    }
+
+    // Input recordings, see replay
+    pub fn replays() -> String {
+        Self::base() + "replays/"
+    }
+
+    // Captures saved by the profiler
+    pub fn profiles() -> String {
+        Self::base() + "profiles/"
+    }
+
+    // Reports written by crash
+    pub fn crashes() -> String {
+        Self::base() + "crashes/"
+    }
 }
 
+// Where things are in the virtual filesystem, see vfs
 pub struct GameDirs;
 impl GameDirs {
-    pub fn all(state: &State) -> Vec<String> {
-        vec![Self::base(state), Self::models(state), Self::shaders(state)]
+    pub fn models() -> String {
+        String::from("models/")
+    }
+
+    pub fn materials() -> String {
+        String::from("materials/")
+    }
+
+    pub fn textures() -> String {
+        String::from("textures/")
+    }
+
+    pub fn shaders() -> String {
+        String::from("shaders/")
+    }
+
+    pub fn fonts() -> String {
+        String::from("fonts/")
+    }
+
+    pub fn strings() -> String {
+        String::from("strings/")
     }
 
-    pub fn base(state: &State) -> String {
-        format!("{}/", state.game_dir)
+    pub fn ui() -> String {
+        String::from("ui/")
     }
 
-    pub fn models(state: &State) -> String {
-        Self::base(state) + "models/"
+    pub fn levels() -> String {
+        String::from("levels/")
     }
 
-    pub fn shaders(state: &State) -> String {
-        Self::base(state) + "shaders/"
+    pub fn scripts() -> String {
+        String::from("scripts/")
     }
 }
@@ -0,0 +1,394 @@
+use super::{vfs, DataDirs};
+use log::{error, info, warn};
+use std::{cmp::Ordering, fmt, fs, path::Path};
+
+// Mods go in the game directory's mods directory, as a directory or a pak archive named after
+// the mod. The user's mods.cfg and --mod flags list which ones to load, one name or path per
+// line. Each mod is mounted over the game and the mods before it, so the last one wins when
+// two of them have the same file.
+pub const MOD_DIR: &str = "mods";
+pub const CONFIG_NAME: &str = "mods.cfg";
+pub const MANIFEST_NAME: &str = "mod.manifest";
+
+// Dotted numbers like 1.2.0, compared a number at a time with missing ones as 0
+#[derive(Clone, Debug, Default, Eq)]
+pub struct Version(Vec<u32>);
+
+impl Version {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        text.split('.')
+            .map(|part| part.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map(Self)
+            .map_err(|err| format!("invalid version {text}: {err}"))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let length = self.0.len().max(other.0.len());
+        (0..length)
+            .map(|i| {
+                let a = self.0.get(i).copied().unwrap_or(0);
+                let b = other.0.get(i).copied().unwrap_or(0);
+                a.cmp(&b)
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(|part| part.to_string()).collect();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dependency {
+    pub name: String,
+    // At least this version, if there is one
+    pub version: Option<Version>,
+}
+
+// Read from mod.manifest at the top of a mod:
+//
+//     name = better_crates
+//     version = 1.2.0
+//     # One line per dependency, with an optional minimum version
+//     depends = hd_textures 1.0
+//     depends = crate_physics
+//
+// Mods without one are named after their directory or archive and have version 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    pub name: String,
+    pub version: Version,
+    pub depends: Vec<Dependency>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut name = None;
+        let mut version = Version::default();
+        let mut depends = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {message}", number + 1);
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected key = value"));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "name" => name = Some(String::from(value)),
+                "version" => version = Version::parse(value).map_err(|err| error(&err))?,
+                "depends" => {
+                    let mut parts = value.split_whitespace();
+                    let Some(name) = parts.next() else {
+                        return Err(error("depends needs a mod name"));
+                    };
+                    let version = parts
+                        .next()
+                        .map(Version::parse)
+                        .transpose()
+                        .map_err(|err| error(&err))?;
+                    depends.push(Dependency {
+                        name: String::from(name),
+                        version,
+                    });
+                }
+                _ => return Err(error(&format!("unknown key {key}"))),
+            }
+        }
+
+        let Some(name) = name else {
+            return Err(String::from("no name"));
+        };
+        Ok(Self {
+            name,
+            version,
+            depends,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Directory(String),
+    Pak(String),
+}
+
+impl Source {
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Directory(path) | Self::Pak(path) => path,
+        }
+    }
+
+    fn read_manifest(&self) -> Option<Result<String, String>> {
+        match self {
+            Self::Directory(path) => {
+                let path = format!("{path}/{MANIFEST_NAME}");
+                Path::new(&path)
+                    .is_file()
+                    .then(|| fs::read_to_string(&path).map_err(|err| err.to_string()))
+            }
+            Self::Pak(path) => match vfs::pak::Pak::open(path) {
+                Ok(pak) => pak.read(MANIFEST_NAME).map(|data| {
+                    data.and_then(|data| String::from_utf8(data).map_err(|err| err.to_string()))
+                }),
+                Err(err) => Some(Err(err)),
+            },
+        }
+    }
+
+    fn mount(&self) -> Result<(), String> {
+        match self {
+            Self::Directory(path) => vfs::mount_root(path),
+            Self::Pak(path) => vfs::mount_pak(path),
+        }
+    }
+
+    // Whether a VFS mount came from this mod
+    fn owns(&self, mount: &str) -> bool {
+        match self {
+            Self::Directory(path) => mount.starts_with(&format!("{path}/")),
+            Self::Pak(path) => mount == path,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Mod {
+    pub manifest: Manifest,
+    pub source: Source,
+}
+
+// A name in the game's mod directory, or a path to a directory or archive anywhere
+fn find(game_dir: &str, entry: &str) -> Result<Source, String> {
+    let candidates = [
+        format!("{game_dir}/{MOD_DIR}/{entry}"),
+        format!("{game_dir}/{MOD_DIR}/{entry}.{}", vfs::pak::PAK_EXTENSION),
+        String::from(entry),
+    ];
+    for candidate in candidates {
+        let path = Path::new(&candidate);
+        let Ok(path) = fs::canonicalize(path) else {
+            continue;
+        };
+        let Some(path) = path.to_str() else {
+            continue;
+        };
+        let path = path
+            .replace("\\\\?\\", "")
+            .replace('\\', "/")
+            .trim_end_matches('/')
+            .to_string();
+        if Path::new(&path).is_dir() {
+            return Ok(Source::Directory(path));
+        } else if Path::new(&path).is_file() {
+            return Ok(Source::Pak(path));
+        }
+    }
+    Err(format!("couldn't find mod {entry}"))
+}
+
+fn open(game_dir: &str, entry: &str) -> Result<Mod, String> {
+    let source = find(game_dir, entry)?;
+    let manifest = match source.read_manifest() {
+        Some(text) => text
+            .and_then(|text| Manifest::parse(&text))
+            .map_err(|err| format!("failed to read manifest for {entry}: {err}"))?,
+        None => Manifest {
+            name: Path::new(source.path())
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map_or_else(|| String::from(entry), String::from),
+            version: Version::default(),
+            depends: Vec::new(),
+        },
+    };
+    Ok(Mod { manifest, source })
+}
+
+// What mods.cfg in the data directory lists, without comments and blank lines
+fn read_config() -> Vec<String> {
+    let path = DataDirs::base() + CONFIG_NAME;
+    match fs::read_to_string(&path) {
+        Ok(text) => text
+            .lines()
+            .map(|line| line.split('#').next().unwrap().trim())
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Mods whose dependencies are all present are kept, and moved after their dependencies if
+// they were listed before them. Anything left is reported and left out.
+fn resolve(mut pending: Vec<Mod>) -> Vec<Mod> {
+    // Taking out a mod can break the ones that depend on it, so this goes until nothing changes
+    loop {
+        let count = pending.len();
+        let available: Vec<(String, Version)> = pending
+            .iter()
+            .map(|loaded| {
+                (
+                    loaded.manifest.name.clone(),
+                    loaded.manifest.version.clone(),
+                )
+            })
+            .collect();
+        pending.retain(|loaded| {
+            for dependency in &loaded.manifest.depends {
+                let found = available.iter().find(|(name, _)| *name == dependency.name);
+                let problem = match (found, &dependency.version) {
+                    (None, _) => Some(String::from("which isn't loaded")),
+                    (Some((_, version)), Some(minimum)) if version < minimum => {
+                        Some(format!("{minimum} or newer, but {version} is loaded"))
+                    }
+                    _ => None,
+                };
+                if let Some(problem) = problem {
+                    error!(
+                        "Not loading mod {} because it needs {} {problem}",
+                        loaded.manifest.name, dependency.name
+                    );
+                    return false;
+                }
+            }
+            true
+        });
+        if pending.len() == count {
+            break;
+        }
+    }
+
+    let mut ordered: Vec<Mod> = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|loaded| {
+            loaded.manifest.depends.iter().all(|dependency| {
+                ordered
+                    .iter()
+                    .any(|other| other.manifest.name == dependency.name)
+            })
+        });
+        let Some(ready) = ready else {
+            for loaded in &pending {
+                error!(
+                    "Not loading mod {} because its dependencies depend on it",
+                    loaded.manifest.name
+                );
+            }
+            break;
+        };
+        if ready != 0 {
+            info!(
+                "Loading mod {} after its dependencies instead of where it was listed",
+                pending[ready].manifest.name
+            );
+        }
+        ordered.push(pending.remove(ready));
+    }
+    ordered
+}
+
+// Mounts the mods mods.cfg lists and then the ones passed with --mod over the game, and
+// logs which file won for every path that's in more than one place
+pub fn load(game_dir: &str, flags: &[String]) -> Vec<Mod> {
+    let mut entries = read_config();
+    entries.extend(flags.iter().cloned());
+
+    let mut mods: Vec<Mod> = Vec::new();
+    for entry in entries {
+        match open(game_dir, &entry) {
+            Ok(loaded) => {
+                if let Some(other) = mods
+                    .iter()
+                    .find(|other| other.manifest.name == loaded.manifest.name)
+                {
+                    warn!(
+                        "Skipping {} because mod {} was already loaded from {}",
+                        loaded.source.path(),
+                        loaded.manifest.name,
+                        other.source.path()
+                    );
+                    continue;
+                }
+                mods.push(loaded);
+            }
+            Err(err) => error!("Not loading mod: {err}"),
+        }
+    }
+
+    let mut mods = resolve(mods);
+    mods.retain(|loaded| match loaded.source.mount() {
+        Ok(()) => {
+            info!(
+                "Loaded mod {} {} from {}",
+                loaded.manifest.name,
+                loaded.manifest.version,
+                loaded.source.path()
+            );
+            true
+        }
+        Err(err) => {
+            error!("Failed to mount mod {}: {err}", loaded.manifest.name);
+            false
+        }
+    });
+
+    if !mods.is_empty() {
+        report_overrides(&mods);
+    }
+    mods
+}
+
+fn report_overrides(mods: &[Mod]) {
+    let owner = |mount: &str| {
+        mods.iter()
+            .find(|loaded| loaded.source.owns(mount))
+            .map(|loaded| loaded.manifest.name.as_str())
+    };
+
+    // Every mod has its own manifest
+    let overrides = vfs::overrides()
+        .into_iter()
+        .filter(|(path, _)| path != MANIFEST_NAME);
+    for (path, mounts) in overrides {
+        // Each mod's own copy of the file, newest first
+        let mut owners: Vec<&str> = mounts.iter().filter_map(|mount| owner(mount)).collect();
+        owners.dedup();
+        if owners.len() > 1 {
+            warn!(
+                "Mods {} all have {path}, using the one from {}",
+                owners.join(", "),
+                owners[0]
+            );
+        }
+        info!(
+            "{path} comes from {}, over {}",
+            mounts[0],
+            mounts[1..].join(", ")
+        );
+    }
+}
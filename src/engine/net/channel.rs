@@ -0,0 +1,437 @@
+// Reliable and unreliable messages over UDP for one connection. Every packet has a sequence
+// number and acks the newest packet received from the other side along with a bitfield of
+// the 32 before it. Reliable messages are sent again until a packet they were in is acked,
+// and come out in the order they were sent. Unreliable ones are sent once and come out
+// whenever they arrive, which is what snapshots and commands want since a newer one is always
+// on its way.
+//
+//     sequence u16, ack u16, ack bits u32
+//     reliable count u8, then an id u16 and data (u16 length) for each
+//     unreliable count u8, then data (u16 length) for each
+//
+// Packets are kept under PACKET_BUDGET. Reliable messages get RELIABLE_BUDGET of it and
+// unreliable ones get the rest, past which they're dropped. Messages up to MAX_MESSAGE_SIZE
+// can be sent, and ones past the budget go in a packet of their own that relies on IP
+// fragmentation.
+
+use super::{
+    protocol::{DATA_HEADER_SIZE, MAX_PACKET_SIZE, PACKET_BUDGET},
+    wire::{Reader, Writer},
+};
+use std::collections::{HashMap, VecDeque};
+
+// Seconds without a packet before the other side is given up on
+pub const TIMEOUT: f64 = 10.0;
+// Reliable messages that haven't been acked yet, past which the connection is hopeless
+const MAX_RELIABLE_PENDING: usize = 1024;
+// Sequence, ack, ack bits and the two counts
+const HEADER_SIZE: usize = 10;
+// How many bytes of reliable messages, with their ids and lengths, go in one packet. At least
+// one message always goes.
+const RELIABLE_BUDGET: usize = 256;
+// The biggest unreliable message that fits in a packet along with the reliable ones
+pub const MAX_UNRELIABLE_SIZE: usize =
+    PACKET_BUDGET - DATA_HEADER_SIZE - HEADER_SIZE - RELIABLE_BUDGET - 2;
+// The biggest message that fits in a packet on its own, with its id and length
+pub const MAX_MESSAGE_SIZE: usize = MAX_PACKET_SIZE - DATA_HEADER_SIZE - HEADER_SIZE - 4;
+// Waits at least this long, in seconds, before sending a reliable message again
+const MIN_RESEND: f64 = 0.1;
+const ACK_BITS: u16 = 32;
+
+fn check_size(data: &[u8]) -> Result<(), String> {
+    if data.len() > MAX_MESSAGE_SIZE {
+        return Err(format!(
+            "{} byte message is too big to send, the most is {MAX_MESSAGE_SIZE}",
+            data.len()
+        ));
+    }
+    Ok(())
+}
+
+// Sequence numbers wrap, so a is newer than b if it's less than half the range ahead of it
+pub fn sequence_newer(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+struct Reliable {
+    id: u16,
+    data: Vec<u8>,
+    last_sent: Option<f64>,
+}
+
+// A packet that hasn't been acked yet
+struct Sent {
+    sequence: u16,
+    time: f64,
+    reliable: Vec<u16>,
+}
+
+pub struct Channel {
+    sequence: u16,
+    remote_sequence: u16,
+    // Bit n is whether remote_sequence - 1 - n was received
+    received_bits: u32,
+    received_any: bool,
+    sent: VecDeque<Sent>,
+
+    reliable_out: VecDeque<Reliable>,
+    next_reliable_id: u16,
+    next_reliable_in: u16,
+    // Reliable messages that came before the ones ahead of them
+    reliable_in: HashMap<u16, Vec<u8>>,
+    unreliable_out: Vec<Vec<u8>>,
+    inbox: VecDeque<Vec<u8>>,
+
+    // Seconds, smoothed
+    rtt: f64,
+    last_received: f64,
+}
+
+impl Channel {
+    // now is in seconds, and only has to agree with the other times given to the channel
+    pub fn new(now: f64) -> Self {
+        Self {
+            sequence: 0,
+            // Acks for this before anything comes in don't match anything that was sent
+            remote_sequence: u16::MAX,
+            received_bits: 0,
+            received_any: false,
+            sent: VecDeque::new(),
+            reliable_out: VecDeque::new(),
+            next_reliable_id: 0,
+            next_reliable_in: 0,
+            reliable_in: HashMap::new(),
+            unreliable_out: Vec::new(),
+            inbox: VecDeque::new(),
+            rtt: 0.1,
+            last_received: now,
+        }
+    }
+
+    pub fn send_reliable(&mut self, data: Vec<u8>) -> Result<(), String> {
+        check_size(&data)?;
+        if self.reliable_out.len() >= MAX_RELIABLE_PENDING {
+            return Err(format!(
+                "{MAX_RELIABLE_PENDING} reliable messages haven't been acked"
+            ));
+        }
+        self.reliable_out.push_back(Reliable {
+            id: self.next_reliable_id,
+            data,
+            last_sent: None,
+        });
+        self.next_reliable_id = self.next_reliable_id.wrapping_add(1);
+        Ok(())
+    }
+
+    // Messages bigger than MAX_UNRELIABLE_SIZE only go if they're the first in their packet,
+    // and are dropped if a big reliable message leaves too little room
+    pub fn send_unreliable(&mut self, data: Vec<u8>) -> Result<(), String> {
+        check_size(&data)?;
+        self.unreliable_out.push(data);
+        Ok(())
+    }
+
+    // The next message that arrived, reliable ones in order
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        self.inbox.pop_front()
+    }
+
+    pub fn rtt(&self) -> f64 {
+        self.rtt
+    }
+
+    pub fn timed_out(&self, now: f64) -> bool {
+        now - self.last_received > TIMEOUT
+    }
+
+    // Everything that's due to go out, with an ack for what came in. This is also what keeps
+    // the connection alive, so it should be sent regularly even when it's empty.
+    pub fn write_packet(&mut self, now: f64) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.u16(self.sequence);
+        writer.u16(self.remote_sequence);
+        writer.u32(self.received_bits);
+
+        let resend = (self.rtt * 1.5).max(MIN_RESEND);
+        let mut reliable = Vec::new();
+        let mut size = 0;
+        for message in &mut self.reliable_out {
+            if reliable.len() == u8::MAX as usize
+                || (!reliable.is_empty() && size + 4 + message.data.len() > RELIABLE_BUDGET)
+            {
+                break;
+            }
+            if message
+                .last_sent
+                .is_some_and(|last_sent| now - last_sent < resend)
+            {
+                continue;
+            }
+            message.last_sent = Some(now);
+            size += 4 + message.data.len();
+            reliable.push(message.id);
+        }
+        writer.u8(reliable.len() as u8);
+        for message in self
+            .reliable_out
+            .iter()
+            .filter(|message| reliable.contains(&message.id))
+        {
+            writer.u16(message.id);
+            // send_reliable made sure it fits
+            writer.blob(&message.data).unwrap();
+        }
+
+        // Whatever a reliable message past the budget left, less the unreliable count
+        let room = MAX_PACKET_SIZE - DATA_HEADER_SIZE - writer.data.len() - 1;
+        let mut count = 0;
+        let mut size = 0;
+        for message in &self.unreliable_out {
+            if count == u8::MAX as usize
+                || size + 2 + message.len() > room
+                || (count > 0 && size + message.len() > MAX_UNRELIABLE_SIZE)
+            {
+                break;
+            }
+            count += 1;
+            size += 2 + message.len();
+        }
+        writer.u8(count as u8);
+        for message in self.unreliable_out.drain(..count) {
+            writer.blob(&message).unwrap();
+        }
+        // Anything past the limit is dropped, like it would be if the packet was lost
+        self.unreliable_out.clear();
+
+        self.sent.push_back(Sent {
+            sequence: self.sequence,
+            time: now,
+            reliable,
+        });
+        // Packets too old to be acked were lost, their reliable messages get sent again
+        while self.sent.len() > ACK_BITS as usize + 1 {
+            self.sent.pop_front();
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+
+        writer.data
+    }
+
+    // Duplicate packets are ignored
+    pub fn read_packet(&mut self, data: &[u8], now: f64) -> Result<(), String> {
+        let mut reader = Reader::new(data);
+        let sequence = reader.u16()?;
+        let ack = reader.u16()?;
+        let ack_bits = reader.u32()?;
+
+        if !self.record_received(sequence) {
+            return Ok(());
+        }
+        self.last_received = now;
+        self.process_ack(ack, ack_bits, now);
+
+        for _ in 0..reader.u8()? {
+            let id = reader.u16()?;
+            let message = reader.blob()?.to_vec();
+            // The other side can't have more than this many waiting for acks
+            if id.wrapping_sub(self.next_reliable_in) < MAX_RELIABLE_PENDING as u16 {
+                self.reliable_in.insert(id, message);
+            }
+        }
+        while let Some(message) = self.reliable_in.remove(&self.next_reliable_in) {
+            self.inbox.push_back(message);
+            self.next_reliable_in = self.next_reliable_in.wrapping_add(1);
+        }
+
+        for _ in 0..reader.u8()? {
+            self.inbox.push_back(reader.blob()?.to_vec());
+        }
+        if !reader.is_empty() {
+            return Err(String::from("data left over at the end of the packet"));
+        }
+
+        Ok(())
+    }
+
+    // False if the packet was already received or is too old to tell
+    fn record_received(&mut self, sequence: u16) -> bool {
+        if !self.received_any {
+            self.received_any = true;
+            self.remote_sequence = sequence;
+            self.received_bits = 0;
+            return true;
+        }
+
+        if sequence_newer(sequence, self.remote_sequence) {
+            let shift = sequence.wrapping_sub(self.remote_sequence);
+            self.received_bits = if shift > ACK_BITS {
+                0
+            } else {
+                // The old newest one becomes bit shift - 1
+                ((self.received_bits as u64) << shift | 1 << (shift - 1)) as u32
+            };
+            self.remote_sequence = sequence;
+            true
+        } else {
+            let age = self.remote_sequence.wrapping_sub(sequence);
+            if age == 0 || age > ACK_BITS {
+                return false;
+            }
+            let bit = 1 << (age - 1);
+            let new = self.received_bits & bit == 0;
+            self.received_bits |= bit;
+            new
+        }
+    }
+
+    fn process_ack(&mut self, ack: u16, ack_bits: u32, now: f64) {
+        let acked = |sequence: u16| {
+            let age = ack.wrapping_sub(sequence);
+            age == 0 || (age <= ACK_BITS && ack_bits & (1 << (age - 1)) != 0)
+        };
+
+        let mut reliable = Vec::new();
+        self.sent.retain(|sent| {
+            if !acked(sent.sequence) {
+                return true;
+            }
+            reliable.extend_from_slice(&sent.reliable);
+            if sent.sequence == ack {
+                self.rtt = self.rtt * 0.9 + (now - sent.time) * 0.1;
+            }
+            false
+        });
+        self.reliable_out
+            .retain(|message| !reliable.contains(&message.id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::net::protocol::Packet;
+
+    // Drops about one in every drop_one_in packets, the same ones every run
+    struct Lossy {
+        state: u64,
+        drop_one_in: u64,
+    }
+
+    impl Lossy {
+        fn arrives(&mut self) -> bool {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            !self.state.is_multiple_of(self.drop_one_in)
+        }
+    }
+
+    #[test]
+    fn reliable_messages_arrive_once_in_order_through_loss() {
+        let mut a = Channel::new(0.0);
+        let mut b = Channel::new(0.0);
+        let mut lossy = Lossy {
+            state: 0x2545f4914f6cdd1d,
+            drop_one_in: 3,
+        };
+
+        let sent: Vec<u32> = (0..500).collect();
+        let mut received = Vec::new();
+        let mut now = 0.0;
+        for chunk in sent.chunks(5) {
+            for message in chunk {
+                a.send_reliable(message.to_le_bytes().to_vec()).unwrap();
+            }
+            for _ in 0..4 {
+                now += 1.0 / 60.0;
+                let packet = a.write_packet(now);
+                assert!(packet.len() + DATA_HEADER_SIZE <= PACKET_BUDGET);
+                if lossy.arrives() {
+                    b.read_packet(&packet, now).unwrap();
+                }
+                let packet = b.write_packet(now);
+                if lossy.arrives() {
+                    a.read_packet(&packet, now).unwrap();
+                }
+                while let Some(message) = b.receive() {
+                    received.push(u32::from_le_bytes(message.try_into().unwrap()));
+                }
+            }
+        }
+        for _ in 0..600 {
+            now += 1.0 / 60.0;
+            let packet = a.write_packet(now);
+            if lossy.arrives() {
+                b.read_packet(&packet, now).unwrap();
+            }
+            let packet = b.write_packet(now);
+            if lossy.arrives() {
+                a.read_packet(&packet, now).unwrap();
+            }
+            while let Some(message) = b.receive() {
+                received.push(u32::from_le_bytes(message.try_into().unwrap()));
+            }
+        }
+
+        assert_eq!(received, sent);
+        assert!(a.reliable_out.is_empty());
+    }
+
+    #[test]
+    fn packets_stay_under_budget() {
+        let mut channel = Channel::new(0.0);
+        for _ in 0..20 {
+            channel.send_reliable(vec![0; 100]).unwrap();
+        }
+        channel
+            .send_unreliable(vec![1; MAX_UNRELIABLE_SIZE])
+            .unwrap();
+        channel.send_unreliable(vec![2; 10]).unwrap();
+
+        let packet = channel.write_packet(0.0);
+        assert!(packet.len() + DATA_HEADER_SIZE <= PACKET_BUDGET);
+        let mut other = Channel::new(0.0);
+        other.read_packet(&packet, 0.0).unwrap();
+        let messages: Vec<Vec<u8>> = std::iter::from_fn(|| other.receive()).collect();
+        // Two reliable messages fit, and only the first unreliable one
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2], vec![1; MAX_UNRELIABLE_SIZE]);
+    }
+
+    #[test]
+    fn the_biggest_messages_fit_in_a_packet() {
+        let mut channel = Channel::new(0.0);
+        assert!(channel
+            .send_reliable(vec![0; MAX_MESSAGE_SIZE + 1])
+            .is_err());
+        assert!(channel
+            .send_unreliable(vec![0; MAX_MESSAGE_SIZE + 1])
+            .is_err());
+
+        // The unreliable one doesn't fit next to the reliable one, so it's dropped
+        channel.send_reliable(vec![1; MAX_MESSAGE_SIZE]).unwrap();
+        channel.send_unreliable(vec![2; MAX_MESSAGE_SIZE]).unwrap();
+        let packet = channel.write_packet(0.0);
+        let data = Packet::Data {
+            salt: 0,
+            channel: &packet,
+        }
+        .write()
+        .unwrap();
+        assert_eq!(data.len(), MAX_PACKET_SIZE);
+
+        let mut other = Channel::new(0.0);
+        other.read_packet(&packet, 0.0).unwrap();
+        assert_eq!(other.receive(), Some(vec![1; MAX_MESSAGE_SIZE]));
+        assert_eq!(other.receive(), None);
+
+        // And goes on its own once the reliable one is acked
+        channel.read_packet(&other.write_packet(0.0), 0.0).unwrap();
+        channel.send_unreliable(vec![2; MAX_MESSAGE_SIZE]).unwrap();
+        let packet = channel.write_packet(0.0);
+        assert!(packet.len() + DATA_HEADER_SIZE <= MAX_PACKET_SIZE);
+        other.read_packet(&packet, 0.0).unwrap();
+        assert_eq!(other.receive(), Some(vec![2; MAX_MESSAGE_SIZE]));
+    }
+}
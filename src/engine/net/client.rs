@@ -0,0 +1,388 @@
+use super::{
+    channel::Channel,
+    prediction::{Prediction, Simulate},
+    protocol::{Command, Message, Packet, PROTOCOL_VERSION},
+    snapshot::Snapshot,
+};
+use log::{debug, info, warn};
+use std::{
+    collections::{HashMap, VecDeque},
+    iter, mem,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+};
+
+// Seconds between connection attempts, and how many are made before giving up
+const CONNECT_RETRY: f64 = 0.5;
+const CONNECT_ATTEMPTS: u32 = 20;
+// Snapshots kept for the server to delta compress against
+const HISTORY: usize = 64;
+// How many of the newest commands the server hasn't acked go in each packet
+const REDUNDANT_COMMANDS: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientEvent {
+    Connected { client: u32 },
+    Disconnected { reason: String },
+    // Something the server sent with Server::send
+    Message(Vec<u8>),
+}
+
+enum Status {
+    // salt is the server's challenge once it answers
+    Connecting {
+        salt: Option<u64>,
+        until_retry: f64,
+        attempts: u32,
+    },
+    Connected {
+        salt: u64,
+        client: u32,
+        channel: Channel,
+    },
+    Disconnected,
+}
+
+// A snapshot that came in this update, and what the server said with it
+struct Received {
+    last_command: Option<u32>,
+    player: Option<u32>,
+}
+
+pub struct Client {
+    socket: UdpSocket,
+    server: SocketAddr,
+    name: String,
+    status: Status,
+    // Seconds since connecting
+    time: f64,
+    tick_rate: u32,
+    snapshots: VecDeque<Snapshot>,
+    // The entities made for each NetId
+    entities: HashMap<u32, legion::Entity>,
+    player: Option<u32>,
+    prediction: Prediction,
+    next_command: u32,
+    events: Vec<ClientEvent>,
+}
+
+impl Client {
+    // Starts connecting to address, a host name or IP with a port. update finishes connecting.
+    pub fn connect(address: &str, name: &str) -> Result<Self, String> {
+        let server = address
+            .to_socket_addrs()
+            .map_err(|err| format!("failed to resolve {address}: {err}"))?
+            .next()
+            .ok_or_else(|| format!("{address} doesn't have an address"))?;
+        let socket = super::bind(if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        info!("Connecting to {server} as {name}");
+
+        Ok(Self {
+            socket,
+            server,
+            name: String::from(name),
+            status: Status::Connecting {
+                salt: None,
+                until_retry: 0.0,
+                attempts: 0,
+            },
+            time: 0.0,
+            tick_rate: 0,
+            snapshots: VecDeque::new(),
+            entities: HashMap::new(),
+            player: None,
+            prediction: Prediction::default(),
+            next_command: 1,
+            events: Vec::new(),
+        })
+    }
+
+    pub fn connected(&self) -> bool {
+        matches!(self.status, Status::Connected { .. })
+    }
+
+    pub fn disconnected(&self) -> bool {
+        matches!(self.status, Status::Disconnected)
+    }
+
+    // The id the server gave this client
+    pub fn client_id(&self) -> Option<u32> {
+        match self.status {
+            Status::Connected { client, .. } => Some(client),
+            _ => None,
+        }
+    }
+
+    // Snapshots a second, 0 until connected
+    pub fn tick_rate(&self) -> u32 {
+        self.tick_rate
+    }
+
+    // Round trip time in seconds
+    pub fn rtt(&self) -> Option<f64> {
+        match &self.status {
+            Status::Connected { channel, .. } => Some(channel.rtt()),
+            _ => None,
+        }
+    }
+
+    // The entity the server lets this client control
+    pub fn player(&self) -> Option<legion::Entity> {
+        self.player.and_then(|id| self.entity(id))
+    }
+
+    // The entity made for a NetId
+    pub fn entity(&self, id: u32) -> Option<legion::Entity> {
+        self.entities.get(&id).copied()
+    }
+
+    // Sends data reliably, it comes out of the server's update as ServerEvent::Message
+    pub fn send(&mut self, data: Vec<u8>) {
+        if let Status::Connected { channel, .. } = &mut self.status {
+            if let Err(err) = channel.send_reliable(Message::Game(data).write()) {
+                self.disconnected_because(&err);
+            }
+        }
+    }
+
+    // Applies what the server sent to world and sends it this frame's command, after running
+    // it on the player's entity. delta is in seconds.
+    pub fn update(
+        &mut self,
+        world: &mut legion::World,
+        command: Option<Command>,
+        delta: f64,
+        simulate: Simulate,
+    ) -> Vec<ClientEvent> {
+        self.time += delta;
+
+        let mut received = None;
+        for (address, data) in super::receive_all(&self.socket) {
+            if address == self.server {
+                self.handle_packet(&data, &mut received);
+            }
+        }
+
+        // Only the newest snapshot matters, the ones before it were replaced by it
+        if let (Some(received), Some(snapshot)) = (received, self.snapshots.back()) {
+            snapshot.apply(world, &mut self.entities);
+            self.player = received.player;
+            let player = self.player();
+            self.prediction
+                .reconcile(world, player, received.last_command, simulate);
+        }
+
+        match &mut self.status {
+            Status::Connecting {
+                salt,
+                until_retry,
+                attempts,
+            } => {
+                *until_retry -= delta;
+                if *until_retry <= 0.0 {
+                    *until_retry = CONNECT_RETRY;
+                    *attempts += 1;
+                    if *attempts > CONNECT_ATTEMPTS {
+                        self.disconnected_because("server didn't answer");
+                    } else {
+                        let packet = match salt {
+                            Some(salt) => Packet::Response { salt: *salt },
+                            None => Packet::Connect {
+                                version: PROTOCOL_VERSION,
+                                name: self.name.clone(),
+                            },
+                        };
+                        super::send(&self.socket, self.server, &packet);
+                    }
+                }
+            }
+            Status::Connected { channel, .. } if channel.timed_out(self.time) => {
+                self.disconnected_because("timed out");
+            }
+            Status::Connected { salt, channel, .. } => {
+                if let Some(mut command) = command {
+                    // The server clamps it the same way, predicting more would always be undone
+                    command.clamp_delta();
+                    command.sequence = self.next_command;
+                    self.next_command += 1;
+                    let player = self.player.and_then(|id| self.entities.get(&id).copied());
+                    self.prediction.predict(world, player, command, simulate);
+                }
+
+                let pending: Vec<Command> = self.prediction.pending().copied().collect();
+                let commands = pending[pending.len().saturating_sub(REDUNDANT_COMMANDS)..].to_vec();
+                let message = Message::Commands {
+                    snapshot_ack: self.snapshots.back().map(|snapshot| snapshot.tick),
+                    commands,
+                };
+                if let Err(err) = channel.send_unreliable(message.write()) {
+                    debug!("Not sending commands: {err}");
+                }
+                // Sent every frame, since commands are per frame
+                let data = channel.write_packet(self.time);
+                super::send(
+                    &self.socket,
+                    self.server,
+                    &Packet::Data {
+                        salt: *salt,
+                        channel: &data,
+                    },
+                );
+            }
+            Status::Disconnected => {}
+        }
+
+        mem::take(&mut self.events)
+    }
+
+    // Leaves the server and removes the entities it sent from world
+    pub fn disconnect(mut self, world: &mut legion::World) {
+        if let Status::Connected { salt, .. } = self.status {
+            super::send(
+                &self.socket,
+                self.server,
+                &Packet::Disconnect {
+                    salt,
+                    reason: String::from("disconnected"),
+                },
+            );
+            info!("Disconnected from {}", self.server);
+        }
+        self.status = Status::Disconnected;
+        for entity in self.entities.values() {
+            world.remove(*entity);
+        }
+    }
+
+    fn disconnected_because(&mut self, reason: &str) {
+        warn!("Disconnected from {}: {reason}", self.server);
+        self.status = Status::Disconnected;
+        self.events.push(ClientEvent::Disconnected {
+            reason: String::from(reason),
+        });
+    }
+
+    fn handle_packet(&mut self, data: &[u8], received: &mut Option<Received>) {
+        let packet = match Packet::read(data) {
+            Some(Ok(packet)) => packet,
+            Some(Err(err)) => {
+                debug!("Bad packet from server: {err}");
+                return;
+            }
+            None => return,
+        };
+
+        match packet {
+            Packet::Challenge { salt: challenge } => {
+                if let Status::Connecting {
+                    salt, until_retry, ..
+                } = &mut self.status
+                {
+                    *salt = Some(challenge);
+                    *until_retry = 0.0;
+                }
+            }
+            Packet::Accept {
+                salt: accepted,
+                client,
+                tick_rate,
+            } => {
+                if !matches!(self.status, Status::Connecting { salt: Some(salt), .. } if salt == accepted)
+                {
+                    return;
+                }
+                info!("Connected to {} as client {client}", self.server);
+                self.tick_rate = tick_rate;
+                self.status = Status::Connected {
+                    salt: accepted,
+                    client,
+                    channel: Channel::new(self.time),
+                };
+                self.events.push(ClientEvent::Connected { client });
+            }
+            Packet::Reject { reason } => {
+                if matches!(self.status, Status::Connecting { .. }) {
+                    self.disconnected_because(&format!("rejected, {reason}"));
+                }
+            }
+            Packet::Data {
+                salt: sent,
+                channel: data,
+            } => {
+                let Status::Connected { salt, channel, .. } = &mut self.status else {
+                    return;
+                };
+                if *salt != sent {
+                    return;
+                }
+                if let Err(err) = channel.read_packet(data, self.time) {
+                    debug!("Bad packet from server: {err}");
+                    return;
+                }
+                let messages: Vec<Vec<u8>> = iter::from_fn(|| channel.receive()).collect();
+                for message in messages {
+                    match Message::read(&message) {
+                        Ok(message) => self.handle_message(message, received),
+                        Err(err) => debug!("Bad message from server: {err}"),
+                    }
+                }
+            }
+            Packet::Disconnect { salt: sent, reason } => {
+                if matches!(self.status, Status::Connected { salt, .. } if salt == sent) {
+                    self.disconnected_because(&reason);
+                }
+            }
+            Packet::Connect { .. } | Packet::Response { .. } => {}
+        }
+    }
+
+    fn handle_message(&mut self, message: Message, received: &mut Option<Received>) {
+        match message {
+            Message::Snapshot {
+                tick,
+                baseline,
+                last_command,
+                player,
+                delta,
+            } => {
+                if self
+                    .snapshots
+                    .back()
+                    .is_some_and(|newest| newest.tick >= tick)
+                {
+                    return;
+                }
+                let base = match baseline {
+                    Some(baseline) => {
+                        match self.snapshots.iter().find(|old| old.tick == baseline) {
+                            Some(base) => Some(base),
+                            None => {
+                                debug!("Dropping snapshot {tick}, baseline {baseline} is gone");
+                                return;
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                match Snapshot::read_delta(&delta, tick, base) {
+                    Ok(snapshot) => {
+                        self.snapshots.push_back(snapshot);
+                        while self.snapshots.len() > HISTORY {
+                            self.snapshots.pop_front();
+                        }
+                        *received = Some(Received {
+                            last_command,
+                            player,
+                        });
+                    }
+                    Err(err) => debug!("Bad snapshot {tick}: {err}"),
+                }
+            }
+            Message::Game(data) => self.events.push(ClientEvent::Message(data)),
+            Message::Commands { .. } => debug!("Server sent commands"),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+// Client/server networking over UDP. The server runs the game and sends snapshots of every
+// entity with a NetId to its clients at its tick rate, and clients send their input to it as
+// Commands every frame. Clients run their own commands on the entity they control straight
+// away and correct it when a snapshot comes in, see prediction.
+//
+//     let mut server = net::Server::bind("0.0.0.0:26000")?.tick_rate(30);
+//     for event in server.update(state.world(), delta, simulate) {
+//         if let net::ServerEvent::Connected { client, .. } = event {
+//             let player = state.world().push((Transform::default(),));
+//             server.replicate(state.world(), player);
+//             server.set_player(client, Some(player));
+//         }
+//     }
+//
+//     let mut client = net::Client::connect("example.com:26000", "player")?;
+//     let command = net::Command::from_input(state.input_state(), ACTIONS, delta as f32);
+//     client.update(state.world(), Some(command), delta, simulate);
+
+pub mod channel;
+pub mod prediction;
+pub mod protocol;
+pub mod snapshot;
+mod wire;
+
+mod client;
+mod server;
+
+pub use client::{Client, ClientEvent};
+pub use prediction::Simulate;
+pub use protocol::Command;
+pub use server::{Server, ServerEvent};
+
+use log::debug;
+use protocol::{Packet, MAX_PACKET_SIZE};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::SystemTime,
+};
+
+pub const DEFAULT_PORT: u16 = 26000;
+
+fn bind(address: &str) -> Result<UdpSocket, String> {
+    let socket =
+        UdpSocket::bind(address).map_err(|err| format!("failed to bind to {address}: {err}"))?;
+    socket
+        .set_nonblocking(true)
+        .map_err(|err| format!("failed to make socket non-blocking: {err}"))?;
+    Ok(socket)
+}
+
+// Every packet waiting on the socket
+fn receive_all(socket: &UdpSocket) -> Vec<(SocketAddr, Vec<u8>)> {
+    let mut packets = Vec::new();
+    let mut buffer = vec![0; MAX_PACKET_SIZE];
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((size, address)) => packets.push((address, buffer[..size].to_vec())),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            // Windows reports ICMP port unreachable from an earlier send here
+            Err(err) if err.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(err) => {
+                debug!("Failed to receive packet: {err}");
+                break;
+            }
+        }
+    }
+    packets
+}
+
+fn send(socket: &UdpSocket, address: SocketAddr, packet: &Packet) {
+    let data = match packet.write() {
+        Ok(data) => data,
+        Err(err) => {
+            debug!("Not sending packet to {address}: {err}");
+            return;
+        }
+    };
+    if data.len() > MAX_PACKET_SIZE {
+        debug!(
+            "Not sending {} byte packet to {address}, it's too big",
+            data.len()
+        );
+        return;
+    }
+    if let Err(err) = socket.send_to(&data, address) {
+        debug!("Failed to send packet to {address}: {err}");
+    }
+}
+
+// Salts only have to be hard to guess, so the standard library's random hash keys are enough
+fn random_salt() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(time) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(time.as_nanos());
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::components, player};
+    use std::{thread, time::Duration};
+
+    const DELTA: f64 = 1.0 / 60.0;
+
+    // A server on a free port and a client connecting to it
+    fn start(server: Server) -> (Server, Client) {
+        let address = server.local_address().unwrap().to_string();
+        let client = Client::connect(&address, "tester").unwrap();
+        (server, client)
+    }
+
+    // Loopback is quick, but not always quick enough to be there by the next call
+    fn wait() {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    #[test]
+    fn clients_connect_talk_and_leave() {
+        let (mut server, mut client) = start(Server::bind("127.0.0.1:0").unwrap());
+        let mut server_world = legion::World::default();
+        let mut client_world = legion::World::default();
+
+        let mut server_events = Vec::new();
+        let mut client_events = Vec::new();
+        for _ in 0..200 {
+            server_events.extend(server.update(&mut server_world, DELTA, player::simulate));
+            client_events.extend(client.update(&mut client_world, None, DELTA, player::simulate));
+            if client.connected() {
+                break;
+            }
+            wait();
+        }
+        assert!(client.connected());
+        assert_eq!(client.client_id(), Some(0));
+        assert_eq!(client_events, vec![ClientEvent::Connected { client: 0 }]);
+        assert_eq!(
+            server_events,
+            vec![ServerEvent::Connected {
+                client: 0,
+                name: String::from("tester"),
+            }]
+        );
+        assert_eq!(server.clients().collect::<Vec<_>>(), vec![(0, "tester")]);
+
+        server.send(0, b"hello".to_vec());
+        client.send(b"hi".to_vec());
+        server_events.clear();
+        client_events.clear();
+        for _ in 0..200 {
+            server_events.extend(server.update(&mut server_world, DELTA, player::simulate));
+            client_events.extend(client.update(&mut client_world, None, DELTA, player::simulate));
+            if !server_events.is_empty() && !client_events.is_empty() {
+                break;
+            }
+            wait();
+        }
+        assert_eq!(client_events, vec![ClientEvent::Message(b"hello".to_vec())]);
+        assert_eq!(
+            server_events,
+            vec![ServerEvent::Message {
+                client: 0,
+                data: b"hi".to_vec(),
+            }]
+        );
+
+        client.disconnect(&mut client_world);
+        server_events.clear();
+        for _ in 0..200 {
+            server_events.extend(server.update(&mut server_world, DELTA, player::simulate));
+            if !server_events.is_empty() {
+                break;
+            }
+            wait();
+        }
+        assert_eq!(
+            server_events,
+            vec![ServerEvent::Disconnected {
+                client: 0,
+                reason: String::from("disconnected"),
+            }]
+        );
+        assert_eq!(server.clients().count(), 0);
+    }
+
+    #[test]
+    fn full_servers_reject() {
+        let (mut server, mut client) = start(Server::bind("127.0.0.1:0").unwrap().max_clients(0));
+        let mut server_world = legion::World::default();
+        let mut client_world = legion::World::default();
+
+        let mut client_events = Vec::new();
+        for _ in 0..200 {
+            server.update(&mut server_world, DELTA, player::simulate);
+            client_events.extend(client.update(&mut client_world, None, DELTA, player::simulate));
+            if client.disconnected() {
+                break;
+            }
+            wait();
+        }
+        assert_eq!(
+            client_events,
+            vec![ClientEvent::Disconnected {
+                reason: String::from("rejected, server is full"),
+            }]
+        );
+    }
+
+    #[test]
+    fn prediction_ends_up_where_the_server_is() {
+        // Fewer snapshots than frames, so most frames are only predicted
+        let (mut server, mut client) = start(Server::bind("127.0.0.1:0").unwrap().tick_rate(20));
+        let mut server_world = legion::World::default();
+        let mut client_world = legion::World::default();
+        let mut server_player = None;
+
+        let position = |world: &legion::World, entity: Option<legion::Entity>| {
+            entity
+                .and_then(|entity| world.entry_ref(entity).ok())
+                .and_then(|entry| entry.get_component::<components::Transform>().ok().copied())
+                .map(|transform| transform.position)
+        };
+
+        // Forward, the first of player::NET_ACTIONS, for two seconds and then nothing for one
+        let mut moved_ahead = false;
+        let mut forward = 0;
+        for frame in 0..600 {
+            for event in server.update(&mut server_world, DELTA, player::simulate) {
+                if let ServerEvent::Connected { client, .. } = event {
+                    let player = server_world.push((components::Transform::default(),));
+                    server.replicate(&mut server_world, player);
+                    server.set_player(client, Some(player));
+                    server_player = Some(player);
+                }
+            }
+
+            let command = client.connected().then(|| Command {
+                delta: DELTA as f32,
+                actions: if frame < 120 { 1 } else { 0 },
+                ..Default::default()
+            });
+            if command.is_some_and(|command| command.actions != 0) {
+                forward += 1;
+            }
+            client.update(&mut client_world, command, DELTA, player::simulate);
+
+            if let (Some(predicted), Some(actual)) = (
+                position(&client_world, client.player()),
+                position(&server_world, server_player),
+            ) {
+                moved_ahead |= predicted.z < actual.z;
+            }
+            wait();
+        }
+
+        let predicted = position(&client_world, client.player()).unwrap();
+        let actual = position(&server_world, server_player).unwrap();
+        assert!(moved_ahead, "the client never got ahead of the server");
+        assert!(
+            (predicted - actual).norm() < 1e-4,
+            "{predicted} != {actual}"
+        );
+        // 5 units a second, give or take what the server cut off
+        let expected = forward as f32 * DELTA as f32 * 5.0;
+        assert!((actual.z + expected).abs() < 0.01, "ended up at {actual}");
+    }
+}
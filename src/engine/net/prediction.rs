@@ -0,0 +1,61 @@
+use super::protocol::Command;
+use std::collections::VecDeque;
+
+// Past this many commands the server hasn't acked, the oldest ones are forgotten
+const MAX_PENDING: usize = 128;
+
+// How the game moves the entity a client controls. The server runs it on the commands it gets
+// and the client runs it on its own straight away, so it has to do the same thing on both
+// given the same world.
+pub type Simulate = fn(&mut legion::World, legion::Entity, &Command);
+
+// Runs the player's commands on the client without waiting for the server, and puts them back
+// on top of the server's state whenever a snapshot comes in, so the player only sees the
+// server's corrections and not its round trip time.
+#[derive(Default)]
+pub struct Prediction {
+    pending: VecDeque<Command>,
+}
+
+impl Prediction {
+    pub fn predict(
+        &mut self,
+        world: &mut legion::World,
+        entity: Option<legion::Entity>,
+        command: Command,
+        simulate: Simulate,
+    ) {
+        if let Some(entity) = entity {
+            simulate(world, entity, &command);
+        }
+        self.pending.push_back(command);
+        while self.pending.len() > MAX_PENDING {
+            self.pending.pop_front();
+        }
+    }
+
+    // After a snapshot replaced the entity's state with the server's, which included every
+    // command up to last_command. The rest are run again on top of it.
+    pub fn reconcile(
+        &mut self,
+        world: &mut legion::World,
+        entity: Option<legion::Entity>,
+        last_command: Option<u32>,
+        simulate: Simulate,
+    ) {
+        if let Some(last_command) = last_command {
+            self.pending
+                .retain(|command| command.sequence > last_command);
+        }
+        if let Some(entity) = entity {
+            for command in &self.pending {
+                simulate(world, entity, command);
+            }
+        }
+    }
+
+    // Oldest first, these get sent until the server acks them
+    pub fn pending(&self) -> impl Iterator<Item = &Command> {
+        self.pending.iter()
+    }
+}
@@ -0,0 +1,311 @@
+// What goes over the wire. Every packet starts with PROTOCOL_ID and a kind, and connecting
+// goes like this, so a server only talks to addresses that can answer it:
+//
+//     client                      server
+//     Connect(version, name) ->
+//                             <-  Challenge(salt)
+//     Response(salt)         ->
+//                             <-  Accept(salt, client, tick rate) or Reject(reason)
+//
+// After that both sides send Data packets with the salt and a channel packet in them, which
+// has Messages in it. Either side can send Disconnect to leave.
+
+use super::wire::{Reader, Writer};
+use crate::engine::input;
+
+pub const PROTOCOL_ID: u32 = u32::from_le_bytes(*b"PRPL");
+// Bumped whenever packets or messages change
+pub const PROTOCOL_VERSION: u32 = 1;
+// The most UDP can carry, which only gets through by IP fragmentation
+pub const MAX_PACKET_SIZE: usize = 65507;
+// What packets are kept under so they fit in one datagram on any link
+pub const PACKET_BUDGET: usize = 1200;
+// Bytes a Data packet has before the channel packet in it
+pub const DATA_HEADER_SIZE: usize = 13;
+// The longest frame a command can say it took. The server clamps to this, and so does the client
+// before predicting with it so the two agree.
+pub const MAX_COMMAND_DELTA: f32 = 0.25;
+
+const CONNECT: u8 = 0;
+const CHALLENGE: u8 = 1;
+const RESPONSE: u8 = 2;
+const ACCEPT: u8 = 3;
+const REJECT: u8 = 4;
+const DATA: u8 = 5;
+const DISCONNECT: u8 = 6;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet<'a> {
+    Connect {
+        version: u32,
+        name: String,
+    },
+    Challenge {
+        salt: u64,
+    },
+    Response {
+        salt: u64,
+    },
+    Accept {
+        salt: u64,
+        client: u32,
+        tick_rate: u32,
+    },
+    Reject {
+        reason: String,
+    },
+    Data {
+        salt: u64,
+        channel: &'a [u8],
+    },
+    Disconnect {
+        salt: u64,
+        reason: String,
+    },
+}
+
+impl<'a> Packet<'a> {
+    // None if it isn't one of ours
+    pub fn read(data: &'a [u8]) -> Option<Result<Self, String>> {
+        let mut reader = Reader::new(data);
+        if reader.u32().ok()? != PROTOCOL_ID {
+            return None;
+        }
+        Some(Self::read_body(&mut reader))
+    }
+
+    fn read_body(reader: &mut Reader<'a>) -> Result<Self, String> {
+        let packet = match reader.u8()? {
+            CONNECT => Self::Connect {
+                version: reader.u32()?,
+                name: reader.string()?,
+            },
+            CHALLENGE => Self::Challenge {
+                salt: reader.u64()?,
+            },
+            RESPONSE => Self::Response {
+                salt: reader.u64()?,
+            },
+            ACCEPT => Self::Accept {
+                salt: reader.u64()?,
+                client: reader.u32()?,
+                tick_rate: reader.u32()?,
+            },
+            REJECT => Self::Reject {
+                reason: reader.string()?,
+            },
+            DATA => Self::Data {
+                salt: reader.u64()?,
+                channel: reader.rest(),
+            },
+            DISCONNECT => Self::Disconnect {
+                salt: reader.u64()?,
+                reason: reader.string()?,
+            },
+            kind => return Err(format!("unknown packet kind {kind}")),
+        };
+        if !reader.is_empty() {
+            return Err(String::from("data left over at the end of the packet"));
+        }
+        Ok(packet)
+    }
+
+    pub fn write(&self) -> Result<Vec<u8>, String> {
+        let mut writer = Writer::new();
+        writer.u32(PROTOCOL_ID);
+        match self {
+            Self::Connect { version, name } => {
+                writer.u8(CONNECT);
+                writer.u32(*version);
+                writer.string(name)?;
+            }
+            Self::Challenge { salt } => {
+                writer.u8(CHALLENGE);
+                writer.u64(*salt);
+            }
+            Self::Response { salt } => {
+                writer.u8(RESPONSE);
+                writer.u64(*salt);
+            }
+            Self::Accept {
+                salt,
+                client,
+                tick_rate,
+            } => {
+                writer.u8(ACCEPT);
+                writer.u64(*salt);
+                writer.u32(*client);
+                writer.u32(*tick_rate);
+            }
+            Self::Reject { reason } => {
+                writer.u8(REJECT);
+                writer.string(reason)?;
+            }
+            Self::Data { salt, channel } => {
+                writer.u8(DATA);
+                writer.u64(*salt);
+                writer.bytes(channel);
+            }
+            Self::Disconnect { salt, reason } => {
+                writer.u8(DISCONNECT);
+                writer.u64(*salt);
+                writer.string(reason)?;
+            }
+        }
+        Ok(writer.data)
+    }
+}
+
+// One frame of a client's input, which the client predicts with and the server simulates
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Command {
+    // Given out by the client, starting from 1
+    pub sequence: u32,
+    // Seconds the frame took
+    pub delta: f32,
+    // A bit for each action in the list given to from_input
+    pub actions: u32,
+    // Mouse movement, or whatever the game uses it for
+    pub look: [f32; 2],
+}
+
+impl Command {
+    // actions is the game's list of networked actions, which has to be the same on the client
+    // and server. Only the first 32 fit.
+    pub fn from_input(input: &input::State, actions: &[&str], delta: f32) -> Self {
+        let actions = actions
+            .iter()
+            .take(32)
+            .enumerate()
+            .filter(|(_, action)| input.action_down(action))
+            .fold(0, |bits, (index, _)| bits | 1 << index);
+        let (x, y) = input.mouse_delta();
+        Self {
+            sequence: 0,
+            delta,
+            actions,
+            look: [x, y],
+        }
+    }
+
+    // Keeps delta between 0 and MAX_COMMAND_DELTA like the server does, NaN becomes 0
+    pub fn clamp_delta(&mut self) {
+        self.delta = if self.delta.is_nan() {
+            0.0
+        } else {
+            self.delta.clamp(0.0, MAX_COMMAND_DELTA)
+        };
+    }
+
+    // Whether the action at index in the game's list was down
+    pub fn action(&self, index: usize) -> bool {
+        index < 32 && self.actions & (1 << index) != 0
+    }
+}
+
+const COMMANDS: u8 = 0;
+const SNAPSHOT: u8 = 1;
+const GAME: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    // Client to server, unreliable. Has the last few commands that haven't been acked, so
+    // losing a packet doesn't lose any, and the newest snapshot the client has.
+    Commands {
+        snapshot_ack: Option<u32>,
+        commands: Vec<Command>,
+    },
+    // Server to client, unreliable. delta is relative to the baseline snapshot, see snapshot.
+    Snapshot {
+        tick: u32,
+        baseline: Option<u32>,
+        // The newest of the client's commands the snapshot includes
+        last_command: Option<u32>,
+        // The NetId of the entity the client controls
+        player: Option<u32>,
+        delta: Vec<u8>,
+    },
+    // Either way, reliable, for whatever the game wants
+    Game(Vec<u8>),
+}
+
+impl Message {
+    pub fn read(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(data);
+        let message = match reader.u8()? {
+            COMMANDS => {
+                let snapshot_ack = reader.option_u32()?;
+                let mut commands = Vec::new();
+                for _ in 0..reader.u8()? {
+                    let sequence = reader.u32()?;
+                    let [delta] = reader.f32s()?;
+                    let actions = reader.u32()?;
+                    let look = reader.f32s()?;
+                    commands.push(Command {
+                        sequence,
+                        delta,
+                        actions,
+                        look,
+                    });
+                }
+                Self::Commands {
+                    snapshot_ack,
+                    commands,
+                }
+            }
+            SNAPSHOT => Self::Snapshot {
+                tick: reader.u32()?,
+                baseline: reader.option_u32()?,
+                last_command: reader.option_u32()?,
+                player: reader.option_u32()?,
+                delta: reader.rest().to_vec(),
+            },
+            GAME => Self::Game(reader.rest().to_vec()),
+            kind => return Err(format!("unknown message kind {kind}")),
+        };
+        if !reader.is_empty() {
+            return Err(String::from("data left over at the end of the message"));
+        }
+        Ok(message)
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        match self {
+            Self::Commands {
+                snapshot_ack,
+                commands,
+            } => {
+                writer.u8(COMMANDS);
+                writer.option_u32(*snapshot_ack);
+                let commands = &commands[..commands.len().min(u8::MAX as usize)];
+                writer.u8(commands.len() as u8);
+                for command in commands {
+                    writer.u32(command.sequence);
+                    writer.f32s(&[command.delta]);
+                    writer.u32(command.actions);
+                    writer.f32s(&command.look);
+                }
+            }
+            Self::Snapshot {
+                tick,
+                baseline,
+                last_command,
+                player,
+                delta,
+            } => {
+                writer.u8(SNAPSHOT);
+                writer.u32(*tick);
+                writer.option_u32(*baseline);
+                writer.option_u32(*last_command);
+                writer.option_u32(*player);
+                writer.bytes(delta);
+            }
+            Self::Game(data) => {
+                writer.u8(GAME);
+                writer.bytes(data);
+            }
+        }
+        writer.data
+    }
+}
@@ -0,0 +1,496 @@
+use super::{
+    channel::{Channel, MAX_UNRELIABLE_SIZE},
+    prediction::Simulate,
+    protocol::{Message, Packet, PROTOCOL_VERSION},
+    snapshot::Snapshot,
+};
+use crate::engine::components;
+use log::{debug, info};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    iter, mem,
+    net::{SocketAddr, UdpSocket},
+};
+
+const DEFAULT_TICK_RATE: u32 = 30;
+const DEFAULT_MAX_CLIENTS: usize = 16;
+// Snapshots kept for each client to delta compress against
+const HISTORY: usize = 64;
+// Seconds an address has to answer its challenge
+const CHALLENGE_TIMEOUT: f64 = 5.0;
+// Clients get as much time for their commands as passes on the server, so sending more of them
+// or longer ones doesn't speed them up. This is how many seconds they can save up, for commands
+// the network held up that come in together.
+const MAX_COMMAND_TIME: f64 = 0.5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerEvent {
+    Connected { client: u32, name: String },
+    Disconnected { client: u32, reason: String },
+    // Something the client sent with Client::send
+    Message { client: u32, data: Vec<u8> },
+}
+
+struct Challenge {
+    salt: u64,
+    name: String,
+    time: f64,
+}
+
+struct Connection {
+    address: SocketAddr,
+    name: String,
+    salt: u64,
+    channel: Channel,
+    // What the client's commands move
+    player: Option<legion::Entity>,
+    snapshot_ack: Option<u32>,
+    last_command: Option<u32>,
+    // Seconds the client's commands can still take up
+    command_time: f64,
+    // What the client ends up with from each snapshot sent to it, oldest first. Snapshots only
+    // have what fit in a packet, so these aren't always what the world was like on their tick.
+    sent: VecDeque<Snapshot>,
+    // How many snapshots in a row each entity's changes didn't fit in, by NetId
+    waiting: HashMap<u32, u32>,
+}
+
+pub struct Server {
+    socket: UdpSocket,
+    tick_rate: u32,
+    max_clients: usize,
+    // Seconds since the server started
+    time: f64,
+    tick: u32,
+    until_tick: f64,
+    challenges: HashMap<SocketAddr, Challenge>,
+    clients: BTreeMap<u32, Connection>,
+    next_client: u32,
+    next_net_id: u32,
+    events: Vec<ServerEvent>,
+}
+
+impl Server {
+    // address is an IP and port, like 0.0.0.0:26000
+    pub fn bind(address: &str) -> Result<Self, String> {
+        let socket = super::bind(address)?;
+        info!("Server listening on {address}");
+        Ok(Self {
+            socket,
+            tick_rate: DEFAULT_TICK_RATE,
+            max_clients: DEFAULT_MAX_CLIENTS,
+            time: 0.0,
+            tick: 0,
+            until_tick: 0.0,
+            challenges: HashMap::new(),
+            clients: BTreeMap::new(),
+            next_client: 0,
+            next_net_id: 0,
+            events: Vec::new(),
+        })
+    }
+
+    // How many snapshots are sent a second
+    pub fn tick_rate(mut self, tick_rate: u32) -> Self {
+        self.tick_rate = tick_rate.max(1);
+        self
+    }
+
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self
+    }
+
+    pub fn local_address(&self) -> Result<SocketAddr, String> {
+        self.socket.local_addr().map_err(|err| err.to_string())
+    }
+
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    // Gives entity a NetId so it gets sent to clients, or returns the one it has
+    pub fn replicate(&mut self, world: &mut legion::World, entity: legion::Entity) -> Option<u32> {
+        let mut entry = world.entry(entity)?;
+        if let Ok(id) = entry.get_component::<components::NetId>() {
+            return Some(id.0);
+        }
+        let id = self.next_net_id;
+        self.next_net_id += 1;
+        entry.add_component(components::NetId(id));
+        Some(id)
+    }
+
+    // Sets which entity client's commands get simulated on
+    pub fn set_player(&mut self, client: u32, entity: Option<legion::Entity>) {
+        if let Some(connection) = self.clients.get_mut(&client) {
+            connection.player = entity;
+        }
+    }
+
+    pub fn player(&self, client: u32) -> Option<legion::Entity> {
+        self.clients
+            .get(&client)
+            .and_then(|connection| connection.player)
+    }
+
+    // Every client's id and name
+    pub fn clients(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.clients
+            .iter()
+            .map(|(id, connection)| (*id, connection.name.as_str()))
+    }
+
+    // Sends data reliably, it comes out of the client's update as ClientEvent::Message
+    pub fn send(&mut self, client: u32, data: Vec<u8>) {
+        let Some(connection) = self.clients.get_mut(&client) else {
+            return;
+        };
+        if let Err(err) = connection
+            .channel
+            .send_reliable(Message::Game(data).write())
+        {
+            self.disconnect(client, &err);
+        }
+    }
+
+    pub fn broadcast(&mut self, data: Vec<u8>) {
+        let clients: Vec<u32> = self.clients.keys().copied().collect();
+        for client in clients {
+            self.send(client, data.clone());
+        }
+    }
+
+    pub fn kick(&mut self, client: u32, reason: &str) {
+        self.disconnect(client, reason);
+    }
+
+    // Reads what clients sent, runs their commands through simulate, and sends snapshots when
+    // a tick is due. delta is in seconds.
+    pub fn update(
+        &mut self,
+        world: &mut legion::World,
+        delta: f64,
+        simulate: Simulate,
+    ) -> Vec<ServerEvent> {
+        self.time += delta;
+        for connection in self.clients.values_mut() {
+            connection.command_time = (connection.command_time + delta).min(MAX_COMMAND_TIME);
+        }
+
+        for (address, data) in super::receive_all(&self.socket) {
+            self.handle_packet(world, address, &data, simulate);
+        }
+
+        let time = self.time;
+        self.challenges
+            .retain(|_, challenge| time - challenge.time < CHALLENGE_TIMEOUT);
+        let timed_out: Vec<u32> = self
+            .clients
+            .iter()
+            .filter(|(_, connection)| connection.channel.timed_out(time))
+            .map(|(id, _)| *id)
+            .collect();
+        for client in timed_out {
+            self.disconnect(client, "timed out");
+        }
+
+        self.until_tick -= delta;
+        if self.until_tick <= 0.0 {
+            let interval = 1.0 / self.tick_rate as f64;
+            // Not catching up after a long frame, the next snapshot has everything anyway
+            self.until_tick = (self.until_tick + interval).max(0.0);
+            self.tick += 1;
+            self.send_snapshots(world);
+        }
+
+        mem::take(&mut self.events)
+    }
+
+    // Tells every client the server is going away
+    pub fn shutdown(mut self) {
+        let clients: Vec<u32> = self.clients.keys().copied().collect();
+        for client in clients {
+            self.disconnect(client, "server shutting down");
+        }
+        info!("Server shut down");
+    }
+
+    fn find(&self, address: SocketAddr, salt: u64) -> Option<u32> {
+        self.clients
+            .iter()
+            .find(|(_, connection)| connection.address == address && connection.salt == salt)
+            .map(|(id, _)| *id)
+    }
+
+    fn handle_packet(
+        &mut self,
+        world: &mut legion::World,
+        address: SocketAddr,
+        data: &[u8],
+        simulate: Simulate,
+    ) {
+        let packet = match Packet::read(data) {
+            Some(Ok(packet)) => packet,
+            Some(Err(err)) => {
+                debug!("Bad packet from {address}: {err}");
+                return;
+            }
+            None => return,
+        };
+
+        match packet {
+            Packet::Connect { version, name } => {
+                if self
+                    .clients
+                    .values()
+                    .any(|connection| connection.address == address)
+                {
+                    return;
+                }
+                let reject = if version != PROTOCOL_VERSION {
+                    Some(format!(
+                        "server uses protocol version {PROTOCOL_VERSION}, not {version}"
+                    ))
+                } else if self.clients.len() >= self.max_clients {
+                    Some(String::from("server is full"))
+                } else {
+                    None
+                };
+                if let Some(reason) = reject {
+                    info!("Rejecting {name} from {address}: {reason}");
+                    super::send(&self.socket, address, &Packet::Reject { reason });
+                    return;
+                }
+
+                let time = self.time;
+                let challenge = self.challenges.entry(address).or_insert_with(|| Challenge {
+                    salt: super::random_salt(),
+                    name,
+                    time,
+                });
+                let salt = challenge.salt;
+                super::send(&self.socket, address, &Packet::Challenge { salt });
+            }
+            Packet::Response { salt } => self.accept(address, salt),
+            Packet::Data { salt, channel } => {
+                let Some(client) = self.find(address, salt) else {
+                    return;
+                };
+                let connection = self.clients.get_mut(&client).unwrap();
+                if let Err(err) = connection.channel.read_packet(channel, self.time) {
+                    debug!("Bad packet from client {client}: {err}");
+                    return;
+                }
+                let messages: Vec<Vec<u8>> =
+                    iter::from_fn(|| connection.channel.receive()).collect();
+                for message in messages {
+                    match Message::read(&message) {
+                        Ok(message) => self.handle_message(world, client, message, simulate),
+                        Err(err) => debug!("Bad message from client {client}: {err}"),
+                    }
+                }
+            }
+            Packet::Disconnect { salt, reason } => {
+                if let Some(client) = self.find(address, salt) {
+                    info!("Client {client} left: {reason}");
+                    self.clients.remove(&client);
+                    self.events
+                        .push(ServerEvent::Disconnected { client, reason });
+                }
+            }
+            Packet::Challenge { .. } | Packet::Accept { .. } | Packet::Reject { .. } => {}
+        }
+    }
+
+    fn accept(&mut self, address: SocketAddr, salt: u64) {
+        // The client didn't get the first Accept
+        if let Some(client) = self.find(address, salt) {
+            let tick_rate = self.tick_rate;
+            super::send(
+                &self.socket,
+                address,
+                &Packet::Accept {
+                    salt,
+                    client,
+                    tick_rate,
+                },
+            );
+            return;
+        }
+
+        if self.clients.len() >= self.max_clients {
+            return;
+        }
+        let Some(challenge) = self
+            .challenges
+            .remove(&address)
+            .filter(|challenge| challenge.salt == salt)
+        else {
+            return;
+        };
+
+        let client = self.next_client;
+        self.next_client += 1;
+        let name = challenge.name;
+        info!("Client {client} ({name}) connected from {address}");
+        self.clients.insert(
+            client,
+            Connection {
+                address,
+                name: name.clone(),
+                salt,
+                channel: Channel::new(self.time),
+                player: None,
+                snapshot_ack: None,
+                last_command: None,
+                command_time: 0.0,
+                sent: VecDeque::new(),
+                waiting: HashMap::new(),
+            },
+        );
+        super::send(
+            &self.socket,
+            address,
+            &Packet::Accept {
+                salt,
+                client,
+                tick_rate: self.tick_rate,
+            },
+        );
+        self.events.push(ServerEvent::Connected { client, name });
+    }
+
+    fn handle_message(
+        &mut self,
+        world: &mut legion::World,
+        client: u32,
+        message: Message,
+        simulate: Simulate,
+    ) {
+        let Some(connection) = self.clients.get_mut(&client) else {
+            return;
+        };
+
+        match message {
+            Message::Commands {
+                snapshot_ack,
+                mut commands,
+            } => {
+                // Packets can come out of order, so the newest ack wins
+                if snapshot_ack > connection.snapshot_ack {
+                    connection.snapshot_ack = snapshot_ack;
+                }
+
+                commands.sort_by_key(|command| command.sequence);
+                for mut command in commands {
+                    if connection
+                        .last_command
+                        .is_some_and(|last| command.sequence <= last)
+                    {
+                        continue;
+                    }
+                    // Anything past the time the client has left is cut off
+                    command.clamp_delta();
+                    command.delta = command.delta.min(connection.command_time as f32);
+                    connection.command_time -= command.delta as f64;
+                    if let Some(player) = connection.player.filter(|player| world.contains(*player))
+                    {
+                        simulate(world, player, &command);
+                    }
+                    connection.last_command = Some(command.sequence);
+                }
+            }
+            Message::Game(data) => self.events.push(ServerEvent::Message { client, data }),
+            Message::Snapshot { .. } => debug!("Client {client} sent a snapshot"),
+        }
+    }
+
+    fn send_snapshots(&mut self, world: &legion::World) {
+        let snapshot = Snapshot::from_world(world, self.tick);
+
+        for (client, connection) in &mut self.clients {
+            // Acks only go up, so nothing older will be asked for again
+            if let Some(ack) = connection.snapshot_ack {
+                connection.sent.retain(|old| old.tick >= ack);
+            }
+            let baseline = connection
+                .snapshot_ack
+                .and_then(|tick| connection.sent.iter().find(|old| old.tick == tick));
+            let player = connection
+                .player
+                .and_then(|player| world.entry_ref(player).ok())
+                .and_then(|entry| entry.get_component::<components::NetId>().ok().copied())
+                .map(|id| id.0);
+            let last_command = connection.last_command;
+            let message = |delta| Message::Snapshot {
+                tick: snapshot.tick,
+                baseline: baseline.map(|baseline| baseline.tick),
+                last_command,
+                player,
+                delta,
+            };
+
+            // The player's own entity goes first, then whatever's waited longest
+            let budget = MAX_UNRELIABLE_SIZE.saturating_sub(message(Vec::new()).write().len());
+            let waiting = &connection.waiting;
+            let delta = snapshot.write_delta(baseline, budget, |id| {
+                if player == Some(id) {
+                    u32::MAX
+                } else {
+                    waiting.get(&id).copied().unwrap_or_default()
+                }
+            });
+            let data = message(delta.data).write();
+            connection.waiting = delta
+                .left_out
+                .iter()
+                .map(|id| {
+                    (
+                        *id,
+                        waiting.get(id).map_or(1, |count| count.saturating_add(1)),
+                    )
+                })
+                .collect();
+            if let Err(err) = connection.channel.send_unreliable(data) {
+                debug!(
+                    "Not sending snapshot {} to client {client}: {err}",
+                    self.tick
+                );
+            }
+            connection.sent.push_back(delta.snapshot);
+            while connection.sent.len() > HISTORY {
+                connection.sent.pop_front();
+            }
+
+            let channel = connection.channel.write_packet(self.time);
+            super::send(
+                &self.socket,
+                connection.address,
+                &Packet::Data {
+                    salt: connection.salt,
+                    channel: &channel,
+                },
+            );
+        }
+    }
+
+    fn disconnect(&mut self, client: u32, reason: &str) {
+        let Some(connection) = self.clients.remove(&client) else {
+            return;
+        };
+        info!("Disconnecting client {client}: {reason}");
+        super::send(
+            &self.socket,
+            connection.address,
+            &Packet::Disconnect {
+                salt: connection.salt,
+                reason: String::from(reason),
+            },
+        );
+        self.events.push(ServerEvent::Disconnected {
+            client,
+            reason: String::from(reason),
+        });
+    }
+}
@@ -0,0 +1,480 @@
+// The state of every networked entity on one server tick. Snapshots are sent as a delta from
+// one the client has already acked, so an entity that didn't change costs nothing and one
+// that moved only sends its transform. Without a baseline everything is sent.
+//
+// Deltas have to fit in a packet, so they're written up to a budget and whatever doesn't fit
+// is left for the next one. The server keeps what each client ended up with to write the next
+// delta against, so anything left out is still different from that and goes in a later one.
+//
+//     removed count u16, then a NetId u32 for each
+//     changed count u16, then for each:
+//         NetId u32, changed parts u8, present parts u8
+//         each changed part that's present, in the order of the flags, like in saves
+
+use super::wire::{Reader, Writer};
+use crate::engine::{components, rendersystem::bounds::Aabb, scene::EntityDesc};
+use legion::{Entity, IntoQuery};
+use log::debug;
+use nalgebra::{Point3, Quaternion, UnitQuaternion, Vector3};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+};
+
+const NAME: u8 = 1 << 0;
+const TRANSFORM: u8 = 1 << 1;
+const BOUNDS: u8 = 1 << 2;
+const MODEL: u8 = 1 << 3;
+const PROPERTIES: u8 = 1 << 4;
+const KNOWN_PARTS: u8 = NAME | TRANSFORM | BOUNDS | MODEL | PROPERTIES;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    // By NetId
+    pub entities: BTreeMap<u32, EntityDesc>,
+}
+
+// What Snapshot::write_delta fit in its budget
+pub struct Delta {
+    pub data: Vec<u8>,
+    // What reading data against the baseline gives
+    pub snapshot: Snapshot,
+    // The NetIds of entities with changes that didn't fit
+    pub left_out: Vec<u32>,
+}
+
+// Which parts of an entity are there
+fn present(entity: &EntityDesc) -> u8 {
+    let mut parts = 0;
+    if entity.name.is_some() {
+        parts |= NAME;
+    }
+    if entity.transform.is_some() {
+        parts |= TRANSFORM;
+    }
+    if entity.bounds.is_some() {
+        parts |= BOUNDS;
+    }
+    if entity.model.is_some() {
+        parts |= MODEL;
+    }
+    if !entity.properties.is_empty() {
+        parts |= PROPERTIES;
+    }
+    parts
+}
+
+// Which parts of an entity are different from the baseline's
+fn changed(entity: &EntityDesc, baseline: Option<&EntityDesc>) -> u8 {
+    let Some(baseline) = baseline else {
+        return present(entity);
+    };
+    let mut parts = 0;
+    if entity.name != baseline.name {
+        parts |= NAME;
+    }
+    if entity.transform != baseline.transform {
+        parts |= TRANSFORM;
+    }
+    if entity.bounds != baseline.bounds {
+        parts |= BOUNDS;
+    }
+    if entity.model != baseline.model {
+        parts |= MODEL;
+    }
+    if entity.properties != baseline.properties {
+        parts |= PROPERTIES;
+    }
+    parts
+}
+
+// parts is the ones that changed and are there, the rest are left out
+fn write_entity(writer: &mut Writer, entity: &EntityDesc, parts: u8) -> Result<(), String> {
+    if parts & NAME != 0 {
+        writer.string(entity.name.as_deref().unwrap_or_default())?;
+    }
+    if let Some(transform) = entity.transform.filter(|_| parts & TRANSFORM != 0) {
+        let rotation = transform.rotation.coords;
+        writer.f32s(transform.position.as_slice());
+        writer.f32s(&[rotation.x, rotation.y, rotation.z, rotation.w]);
+        writer.f32s(transform.scale.as_slice());
+    }
+    if let Some(bounds) = entity.bounds.filter(|_| parts & BOUNDS != 0) {
+        writer.f32s(bounds.min.coords.as_slice());
+        writer.f32s(bounds.max.coords.as_slice());
+    }
+    if parts & MODEL != 0 {
+        writer.string(entity.model.as_deref().unwrap_or_default())?;
+    }
+    if parts & PROPERTIES != 0 {
+        let count = u16::try_from(entity.properties.len())
+            .map_err(|_| format!("{} properties is too many", entity.properties.len()))?;
+        writer.u16(count);
+        for (key, value) in &entity.properties {
+            writer.string(key)?;
+            writer.string(value)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Changes the parts of entity that are in changed, to what's in the packet or nothing
+fn read_entity(
+    reader: &mut Reader,
+    entity: &mut EntityDesc,
+    changed: u8,
+    present: u8,
+) -> Result<(), String> {
+    let read = |part: u8| changed & present & part != 0;
+
+    if changed & NAME != 0 {
+        entity.name = if read(NAME) {
+            Some(reader.string()?)
+        } else {
+            None
+        };
+    }
+    if changed & TRANSFORM != 0 {
+        entity.transform = if read(TRANSFORM) {
+            let [px, py, pz, rx, ry, rz, rw, sx, sy, sz] = reader.f32s()?;
+            Some(components::Transform::new(
+                Vector3::new(px, py, pz),
+                UnitQuaternion::from_quaternion(Quaternion::new(rw, rx, ry, rz)),
+                Vector3::new(sx, sy, sz),
+            ))
+        } else {
+            None
+        };
+    }
+    if changed & BOUNDS != 0 {
+        entity.bounds = if read(BOUNDS) {
+            let [min_x, min_y, min_z, max_x, max_y, max_z] = reader.f32s()?;
+            Some(Aabb::new(
+                Point3::new(min_x, min_y, min_z),
+                Point3::new(max_x, max_y, max_z),
+            ))
+        } else {
+            None
+        };
+    }
+    if changed & MODEL != 0 {
+        entity.model = if read(MODEL) {
+            Some(reader.string()?)
+        } else {
+            None
+        };
+    }
+    if changed & PROPERTIES != 0 {
+        entity.properties.clear();
+        if read(PROPERTIES) {
+            for _ in 0..reader.u16()? {
+                let key = reader.string()?;
+                let value = reader.string()?;
+                entity.properties.insert(key, value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl Snapshot {
+    // Every entity with a NetId
+    pub fn from_world(world: &legion::World, tick: u32) -> Self {
+        let mut query = <(
+            &components::NetId,
+            Option<&components::Name>,
+            Option<&components::Transform>,
+            Option<&components::Bounds>,
+            Option<&components::ModelRef>,
+            Option<&components::Properties>,
+        )>::query();
+        let entities = query
+            .iter(world)
+            .map(|(id, name, transform, bounds, model, properties)| {
+                let entity = EntityDesc {
+                    name: name.map(|name| name.0.clone()),
+                    transform: transform.copied(),
+                    bounds: bounds.map(|bounds| bounds.0),
+                    model: model.map(|model| model.0.clone()),
+                    properties: properties
+                        .map(|properties| properties.0.clone())
+                        .unwrap_or_default(),
+                };
+                (id.0, entity)
+            })
+            .collect();
+
+        Self { tick, entities }
+    }
+
+    // Writes as much of the delta from baseline as fits in budget bytes. Removals go first, then
+    // changes with the highest priority. The first change always goes, even if it's too big, so
+    // nothing waits forever.
+    pub fn write_delta(
+        &self,
+        baseline: Option<&Snapshot>,
+        budget: usize,
+        priority: impl Fn(u32) -> u32,
+    ) -> Delta {
+        let mut snapshot = Snapshot {
+            tick: self.tick,
+            entities: baseline
+                .map(|baseline| baseline.entities.clone())
+                .unwrap_or_default(),
+        };
+
+        // The two counts take 4 bytes and each removal is a NetId
+        let room = (budget.saturating_sub(4) / 4).min(u16::MAX as usize);
+        let removed: Vec<u32> = snapshot
+            .entities
+            .keys()
+            .filter(|id| !self.entities.contains_key(id))
+            .take(room)
+            .copied()
+            .collect();
+        for id in &removed {
+            snapshot.entities.remove(id);
+        }
+
+        let mut changes: Vec<(u32, &EntityDesc, u8)> = self
+            .entities
+            .iter()
+            .map(|(id, entity)| {
+                let old = baseline.and_then(|baseline| baseline.entities.get(id));
+                (*id, entity, changed(entity, old))
+            })
+            .filter(|(id, _, parts)| {
+                *parts != 0 || !baseline.is_some_and(|baseline| baseline.entities.contains_key(id))
+            })
+            .collect();
+        // Stable, so ties stay in NetId order
+        changes.sort_by_key(|(id, _, _)| Reverse(priority(*id)));
+
+        let mut size = 4 + removed.len() * 4;
+        let mut written = Writer::new();
+        let mut count = 0;
+        let mut left_out = Vec::new();
+        for (id, entity, parts) in changes {
+            let there = present(entity);
+            let mut writer = Writer::new();
+            writer.u32(id);
+            writer.u8(parts);
+            writer.u8(there);
+            if let Err(err) = write_entity(&mut writer, entity, parts & there) {
+                debug!("Not sending entity {id}: {err}");
+                continue;
+            }
+            if count == u16::MAX as usize || (count > 0 && size + writer.data.len() > budget) {
+                left_out.push(id);
+                continue;
+            }
+            size += writer.data.len();
+            written.bytes(&writer.data);
+            count += 1;
+            snapshot.entities.insert(id, entity.clone());
+        }
+
+        let mut writer = Writer::new();
+        writer.u16(removed.len() as u16);
+        for id in removed {
+            writer.u32(id);
+        }
+        writer.u16(count as u16);
+        writer.bytes(&written.data);
+
+        Delta {
+            data: writer.data,
+            snapshot,
+            left_out,
+        }
+    }
+
+    // baseline has to be the snapshot the delta was written against
+    pub fn read_delta(data: &[u8], tick: u32, baseline: Option<&Snapshot>) -> Result<Self, String> {
+        let mut reader = Reader::new(data);
+        let mut entities = baseline
+            .map(|baseline| baseline.entities.clone())
+            .unwrap_or_default();
+
+        for _ in 0..reader.u16()? {
+            entities.remove(&reader.u32()?);
+        }
+        for _ in 0..reader.u16()? {
+            let id = reader.u32()?;
+            let changed = reader.u8()?;
+            let present = reader.u8()?;
+            if (changed | present) & !KNOWN_PARTS != 0 {
+                return Err(format!("entity {id} has unknown parts {changed:#x}"));
+            }
+            let entity = entities.entry(id).or_default();
+            read_entity(&mut reader, entity, changed, present)
+                .map_err(|err| format!("entity {id}: {err}"))?;
+        }
+        if !reader.is_empty() {
+            return Err(String::from("data left over at the end of the snapshot"));
+        }
+
+        Ok(Self { tick, entities })
+    }
+
+    // Makes world match the snapshot. entities maps NetIds to the entities made for them,
+    // and is kept up to date as they're spawned and despawned.
+    pub fn apply(&self, world: &mut legion::World, entities: &mut HashMap<u32, Entity>) {
+        entities.retain(|id, entity| {
+            let keep = self.entities.contains_key(id) && world.contains(*entity);
+            if !keep {
+                world.remove(*entity);
+            }
+            keep
+        });
+
+        for (id, desc) in &self.entities {
+            let entity = *entities
+                .entry(*id)
+                .or_insert_with(|| world.push((components::NetId(*id),)));
+            let mut entry = world.entry(entity).unwrap();
+            match &desc.name {
+                Some(name) => entry.add_component(components::Name(name.clone())),
+                None => entry.remove_component::<components::Name>(),
+            }
+            match desc.transform {
+                Some(transform) => entry.add_component(transform),
+                None => entry.remove_component::<components::Transform>(),
+            }
+            match desc.bounds {
+                Some(bounds) => entry.add_component(components::Bounds(bounds)),
+                None => entry.remove_component::<components::Bounds>(),
+            }
+            match &desc.model {
+                Some(model) => entry.add_component(components::ModelRef(model.clone())),
+                None => entry.remove_component::<components::ModelRef>(),
+            }
+            if desc.properties.is_empty() {
+                entry.remove_component::<components::Properties>();
+            } else {
+                entry.add_component(components::Properties(desc.properties.clone()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(name: &str, x: f32) -> EntityDesc {
+        EntityDesc {
+            name: Some(String::from(name)),
+            transform: Some(components::Transform::new(
+                Vector3::new(x, 0.0, 0.0),
+                UnitQuaternion::identity(),
+                Vector3::new(1.0, 1.0, 1.0),
+            )),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn delta_round_trips() {
+        let mut old = Snapshot {
+            tick: 1,
+            entities: BTreeMap::new(),
+        };
+        old.entities.insert(1, entity("a", 0.0));
+        old.entities.insert(2, entity("b", 0.0));
+        old.entities.insert(3, entity("c", 0.0));
+
+        let full = old.write_delta(None, 1000, |_| 0);
+        assert!(full.left_out.is_empty());
+        assert_eq!(full.snapshot, old);
+        let client_old = Snapshot::read_delta(&full.data, 1, None).unwrap();
+        assert_eq!(client_old, old);
+
+        let mut new = old.clone();
+        new.tick = 2;
+        new.entities.remove(&2);
+        let moved = new.entities.get_mut(&1).unwrap();
+        moved.transform.as_mut().unwrap().position.x = 5.0;
+        moved.name = None;
+        moved.model = Some(String::from("test"));
+        moved
+            .properties
+            .insert(String::from("health"), String::from("100"));
+        new.entities.insert(4, EntityDesc::default());
+
+        let delta = new.write_delta(Some(&old), 1000, |_| 0);
+        assert_eq!(delta.snapshot, new);
+        assert!(delta.data.len() < full.data.len());
+        let client_new = Snapshot::read_delta(&delta.data, 2, Some(&client_old)).unwrap();
+        assert_eq!(client_new, new);
+
+        // Nothing changed, so it's just the two counts
+        assert_eq!(new.write_delta(Some(&new), 1000, |_| 0).data.len(), 4);
+    }
+
+    #[test]
+    fn deltas_over_budget_carry_over() {
+        const BUDGET: usize = 500;
+        let mut snapshot = Snapshot::default();
+        for id in 0..100 {
+            snapshot
+                .entities
+                .insert(id, entity(&format!("entity {id}"), id as f32));
+        }
+        // Too big for any packet, so it goes on its own
+        snapshot
+            .entities
+            .get_mut(&50)
+            .unwrap()
+            .properties
+            .insert(String::from("big"), "x".repeat(BUDGET * 2));
+
+        let mut server: Option<Snapshot> = None;
+        let mut client: Option<Snapshot> = None;
+        let mut waiting: HashMap<u32, u32> = HashMap::new();
+        let mut left_out = usize::MAX;
+        for tick in 1..100 {
+            snapshot.tick = tick;
+            let delta = snapshot.write_delta(server.as_ref(), BUDGET, |id| {
+                if id == 99 {
+                    u32::MAX
+                } else {
+                    waiting.get(&id).copied().unwrap_or_default()
+                }
+            });
+            if tick == 1 {
+                assert!(delta.snapshot.entities.contains_key(&99));
+            }
+            // Only something too big for any packet goes over, and then on its own
+            let changes = u16::from_le_bytes([delta.data[2], delta.data[3]]);
+            assert!(delta.data.len() <= BUDGET || changes == 1);
+            assert!(delta.left_out.len() < left_out);
+            left_out = delta.left_out.len();
+            waiting = delta
+                .left_out
+                .iter()
+                .map(|id| (*id, waiting.get(id).map_or(1, |count| count + 1)))
+                .collect();
+
+            let read = Snapshot::read_delta(&delta.data, tick, client.as_ref()).unwrap();
+            assert_eq!(read, delta.snapshot);
+            client = Some(read);
+            server = Some(delta.snapshot);
+            if left_out == 0 {
+                break;
+            }
+        }
+        assert_eq!(client.unwrap().entities, snapshot.entities);
+
+        // Removing most of them doesn't fit either
+        let mut fewer = snapshot.clone();
+        fewer.tick += 1;
+        fewer.entities.retain(|id, _| *id < 10);
+        let delta = fewer.write_delta(server.as_ref(), 100, |_| 0);
+        assert!(delta.data.len() <= 100);
+        assert!(delta.snapshot.entities.len() > 10);
+    }
+}
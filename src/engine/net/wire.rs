@@ -0,0 +1,143 @@
+// Reading and writing packets. Everything is little endian, strings are a u16 length then
+// UTF-8, and anything that reads past the end fails instead of panicking, since packets come
+// from anyone.
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offset == self.data.len()
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .offset
+            .checked_add(count)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| format!("unexpected end of packet at offset {}", self.offset))?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    // A u8 for whether it's there, then the value if it is
+    pub fn option_u32(&mut self) -> Result<Option<u32>, String> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => self.u32().map(Some),
+        }
+    }
+
+    pub fn f32s<const N: usize>(&mut self) -> Result<[f32; N], String> {
+        let mut values = [0.0; N];
+        for value in &mut values {
+            *value = f32::from_le_bytes(self.bytes(4)?.try_into().unwrap());
+        }
+        Ok(values)
+    }
+
+    pub fn string(&mut self) -> Result<String, String> {
+        let offset = self.offset;
+        let length = self.u16()? as usize;
+        String::from_utf8(self.bytes(length)?.to_vec())
+            .map_err(|err| format!("invalid string at offset {offset}: {err}"))
+    }
+
+    // A u16 length and that many bytes
+    pub fn blob(&mut self) -> Result<&'a [u8], String> {
+        let length = self.u16()? as usize;
+        self.bytes(length)
+    }
+
+    // The rest of the packet
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.offset..];
+        self.offset = self.data.len();
+        rest
+    }
+}
+
+#[derive(Default)]
+pub struct Writer {
+    pub data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn option_u32(&mut self, value: Option<u32>) {
+        self.u8(value.is_some() as u8);
+        if let Some(value) = value {
+            self.u32(value);
+        }
+    }
+
+    pub fn f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.bytes(&value.to_le_bytes());
+        }
+    }
+
+    // Fails for strings longer than a u16 can say, instead of cutting them off
+    pub fn string(&mut self, value: &str) -> Result<(), String> {
+        self.blob(value.as_bytes())
+    }
+
+    pub fn blob(&mut self, value: &[u8]) -> Result<(), String> {
+        let length = u16::try_from(value.len()).map_err(|_| {
+            format!(
+                "{} bytes is too long to send, the most is {}",
+                value.len(),
+                u16::MAX
+            )
+        })?;
+        self.u16(length);
+        self.bytes(value);
+        Ok(())
+    }
+}
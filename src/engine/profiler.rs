@@ -0,0 +1,292 @@
+// Where the time goes each frame. Code times itself with scopes, which last until they're
+// dropped:
+//
+//     let _scope = profiler::scope("physics");
+//
+// and the render backend adds how long each render graph pass took on the GPU. Frames are kept
+// in a ring buffer, which the developer UI summarizes and save_capture writes out in Chrome's
+// trace format, for chrome://tracing, Perfetto or Speedscope.
+
+use super::DataDirs;
+use chrono::Local;
+use log::info;
+use once_cell::sync::Lazy;
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    fmt::Write,
+    fs,
+    sync::Mutex,
+    thread::{self, ThreadId},
+    time::Instant,
+};
+
+// Frames kept, about 5 seconds at 60 FPS
+pub const HISTORY: usize = 300;
+// Scopes past this in one frame are dropped, in case something times itself in a loop
+const MAX_MARKERS: usize = 4096;
+
+// Times are in milliseconds since the profiler started
+#[derive(Clone, Debug)]
+pub struct CpuMarker {
+    pub name: &'static str,
+    // Index into the thread names
+    pub thread: usize,
+    // How many scopes on the same thread it's inside of
+    pub depth: u32,
+    pub start: f64,
+    pub duration: f64,
+}
+
+// start is in milliseconds since the first pass of the frame started on the GPU
+#[derive(Clone, Debug)]
+pub struct GpuPass {
+    pub name: String,
+    pub start: f64,
+    pub duration: f64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Frame {
+    pub number: u64,
+    pub start: f64,
+    pub duration: f64,
+    pub cpu: Vec<CpuMarker>,
+    // Shows up a few frames later, once the GPU is done with it
+    pub gpu: Vec<GpuPass>,
+}
+
+impl Frame {
+    // Milliseconds from the start of the first pass to the end of the last one
+    pub fn gpu_time(&self) -> f64 {
+        self.gpu
+            .iter()
+            .map(|pass| pass.start + pass.duration)
+            .fold(0.0, f64::max)
+    }
+}
+
+struct Profiler {
+    frames: VecDeque<Frame>,
+    current: Frame,
+    threads: Vec<(ThreadId, String)>,
+}
+
+static START: Lazy<Instant> = Lazy::new(Instant::now);
+static PROFILER: Lazy<Mutex<Profiler>> = Lazy::new(|| {
+    Mutex::new(Profiler {
+        frames: VecDeque::new(),
+        current: Frame::default(),
+        threads: Vec::new(),
+    })
+});
+
+thread_local! {
+    static DEPTH: Cell<u32> = const { Cell::new(0) };
+}
+
+fn now() -> f64 {
+    START.elapsed().as_secs_f64() * 1000.0
+}
+
+// Times from when it's made until it's dropped
+pub struct Scope {
+    name: &'static str,
+    start: f64,
+    depth: u32,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let end = now();
+        DEPTH.with(|depth| depth.set(self.depth));
+
+        let Ok(mut profiler) = PROFILER.lock() else {
+            return;
+        };
+        if profiler.current.cpu.len() >= MAX_MARKERS {
+            return;
+        }
+        let id = thread::current().id();
+        let thread = match profiler.threads.iter().position(|(other, _)| *other == id) {
+            Some(thread) => thread,
+            None => {
+                let name = thread::current()
+                    .name()
+                    .map_or_else(|| format!("{id:?}"), String::from);
+                profiler.threads.push((id, name));
+                profiler.threads.len() - 1
+            }
+        };
+        profiler.current.cpu.push(CpuMarker {
+            name: self.name,
+            thread,
+            depth: self.depth,
+            start: self.start,
+            duration: end - self.start,
+        });
+    }
+}
+
+pub fn scope(name: &'static str) -> Scope {
+    let depth = DEPTH.with(|depth| {
+        let value = depth.get();
+        depth.set(value + 1);
+        value
+    });
+    Scope {
+        name,
+        start: now(),
+        depth,
+    }
+}
+
+// Ends the current frame and starts the next one, called once a frame by engine::State
+pub fn frame() {
+    let Ok(mut profiler) = PROFILER.lock() else {
+        return;
+    };
+    let now = now();
+    let number = profiler.current.number;
+    let mut finished = std::mem::replace(
+        &mut profiler.current,
+        Frame {
+            number: number + 1,
+            start: now,
+            ..Default::default()
+        },
+    );
+    // The first frame is just whatever happened before the game started
+    if number == 0 {
+        return;
+    }
+    finished.duration = now - finished.start;
+    if profiler.frames.len() >= HISTORY {
+        profiler.frames.pop_front();
+    }
+    profiler.frames.push_back(finished);
+}
+
+// GPU timings for the frame frames_ago before the current one
+pub fn gpu_frame(frames_ago: u64, passes: Vec<GpuPass>) {
+    let Ok(mut profiler) = PROFILER.lock() else {
+        return;
+    };
+    let Some(number) = profiler.current.number.checked_sub(frames_ago) else {
+        return;
+    };
+    if let Some(frame) = profiler
+        .frames
+        .iter_mut()
+        .rev()
+        .find(|frame| frame.number == number)
+    {
+        frame.gpu = passes;
+    }
+}
+
+// Every finished frame in the history, oldest first
+pub fn frames() -> Vec<Frame> {
+    PROFILER
+        .lock()
+        .map(|profiler| profiler.frames.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+// Makes text safe to put in a JSON string
+pub fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            character if (character as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", character as u32);
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+// The history in Chrome's trace event format. The CPU is process 1 with a thread for each
+// thread that timed something, and the GPU is process 2. The GPU's clock isn't the CPU's, so
+// each frame's passes are lined up with the start of the frame that recorded them.
+pub fn chrome_trace() -> String {
+    let Ok(profiler) = PROFILER.lock() else {
+        return String::from("{\"traceEvents\":[]}");
+    };
+
+    let mut events = vec![
+        String::from(r#"{"ph":"M","name":"process_name","pid":1,"args":{"name":"CPU"}}"#),
+        String::from(r#"{"ph":"M","name":"process_name","pid":2,"args":{"name":"GPU"}}"#),
+        String::from(r#"{"ph":"M","name":"thread_name","pid":2,"tid":0,"args":{"name":"Queue"}}"#),
+    ];
+    for (index, (_, name)) in profiler.threads.iter().enumerate() {
+        events.push(format!(
+            r#"{{"ph":"M","name":"thread_name","pid":1,"tid":{index},"args":{{"name":"{}"}}}}"#,
+            escape_json(name)
+        ));
+    }
+
+    // Microseconds, which is what the format uses
+    let event = |name: &str, category: &str, pid: u32, tid: usize, start: f64, duration: f64| {
+        format!(
+            r#"{{"ph":"X","name":"{}","cat":"{category}","pid":{pid},"tid":{tid},"ts":{:.3},"dur":{:.3}}}"#,
+            escape_json(name),
+            start * 1000.0,
+            duration * 1000.0
+        )
+    };
+    for frame in &profiler.frames {
+        events.push(event(
+            &format!("Frame {}", frame.number),
+            "frame",
+            1,
+            0,
+            frame.start,
+            frame.duration,
+        ));
+        for marker in &frame.cpu {
+            events.push(event(
+                marker.name,
+                "cpu",
+                1,
+                marker.thread,
+                marker.start,
+                marker.duration,
+            ));
+        }
+        for pass in &frame.gpu {
+            events.push(event(
+                &pass.name,
+                "gpu",
+                2,
+                0,
+                frame.start + pass.start,
+                pass.duration,
+            ));
+        }
+    }
+
+    format!(
+        "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n{}\n]}}\n",
+        events.join(",\n")
+    )
+}
+
+// Writes the history to DataDirs::profiles and returns the path
+pub fn save_capture() -> Result<String, String> {
+    let date = Local::now().format("%Y-%m-%d_%H-%M-%S");
+    let path = format!(
+        "{}{}-{date}.json",
+        DataDirs::profiles(),
+        crate::GAME_EXECUTABLE_NAME
+    );
+    fs::write(&path, chrome_trace()).map_err(|err| format!("failed to write {path}: {err}"))?;
+    info!("Saved profile to {path}");
+    Ok(path)
+}
@@ -0,0 +1,228 @@
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    // Empty if there aren't any points
+    pub fn from_points<'a, I>(points: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Point3<f32>>,
+    {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Self::new(Point3::origin(), Point3::origin());
+        };
+        points.fold(Self::new(*first, *first), |aabb, point| {
+            Self::new(aabb.min.inf(point), aabb.max.sup(point))
+        })
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self::new(self.min.inf(&other.min), self.max.sup(&other.max))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    // Half the size
+    pub fn extents(&self) -> Vector3<f32> {
+        (self.max - self.min) / 2.0
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    pub fn grow(&self, amount: f32) -> Self {
+        let amount = Vector3::repeat(amount);
+        Self::new(self.min - amount, self.max + amount)
+    }
+
+    pub fn contains(&self, other: &Self) -> bool {
+        self.min <= other.min && other.max <= self.max
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min <= other.max && other.min <= self.max
+    }
+
+    // 0 if the point is inside
+    pub fn distance_squared(&self, point: &Point3<f32>) -> f32 {
+        (point.sup(&self.min).inf(&self.max) - point).norm_squared()
+    }
+
+    // How far along the ray it first touches the box, which is 0 if it starts inside
+    pub fn ray_distance(
+        &self,
+        origin: &Point3<f32>,
+        direction: &Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<f32> {
+        let (mut near, mut far) = (0.0_f32, max_distance);
+        for i in 0..3 {
+            // Infinite for rays parallel to this axis, which makes the slab everything or
+            // nothing
+            let inverse = 1.0 / direction[i];
+            let mut entry = (self.min[i] - origin[i]) * inverse;
+            let mut exit = (self.max[i] - origin[i]) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut entry, &mut exit);
+            }
+            near = near.max(entry);
+            far = far.min(exit);
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    // The box around the transformed box, from Graphics Gems' "Transforming Axis-Aligned
+    // Bounding Boxes"
+    pub fn transform(&self, transform: &Matrix4<f32>) -> Self {
+        let translation = Point3::new(transform.m14, transform.m24, transform.m34);
+        let (mut min, mut max) = (translation, translation);
+        for i in 0..3 {
+            for j in 0..3 {
+                let a = transform[(i, j)] * self.min[j];
+                let b = transform[(i, j)] * self.max[j];
+                min[i] += a.min(b);
+                max[i] += a.max(b);
+            }
+        }
+        Self::new(min, max)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Point3<f32>, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    // Centered on the box around the points, which isn't the smallest sphere but is close
+    // enough for culling
+    pub fn from_points<'a, I>(points: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Point3<f32>> + Clone,
+    {
+        let center = Aabb::from_points(points.clone()).center();
+        let radius = points
+            .into_iter()
+            .map(|point| nalgebra::distance(&center, point))
+            .fold(0.0, f32::max);
+        Self::new(center, radius)
+    }
+
+    // Scaled by the largest axis, so non-uniform scales make it bigger than it needs to be
+    pub fn transform(&self, transform: &Matrix4<f32>) -> Self {
+        let scale = (0..3)
+            .map(|column| transform.fixed_view::<3, 1>(0, column).norm())
+            .fold(0.0, f32::max);
+        Self::new(transform.transform_point(&self.center), self.radius * scale)
+    }
+}
+
+// Both, since spheres are quicker to test but boxes are usually tighter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: Sphere,
+}
+
+impl Bounds {
+    pub fn from_points(points: &[Point3<f32>]) -> Self {
+        Self {
+            aabb: Aabb::from_points(points),
+            sphere: Sphere::from_points(points),
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let aabb = self.aabb.union(&other.aabb);
+        let center = aabb.center();
+        let radius = [self.sphere, other.sphere]
+            .iter()
+            .map(|sphere| nalgebra::distance(&center, &sphere.center) + sphere.radius)
+            .fold(0.0, f32::max);
+        Self {
+            aabb,
+            sphere: Sphere::new(center, radius),
+        }
+    }
+
+    pub fn transform(&self, transform: &Matrix4<f32>) -> Self {
+        Self {
+            aabb: self.aabb.transform(transform),
+            sphere: self.sphere.transform(transform),
+        }
+    }
+}
+
+// Planes facing inwards as (normal, distance), in world space if made from a view
+// projection matrix
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // From "Fast Extraction of Viewing Frustum Planes from the World-View-Projection Matrix",
+    // with depth from 0 to 1 like shadow::perspective makes
+    pub fn from_matrix(view_projection: &Matrix4<f32>) -> Self {
+        let row = |i: usize| view_projection.row(i).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            let length = plane.xyz().norm();
+            if length > 0.0 {
+                plane / length
+            } else {
+                plane
+            }
+        });
+        Self { planes }
+    }
+
+    fn distance(plane: &Vector4<f32>, point: &Point3<f32>) -> f32 {
+        plane.xyz().dot(&point.coords) + plane.w
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| Self::distance(plane, &sphere.center) >= -sphere.radius)
+    }
+
+    // Checks the corner furthest along each plane's normal
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let corner = Point3::from(Vector3::from_fn(|i, _| {
+                if plane[i] >= 0.0 {
+                    aabb.max[i]
+                } else {
+                    aabb.min[i]
+                }
+            }));
+            Self::distance(plane, &corner) >= 0.0
+        })
+    }
+
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        self.intersects_sphere(&bounds.sphere) && self.intersects_aabb(&bounds.aabb)
+    }
+}
@@ -0,0 +1,490 @@
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+use std::f32::consts::TAU;
+
+// Text is drawn with lines like a 16 segment display, in a cell this many pixels big
+const GLYPH_WIDTH: f32 = 8.0;
+const GLYPH_HEIGHT: f32 = 12.0;
+const GLYPH_ADVANCE: f32 = 12.0;
+const LINE_HEIGHT: f32 = 18.0;
+const CIRCLE_SEGMENTS: usize = 32;
+
+// Where each segment goes in the glyph cell, from the top left corner
+const SEGMENTS: [((f32, f32), (f32, f32)); 18] = [
+    ((0.0, 0.0), (0.5, 0.0)),
+    ((0.5, 0.0), (1.0, 0.0)),
+    ((1.0, 0.0), (1.0, 0.5)),
+    ((1.0, 0.5), (1.0, 1.0)),
+    ((0.5, 1.0), (1.0, 1.0)),
+    ((0.0, 1.0), (0.5, 1.0)),
+    ((0.0, 0.5), (0.0, 1.0)),
+    ((0.0, 0.0), (0.0, 0.5)),
+    ((0.0, 0.5), (0.5, 0.5)),
+    ((0.5, 0.5), (1.0, 0.5)),
+    ((0.0, 0.0), (0.5, 0.5)),
+    ((0.5, 0.0), (0.5, 0.5)),
+    ((1.0, 0.0), (0.5, 0.5)),
+    ((0.0, 1.0), (0.5, 0.5)),
+    ((0.5, 0.5), (0.5, 1.0)),
+    ((1.0, 1.0), (0.5, 0.5)),
+    ((0.5, 0.85), (0.5, 1.0)),
+    ((0.5, 0.2), (0.5, 0.35)),
+];
+
+const TOP_LEFT: u32 = 1 << 0;
+const TOP_RIGHT: u32 = 1 << 1;
+const RIGHT_UPPER: u32 = 1 << 2;
+const RIGHT_LOWER: u32 = 1 << 3;
+const BOTTOM_RIGHT: u32 = 1 << 4;
+const BOTTOM_LEFT: u32 = 1 << 5;
+const LEFT_LOWER: u32 = 1 << 6;
+const LEFT_UPPER: u32 = 1 << 7;
+const MIDDLE_LEFT: u32 = 1 << 8;
+const MIDDLE_RIGHT: u32 = 1 << 9;
+const DIAGONAL_UPPER_LEFT: u32 = 1 << 10;
+const CENTER_UPPER: u32 = 1 << 11;
+const DIAGONAL_UPPER_RIGHT: u32 = 1 << 12;
+const DIAGONAL_LOWER_LEFT: u32 = 1 << 13;
+const CENTER_LOWER: u32 = 1 << 14;
+const DIAGONAL_LOWER_RIGHT: u32 = 1 << 15;
+const DOT: u32 = 1 << 16;
+const UPPER_DOT: u32 = 1 << 17;
+
+const TOP: u32 = TOP_LEFT | TOP_RIGHT;
+const BOTTOM: u32 = BOTTOM_LEFT | BOTTOM_RIGHT;
+const MIDDLE: u32 = MIDDLE_LEFT | MIDDLE_RIGHT;
+const LEFT: u32 = LEFT_UPPER | LEFT_LOWER;
+const RIGHT: u32 = RIGHT_UPPER | RIGHT_LOWER;
+const CENTER: u32 = CENTER_UPPER | CENTER_LOWER;
+
+// Lowercase letters are drawn as uppercase, anything else unknown is an empty box
+fn glyph(character: char) -> u32 {
+    match character.to_ascii_uppercase() {
+        ' ' => 0,
+        '0' => TOP | RIGHT | BOTTOM | LEFT | DIAGONAL_UPPER_RIGHT | DIAGONAL_LOWER_LEFT,
+        '1' => RIGHT | DIAGONAL_UPPER_RIGHT,
+        '2' => TOP | RIGHT_UPPER | MIDDLE | LEFT_LOWER | BOTTOM,
+        '3' => TOP | RIGHT | MIDDLE_RIGHT | BOTTOM,
+        '4' => LEFT_UPPER | MIDDLE | RIGHT,
+        '5' => TOP | LEFT_UPPER | MIDDLE | RIGHT_LOWER | BOTTOM,
+        '6' => TOP | LEFT | MIDDLE | RIGHT_LOWER | BOTTOM,
+        '7' => TOP | RIGHT,
+        '8' => TOP | RIGHT | MIDDLE | LEFT | BOTTOM,
+        '9' => TOP | LEFT_UPPER | MIDDLE | RIGHT | BOTTOM,
+        'A' => TOP | LEFT | RIGHT | MIDDLE,
+        'B' => TOP | RIGHT | BOTTOM | CENTER | MIDDLE_RIGHT,
+        'C' => TOP | LEFT | BOTTOM,
+        'D' => TOP | RIGHT | BOTTOM | CENTER,
+        'E' => TOP | LEFT | BOTTOM | MIDDLE_LEFT,
+        'F' => TOP | LEFT | MIDDLE_LEFT,
+        'G' => TOP | LEFT | BOTTOM | RIGHT_LOWER | MIDDLE_RIGHT,
+        'H' => LEFT | RIGHT | MIDDLE,
+        'I' => TOP | CENTER | BOTTOM,
+        'J' => RIGHT | BOTTOM | LEFT_LOWER,
+        'K' => LEFT | MIDDLE_LEFT | DIAGONAL_UPPER_RIGHT | DIAGONAL_LOWER_RIGHT,
+        'L' => LEFT | BOTTOM,
+        'M' => LEFT | RIGHT | DIAGONAL_UPPER_LEFT | DIAGONAL_UPPER_RIGHT,
+        'N' => LEFT | RIGHT | DIAGONAL_UPPER_LEFT | DIAGONAL_LOWER_RIGHT,
+        'O' => TOP | RIGHT | BOTTOM | LEFT,
+        'P' => TOP | LEFT | RIGHT_UPPER | MIDDLE,
+        'Q' => TOP | RIGHT | BOTTOM | LEFT | DIAGONAL_LOWER_RIGHT,
+        'R' => TOP | LEFT | RIGHT_UPPER | MIDDLE | DIAGONAL_LOWER_RIGHT,
+        'S' => TOP | LEFT_UPPER | MIDDLE | RIGHT_LOWER | BOTTOM,
+        'T' => TOP | CENTER,
+        'U' => LEFT | RIGHT | BOTTOM,
+        'V' => LEFT | DIAGONAL_LOWER_LEFT | DIAGONAL_UPPER_RIGHT,
+        'W' => LEFT | RIGHT | DIAGONAL_LOWER_LEFT | DIAGONAL_LOWER_RIGHT,
+        'X' => {
+            DIAGONAL_UPPER_LEFT | DIAGONAL_UPPER_RIGHT | DIAGONAL_LOWER_LEFT | DIAGONAL_LOWER_RIGHT
+        }
+        'Y' => DIAGONAL_UPPER_LEFT | DIAGONAL_UPPER_RIGHT | CENTER_LOWER,
+        'Z' => TOP | DIAGONAL_UPPER_RIGHT | DIAGONAL_LOWER_LEFT | BOTTOM,
+        '-' => MIDDLE,
+        '+' => MIDDLE | CENTER,
+        '*' => {
+            MIDDLE
+                | CENTER
+                | DIAGONAL_UPPER_LEFT
+                | DIAGONAL_UPPER_RIGHT
+                | DIAGONAL_LOWER_LEFT
+                | DIAGONAL_LOWER_RIGHT
+        }
+        '=' => MIDDLE | BOTTOM,
+        '_' => BOTTOM,
+        '/' => DIAGONAL_UPPER_RIGHT | DIAGONAL_LOWER_LEFT,
+        '\\' => DIAGONAL_UPPER_LEFT | DIAGONAL_LOWER_RIGHT,
+        '(' | '<' => DIAGONAL_UPPER_RIGHT | DIAGONAL_LOWER_RIGHT,
+        ')' | '>' => DIAGONAL_UPPER_LEFT | DIAGONAL_LOWER_LEFT,
+        '[' => TOP_LEFT | LEFT | BOTTOM_LEFT,
+        ']' => TOP_RIGHT | RIGHT | BOTTOM_RIGHT,
+        '|' => CENTER,
+        '\'' => CENTER_UPPER,
+        '"' => CENTER_UPPER | RIGHT_UPPER,
+        '.' | ',' => DOT,
+        ':' | ';' => DOT | UPPER_DOT,
+        '!' => CENTER_UPPER | DOT,
+        '?' => TOP | RIGHT_UPPER | MIDDLE_RIGHT | DOT,
+        '%' => DIAGONAL_UPPER_RIGHT | DIAGONAL_LOWER_LEFT | TOP_LEFT | BOTTOM_RIGHT,
+        '$' => TOP | LEFT_UPPER | MIDDLE | RIGHT_LOWER | BOTTOM | CENTER,
+        '#' => MIDDLE | CENTER | RIGHT | BOTTOM,
+        _ => TOP | RIGHT | BOTTOM | LEFT,
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DebugOptions {
+    // Seconds to keep drawing it for, 0 is only the next frame
+    pub duration: f32,
+    // Whether the scene can hide it, screen space text never is
+    pub depth_test: bool,
+}
+
+impl Default for DebugOptions {
+    fn default() -> Self {
+        Self {
+            duration: 0.0,
+            depth_test: true,
+        }
+    }
+}
+
+impl DebugOptions {
+    pub fn duration(self, duration: f32) -> Self {
+        Self { duration, ..self }
+    }
+
+    pub fn overlay(self) -> Self {
+        Self {
+            depth_test: false,
+            ..self
+        }
+    }
+}
+
+// Positions are already in clip space, so the shader doesn't have to do anything
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct DebugVertex {
+    position: [f32; 4],
+    color: [f32; 4],
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TextPosition {
+    World(Point3<f32>),
+    // Pixels from the top left corner
+    Screen(f32, f32),
+}
+
+struct DebugLine {
+    from: Point3<f32>,
+    to: Point3<f32>,
+    color: Vector4<f32>,
+    options: DebugOptions,
+}
+
+struct DebugText {
+    position: TextPosition,
+    text: String,
+    color: Vector4<f32>,
+    options: DebugOptions,
+}
+
+// What's left of the frame's debug drawing once it's been turned into lines. Depth tested
+// lines come first.
+pub struct DebugGeometry {
+    pub vertices: Vec<DebugVertex>,
+    pub depth_tested: u32,
+}
+
+// Shapes queued from anywhere, which get drawn over the scene after post processing. Colors
+// are what ends up on screen, without any lighting or tone mapping.
+#[derive(Default)]
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+    texts: Vec<DebugText>,
+}
+
+impl DebugDraw {
+    pub fn line(
+        &mut self,
+        from: Point3<f32>,
+        to: Point3<f32>,
+        color: Vector4<f32>,
+        options: DebugOptions,
+    ) {
+        self.lines.push(DebugLine {
+            from,
+            to,
+            color,
+            options,
+        });
+    }
+
+    // The head is a fifth of the length
+    pub fn arrow(
+        &mut self,
+        from: Point3<f32>,
+        to: Point3<f32>,
+        color: Vector4<f32>,
+        options: DebugOptions,
+    ) {
+        self.line(from, to, color, options);
+
+        let direction = to - from;
+        let length = direction.norm();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let direction = direction / length;
+        let up = if direction.y.abs() < 0.99 {
+            Vector3::y()
+        } else {
+            Vector3::x()
+        };
+        let side = direction.cross(&up).normalize();
+        let up = side.cross(&direction);
+        let head = length * 0.2;
+        let base = to - direction * head;
+        for offset in [side, -side, up, -up] {
+            self.line(to, base + offset * head * 0.5, color, options);
+        }
+    }
+
+    pub fn aabb(
+        &mut self,
+        min: Point3<f32>,
+        max: Point3<f32>,
+        color: Vector4<f32>,
+        options: DebugOptions,
+    ) {
+        let corner = |index: usize| {
+            Point3::new(
+                if index & 1 != 0 { max.x } else { min.x },
+                if index & 2 != 0 { max.y } else { min.y },
+                if index & 4 != 0 { max.z } else { min.z },
+            )
+        };
+        self.box_edges(corner, color, options);
+    }
+
+    // Three circles around the axes
+    pub fn sphere(
+        &mut self,
+        center: Point3<f32>,
+        radius: f32,
+        color: Vector4<f32>,
+        options: DebugOptions,
+    ) {
+        let axes = [
+            (Vector3::x(), Vector3::y()),
+            (Vector3::y(), Vector3::z()),
+            (Vector3::z(), Vector3::x()),
+        ];
+        for (u, v) in axes {
+            let point = |segment: usize| {
+                let angle = segment as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+                center + (u * angle.cos() + v * angle.sin()) * radius
+            };
+            for segment in 0..CIRCLE_SEGMENTS {
+                self.line(point(segment), point(segment + 1), color, options);
+            }
+        }
+    }
+
+    // The volume a view projection matrix (like a camera's or a shadow's) can see
+    pub fn frustum(
+        &mut self,
+        view_projection: &Matrix4<f32>,
+        color: Vector4<f32>,
+        options: DebugOptions,
+    ) {
+        let Some(inverse) = view_projection.try_inverse() else {
+            return;
+        };
+        let corner = |index: usize| {
+            let x = if index & 1 != 0 { 1.0 } else { -1.0 };
+            let y = if index & 2 != 0 { 1.0 } else { -1.0 };
+            let z = if index & 4 != 0 { 1.0 } else { 0.0 };
+            inverse.transform_point(&Point3::new(x, y, z))
+        };
+        self.box_edges(corner, color, options);
+    }
+
+    // X, Y and Z in red, green and blue
+    pub fn axes(&mut self, transform: &Matrix4<f32>, size: f32, options: DebugOptions) {
+        let origin = transform.transform_point(&Point3::origin());
+        let axes = [
+            (Vector3::x(), Vector4::new(1.0, 0.0, 0.0, 1.0)),
+            (Vector3::y(), Vector4::new(0.0, 1.0, 0.0, 1.0)),
+            (Vector3::z(), Vector4::new(0.0, 0.0, 1.0, 1.0)),
+        ];
+        for (axis, color) in axes {
+            let end = transform.transform_point(&Point3::from(axis * size));
+            self.arrow(origin, end, color, options);
+        }
+    }
+
+    // Stays the same size on screen, starting at position
+    pub fn text(
+        &mut self,
+        position: Point3<f32>,
+        text: &str,
+        color: Vector4<f32>,
+        options: DebugOptions,
+    ) {
+        self.texts.push(DebugText {
+            position: TextPosition::World(position),
+            text: String::from(text),
+            color,
+            options,
+        });
+    }
+
+    pub fn screen_text(
+        &mut self,
+        x: f32,
+        y: f32,
+        text: &str,
+        color: Vector4<f32>,
+        options: DebugOptions,
+    ) {
+        self.texts.push(DebugText {
+            position: TextPosition::Screen(x, y),
+            text: String::from(text),
+            color,
+            options: options.overlay(),
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.texts.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.texts.is_empty()
+    }
+
+    fn box_edges<F>(&mut self, corner: F, color: Vector4<f32>, options: DebugOptions)
+    where
+        F: Fn(usize) -> Point3<f32>,
+    {
+        // Corners that differ by one bit share an edge
+        for index in 0..8 {
+            for bit in [1, 2, 4] {
+                if index & bit == 0 {
+                    self.line(corner(index), corner(index | bit), color, options);
+                }
+            }
+        }
+    }
+
+    // Turns everything into lines for this frame, then forgets whatever has run out of time.
+    // Colors get decoded if the backbuffer encodes them, since they're meant to be what's on
+    // screen.
+    pub fn build(
+        &mut self,
+        view_projection: &Matrix4<f32>,
+        screen_size: (u32, u32),
+        srgb_backbuffer: bool,
+        delta: f32,
+    ) -> DebugGeometry {
+        let vertex = |position: Vector4<f32>, color: &Vector4<f32>| DebugVertex {
+            position: position.into(),
+            color: if srgb_backbuffer {
+                [
+                    srgb_decode(color.x),
+                    srgb_decode(color.y),
+                    srgb_decode(color.z),
+                    color.w,
+                ]
+            } else {
+                (*color).into()
+            },
+        };
+
+        let (width, height) = (screen_size.0 as f32, screen_size.1 as f32);
+        let mut passes = [Vec::new(), Vec::new()];
+        for line in &self.lines {
+            let vertices = &mut passes[!line.options.depth_test as usize];
+            vertices.push(vertex(
+                view_projection * line.from.to_homogeneous(),
+                &line.color,
+            ));
+            vertices.push(vertex(
+                view_projection * line.to.to_homogeneous(),
+                &line.color,
+            ));
+        }
+        for text in &self.texts {
+            let anchor = match text.position {
+                TextPosition::World(position) => view_projection * position.to_homogeneous(),
+                TextPosition::Screen(x, y) => {
+                    Vector4::new(x / width * 2.0 - 1.0, y / height * 2.0 - 1.0, 0.0, 1.0)
+                }
+            };
+            // Behind the camera
+            if anchor.w <= 0.0 {
+                continue;
+            }
+
+            // Pixel offsets are scaled by w so they stay the same after the divide
+            let pixel = |x: f32, y: f32| {
+                anchor
+                    + Vector4::new(
+                        x / width * 2.0 * anchor.w,
+                        y / height * 2.0 * anchor.w,
+                        0.0,
+                        0.0,
+                    )
+            };
+            let vertices = &mut passes[!text.options.depth_test as usize];
+            let (mut x, mut y) = (0.0, 0.0);
+            for character in text.text.chars() {
+                if character == '\n' {
+                    x = 0.0;
+                    y += LINE_HEIGHT;
+                    continue;
+                }
+
+                let segments = glyph(character);
+                for (index, (start, end)) in SEGMENTS.iter().enumerate() {
+                    if segments & (1 << index) != 0 {
+                        for (segment_x, segment_y) in [start, end] {
+                            vertices.push(vertex(
+                                pixel(x + segment_x * GLYPH_WIDTH, y + segment_y * GLYPH_HEIGHT),
+                                &text.color,
+                            ));
+                        }
+                    }
+                }
+                x += GLYPH_ADVANCE;
+            }
+        }
+
+        for line in &mut self.lines {
+            line.options.duration -= delta;
+        }
+        for text in &mut self.texts {
+            text.options.duration -= delta;
+        }
+        self.lines.retain(|line| line.options.duration > 0.0);
+        self.texts.retain(|text| text.options.duration > 0.0);
+
+        let [mut vertices, overlay] = passes;
+        let depth_tested = vertices.len() as u32;
+        vertices.extend(overlay);
+        DebugGeometry {
+            vertices,
+            depth_tested,
+        }
+    }
+}
+
+fn srgb_decode(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
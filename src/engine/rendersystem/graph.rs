@@ -0,0 +1,532 @@
+use log::{error, trace};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceHandle(usize);
+
+impl ResourceHandle {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextureFormat {
+    Rgba8,
+    Rgba16Float,
+    R16Float,
+    R32Float,
+    // Whatever the backend picked for depth buffers
+    Depth,
+    // Same as the swap chain
+    Surface,
+}
+
+impl TextureFormat {
+    pub fn is_depth(&self) -> bool {
+        *self == Self::Depth
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureSize {
+    Backbuffer,
+    Scaled(f32),
+    Fixed(u32, u32),
+}
+
+impl TextureSize {
+    pub fn resolve(&self, backbuffer_size: (u32, u32)) -> (u32, u32) {
+        let (width, height) = match *self {
+            Self::Backbuffer => backbuffer_size,
+            Self::Scaled(scale) => (
+                (backbuffer_size.0 as f32 * scale) as u32,
+                (backbuffer_size.1 as f32 * scale) as u32,
+            ),
+            Self::Fixed(width, height) => (width, height),
+        };
+        (width.max(1), height.max(1))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureDesc {
+    pub format: TextureFormat,
+    pub size: TextureSize,
+    pub layers: u32,
+    pub samples: u32,
+}
+
+impl TextureDesc {
+    pub fn new(format: TextureFormat, size: TextureSize) -> Self {
+        Self {
+            format,
+            size,
+            layers: 1,
+            samples: 1,
+        }
+    }
+
+    pub fn layers(self, layers: u32) -> Self {
+        Self { layers, ..self }
+    }
+
+    pub fn samples(self, samples: u32) -> Self {
+        Self { samples, ..self }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceDesc {
+    Backbuffer,
+    Texture(TextureDesc),
+    Buffer(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceState {
+    Undefined,
+    ColorAttachment,
+    DepthAttachment,
+    DepthRead,
+    ShaderRead,
+    StorageRead,
+    StorageWrite,
+    IndirectRead,
+    TransferSrc,
+    TransferDst,
+    Present,
+}
+
+impl ResourceState {
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Self::ColorAttachment | Self::DepthAttachment | Self::StorageWrite | Self::TransferDst
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadOp {
+    Load,
+    // Depth attachments use the first component
+    Clear([f32; 4]),
+    DontCare,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Attachment {
+    pub resource: ResourceHandle,
+    pub load: LoadOp,
+    // Renders to one layer of an array texture instead of all of them
+    pub layer: Option<u32>,
+    // Single sampled texture a multisampled attachment gets resolved into at the end of the pass
+    pub resolve: Option<ResourceHandle>,
+}
+
+impl Attachment {
+    // Writing one layer leaves the others alone, so earlier passes still matter
+    fn overwrites(&self) -> bool {
+        self.load != LoadOp::Load && self.layer.is_none()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Transition {
+    pub resource: ResourceHandle,
+    pub state: ResourceState,
+    // The old contents aren't needed, so the backend can skip preserving them
+    pub discard: bool,
+}
+
+struct Resource {
+    name: String,
+    desc: ResourceDesc,
+    history: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Usage {
+    resource: ResourceHandle,
+    state: ResourceState,
+    overwrite: bool,
+}
+
+pub struct Pass<'a> {
+    name: String,
+    color_attachments: Vec<Attachment>,
+    depth_attachment: Option<Attachment>,
+    depth_write: bool,
+    usages: Vec<Usage>,
+    side_effects: bool,
+    callback: Option<Box<dyn FnMut(&mut super::State) + 'a>>,
+}
+
+impl<'a> Pass<'a> {
+    fn attachment_usages(&self) -> Vec<Usage> {
+        let mut usages: Vec<Usage> = self
+            .color_attachments
+            .iter()
+            .map(|attachment| Usage {
+                resource: attachment.resource,
+                state: ResourceState::ColorAttachment,
+                overwrite: attachment.overwrites(),
+            })
+            .collect();
+        if let Some(attachment) = self.depth_attachment {
+            usages.push(Usage {
+                resource: attachment.resource,
+                state: if self.depth_write {
+                    ResourceState::DepthAttachment
+                } else {
+                    ResourceState::DepthRead
+                },
+                overwrite: self.depth_write && attachment.overwrites(),
+            });
+        }
+
+        // Resolves replace the whole target
+        usages.extend(
+            self.color_attachments
+                .iter()
+                .filter_map(|attachment| attachment.resolve)
+                .map(|resource| Usage {
+                    resource,
+                    state: ResourceState::ColorAttachment,
+                    overwrite: true,
+                }),
+        );
+        usages.extend(
+            self.depth_attachment
+                .and_then(|attachment| attachment.resolve)
+                .map(|resource| Usage {
+                    resource,
+                    state: ResourceState::DepthAttachment,
+                    overwrite: true,
+                }),
+        );
+
+        usages
+    }
+}
+
+pub struct PassBuilder<'g, 'a> {
+    pass: &'g mut Pass<'a>,
+}
+
+impl<'g, 'a> PassBuilder<'g, 'a> {
+    pub fn color(self, resource: ResourceHandle, load: LoadOp) -> Self {
+        self.pass.color_attachments.push(Attachment {
+            resource,
+            load,
+            layer: None,
+            resolve: None,
+        });
+        self
+    }
+
+    pub fn depth(self, resource: ResourceHandle, load: LoadOp) -> Self {
+        self.pass.depth_attachment = Some(Attachment {
+            resource,
+            load,
+            layer: None,
+            resolve: None,
+        });
+        self.pass.depth_write = true;
+        self
+    }
+
+    pub fn depth_layer(self, resource: ResourceHandle, layer: u32, load: LoadOp) -> Self {
+        self.pass.depth_attachment = Some(Attachment {
+            resource,
+            load,
+            layer: Some(layer),
+            resolve: None,
+        });
+        self.pass.depth_write = true;
+        self
+    }
+
+    // Depth testing against an earlier pass's depth without writing to it
+    pub fn depth_read_only(self, resource: ResourceHandle) -> Self {
+        self.pass.depth_attachment = Some(Attachment {
+            resource,
+            load: LoadOp::Load,
+            layer: None,
+            resolve: None,
+        });
+        self.pass.depth_write = false;
+        self
+    }
+
+    // Resolves a multisampled color or depth attachment of this pass into target, which has
+    // to be the same format with one sample
+    pub fn resolve(self, attachment: ResourceHandle, target: ResourceHandle) -> Self {
+        let found = self
+            .pass
+            .color_attachments
+            .iter_mut()
+            .chain(self.pass.depth_attachment.iter_mut())
+            .find(|existing| existing.resource == attachment);
+        match found {
+            Some(existing) => existing.resolve = Some(target),
+            None => error!(
+                "Render pass {} resolves {attachment:?}, which isn't one of its attachments",
+                self.pass.name
+            ),
+        }
+        self
+    }
+
+    pub fn sample(self, resource: ResourceHandle) -> Self {
+        self.usage(resource, ResourceState::ShaderRead, false)
+    }
+
+    pub fn read_buffer(self, resource: ResourceHandle) -> Self {
+        self.usage(resource, ResourceState::StorageRead, false)
+    }
+
+    pub fn write_buffer(self, resource: ResourceHandle) -> Self {
+        self.usage(resource, ResourceState::StorageWrite, false)
+    }
+
+    pub fn indirect(self, resource: ResourceHandle) -> Self {
+        self.usage(resource, ResourceState::IndirectRead, false)
+    }
+
+    pub fn copy_from(self, resource: ResourceHandle) -> Self {
+        self.usage(resource, ResourceState::TransferSrc, false)
+    }
+
+    pub fn copy_to(self, resource: ResourceHandle) -> Self {
+        self.usage(resource, ResourceState::TransferDst, true)
+    }
+
+    // Keeps the pass even if nothing reads what it writes
+    pub fn side_effects(self) -> Self {
+        self.pass.side_effects = true;
+        self
+    }
+
+    pub fn execute<F>(self, callback: F)
+    where
+        F: FnMut(&mut super::State) + 'a,
+    {
+        self.pass.callback = Some(Box::new(callback));
+    }
+
+    fn usage(self, resource: ResourceHandle, state: ResourceState, overwrite: bool) -> Self {
+        self.pass.usages.push(Usage {
+            resource,
+            state,
+            overwrite,
+        });
+        self
+    }
+}
+
+pub struct CompiledResource {
+    pub name: String,
+    pub desc: ResourceDesc,
+    // Has to be the same image every frame, see RenderGraph::history_texture
+    pub history: bool,
+    pub first_pass: usize,
+    pub last_pass: usize,
+}
+
+pub struct CompiledPass {
+    pub index: usize,
+    pub name: String,
+    pub transitions: Vec<Transition>,
+    pub color_attachments: Vec<Attachment>,
+    pub depth_attachment: Option<Attachment>,
+    pub depth_write: bool,
+    // In the order the pass declared them, for binding
+    pub sampled: Vec<ResourceHandle>,
+}
+
+pub struct CompiledGraph {
+    pub passes: Vec<CompiledPass>,
+    // Indexed by ResourceHandle, None if no pass that survived culling uses it
+    pub resources: Vec<Option<CompiledResource>>,
+    pub final_transitions: Vec<Transition>,
+}
+
+pub struct RenderGraph<'a> {
+    resources: Vec<Resource>,
+    passes: Vec<Pass<'a>>,
+    backbuffer: ResourceHandle,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            resources: vec![Resource {
+                name: String::from("backbuffer"),
+                desc: ResourceDesc::Backbuffer,
+                history: false,
+            }],
+            passes: Vec::new(),
+            backbuffer: ResourceHandle(0),
+        }
+    }
+
+    pub fn backbuffer(&self) -> ResourceHandle {
+        self.backbuffer
+    }
+
+    pub fn create_texture(&mut self, name: &str, desc: TextureDesc) -> ResourceHandle {
+        self.add_resource(name, ResourceDesc::Texture(desc), false)
+    }
+
+    // Keeps its contents from one frame to the next, so a pass can read what the last frame
+    // wrote. The name is what identifies it between frames, and it starts out as zero.
+    pub fn history_texture(&mut self, name: &str, desc: TextureDesc) -> ResourceHandle {
+        self.add_resource(name, ResourceDesc::Texture(desc), true)
+    }
+
+    pub fn create_buffer(&mut self, name: &str, size: u64) -> ResourceHandle {
+        self.add_resource(name, ResourceDesc::Buffer(size), false)
+    }
+
+    pub fn add_pass<'g>(&'g mut self, name: &str) -> PassBuilder<'g, 'a> {
+        self.passes.push(Pass {
+            name: String::from(name),
+            color_attachments: Vec::new(),
+            depth_attachment: None,
+            depth_write: false,
+            usages: Vec::new(),
+            side_effects: false,
+            callback: None,
+        });
+        PassBuilder {
+            pass: self.passes.last_mut().unwrap(),
+        }
+    }
+
+    fn add_resource(&mut self, name: &str, desc: ResourceDesc, history: bool) -> ResourceHandle {
+        self.resources.push(Resource {
+            name: String::from(name),
+            desc,
+            history,
+        });
+        ResourceHandle(self.resources.len() - 1)
+    }
+
+    pub fn compile(&self) -> CompiledGraph {
+        // Walk backwards from the backbuffer and history textures to find the passes that
+        // actually contribute to them, anything that gets overwritten before it's read
+        // doesn't count
+        let mut needed: Vec<bool> = self
+            .resources
+            .iter()
+            .map(|resource| resource.history)
+            .collect();
+        needed[self.backbuffer.0] = true;
+        let mut alive = vec![false; self.passes.len()];
+        for (i, pass) in self.passes.iter().enumerate().rev() {
+            let usages: Vec<Usage> = pass
+                .attachment_usages()
+                .into_iter()
+                .chain(pass.usages.iter().copied())
+                .collect();
+
+            alive[i] = pass.side_effects
+                || usages
+                    .iter()
+                    .any(|usage| usage.state.is_write() && needed[usage.resource.0]);
+            if !alive[i] {
+                trace!("Culling render pass {}", pass.name);
+                continue;
+            }
+
+            for usage in usages.iter().filter(|usage| usage.overwrite) {
+                needed[usage.resource.0] = false;
+            }
+            for usage in usages.iter().filter(|usage| !usage.overwrite) {
+                needed[usage.resource.0] = true;
+            }
+        }
+
+        let mut states = vec![ResourceState::Undefined; self.resources.len()];
+        let mut resources: Vec<Option<CompiledResource>> =
+            self.resources.iter().map(|_| None).collect();
+        let mut passes = Vec::new();
+        for (i, pass) in self.passes.iter().enumerate().filter(|(i, _)| alive[*i]) {
+            let compiled_index = passes.len();
+            let mut transitions = Vec::new();
+            for usage in pass.attachment_usages().iter().chain(pass.usages.iter()) {
+                let resource = usage.resource.0;
+                let previous = states[resource];
+                let history = self.resources[resource].history;
+                // Writes always need a barrier, even without a layout change
+                if previous != usage.state || usage.state.is_write() {
+                    transitions.push(Transition {
+                        resource: usage.resource,
+                        state: usage.state,
+                        discard: usage.overwrite
+                            || (previous == ResourceState::Undefined && !history),
+                    });
+                }
+                states[resource] = usage.state;
+
+                match &mut resources[resource] {
+                    Some(compiled) => compiled.last_pass = compiled_index,
+                    None => {
+                        resources[resource] = Some(CompiledResource {
+                            name: self.resources[resource].name.clone(),
+                            desc: self.resources[resource].desc,
+                            history,
+                            first_pass: compiled_index,
+                            last_pass: compiled_index,
+                        })
+                    }
+                }
+            }
+
+            passes.push(CompiledPass {
+                index: i,
+                name: pass.name.clone(),
+                transitions,
+                color_attachments: pass.color_attachments.clone(),
+                depth_attachment: pass.depth_attachment,
+                depth_write: pass.depth_write,
+                sampled: pass
+                    .usages
+                    .iter()
+                    .filter(|usage| usage.state == ResourceState::ShaderRead)
+                    .map(|usage| usage.resource)
+                    .collect(),
+            });
+        }
+
+        let mut final_transitions = Vec::new();
+        if resources[self.backbuffer.0].is_some() {
+            final_transitions.push(Transition {
+                resource: self.backbuffer,
+                state: ResourceState::Present,
+                discard: false,
+            });
+        }
+
+        CompiledGraph {
+            passes,
+            resources,
+            final_transitions,
+        }
+    }
+
+    pub fn execute(mut self, state: &mut super::State) {
+        let compiled = self.compile();
+
+        state.backend.begin_graph(&compiled);
+        for pass in &compiled.passes {
+            state.backend.begin_pass(&compiled, pass);
+            if let Some(callback) = self.passes[pass.index].callback.as_mut() {
+                callback(state);
+            }
+            state.backend.end_pass(&compiled, pass);
+        }
+        state.backend.end_graph(&compiled);
+    }
+}
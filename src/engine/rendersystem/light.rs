@@ -0,0 +1,201 @@
+use super::{Renderable, State};
+use nalgebra::{Matrix4, Point3, Vector3};
+
+pub const MAX_LIGHTS: usize = 16;
+
+// Lights shine down -Z of the transform they're rendered with, and spot and point lights sit
+// at its origin
+
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionalLight {
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    pub cast_shadows: bool,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            color: Vector3::new(1.0, 1.0, 1.0),
+            intensity: 1.0,
+            cast_shadows: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SpotLight {
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    pub range: f32,
+    // Half angles in radians, light fades out between them
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    pub cast_shadows: bool,
+}
+
+impl Default for SpotLight {
+    fn default() -> Self {
+        Self {
+            color: Vector3::new(1.0, 1.0, 1.0),
+            intensity: 1.0,
+            range: 10.0,
+            inner_angle: 20f32.to_radians(),
+            outer_angle: 30f32.to_radians(),
+            cast_shadows: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    pub range: f32,
+    pub cast_shadows: bool,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        Self {
+            color: Vector3::new(1.0, 1.0, 1.0),
+            intensity: 1.0,
+            range: 10.0,
+            cast_shadows: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Spot {
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    Point {
+        range: f32,
+    },
+}
+
+impl LightKind {
+    // Matches the light types in the shaders
+    fn index(&self) -> f32 {
+        match self {
+            Self::Directional => 0.0,
+            Self::Spot { .. } => 1.0,
+            Self::Point { .. } => 2.0,
+        }
+    }
+}
+
+// A light in world space, queued for the current frame
+#[derive(Clone, Copy, Debug)]
+pub struct QueuedLight {
+    pub kind: LightKind,
+    pub position: Point3<f32>,
+    pub direction: Vector3<f32>,
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    pub cast_shadows: bool,
+}
+
+impl QueuedLight {
+    fn new(
+        kind: LightKind,
+        color: Vector3<f32>,
+        intensity: f32,
+        cast_shadows: bool,
+        transform: &Matrix4<f32>,
+    ) -> Self {
+        Self {
+            kind,
+            position: transform.transform_point(&Point3::origin()),
+            direction: transform.transform_vector(&-Vector3::z()).normalize(),
+            color,
+            intensity,
+            cast_shadows,
+        }
+    }
+
+    // First shadow matrix is -1 if the light has no shadow map this frame
+    pub fn data(&self, first_shadow_matrix: i32, shadow_layer: u32) -> LightData {
+        let (range, cos_inner, cos_outer) = match self.kind {
+            LightKind::Directional => (0.0, 0.0, 0.0),
+            LightKind::Spot {
+                range,
+                inner_angle,
+                outer_angle,
+            } => (range, inner_angle.cos(), outer_angle.cos()),
+            LightKind::Point { range } => (range, 0.0, 0.0),
+        };
+
+        LightData {
+            position_range: [self.position.x, self.position.y, self.position.z, range],
+            direction_type: [
+                self.direction.x,
+                self.direction.y,
+                self.direction.z,
+                self.kind.index(),
+            ],
+            color_intensity: [self.color.x, self.color.y, self.color.z, self.intensity],
+            cone_shadow: [
+                cos_inner,
+                cos_outer,
+                first_shadow_matrix as f32,
+                shadow_layer as f32,
+            ],
+        }
+    }
+}
+
+// Laid out like the Light struct in the shaders
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct LightData {
+    position_range: [f32; 4],
+    direction_type: [f32; 4],
+    color_intensity: [f32; 4],
+    cone_shadow: [f32; 4],
+}
+
+impl Renderable for DirectionalLight {
+    fn render(&self, state: &mut State, transform: &Matrix4<f32>) {
+        state.queue_light(QueuedLight::new(
+            LightKind::Directional,
+            self.color,
+            self.intensity,
+            self.cast_shadows,
+            transform,
+        ));
+    }
+}
+
+impl Renderable for SpotLight {
+    fn render(&self, state: &mut State, transform: &Matrix4<f32>) {
+        state.queue_light(QueuedLight::new(
+            LightKind::Spot {
+                range: self.range,
+                inner_angle: self.inner_angle,
+                outer_angle: self.outer_angle.max(self.inner_angle),
+            },
+            self.color,
+            self.intensity,
+            self.cast_shadows,
+            transform,
+        ));
+    }
+}
+
+impl Renderable for PointLight {
+    fn render(&self, state: &mut State, transform: &Matrix4<f32>) {
+        state.queue_light(QueuedLight::new(
+            LightKind::Point { range: self.range },
+            self.color,
+            self.intensity,
+            self.cast_shadows,
+            transform,
+        ));
+    }
+}
@@ -0,0 +1,196 @@
+use nalgebra::{Vector3, Vector4};
+
+pub const MAX_MATERIALS: usize = 1024;
+pub const MAX_TEXTURES: usize = 1024;
+pub const MATERIAL_EXTENSION: &str = "mat";
+
+// Which texture is which in MaterialData and the shaders
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureSlot {
+    BaseColor,
+    Normal,
+    MetallicRoughness,
+    Occlusion,
+    Emissive,
+}
+
+impl TextureSlot {
+    pub const ALL: [Self; 5] = [
+        Self::BaseColor,
+        Self::Normal,
+        Self::MetallicRoughness,
+        Self::Occlusion,
+        Self::Emissive,
+    ];
+
+    // Colors are stored in sRGB, everything else is data
+    pub fn srgb(&self) -> bool {
+        matches!(self, Self::BaseColor | Self::Emissive)
+    }
+}
+
+// A metallic-roughness material, which is read from a text file that looks like this:
+//
+//     # Comments start with a hash
+//     shader = basic
+//     base_color = 1.0 1.0 1.0 1.0
+//     base_color_map = bricks/albedo.png
+//     normal_map = bricks/normal.png
+//     normal_scale = 1.0
+//     metallic = 0.0
+//     roughness = 1.0
+//     metallic_roughness_map = bricks/orm.png
+//     occlusion_map = bricks/orm.png
+//     occlusion_strength = 1.0
+//     emissive = 0.0 0.0 0.0
+//     emissive_map = bricks/emissive.png
+//
+// Everything is optional. Maps are relative to the game's texture directory and multiply
+// the matching factor. Metalness is read from blue and roughness from green, like glTF.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialDesc {
+    pub shader: String,
+    pub base_color: Vector4<f32>,
+    pub base_color_map: Option<String>,
+    pub normal_map: Option<String>,
+    pub normal_scale: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub metallic_roughness_map: Option<String>,
+    pub occlusion_map: Option<String>,
+    pub occlusion_strength: f32,
+    pub emissive: Vector3<f32>,
+    pub emissive_map: Option<String>,
+}
+
+impl Default for MaterialDesc {
+    fn default() -> Self {
+        Self {
+            shader: String::from("basic"),
+            base_color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            base_color_map: None,
+            normal_map: None,
+            normal_scale: 1.0,
+            metallic: 0.0,
+            roughness: 1.0,
+            metallic_roughness_map: None,
+            occlusion_map: None,
+            occlusion_strength: 1.0,
+            emissive: Vector3::zeros(),
+            emissive_map: None,
+        }
+    }
+}
+
+impl MaterialDesc {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut desc = Self::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {message}", number + 1);
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected key = value"));
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            let numbers = || {
+                value
+                    .split_whitespace()
+                    .map(|number| number.parse::<f32>())
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map_err(|err| error(&format!("invalid number in {key}: {err}")))
+            };
+            let scalar = || match numbers()?.as_slice() {
+                [value] => Ok(*value),
+                _ => Err(error(&format!("{key} takes one number"))),
+            };
+            let path = || {
+                if value.is_empty() {
+                    Err(error(&format!("{key} needs a path")))
+                } else {
+                    Ok(Some(String::from(value)))
+                }
+            };
+
+            match key {
+                "shader" => desc.shader = String::from(value),
+                "base_color" => {
+                    desc.base_color = match numbers()?.as_slice() {
+                        [r, g, b] => Vector4::new(*r, *g, *b, 1.0),
+                        [r, g, b, a] => Vector4::new(*r, *g, *b, *a),
+                        _ => return Err(error("base_color takes three or four numbers")),
+                    }
+                }
+                "base_color_map" => desc.base_color_map = path()?,
+                "normal_map" => desc.normal_map = path()?,
+                "normal_scale" => desc.normal_scale = scalar()?,
+                "metallic" => desc.metallic = scalar()?,
+                "roughness" => desc.roughness = scalar()?,
+                "metallic_roughness_map" => desc.metallic_roughness_map = path()?,
+                "occlusion_map" => desc.occlusion_map = path()?,
+                "occlusion_strength" => desc.occlusion_strength = scalar()?,
+                "emissive" => {
+                    desc.emissive = match numbers()?.as_slice() {
+                        [r, g, b] => Vector3::new(*r, *g, *b),
+                        _ => return Err(error("emissive takes three numbers")),
+                    }
+                }
+                "emissive_map" => desc.emissive_map = path()?,
+                _ => return Err(error(&format!("unknown key {key}"))),
+            }
+        }
+
+        Ok(desc)
+    }
+
+    pub fn map(&self, slot: TextureSlot) -> Option<&String> {
+        match slot {
+            TextureSlot::BaseColor => self.base_color_map.as_ref(),
+            TextureSlot::Normal => self.normal_map.as_ref(),
+            TextureSlot::MetallicRoughness => self.metallic_roughness_map.as_ref(),
+            TextureSlot::Occlusion => self.occlusion_map.as_ref(),
+            TextureSlot::Emissive => self.emissive_map.as_ref(),
+        }
+    }
+
+    // Texture indices are filled in separately once the maps are loaded
+    pub fn data(&self) -> MaterialData {
+        MaterialData {
+            base_color: self.base_color.into(),
+            emissive_normal_scale: [
+                self.emissive.x,
+                self.emissive.y,
+                self.emissive.z,
+                self.normal_scale,
+            ],
+            metallic_roughness_occlusion: [
+                self.metallic,
+                self.roughness,
+                self.occlusion_strength,
+                0.0,
+            ],
+            textures: [0; 8],
+        }
+    }
+}
+
+// Laid out like the Material struct in the shaders, std430
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct MaterialData {
+    base_color: [f32; 4],
+    emissive_normal_scale: [f32; 4],
+    metallic_roughness_occlusion: [f32; 4],
+    textures: [u32; 8],
+}
+
+impl MaterialData {
+    pub fn set_texture(&mut self, slot: TextureSlot, texture: u32) {
+        self.textures[slot as usize] = texture;
+    }
+}
@@ -1,7 +1,17 @@
-use log::{error, info};
+use crate::engine::profiler;
+use log::{debug, error, info, warn};
 use nalgebra::*;
-use std::{any::Any, mem};
+use std::{any::Any, collections::HashMap, mem, time::Instant};
 
+pub mod bounds;
+pub mod debug_draw;
+pub mod graph;
+pub mod light;
+pub mod material;
+pub mod post;
+pub mod shadow;
+pub mod skinned;
+pub mod ui;
 #[cfg(not(any(target_os = "macos", target_os = "ios", xbox)))]
 mod vulkan;
 
@@ -10,9 +20,44 @@ pub trait RenderBackend {
     fn init(video: &Box<dyn crate::platform::video::VideoBackend>) -> Box<dyn RenderBackend>
     where
         Self: Sized;
-    fn load_resources(&mut self, models: &Vec<u8>);
+    fn load_resources(&mut self);
+    fn create_mesh(
+        &mut self,
+        vertices: &[u8],
+        vertex_stride: usize,
+        indices: &[u32],
+    ) -> Result<MeshHandle, String>;
+    fn destroy_mesh(&mut self, mesh: MeshHandle);
+    fn create_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        srgb: bool,
+    ) -> Result<TextureHandle, String>;
+    fn destroy_texture(&mut self, texture: TextureHandle);
     fn begin_commands(&mut self, video: &Box<dyn crate::platform::video::VideoBackend>);
-    fn render_model(&mut self, model: &Model);
+    fn begin_graph(&mut self, graph: &graph::CompiledGraph);
+    fn begin_pass(&mut self, graph: &graph::CompiledGraph, pass: &graph::CompiledPass);
+    fn end_pass(&mut self, graph: &graph::CompiledGraph, pass: &graph::CompiledPass);
+    fn end_graph(&mut self, graph: &graph::CompiledGraph);
+    // How long each pass took in a frame the GPU finished since the last call, and how many
+    // frames ago that was
+    fn gpu_timings(&mut self) -> Option<(u64, Vec<profiler::GpuPass>)>;
+    fn update_uniforms(&mut self, uniforms: &UniformData);
+    fn update_materials(&mut self, materials: &[material::MaterialData]);
+    // Skinning matrices for every skinned instance this frame, which they index into
+    fn update_joints(&mut self, joints: &[Matrix4<f32>]);
+    fn prepare_batches(&mut self, batches: &[DrawBatch], instances: &[InstanceData]);
+    fn draw_batches(&mut self, batches: &[DrawBatch], view_projection: &Matrix4<f32>);
+    // One triangle covering the whole pass, with the constants pushed for the fragment shader
+    fn draw_fullscreen(&mut self, shader: &dyn ShaderData, constants: &[f32; 16]);
+    fn prepare_debug(&mut self, vertices: &[debug_draw::DebugVertex]);
+    // Lines from the frame's prepare_debug vertices
+    fn draw_debug(&mut self, shader: &dyn ShaderData, first_vertex: u32, vertex_count: u32);
+    fn prepare_ui(&mut self, vertices: &[ui::UiVertex], indices: &[u32]);
+    // Draws from the frame's prepare_ui buffers over the whole backbuffer
+    fn draw_ui(&mut self, shader: &dyn ShaderData, draws: &[ui::UiDraw]);
     fn present(&mut self);
     fn unload_resources(&mut self);
     fn shutdown(&mut self);
@@ -20,8 +65,49 @@ pub trait RenderBackend {
     fn is_initialized(&self) -> bool;
     fn is_loaded(&self) -> bool;
     fn is_in_frame(&self) -> bool;
+    fn backbuffer_size(&self) -> (u32, u32);
+    // Sample counts that work for both color and depth attachments, each one is a bit
+    fn sample_counts(&self) -> u32;
+    // Whether writes to the backbuffer get encoded to sRGB by the hardware
+    fn surface_is_srgb(&self) -> bool;
+    fn set_indirect_drawing(&mut self, enabled: bool) -> bool;
+    fn memory_stats(&self) -> MemoryStats;
 
     fn create_shader(&self, shader_path: &String, name: &String) -> Result<Box<dyn ShaderData>, String>;
+    // Uses the built in fullscreen triangle vertex shader with name's fragment shader
+    fn create_fullscreen_shader(
+        &self,
+        shader_dir: &String,
+        name: &String,
+    ) -> Result<Box<dyn ShaderData>, String>;
+    // Draws lines in clip space, without depth_test it's drawn over everything
+    fn create_debug_shader(
+        &self,
+        shader_dir: &String,
+        depth_test: bool,
+    ) -> Result<Box<dyn ShaderData>, String>;
+    fn create_ui_shader(&self, shader_dir: &String) -> Result<Box<dyn ShaderData>, String>;
+    // Uses the built in skinning vertex shader with name's fragment shader
+    fn create_skinned_shader(
+        &self,
+        shader_dir: &String,
+        name: &String,
+    ) -> Result<Box<dyn ShaderData>, String>;
+}
+
+// What a shader's pipeline takes as input, and how it draws
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderKind {
+    // Vertices from the mesh heap and InstanceData, depth tested and culled
+    Mesh,
+    // Like Mesh, but with SkinnedVertex moved by the frame's joint matrices
+    SkinnedMesh,
+    // One triangle made from the vertex index, without depth
+    Fullscreen,
+    // Colored lines with DebugVertex, blended and not written to depth
+    Debug { depth_test: bool },
+    // Textured triangles with UiVertex, blended with premultiplied alpha
+    Ui,
 }
 
 #[derive(Clone, Debug)]
@@ -66,10 +152,195 @@ impl std::fmt::Display for RenderApi {
     }
 }
 
+// Slots are reused once a mesh is destroyed, so handles also have the slot's generation to
+// tell a destroyed mesh from whatever took its place
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshHandle {
+    index: usize,
+    generation: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ShaderHandle(usize);
+
+// Also the texture's index in the shaders' texture array
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextureHandle(usize);
+
+// Also the material's index in the material buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialHandle(usize);
+
+// Models switch to their first lower detail level below this much of the screen's height
+const LOD_COVERAGE: f32 = 0.5;
+// How far past a switching point things have to go before switching back
+const LOD_HYSTERESIS: f32 = 0.1;
+
+struct DrawItem {
+    shader: ShaderHandle,
+    mesh: MeshHandle,
+    material: MaterialHandle,
+    transform: Matrix4<f32>,
+    // In world space, None is never culled
+    bounds: Option<bounds::Bounds>,
+    // Where the instance's skinning matrices start in the frame's joints
+    first_joint: Option<u32>,
+}
+
+// What the instance buffer holds for each instance
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct InstanceData {
+    transform: Matrix4<f32>,
+    material: u32,
+    first_joint: u32,
+    padding: [u32; 2],
+}
+
+#[derive(Clone, Copy)]
+struct QueuedBatch {
+    shader: ShaderHandle,
+    mesh: MeshHandle,
+    skinned: bool,
+    first_instance: u32,
+    instance_count: u32,
+}
+
+// Every instance of the same mesh drawn with the same shader, with transforms and materials at
+// first_instance..first_instance + instance_count in the instance buffer. Each pass draws
+// the same batches in the same order as the frame's prepare_batches, but can swap the shader.
+// The shader is None if it was destroyed since, and the batch stays in its place so the rest
+// still line up with their indirect commands.
+pub struct DrawBatch<'a> {
+    shader: Option<&'a dyn ShaderData>,
+    mesh: MeshHandle,
+    first_instance: u32,
+    instance_count: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DrawStats {
+    pub instances: usize,
+    pub batches: usize,
+    // Draws that weren't visible from the camera or any shadow map
+    pub culled: usize,
+}
+
+// Bytes and live objects in one kind of GPU memory use, counted as they're created and
+// destroyed
+#[derive(Clone, Debug, Default)]
+pub struct MemoryUsage {
+    pub name: &'static str,
+    pub bytes: u64,
+    pub count: usize,
+}
+
+// What the driver says a memory heap has room for, usage is the whole process's
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapBudget {
+    pub size: u64,
+    pub usage: u64,
+    pub budget: u64,
+    pub device_local: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStats {
+    pub categories: Vec<MemoryUsage>,
+    pub heaps: Vec<HeapBudget>,
+    // What the backend's allocator handed out, out of the blocks it got from the driver
+    pub allocated: u64,
+    pub reserved: u64,
+    pub mesh_heap_used: u64,
+    pub mesh_heap_capacity: u64,
+    pub staging_capacity: u64,
+}
+
+impl MemoryStats {
+    pub fn to_json(&self) -> String {
+        let categories: Vec<String> = self
+            .categories
+            .iter()
+            .map(|category| {
+                format!(
+                    r#"    {{"name":"{}","bytes":{},"count":{}}}"#,
+                    category.name, category.bytes, category.count
+                )
+            })
+            .collect();
+        let heaps: Vec<String> = self
+            .heaps
+            .iter()
+            .map(|heap| {
+                format!(
+                    r#"    {{"size":{},"usage":{},"budget":{},"device_local":{}}}"#,
+                    heap.size, heap.usage, heap.budget, heap.device_local
+                )
+            })
+            .collect();
+        format!(
+            "{{\n  \"allocated\":{},\n  \"reserved\":{},\n  \"mesh_heap\":{{\"used\":{},\"capacity\":{}}},\n  \"staging_capacity\":{},\n  \"categories\":[\n{}\n  ],\n  \"heaps\":[\n{}\n  ]\n}}\n",
+            self.allocated,
+            self.reserved,
+            self.mesh_heap_used,
+            self.mesh_heap_capacity,
+            self.staging_capacity,
+            categories.join(",\n"),
+            heaps.join(",\n")
+        )
+    }
+}
+
+fn as_bytes<T>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, mem::size_of_val(data)) }
+}
+
 pub struct State {
     render_api: RenderApi,
     backend: Box<dyn RenderBackend>,
-    models: Vec<u8>,
+
+    shaders: Vec<Option<Box<dyn ShaderData>>>,
+    free_shaders: Vec<usize>,
+    // Shaders loaded by name for materials, which stay around until shutdown
+    shader_cache: HashMap<String, ShaderHandle>,
+
+    materials: Vec<Option<MaterialEntry>>,
+    material_data: Vec<material::MaterialData>,
+    free_materials: Vec<usize>,
+    material_names: HashMap<String, MaterialHandle>,
+    // Stand-ins for maps a material doesn't have
+    white_texture: Option<TextureHandle>,
+    flat_normal_texture: Option<TextureHandle>,
+    default_material: Option<MaterialHandle>,
+
+    uniforms: UniformData,
+    draws: Vec<DrawItem>,
+    batches: Vec<QueuedBatch>,
+    joints: Vec<Matrix4<f32>>,
+    draw_stats: DrawStats,
+    msaa_samples: u32,
+    culling: bool,
+    lod_bias: f32,
+
+    lights: Vec<light::QueuedLight>,
+    shadow_settings: shadow::ShadowSettings,
+    // For meshes and skinned meshes
+    shadow_shaders: Option<(ShaderHandle, ShaderHandle)>,
+
+    post_settings: post::PostSettings,
+    // Without these the scene is drawn straight to the backbuffer
+    post_shaders: Option<post::PostShaders>,
+    // For exposure adaptation and debug drawing durations
+    last_frame: Option<Instant>,
+
+    debug_draw: debug_draw::DebugDraw,
+    // Depth tested and overlay
+    debug_shaders: Option<(ShaderHandle, ShaderHandle)>,
+
+    ui_meshes: Vec<ui::UiMesh>,
+    // Drawn after the rest of the UI, for engine tools
+    ui_overlay_meshes: Vec<ui::UiMesh>,
+    ui_shader: Option<ShaderHandle>,
 }
 
 impl State {
@@ -87,41 +358,1022 @@ impl State {
         };
         info!("Render system initialization succeeded");
 
-        Self {
+        let mut self_ = Self {
             render_api,
             backend,
-            models: Vec::new(),
+            shaders: Vec::new(),
+            free_shaders: Vec::new(),
+            shader_cache: HashMap::new(),
+            materials: Vec::new(),
+            material_data: Vec::new(),
+            free_materials: Vec::new(),
+            material_names: HashMap::new(),
+            white_texture: None,
+            flat_normal_texture: None,
+            default_material: None,
+            uniforms: UniformData::default(),
+            draws: Vec::new(),
+            batches: Vec::new(),
+            joints: Vec::new(),
+            draw_stats: DrawStats::default(),
+            msaa_samples: 1,
+            culling: true,
+            lod_bias: 0.0,
+            lights: Vec::new(),
+            shadow_settings: shadow::ShadowSettings::default(),
+            shadow_shaders: None,
+            post_settings: post::PostSettings::default(),
+            post_shaders: None,
+            last_frame: None,
+            debug_draw: debug_draw::DebugDraw::default(),
+            debug_shaders: None,
+            ui_meshes: Vec::new(),
+            ui_overlay_meshes: Vec::new(),
+            ui_shader: None,
+        };
+        self_.white_texture = self_.create_default_texture("white", [0xFF, 0xFF, 0xFF, 0xFF]);
+        self_.flat_normal_texture =
+            self_.create_default_texture("flat normal", [0x80, 0x80, 0xFF, 0xFF]);
+
+        self_
+    }
+
+    fn create_default_texture(&mut self, name: &str, pixel: [u8; 4]) -> Option<TextureHandle> {
+        match self.backend.create_texture(1, 1, &pixel, false) {
+            Ok(texture) => Some(texture),
+            Err(err) => {
+                error!("Failed to create {name} texture: {err}");
+                None
+            }
         }
     }
 
+    // Meshes can be created and destroyed at any time, this just waits for pending uploads
     pub fn load_resources(&mut self) {
-        if self.backend.is_initialized() && !self.backend.is_loaded() {
+        if self.backend.is_initialized() {
             info!("Loading resources");
-            self.backend.load_resources(&mut self.models);
+            self.backend.load_resources();
             info!("Done loading resources");
         }
     }
 
     pub fn begin_commands(&mut self, video: &Box<dyn crate::platform::video::VideoBackend>) {
-        self.backend.begin_commands(video)
+        self.backend.begin_commands(video);
+        if let Some((frames_ago, passes)) = self.backend.gpu_timings() {
+            profiler::gpu_frame(frames_ago, passes);
+        }
+    }
+
+    // The shadow, post processing, debug and UI shaders and the material used for meshes
+    // without one are engine data rather than something a game provides
+    pub fn load_builtin_resources(&mut self, shader_dir: &str) {
+        match self.cached_shader(shader_dir, "shadow").and_then(|shader| {
+            self.cached_skinned_shader(shader_dir, "shadow")
+                .map(|skinned| (shader, skinned))
+        }) {
+            Ok(shaders) => self.shadow_shaders = Some(shaders),
+            Err(err) => error!("Failed to load shadow shaders, shadows are disabled: {err}"),
+        }
+
+        match post::PostShaders::load(|name| self.fullscreen_shader(shader_dir, name)) {
+            Ok(shaders) => self.post_shaders = Some(shaders),
+            Err(err) => error!(
+                "Failed to load post processing shaders, rendering straight to the screen: {err}"
+            ),
+        }
+
+        let shader_path = String::from(shader_dir);
+        let debug_shader = |state: &mut Self, depth_test| {
+            let data = state
+                .backend
+                .create_debug_shader(&shader_path, depth_test)?;
+            Ok::<_, String>(state.add_shader(data))
+        };
+        match debug_shader(self, true).and_then(|depth_tested| {
+            debug_shader(self, false).map(|overlay| (depth_tested, overlay))
+        }) {
+            Ok(shaders) => self.debug_shaders = Some(shaders),
+            Err(err) => error!("Failed to load debug shaders, debug drawing is disabled: {err}"),
+        }
+
+        match self.backend.create_ui_shader(&shader_path) {
+            Ok(data) => self.ui_shader = Some(self.add_shader(data)),
+            Err(err) => error!("Failed to load UI shader, UI is disabled: {err}"),
+        }
+
+        match self.create_material(
+            "default",
+            &material::MaterialDesc::default(),
+            shader_dir,
+            "",
+        ) {
+            Ok(material) => self.default_material = Some(material),
+            Err(err) => error!("Failed to create default material: {err}"),
+        }
+    }
+
+    fn cached_shader(&mut self, shader_dir: &str, name: &str) -> Result<ShaderHandle, String> {
+        if let Some(shader) = self.shader_cache.get(name) {
+            return Ok(*shader);
+        }
+
+        let name = String::from(name);
+        let data = self
+            .backend
+            .create_shader(&format!("{shader_dir}{name}"), &name)?;
+        let shader = self.add_shader(data);
+        self.shader_cache.insert(name, shader);
+        Ok(shader)
+    }
+
+    fn cached_skinned_shader(
+        &mut self,
+        shader_dir: &str,
+        name: &str,
+    ) -> Result<ShaderHandle, String> {
+        let key = format!("{name} skinned");
+        if let Some(shader) = self.shader_cache.get(&key) {
+            return Ok(*shader);
+        }
+
+        let data = self
+            .backend
+            .create_skinned_shader(&String::from(shader_dir), &String::from(name))?;
+        let shader = self.add_shader(data);
+        self.shader_cache.insert(key, shader);
+        Ok(shader)
+    }
+
+    fn fullscreen_shader(&mut self, shader_dir: &str, name: &str) -> Result<ShaderHandle, String> {
+        let data = self
+            .backend
+            .create_fullscreen_shader(&String::from(shader_dir), &String::from(name))?;
+        Ok(self.add_shader(data))
+    }
+
+    // Materials are shared by name, so this just adds a reference if one called name exists
+    fn create_material(
+        &mut self,
+        name: &str,
+        desc: &material::MaterialDesc,
+        shader_dir: &str,
+        texture_dir: &str,
+    ) -> Result<MaterialHandle, String> {
+        if let Some(material) = self.acquire_material(name) {
+            return Ok(material);
+        }
+
+        let index = self
+            .free_materials
+            .last()
+            .copied()
+            .unwrap_or(self.materials.len());
+        if index >= material::MAX_MATERIALS {
+            error!(
+                "Not creating material {name} because there are already {} of them",
+                material::MAX_MATERIALS
+            );
+            return Err(String::from("too many materials"));
+        }
+
+        info!("Creating material {name}");
+        let shader = self.cached_shader(shader_dir, &desc.shader)?;
+        let skinned_shader = match self.cached_skinned_shader(shader_dir, &desc.shader) {
+            Ok(shader) => Some(shader),
+            Err(err) => {
+                warn!("Material {name} can't be used on skinned meshes: {err}");
+                None
+            }
+        };
+
+        let mut data = desc.data();
+        let mut textures: Vec<RenderTexture> = Vec::new();
+        for slot in material::TextureSlot::ALL {
+            let texture = match desc.map(slot) {
+                Some(path) => {
+                    let full_path = format!("{texture_dir}{path}");
+                    let texture = super::vfs::open(&full_path)
+                        .and_then(|data| {
+                            image::load_from_memory(&data).map_err(|err| err.to_string())
+                        })
+                        .map_err(|err| format!("failed to load {full_path}: {err}"))
+                        .and_then(|image| {
+                            RenderTexture::new(self, path, &image.to_rgba8(), slot.srgb())
+                        });
+                    match texture {
+                        Ok(texture) => {
+                            let handle = texture.handle;
+                            textures.push(texture);
+                            Some(handle)
+                        }
+                        Err(err) => {
+                            error!("Failed to create material {name}: {err}");
+                            for texture in textures {
+                                texture.destroy(self);
+                            }
+                            return Err(err);
+                        }
+                    }
+                }
+                None if slot == material::TextureSlot::Normal => self.flat_normal_texture,
+                None => self.white_texture,
+            };
+            data.set_texture(slot, texture.map_or(0, |texture| texture.0 as u32));
+        }
+
+        let entry = MaterialEntry {
+            name: String::from(name),
+            refs: 1,
+            shader,
+            skinned_shader,
+            textures,
+        };
+        let material = match self.free_materials.pop() {
+            Some(index) => {
+                self.materials[index] = Some(entry);
+                self.material_data[index] = data;
+                MaterialHandle(index)
+            }
+            None => {
+                self.materials.push(Some(entry));
+                self.material_data.push(data);
+                MaterialHandle(self.materials.len() - 1)
+            }
+        };
+        self.material_names.insert(String::from(name), material);
+
+        Ok(material)
+    }
+
+    fn acquire_material(&mut self, name: &str) -> Option<MaterialHandle> {
+        let material = *self.material_names.get(name)?;
+        self.materials[material.0].as_mut().unwrap().refs += 1;
+        Some(material)
+    }
+
+    fn release_material(&mut self, material: MaterialHandle) {
+        let Some(entry) = self
+            .materials
+            .get_mut(material.0)
+            .and_then(|entry| entry.as_mut())
+        else {
+            return;
+        };
+
+        entry.refs -= 1;
+        if entry.refs == 0 {
+            let entry = self.materials[material.0].take().unwrap();
+            info!("Destroying material {}", entry.name);
+            for texture in entry.textures {
+                texture.destroy(self);
+            }
+            self.material_names.remove(&entry.name);
+            self.material_data[material.0] = material::MaterialData::default();
+            self.free_materials.push(material.0);
+        }
+    }
+
+    fn material_shader(&self, material: MaterialHandle) -> Option<ShaderHandle> {
+        self.materials
+            .get(material.0)
+            .and_then(|entry| entry.as_ref())
+            .map(|entry| entry.shader)
+    }
+
+    fn material_skinned_shader(&self, material: MaterialHandle) -> Option<ShaderHandle> {
+        self.materials
+            .get(material.0)
+            .and_then(|entry| entry.as_ref())
+            .and_then(|entry| entry.skinned_shader)
     }
 
     pub fn present(&mut self) {
+        if self.backend.is_in_frame() {
+            let _scope = profiler::scope("render graph");
+            self.build_graph().execute(self);
+        }
+        self.draws.clear();
+        self.joints.clear();
+        self.lights.clear();
+        self.ui_meshes.clear();
+        self.ui_overlay_meshes.clear();
+        let _scope = profiler::scope("present");
         self.backend.present()
     }
 
+    fn build_graph<'a>(&mut self) -> graph::RenderGraph<'a> {
+        let shadow_views = self.prepare_lights();
+        self.prepare_draws(&shadow_views);
+
+        let now = Instant::now();
+        let delta = self
+            .last_frame
+            .map_or(0.0, |last_frame| (now - last_frame).as_secs_f32());
+        self.last_frame = Some(now);
+
+        let mut graph = graph::RenderGraph::new();
+        let backbuffer = graph.backbuffer();
+        let depth = graph.create_texture(
+            "depth",
+            graph::TextureDesc::new(graph::TextureFormat::Depth, graph::TextureSize::Backbuffer)
+                .samples(self.msaa_samples),
+        );
+
+        let settings = self.shadow_settings;
+        let mut shadow_map = |name, resolution, layers: usize| {
+            graph.create_texture(
+                name,
+                graph::TextureDesc::new(
+                    graph::TextureFormat::Depth,
+                    graph::TextureSize::Fixed(resolution, resolution),
+                )
+                .layers(layers as u32),
+            )
+        };
+        let cascades = shadow_map(
+            "cascade shadows",
+            settings.cascade_resolution,
+            shadow::CASCADE_COUNT,
+        );
+        let spot_shadows = shadow_map(
+            "spot shadows",
+            settings.spot_resolution,
+            shadow::MAX_SPOT_SHADOWS,
+        );
+        let point_shadows = shadow_map(
+            "point shadows",
+            settings.point_resolution,
+            shadow::MAX_POINT_SHADOWS * 6,
+        );
+
+        if let Some(shadow_shaders) = self.shadow_shaders {
+            for view in shadow_views {
+                let target = match view.map {
+                    shadow::ShadowMap::Cascades => cascades,
+                    shadow::ShadowMap::Spot => spot_shadows,
+                    shadow::ShadowMap::Point => point_shadows,
+                };
+                graph
+                    .add_pass(&format!("{:?} shadow {}", view.map, view.layer))
+                    .depth_layer(target, view.layer, graph::LoadOp::Clear([1.0, 0.0, 0.0, 0.0]))
+                    .execute(move |state| {
+                        state.draw_scene(Some(shadow_shaders), &view.view_projection)
+                    });
+            }
+        }
+
+        // Lighting is done in linear HDR, which post processing brings down to the backbuffer
+        let (color, color_format) = match self.post_shaders {
+            Some(_) => {
+                let desc = graph::TextureDesc::new(
+                    graph::TextureFormat::Rgba16Float,
+                    graph::TextureSize::Backbuffer,
+                );
+                (graph.create_texture("hdr color", desc), desc.format)
+            }
+            None => (backbuffer, graph::TextureFormat::Surface),
+        };
+        // With MSAA the scene is drawn to a multisampled target that gets resolved into color
+        let scene_color = if self.msaa_samples > 1 {
+            graph.create_texture(
+                "multisampled color",
+                graph::TextureDesc::new(color_format, graph::TextureSize::Backbuffer)
+                    .samples(self.msaa_samples),
+            )
+        } else {
+            color
+        };
+
+        // Debug lines are depth tested against a single sample depth buffer, which MSAA needs
+        // a resolved copy of
+        let view_projection = self.uniforms.projection * self.uniforms.view;
+        let debug = self.prepare_debug(&view_projection, delta);
+        let debug_depth = if debug.is_some() && self.msaa_samples > 1 {
+            graph.create_texture(
+                "resolved depth",
+                graph::TextureDesc::new(
+                    graph::TextureFormat::Depth,
+                    graph::TextureSize::Backbuffer,
+                ),
+            )
+        } else {
+            depth
+        };
+
+        // The order things are sampled in is the order shaders see them in
+        let mut scene = graph
+            .add_pass("scene")
+            .color(scene_color, graph::LoadOp::Clear([0.0, 0.0, 0.0, 1.0]))
+            .depth(depth, graph::LoadOp::Clear([1.0, 0.0, 0.0, 0.0]))
+            .sample(cascades)
+            .sample(spot_shadows)
+            .sample(point_shadows);
+        if scene_color != color {
+            scene = scene.resolve(scene_color, color);
+        }
+        if debug_depth != depth {
+            scene = scene.resolve(depth, debug_depth);
+        }
+        scene.execute(move |state| state.draw_scene(None, &view_projection));
+
+        if let Some(shaders) = &self.post_shaders {
+            post::add_passes(
+                &mut graph,
+                shaders,
+                &self.post_settings,
+                color,
+                delta,
+                self.backend.surface_is_srgb(),
+            );
+        }
+
+        // Drawn last so post processing doesn't change the colors
+        if let Some((shaders, depth_tested)) = debug {
+            graph
+                .add_pass("debug")
+                .color(backbuffer, graph::LoadOp::Load)
+                .depth_read_only(debug_depth)
+                .execute(move |state| state.draw_debug(shaders, depth_tested));
+        }
+
+        // UI goes over everything, including debug drawing
+        self.ui_meshes.append(&mut self.ui_overlay_meshes);
+        if let Some(ui_shader) = self.ui_shader {
+            let (vertices, indices, draws) =
+                ui::batch(&self.ui_meshes, self.backend.backbuffer_size());
+            if !draws.is_empty() {
+                self.backend.prepare_ui(&vertices, &indices);
+                graph
+                    .add_pass("ui")
+                    .color(backbuffer, graph::LoadOp::Load)
+                    .execute(move |state| state.draw_ui(ui_shader, &draws));
+            }
+        }
+
+        graph
+    }
+
+    // Uploads the frame's debug lines, returning the shaders and how many vertices are depth
+    // tested if there's anything to draw
+    fn prepare_debug(
+        &mut self,
+        view_projection: &Matrix4<f32>,
+        delta: f32,
+    ) -> Option<((ShaderHandle, ShaderHandle), (u32, u32))> {
+        let Some(shaders) = self.debug_shaders else {
+            self.debug_draw.clear();
+            return None;
+        };
+        if self.debug_draw.is_empty() {
+            return None;
+        }
+
+        let geometry = self.debug_draw.build(
+            view_projection,
+            self.backend.backbuffer_size(),
+            self.backend.surface_is_srgb(),
+            delta,
+        );
+        if geometry.vertices.is_empty() {
+            return None;
+        }
+        self.backend.prepare_debug(&geometry.vertices);
+        Some((
+            shaders,
+            (
+                geometry.depth_tested,
+                geometry.vertices.len() as u32 - geometry.depth_tested,
+            ),
+        ))
+    }
+
+    fn draw_ui(&mut self, shader: ShaderHandle, draws: &[ui::UiDraw]) {
+        let data = self.shaders.get(shader.0).and_then(|data| data.as_deref());
+        match data {
+            Some(data) => self.backend.draw_ui(data, draws),
+            None => error!("Skipping UI draw with destroyed shader {shader:?}"),
+        }
+    }
+
+    fn draw_debug(&mut self, shaders: (ShaderHandle, ShaderHandle), counts: (u32, u32)) {
+        let (depth_tested, overlay) = counts;
+        for (shader, first_vertex, vertex_count) in [
+            (shaders.0, 0, depth_tested),
+            (shaders.1, depth_tested, overlay),
+        ] {
+            if vertex_count == 0 {
+                continue;
+            }
+            let data = self.shaders.get(shader.0).and_then(|data| data.as_deref());
+            match data {
+                Some(data) => self.backend.draw_debug(data, first_vertex, vertex_count),
+                None => error!("Skipping debug draw with destroyed shader {shader:?}"),
+            }
+        }
+    }
+
+    pub fn set_view(&mut self, view: Matrix4<f32>, projection: Matrix4<f32>) {
+        self.uniforms.view = view;
+        self.uniforms.projection = projection;
+        let camera = view
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity)
+            .transform_point(&Point3::origin());
+        self.uniforms.camera_position = [camera.x, camera.y, camera.z, 1.0];
+    }
+
+    // The ray from the camera through a point on the screen in pixels, for picking. It goes
+    // from the near plane at 0 to the far plane at 1.
+    pub fn screen_ray(&self, x: f32, y: f32) -> Option<(Point3<f32>, Vector3<f32>)> {
+        let (width, height) = self.backbuffer_size();
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let inverse = (self.uniforms.projection * self.uniforms.view).try_inverse()?;
+        // Vulkan's y goes down like the screen's
+        let x = x / width as f32 * 2.0 - 1.0;
+        let y = y / height as f32 * 2.0 - 1.0;
+        let near = inverse.transform_point(&Point3::new(x, y, 0.0));
+        let far = inverse.transform_point(&Point3::new(x, y, 1.0));
+        Some((near, far - near))
+    }
+
+    pub fn set_ambient_light(&mut self, color: Vector3<f32>) {
+        self.uniforms.ambient = [color.x, color.y, color.z, 1.0];
+    }
+
+    pub fn shadow_settings(&mut self) -> &mut shadow::ShadowSettings {
+        &mut self.shadow_settings
+    }
+
+    // Takes effect next frame
+    pub fn post_settings(&mut self) -> &mut post::PostSettings {
+        &mut self.post_settings
+    }
+
+    pub fn debug_draw(&mut self) -> &mut debug_draw::DebugDraw {
+        &mut self.debug_draw
+    }
+
+    // Drawn this frame over everything else
+    pub fn queue_ui(&mut self, mesh: ui::UiMesh) {
+        self.ui_meshes.push(mesh);
+    }
+
+    // Drawn over the game's UI no matter when it's queued
+    pub fn queue_ui_overlay(&mut self, mesh: ui::UiMesh) {
+        self.ui_overlay_meshes.push(mesh);
+    }
+
+    // For textures that don't come from files, like font atlases. Pixels are RGBA8.
+    pub fn create_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        srgb: bool,
+    ) -> Result<TextureHandle, String> {
+        self.backend.create_texture(width, height, pixels, srgb)
+    }
+
+    pub fn destroy_texture(&mut self, texture: TextureHandle) {
+        self.backend.destroy_texture(texture);
+    }
+
+    pub fn backbuffer_size(&self) -> (u32, u32) {
+        self.backend.backbuffer_size()
+    }
+
+    fn queue_light(&mut self, light: light::QueuedLight) {
+        if self.backend.is_in_frame() {
+            self.lights.push(light);
+        }
+    }
+
+    // Fills in the light uniforms and works out which shadow maps need rendering
+    fn prepare_lights(&mut self) -> Vec<shadow::ShadowView> {
+        if self.lights.len() > light::MAX_LIGHTS {
+            debug!(
+                "Dropping {} light(s) over the limit of {}",
+                self.lights.len() - light::MAX_LIGHTS,
+                light::MAX_LIGHTS
+            );
+            self.lights.truncate(light::MAX_LIGHTS);
+        }
+
+        let settings = self.shadow_settings;
+        let mut views = Vec::new();
+        let (mut have_cascades, mut spot_count, mut point_count) = (false, 0, 0);
+        for (i, queued) in self.lights.iter().enumerate() {
+            let first_matrix = views.len();
+            let shadows = queued.cast_shadows && self.shadow_shaders.is_some();
+            let layer = match queued.kind {
+                light::LightKind::Directional if shadows && !have_cascades => {
+                    have_cascades = true;
+                    let (splits, matrices) = shadow::cascades(
+                        &self.uniforms.view,
+                        &self.uniforms.projection,
+                        &queued.direction,
+                        &settings,
+                    );
+                    self.uniforms.cascade_splits = splits;
+                    views.extend(matrices.iter().enumerate().map(|(cascade, matrix)| {
+                        shadow::ShadowView {
+                            map: shadow::ShadowMap::Cascades,
+                            layer: cascade as u32,
+                            view_projection: *matrix,
+                        }
+                    }));
+                    Some(0)
+                }
+                light::LightKind::Spot {
+                    range, outer_angle, ..
+                } if shadows && spot_count < shadow::MAX_SPOT_SHADOWS => {
+                    let layer = spot_count as u32;
+                    views.push(shadow::ShadowView {
+                        map: shadow::ShadowMap::Spot,
+                        layer,
+                        view_projection: shadow::spot(
+                            &queued.position,
+                            &queued.direction,
+                            outer_angle,
+                            range,
+                        ),
+                    });
+                    spot_count += 1;
+                    Some(layer)
+                }
+                light::LightKind::Point { range }
+                    if shadows && point_count < shadow::MAX_POINT_SHADOWS =>
+                {
+                    let base_layer = point_count as u32 * 6;
+                    views.extend(
+                        shadow::point_faces(&queued.position, range)
+                            .iter()
+                            .enumerate()
+                            .map(|(face, matrix)| shadow::ShadowView {
+                                map: shadow::ShadowMap::Point,
+                                layer: base_layer + face as u32,
+                                view_projection: *matrix,
+                            }),
+                    );
+                    point_count += 1;
+                    Some(base_layer)
+                }
+                _ => None,
+            };
+
+            self.uniforms.lights[i] = match layer {
+                Some(layer) => queued.data(first_matrix as i32, layer),
+                None => queued.data(-1, 0),
+            };
+        }
+        for (i, view) in views.iter().enumerate() {
+            self.uniforms.shadow_matrices[i] = view.view_projection;
+        }
+        self.uniforms.light_info = [self.lights.len() as u32, settings.pcf_radius, 0, 0];
+
+        views
+    }
+
+    // Falls back to direct draws if the GPU can't do indirect ones, returns what's in use
+    pub fn set_indirect_drawing(&mut self, enabled: bool) -> bool {
+        let enabled = self.backend.set_indirect_drawing(enabled);
+        info!(
+            "Indirect drawing {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        enabled
+    }
+
+    pub fn draw_stats(&self) -> DrawStats {
+        self.draw_stats
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.backend.memory_stats()
+    }
+
+    // Writes memory_stats to the log directory as JSON and returns the path
+    pub fn dump_memory_stats(&self) -> Result<String, String> {
+        let date = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S");
+        let path = format!(
+            "{}{}-memory-{date}.json",
+            crate::engine::DataDirs::logs(),
+            crate::GAME_EXECUTABLE_NAME
+        );
+        std::fs::write(&path, self.memory_stats().to_json())
+            .map_err(|err| format!("failed to write {path}: {err}"))?;
+        info!("Saved memory stats to {path}");
+        Ok(path)
+    }
+
+    // Picks the highest sample count the GPU can do that isn't over samples, and returns it.
+    // 1 turns MSAA off.
+    pub fn set_msaa_samples(&mut self, samples: u32) -> u32 {
+        let supported = self.backend.sample_counts();
+        self.msaa_samples = (0..u32::BITS)
+            .rev()
+            .map(|bit| 1 << bit)
+            .find(|count| *count <= samples.max(1) && supported & count != 0)
+            .unwrap_or(1);
+        if self.msaa_samples != samples {
+            warn!(
+                "{samples}x MSAA isn't supported, using {}x instead",
+                self.msaa_samples
+            );
+        }
+        info!("Using {}x MSAA", self.msaa_samples);
+        self.msaa_samples
+    }
+
+    fn queue_draw(
+        &mut self,
+        mesh: MeshHandle,
+        material: MaterialHandle,
+        transform: Matrix4<f32>,
+        bounds: Option<bounds::Bounds>,
+    ) {
+        let Some(shader) = self.material_shader(material) else {
+            error!("Skipping draw with destroyed material {material:?}");
+            return;
+        };
+
+        self.draws.push(DrawItem {
+            shader,
+            mesh,
+            material,
+            transform,
+            bounds,
+            first_joint: None,
+        });
+    }
+
+    // Adds skinning matrices for this frame, returning where they start for
+    // queue_skinned_draw. None if there isn't room for them.
+    fn queue_joints(&mut self, joints: &[Matrix4<f32>]) -> Option<u32> {
+        if self.joints.len() + joints.len() > skinned::MAX_JOINT_MATRICES {
+            debug!(
+                "Dropping {} joint matrices over the limit of {}",
+                joints.len(),
+                skinned::MAX_JOINT_MATRICES
+            );
+            return None;
+        }
+
+        let first_joint = self.joints.len() as u32;
+        self.joints.extend_from_slice(joints);
+        Some(first_joint)
+    }
+
+    fn queue_skinned_draw(
+        &mut self,
+        mesh: MeshHandle,
+        material: MaterialHandle,
+        transform: Matrix4<f32>,
+        bounds: Option<bounds::Bounds>,
+        first_joint: u32,
+    ) {
+        let Some(shader) = self.material_skinned_shader(material) else {
+            error!("Skipping skinned draw with material {material:?} that has no skinned shader");
+            return;
+        };
+
+        self.draws.push(DrawItem {
+            shader,
+            mesh,
+            material,
+            transform,
+            bounds,
+            first_joint: Some(first_joint),
+        });
+    }
+
+    // Throws out draws that can't be seen from the camera or any shadow map. Every pass draws
+    // the same instances, so a draw only in a shadow map still gets drawn from the camera,
+    // where the GPU clips it.
+    fn cull_draws(&mut self, shadow_views: &[shadow::ShadowView]) -> usize {
+        if !self.culling {
+            return 0;
+        }
+
+        let frustums: Vec<bounds::Frustum> = [self.uniforms.projection * self.uniforms.view]
+            .iter()
+            .chain(shadow_views.iter().map(|view| &view.view_projection))
+            .map(bounds::Frustum::from_matrix)
+            .collect();
+        let count = self.draws.len();
+        self.draws.retain(|draw| {
+            draw.bounds.map_or(true, |bounds| {
+                frustums.iter().any(|frustum| frustum.intersects(&bounds))
+            })
+        });
+        count - self.draws.len()
+    }
+
+    // Culling is on by default
+    pub fn set_culling(&mut self, enabled: bool) {
+        self.culling = enabled;
+    }
+
+    // Each step of bias halves how big things have to be on screen before they switch to the
+    // next level, so positive is less detail and negative is more
+    pub fn set_lod_bias(&mut self, bias: f32) {
+        self.lod_bias = bias;
+    }
+
+    // How much of the screen's height the sphere covers from the camera, roughly
+    fn screen_coverage(&self, sphere: &bounds::Sphere) -> f32 {
+        let camera = Point3::new(
+            self.uniforms.camera_position[0],
+            self.uniforms.camera_position[1],
+            self.uniforms.camera_position[2],
+        );
+        let distance = nalgebra::distance(&camera, &sphere.center);
+        if distance <= sphere.radius {
+            return f32::INFINITY;
+        }
+        sphere.radius * self.uniforms.projection[(1, 1)].abs() / distance
+    }
+
+    // Level n is used below LOD_COVERAGE / 2^(n - 1) of the screen's height. With a current
+    // level, it has to go past that by LOD_HYSTERESIS before it changes.
+    fn select_lod(
+        &self,
+        sphere: &bounds::Sphere,
+        level_count: usize,
+        current: Option<usize>,
+    ) -> usize {
+        if level_count <= 1 {
+            return 0;
+        }
+
+        let coverage = self.screen_coverage(sphere) * 2.0_f32.powf(-self.lod_bias);
+        let threshold = |level: usize| LOD_COVERAGE / 2.0_f32.powi(level as i32 - 1);
+        let last = level_count - 1;
+        match current {
+            Some(current) => {
+                let mut level = current.min(last);
+                while level < last && coverage < threshold(level + 1) * (1.0 - LOD_HYSTERESIS) {
+                    level += 1;
+                }
+                while level > 0 && coverage > threshold(level) * (1.0 + LOD_HYSTERESIS) {
+                    level -= 1;
+                }
+                level
+            }
+            None => (1..=last)
+                .take_while(|level| coverage < threshold(*level))
+                .last()
+                .unwrap_or(0),
+        }
+    }
+
+    fn prepare_draws(&mut self, shadow_views: &[shadow::ShadowView]) {
+        let culled = self.cull_draws(shadow_views);
+
+        // Sorting by shader then mesh means pipelines change as little as possible and every
+        // instance of a mesh ends up next to each other
+        self.draws.sort_by_key(|draw| (draw.shader, draw.mesh));
+
+        let mut instances = Vec::with_capacity(self.draws.len());
+        let mut batches: Vec<DrawBatch> = Vec::new();
+        self.batches.clear();
+        let mut last_key = None;
+        for draw in &self.draws {
+            let Some(shader) = self.shaders.get(draw.shader.0).and_then(|shader| shader.as_deref()) else {
+                error!("Skipping draw with destroyed shader {:?}", draw.shader);
+                continue;
+            };
+
+            if last_key == Some((draw.shader, draw.mesh)) {
+                batches.last_mut().unwrap().instance_count += 1;
+                self.batches.last_mut().unwrap().instance_count += 1;
+            } else {
+                batches.push(DrawBatch {
+                    shader: Some(shader),
+                    mesh: draw.mesh,
+                    first_instance: instances.len() as u32,
+                    instance_count: 1,
+                });
+                self.batches.push(QueuedBatch {
+                    shader: draw.shader,
+                    mesh: draw.mesh,
+                    skinned: draw.first_joint.is_some(),
+                    first_instance: instances.len() as u32,
+                    instance_count: 1,
+                });
+                last_key = Some((draw.shader, draw.mesh));
+            }
+            instances.push(InstanceData {
+                transform: draw.transform,
+                material: draw.material.0 as u32,
+                first_joint: draw.first_joint.unwrap_or(0),
+                padding: [0; 2],
+            });
+        }
+
+        self.draw_stats = DrawStats {
+            instances: instances.len(),
+            batches: batches.len(),
+            culled,
+        };
+
+        self.backend.update_uniforms(&self.uniforms);
+        self.backend.update_materials(&self.material_data);
+        self.backend.update_joints(&self.joints);
+        self.backend.prepare_batches(&batches, &instances);
+    }
+
+    // Draws everything queued this frame from one point of view, optionally with every
+    // batch using the same shader (for depth only passes like shadows). Overrides are for
+    // meshes and skinned meshes.
+    fn draw_scene(
+        &mut self,
+        shader_override: Option<(ShaderHandle, ShaderHandle)>,
+        view_projection: &Matrix4<f32>,
+    ) {
+        let shader_data = |shader: ShaderHandle| {
+            self.shaders
+                .get(shader.0)
+                .and_then(|shader| shader.as_deref())
+        };
+        let override_data = shader_override.and_then(|(mesh, skinned)| {
+            shader_data(mesh).zip(shader_data(skinned))
+        });
+        let batches: Vec<DrawBatch> = self
+            .batches
+            .iter()
+            .map(|batch| DrawBatch {
+                shader: match override_data {
+                    Some((_, skinned)) if batch.skinned => Some(skinned),
+                    Some((mesh, _)) => Some(mesh),
+                    None => shader_data(batch.shader),
+                },
+                mesh: batch.mesh,
+                first_instance: batch.first_instance,
+                instance_count: batch.instance_count,
+            })
+            .collect();
+
+        self.backend.draw_batches(&batches, view_projection);
+    }
+
+    fn draw_fullscreen(&mut self, shader: ShaderHandle, constants: &[f32; 16]) {
+        let data = self.shaders.get(shader.0).and_then(|data| data.as_deref());
+        match data {
+            Some(data) => self.backend.draw_fullscreen(data, constants),
+            None => error!("Skipping fullscreen draw with destroyed shader {shader:?}"),
+        }
+    }
+
+    fn add_shader(&mut self, data: Box<dyn ShaderData>) -> ShaderHandle {
+        match self.free_shaders.pop() {
+            Some(index) => {
+                self.shaders[index] = Some(data);
+                ShaderHandle(index)
+            }
+            None => {
+                self.shaders.push(Some(data));
+                ShaderHandle(self.shaders.len() - 1)
+            }
+        }
+    }
+
+    fn remove_shader(&mut self, shader: ShaderHandle) {
+        if let Some(mut data) = self.shaders.get_mut(shader.0).and_then(|data| data.take()) {
+            data.destroy(&self.backend);
+            self.free_shaders.push(shader.0);
+        }
+    }
+
     pub fn unload_resources(&mut self) {
         if self.backend.is_initialized() && self.backend.is_loaded() {
             info!("Unloading resources");
             self.backend.unload_resources();
-            self.models.clear();
             info!("Done unloading resources");
         }
     }
 
     pub fn shutdown(mut self) {
         info!("Render system shutdown started");
+        for index in 0..self.materials.len() {
+            if let Some(entry) = self.materials[index].as_mut() {
+                entry.refs = 1;
+                self.release_material(MaterialHandle(index));
+            }
+        }
+        for texture in [self.white_texture.take(), self.flat_normal_texture.take()]
+            .into_iter()
+            .flatten()
+        {
+            self.backend.destroy_texture(texture);
+        }
         self.unload_resources();
+        for index in 0..self.shaders.len() {
+            self.remove_shader(ShaderHandle(index));
+        }
+        self.shader_cache.clear();
         self.backend.shutdown();
         info!("Render system shutdown succeeded");
     }
@@ -134,110 +1386,371 @@ pub trait ShaderData {
 
 pub struct Shader {
     name: String,
-    data: Box<dyn ShaderData>
+    handle: ShaderHandle,
 }
 
 impl Shader {
-    pub fn new(state: &super::State, name: &str) -> Result<Self, String> {
+    pub fn new(state: &mut super::State, name: &str) -> Result<Self, String> {
         let name = String::from(name);
-        let shader_path = format!("{}/{name}", super::GameDirs::shaders(state));
-        let data = state.render_state().backend.create_shader(&shader_path, &name)?;
-        Ok(Self {
-            name,
-            data
-        })
+        let shader_path = format!("{}{name}", super::GameDirs::shaders());
+        let render = state.render_state();
+        let data = render.backend.create_shader(&shader_path, &name)?;
+        let handle = render.add_shader(data);
+        Ok(Self { name, handle })
+    }
+
+    pub fn destroy(self, state: &mut State) {
+        info!("Destroying shader {}", self.name);
+        state.remove_shader(self.handle);
     }
 }
 
+// Model matrices are per instance, see DrawBatch, and each pass pushes its own view
+// projection matrix. Laid out to match std140.
 #[repr(C)]
 pub struct UniformData {
-    model: Matrix4<f64>,
-    view: Matrix4<f64>,
-    projection: Matrix4<f64>,
+    view: Matrix4<f32>,
+    projection: Matrix4<f32>,
+    camera_position: [f32; 4],
+    ambient: [f32; 4],
+    // View space depth each cascade ends at
+    cascade_splits: [f32; shadow::CASCADE_COUNT],
+    // Light count and PCF radius
+    light_info: [u32; 4],
+    shadow_matrices: [Matrix4<f32>; shadow::MAX_SHADOW_VIEWS],
+    lights: [light::LightData; light::MAX_LIGHTS],
+}
+
+impl Default for UniformData {
+    fn default() -> Self {
+        Self {
+            view: Matrix4::identity(),
+            projection: Matrix4::identity(),
+            camera_position: [0.0, 0.0, 0.0, 1.0],
+            ambient: [0.03, 0.03, 0.03, 1.0],
+            cascade_splits: [0.0; shadow::CASCADE_COUNT],
+            light_info: [0; 4],
+            shadow_matrices: [Matrix4::identity(); shadow::MAX_SHADOW_VIEWS],
+            lights: [light::LightData::default(); light::MAX_LIGHTS],
+        }
+    }
 }
 
 pub struct RenderTexture {
     name: String,
-    texture: image::RgbaImage,
+    handle: TextureHandle,
 }
 
 impl RenderTexture {
-    pub fn new(_state: &State, name: &str, texture: image::RgbaImage) -> Result<Self, String> {
+    // Colors should be sRGB, anything that's data (normals, roughness, etc) shouldn't be
+    pub fn new(
+        state: &mut State,
+        name: &str,
+        texture: &image::RgbaImage,
+        srgb: bool,
+    ) -> Result<Self, String> {
+        debug!(
+            "Creating {}x{} texture {name}",
+            texture.width(),
+            texture.height()
+        );
+        let handle = state.backend.create_texture(
+            texture.width(),
+            texture.height(),
+            texture.as_raw(),
+            srgb,
+        )?;
         Ok(Self {
             name: String::from(name),
-            texture
+            handle,
         })
     }
+
+    pub fn destroy(self, state: &mut State) {
+        debug!("Destroying texture {}", self.name);
+        state.backend.destroy_texture(self.handle);
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    // For things that refer to textures directly, like PostSettings::color_grading
+    pub fn handle(&self) -> TextureHandle {
+        self.handle
+    }
+}
+
+struct MaterialEntry {
+    name: String,
+    refs: usize,
+    shader: ShaderHandle,
+    // None if the shader's fragment shader doesn't work with the skinning vertex shader
+    skinned_shader: Option<ShaderHandle>,
+    textures: Vec<RenderTexture>,
 }
 
-pub struct Material<'a> {
+// A reference to a material, which is freed once every reference is destroyed
+pub struct Material {
     name: String,
-    shader: &'a Shader,
-    texture: &'a RenderTexture,
+    handle: MaterialHandle,
 }
 
-impl<'a> Material<'a> {
+impl Material {
     pub fn new(
-        state: &mut State,
+        state: &mut super::State,
         name: &str,
-        shader: &'a Shader,
-        texture: &'a RenderTexture,
-    ) -> Result<Self, ()> {
+        desc: &material::MaterialDesc,
+    ) -> Result<Self, String> {
+        let shader_dir = super::GameDirs::shaders();
+        let texture_dir = super::GameDirs::textures();
+        let handle = state
+            .render_state()
+            .create_material(name, desc, &shader_dir, &texture_dir)?;
         Ok(Self {
             name: String::from(name),
-            shader,
-            texture,
+            handle,
+        })
+    }
+
+    // Reads name.mat from the game's material directory, unless it's already loaded
+    pub fn load(state: &mut super::State, name: &str) -> Result<Self, String> {
+        if let Some(handle) = state.render_state().acquire_material(name) {
+            return Ok(Self {
+                name: String::from(name),
+                handle,
+            });
+        }
+
+        let path = format!(
+            "{}{name}.{}",
+            super::GameDirs::materials(),
+            material::MATERIAL_EXTENSION
+        );
+        let text = super::vfs::read_to_string(&path).map_err(|err| {
+            error!("Failed to read material {path}: {err}");
+            err
+        })?;
+        let desc = material::MaterialDesc::parse(&text).map_err(|err| {
+            error!("Failed to parse material {path}: {err}");
+            err
+        })?;
+
+        Self::new(state, name, &desc)
+    }
+
+    // The material meshes without one get
+    pub fn builtin(state: &mut State) -> Option<Self> {
+        let handle = state.default_material?;
+        state.materials[handle.0].as_mut().unwrap().refs += 1;
+        Some(Self {
+            name: String::from("default"),
+            handle,
         })
     }
 
+    pub fn destroy(self, state: &mut State) {
+        state.release_material(self.handle);
+    }
+
     pub fn name(&self) -> &String {
         &self.name
     }
 }
 
 pub trait Renderable {
-    fn render(&self, state: &mut State);
+    fn render(&self, state: &mut State, transform: &Matrix4<f32>);
 }
 
 #[derive(PartialEq)]
+#[repr(C)]
 pub struct Vertex {
     position: Vector3<f32>,
     texture_coordinate: Vector2<f32>,
     normal: Vector3<f32>,
 }
 
-#[derive(Clone)]
-pub struct Model<'a> {
+// An OBJ file's models and material library
+type ObjContents = (
+    Vec<tobj::Model>,
+    Result<Vec<tobj::Material>, tobj::LoadError>,
+);
+
+// One mesh per material, None is the default material
+type ModelMeshes = Vec<(MeshHandle, Option<usize>, bounds::Bounds)>;
+
+pub struct Model {
     name: String,
-    offset: usize,
-    vertices_size: usize,
-    indices_size: usize,
-    material: &'a Material<'a>
+    // Most detailed first
+    levels: Vec<ModelMeshes>,
+    materials: Vec<Material>,
+    // Around the first level
+    bounds: bounds::Bounds,
 }
 
-impl<'a> Model<'a> {
-    pub fn new(state: &mut State, name: &str, models: &mut Vec<tobj::Model>, material: &'a Material<'a>) -> Self {
-        if !state.backend.is_initialized() || state.backend.is_loaded() {
-            error!("Not creating model {name} at this time");
+impl Model {
+    // Loads name.obj from the game's model directory, along with the material files named by
+    // its material library. Lower detail levels are loaded from name.lod1.obj, name.lod2.obj
+    // and so on until one doesn't exist, and should use the same material library.
+    pub fn load(state: &mut super::State, name: &str) -> Result<Self, String> {
+        let options = tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        };
+        let path = format!("{}{name}.obj", super::GameDirs::models());
+        let (models, library) = Self::load_obj(&path, &options).map_err(|err| {
+            error!("Failed to load model {path}: {err}");
+            err
+        })?;
+
+        let mut levels = vec![models];
+        loop {
+            let path = format!(
+                "{}{name}.lod{}.obj",
+                super::GameDirs::models(),
+                levels.len()
+            );
+            if !super::vfs::exists(&path) {
+                break;
+            }
+            match Self::load_obj(&path, &options) {
+                Ok((models, _)) => levels.push(models),
+                Err(err) => {
+                    warn!("Failed to load model LOD {path}, skipping the rest: {err}");
+                    break;
+                }
+            }
+        }
+
+        let library = library.unwrap_or_else(|err| {
+            warn!("Model {name} has no material library, using the default material: {err}");
+            Vec::new()
+        });
+        let mut materials = Vec::with_capacity(library.len());
+        for entry in &library {
+            match Material::load(state, &entry.name)
+                .or_else(|err| Material::builtin(state.render_state()).ok_or(err))
+            {
+                Ok(material) => materials.push(material),
+                Err(err) => {
+                    for material in materials {
+                        material.destroy(state.render_state());
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        Self::new(state.render_state(), name, &mut levels, materials)
+    }
+
+    // Material libraries are relative to the OBJ file like they would be on disk
+    fn load_obj(path: &str, options: &tobj::LoadOptions) -> Result<ObjContents, String> {
+        let data = super::vfs::open(path)?;
+        let directory = path.rsplit_once('/').map_or("", |(directory, _)| directory);
+        tobj::load_obj_buf(&mut data.as_slice(), options, |library| {
+            let path = format!("{directory}/{}", library.display());
+            let data = super::vfs::open(&path).map_err(|err| {
+                warn!("Failed to read material library {path}: {err}");
+                tobj::LoadError::OpenFileFailed
+            })?;
+            tobj::load_mtl_buf(&mut data.as_slice())
+        })
+        .map_err(|err| err.to_string())
+    }
+
+    // Each level is a LOD, most detailed first. Meshes use the material at their material_id,
+    // the materials belong to the model afterwards.
+    pub fn new(
+        state: &mut State,
+        name: &str,
+        levels: &mut [Vec<tobj::Model>],
+        materials: Vec<Material>,
+    ) -> Result<Self, String> {
+        if !state.backend.is_initialized() {
+            error!("Not creating model {name} because the render backend isn't initialized");
+            for material in materials {
+                material.destroy(state);
+            }
+            return Err(String::from("render backend not initialized"));
         }
 
-        info!("Creating model {name}");
+        info!("Creating model {name} with {} level(s)", levels.len());
 
+        let mut created: Vec<ModelMeshes> = Vec::with_capacity(levels.len());
+        for models in levels {
+            match Self::create_meshes(state, name, models, materials.len()) {
+                Ok(meshes) => created.push(meshes),
+                Err(err) => {
+                    for (mesh, _, _) in created.into_iter().flatten() {
+                        state.backend.destroy_mesh(mesh);
+                    }
+                    for material in materials {
+                        material.destroy(state);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let bounds = created
+            .first()
+            .into_iter()
+            .flatten()
+            .map(|(_, _, bounds)| *bounds)
+            .reduce(|all, bounds| all.union(&bounds))
+            .unwrap_or_else(|| bounds::Bounds::from_points(&[]));
+        Ok(Self {
+            name: String::from(name),
+            levels: created,
+            materials,
+            bounds,
+        })
+    }
+
+    fn create_meshes(
+        state: &mut State,
+        name: &str,
+        models: &mut [tobj::Model],
+        material_count: usize,
+    ) -> Result<ModelMeshes, String> {
         // largely based on https://github.com/bwasty/learn-opengl-rs/blob/master/src/model.rs
-        let mut all_vertices = Vec::new();
-        let mut all_indices: Vec<u32> = Vec::new();
+        let mut groups: Vec<(Option<usize>, Vec<Vertex>, Vec<u32>)> = Vec::new();
         for model in models {
             let mesh = &mut model.mesh;
+            let material = mesh.material_id.filter(|id| *id < material_count);
+            let group = match groups.iter().position(|group| group.0 == material) {
+                Some(group) => group,
+                None => {
+                    groups.push((material, Vec::new(), Vec::new()));
+                    groups.len() - 1
+                }
+            };
+            let (_, all_vertices, all_indices) = &mut groups[group];
 
-            assert!(!mesh.normals.is_empty() && !mesh.texcoords.is_empty());
+            // Separate normal and texture coordinate indices don't line up with the vertices
+            if !mesh.normal_indices.is_empty() || !mesh.texcoord_indices.is_empty() {
+                error!("Model {name} wasn't loaded with single_index, attributes will be wrong");
+            }
 
             let vertex_count = mesh.positions.len() / 3;
+            let (p, t) = (&mesh.positions, &mesh.texcoords);
+            let n = if mesh.normals.len() == mesh.positions.len() {
+                mesh.normals.clone()
+            } else {
+                debug!("Generating normals for mesh {}", model.name);
+                Self::generate_normals(p, &mesh.indices)
+            };
             let mut vertices = Vec::with_capacity(vertex_count);
-            let (p, t, n) = (&mesh.positions, &mesh.texcoords, &mesh.normals);
             for i in 0..vertex_count {
                 let position = Vector3::new(p[i * 3 + 0], p[i * 3 + 1], p[i * 3 + 2]);
-                let texture_coordinate = Vector2::new(t[i * 2 + 0], t[i * 2 + 1]);
-                let normal = Vector3::new(0f32, 0f32, 0f32); // Vector3::new(n[i * 3 + 0], n[i * 3 + 1], n[i * 3 + 2]);
+                let texture_coordinate = if t.len() >= (i + 1) * 2 {
+                    Vector2::new(t[i * 2 + 0], t[i * 2 + 1])
+                } else {
+                    Vector2::zeros()
+                };
+                let normal = Vector3::new(n[i * 3 + 0], n[i * 3 + 1], n[i * 3 + 2]);
                 vertices.push(Vertex {
                     position,
                     texture_coordinate,
@@ -245,36 +1758,120 @@ impl<'a> Model<'a> {
                 })
             }
 
+            let base_vertex = all_vertices.len() as u32;
             all_vertices.append(&mut vertices);
-            all_indices.append(&mut mesh.indices);
+            all_indices.extend(mesh.indices.iter().map(|index| index + base_vertex));
+        }
+
+        let mut meshes = Vec::with_capacity(groups.len());
+        for (material, vertices, indices) in &groups {
+            let points: Vec<Point3<f32>> = vertices
+                .iter()
+                .map(|vertex| Point3::from(vertex.position))
+                .collect();
+            let bounds = bounds::Bounds::from_points(&points);
+            match state
+                .backend
+                .create_mesh(as_bytes(vertices), mem::size_of::<Vertex>(), indices)
+            {
+                Ok(mesh) => meshes.push((mesh, *material, bounds)),
+                Err(err) => {
+                    for (mesh, _, _) in meshes {
+                        state.backend.destroy_mesh(mesh);
+                    }
+                    return Err(err);
+                }
+            }
         }
 
-        let vertices_size = all_vertices.len() * mem::size_of::<Vertex>();
-        let indices_size = all_indices.len() * mem::size_of::<u32>();
+        Ok(meshes)
+    }
+
+    // In model space, around every mesh
+    pub fn bounds(&self) -> bounds::Bounds {
+        self.bounds
+    }
 
-        let mut data = Vec::new();
-        let vertices = all_vertices.into_raw_parts();
-        data.append(&mut unsafe { Vec::from_raw_parts(vertices.0 as *mut u8, vertices_size, vertices_size) });
-        let indices = all_indices.into_raw_parts();
-        data.append(&mut unsafe { Vec::from_raw_parts(indices.0 as *mut u8, indices_size, indices_size) });
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
 
-        let offset = state.models.len();
-        state.models.append(&mut data);
+    // Picks a level with hysteresis from the last one, so instances that stay around the same
+    // size on screen don't keep switching. Each instance should have its own level.
+    pub fn render_lod(&self, state: &mut State, transform: &Matrix4<f32>, level: &mut usize) {
+        let sphere = self.bounds.sphere.transform(transform);
+        *level = state.select_lod(&sphere, self.levels.len(), Some(*level));
+        self.render_level(state, transform, *level);
+    }
 
-        Self { 
-            name: String::from(name),
-            offset,
-            vertices_size,
-            indices_size,
-            material
+    fn render_level(&self, state: &mut State, transform: &Matrix4<f32>, level: usize) {
+        if !state.backend.is_in_frame() {
+            return;
+        }
+        let Some(meshes) = self.levels.get(level) else {
+            return;
+        };
+        for (mesh, material, bounds) in meshes {
+            let material = match material {
+                Some(material) => Some(self.materials[*material].handle),
+                None => state.default_material,
+            };
+            if let Some(material) = material {
+                let bounds = bounds.transform(transform);
+                state.queue_draw(*mesh, material, *transform, Some(bounds));
+            }
         }
     }
-}
 
-impl<'a> Renderable for Model<'a> {
-    fn render(&self, state: &mut State) {
-        if state.backend.is_in_frame() {
-            state.backend.render_model(self);
+    // Area weighted average of the faces around each vertex
+    fn generate_normals(positions: &[f32], indices: &[u32]) -> Vec<f32> {
+        let position = |index: u32| {
+            let index = index as usize * 3;
+            Vector3::new(
+                positions[index],
+                positions[index + 1],
+                positions[index + 2],
+            )
+        };
+
+        let mut normals = vec![Vector3::zeros(); positions.len() / 3];
+        for triangle in indices.chunks_exact(3) {
+            let (a, b, c) = (
+                position(triangle[0]),
+                position(triangle[1]),
+                position(triangle[2]),
+            );
+            let face_normal = (b - a).cross(&(c - a));
+            for index in triangle {
+                normals[*index as usize] += face_normal;
+            }
+        }
+
+        normals
+            .iter()
+            .flat_map(|normal| {
+                let normal = normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::y);
+                [normal.x, normal.y, normal.z]
+            })
+            .collect()
+    }
+
+    pub fn destroy(self, state: &mut State) {
+        info!("Destroying model {}", self.name);
+        for (mesh, _, _) in self.levels.into_iter().flatten() {
+            state.backend.destroy_mesh(mesh);
+        }
+        for material in self.materials {
+            material.destroy(state);
         }
     }
 }
+
+// Without anywhere to keep the last level there's no hysteresis, Model::render_lod has it
+impl Renderable for Model {
+    fn render(&self, state: &mut State, transform: &Matrix4<f32>) {
+        let sphere = self.bounds.sphere.transform(transform);
+        let level = state.select_lod(&sphere, self.levels.len(), None);
+        self.render_level(state, transform, level);
+    }
+}
@@ -0,0 +1,291 @@
+use super::{graph, ShaderHandle, State, TextureHandle};
+
+pub const BLOOM_LEVELS: usize = 5;
+// Color grading tables are a 16x16x16 cube laid out as a row of 16 slices, so a 256x16 image
+// going from red on X, green on Y and blue across the slices. They're made with srgb off,
+// since they map sRGB encoded colors to sRGB encoded colors.
+pub const LUT_SIZE: u32 = 16;
+
+const LUMINANCE_SIZE: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMapper {
+    // Just clamps, mostly for comparing against
+    None,
+    Reinhard,
+    Aces,
+    Uncharted2,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PostSettings {
+    pub bloom: bool,
+    // Anything brighter than this (after exposure) starts to bloom
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub auto_exposure: bool,
+    // In stops, on top of auto exposure if that's on
+    pub exposure: f32,
+    // Auto exposure keeps the average scene luminance between these
+    pub min_luminance: f32,
+    pub max_luminance: f32,
+    // How quickly auto exposure catches up, higher is faster
+    pub adaptation_speed: f32,
+    pub tone_mapper: ToneMapper,
+    pub color_grading: Option<TextureHandle>,
+    pub fxaa: bool,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.05,
+            auto_exposure: true,
+            exposure: 0.0,
+            min_luminance: 0.01,
+            max_luminance: 10.0,
+            adaptation_speed: 1.5,
+            tone_mapper: ToneMapper::Aces,
+            color_grading: None,
+            fxaa: true,
+        }
+    }
+}
+
+// Fragment shaders that go with the fullscreen triangle, see create_fullscreen_shader
+#[derive(Clone, Copy, Debug)]
+pub struct PostShaders {
+    luminance: ShaderHandle,
+    copy: ShaderHandle,
+    adapt: ShaderHandle,
+    bloom_downsample: ShaderHandle,
+    bloom_upsample: ShaderHandle,
+    tonemap: ShaderHandle,
+    fxaa: ShaderHandle,
+    present: ShaderHandle,
+}
+
+impl PostShaders {
+    pub fn load<F>(mut load: F) -> Result<Self, String>
+    where
+        F: FnMut(&str) -> Result<ShaderHandle, String>,
+    {
+        Ok(Self {
+            luminance: load("luminance")?,
+            copy: load("copy")?,
+            adapt: load("adapt")?,
+            bloom_downsample: load("bloom_downsample")?,
+            bloom_upsample: load("bloom_upsample")?,
+            tonemap: load("tonemap")?,
+            fxaa: load("fxaa")?,
+            present: load("present")?,
+        })
+    }
+}
+
+fn flag(value: bool) -> f32 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn fullscreen_pass<'a>(
+    graph: &mut graph::RenderGraph<'a>,
+    name: &str,
+    target: graph::ResourceHandle,
+    sources: &[graph::ResourceHandle],
+    shader: ShaderHandle,
+    constants: [f32; 16],
+) {
+    let mut pass = graph.add_pass(name).color(target, graph::LoadOp::DontCare);
+    for source in sources {
+        pass = pass.sample(*source);
+    }
+    pass.execute(move |state: &mut State| state.draw_fullscreen(shader, &constants));
+}
+
+// Turns the HDR scene into what goes on the backbuffer. srgb_backbuffer is whether the
+// backbuffer encodes on its own, otherwise the shaders do it.
+pub fn add_passes(
+    graph: &mut graph::RenderGraph,
+    shaders: &PostShaders,
+    settings: &PostSettings,
+    hdr: graph::ResourceHandle,
+    delta: f32,
+    srgb_backbuffer: bool,
+) {
+    let backbuffer = graph.backbuffer();
+    let texture = |format, size| graph::TextureDesc::new(format, size);
+
+    // The average is the geometric mean, each bilinear tap halving the size averages the logs
+    // of four texels. The adapted luminance is kept between frames.
+    let exposure = if settings.auto_exposure {
+        let mut size = LUMINANCE_SIZE;
+        let mut luminance = graph.create_texture(
+            "log luminance",
+            texture(
+                graph::TextureFormat::R16Float,
+                graph::TextureSize::Fixed(size, size),
+            ),
+        );
+        fullscreen_pass(
+            graph,
+            "luminance",
+            luminance,
+            &[hdr],
+            shaders.luminance,
+            [0.0; 16],
+        );
+        while size > 1 {
+            size /= 2;
+            let next = graph.create_texture(
+                &format!("log luminance {size}"),
+                texture(
+                    graph::TextureFormat::R16Float,
+                    graph::TextureSize::Fixed(size, size),
+                ),
+            );
+            fullscreen_pass(
+                graph,
+                &format!("luminance {size}"),
+                next,
+                &[luminance],
+                shaders.copy,
+                [0.0; 16],
+            );
+            luminance = next;
+        }
+
+        let one_texel = texture(
+            graph::TextureFormat::R16Float,
+            graph::TextureSize::Fixed(1, 1),
+        );
+        let history = graph.history_texture("adapted luminance", one_texel);
+        let adapted = graph.create_texture("adapted luminance this frame", one_texel);
+        let mut constants = [0.0; 16];
+        constants[..4].copy_from_slice(&[
+            delta,
+            settings.adaptation_speed,
+            settings.min_luminance,
+            settings.max_luminance,
+        ]);
+        fullscreen_pass(
+            graph,
+            "adapt exposure",
+            adapted,
+            &[luminance, history],
+            shaders.adapt,
+            constants,
+        );
+        fullscreen_pass(
+            graph,
+            "keep exposure",
+            history,
+            &[adapted],
+            shaders.copy,
+            [0.0; 16],
+        );
+        Some(adapted)
+    } else {
+        None
+    };
+
+    // Downsampling with the threshold on the first level, then adding each level back onto the
+    // one above it on the way up. The threshold needs the exposure, which is why that's first.
+    let bloom = if settings.bloom {
+        let level_size = |level: usize| graph::TextureSize::Scaled(0.5f32.powi(level as i32 + 1));
+        let mut levels = Vec::with_capacity(BLOOM_LEVELS);
+        for level in 0..BLOOM_LEVELS {
+            let target = graph.create_texture(
+                &format!("bloom down {level}"),
+                texture(graph::TextureFormat::Rgba16Float, level_size(level)),
+            );
+            let mut constants = [0.0; 16];
+            constants[..4].copy_from_slice(&[
+                flag(level == 0),
+                settings.bloom_threshold,
+                flag(exposure.is_some()),
+                settings.exposure,
+            ]);
+            let source = levels.last().copied().unwrap_or(hdr);
+            fullscreen_pass(
+                graph,
+                &format!("bloom down {level}"),
+                target,
+                &[source, exposure.unwrap_or(hdr)],
+                shaders.bloom_downsample,
+                constants,
+            );
+            levels.push(target);
+        }
+
+        let mut upper = *levels.last().unwrap();
+        for level in (0..BLOOM_LEVELS - 1).rev() {
+            let target = graph.create_texture(
+                &format!("bloom up {level}"),
+                texture(graph::TextureFormat::Rgba16Float, level_size(level)),
+            );
+            fullscreen_pass(
+                graph,
+                &format!("bloom up {level}"),
+                target,
+                &[levels[level], upper],
+                shaders.bloom_upsample,
+                [0.0; 16],
+            );
+            upper = target;
+        }
+        Some(upper)
+    } else {
+        None
+    };
+
+    // Shaders always get the same bindings, things that are off get the scene in their place
+    let ldr = graph.create_texture(
+        "ldr color",
+        texture(graph::TextureFormat::Rgba8, graph::TextureSize::Backbuffer),
+    );
+    let mut constants = [0.0; 16];
+    constants[..7].copy_from_slice(&[
+        settings.exposure,
+        flag(exposure.is_some()),
+        if bloom.is_some() {
+            settings.bloom_intensity
+        } else {
+            0.0
+        },
+        settings.tone_mapper as u32 as f32,
+        flag(settings.color_grading.is_some()),
+        settings.color_grading.map_or(0.0, |lut| lut.0 as f32),
+        LUT_SIZE as f32,
+    ]);
+    fullscreen_pass(
+        graph,
+        "tonemap",
+        ldr,
+        &[hdr, bloom.unwrap_or(hdr), exposure.unwrap_or(hdr)],
+        shaders.tonemap,
+        constants,
+    );
+
+    // Everything up to here is sRGB encoded, so it has to be decoded again if the backbuffer
+    // is going to encode it
+    let mut constants = [0.0; 16];
+    constants[0] = flag(srgb_backbuffer);
+    fullscreen_pass(
+        graph,
+        if settings.fxaa { "fxaa" } else { "present" },
+        backbuffer,
+        &[ldr],
+        if settings.fxaa {
+            shaders.fxaa
+        } else {
+            shaders.present
+        },
+        constants,
+    );
+}
@@ -0,0 +1,25 @@
+#version 460
+
+layout (set = 1, binding = 0) uniform sampler2D average_luminance;
+layout (set = 1, binding = 1) uniform sampler2D last_luminance;
+
+// Frame time, adaptation speed, minimum and maximum luminance
+layout (push_constant) uniform constants {
+    vec4 parameters[4];
+} push_constants;
+
+layout (location = 0) out float out_luminance;
+
+void main() {
+    vec4 parameters = push_constants.parameters[0];
+    float target = clamp(exp2(texelFetch(average_luminance, ivec2(0), 0).r), parameters.z, parameters.w);
+    float last = texelFetch(last_luminance, ivec2(0), 0).r;
+
+    // The history starts out as zero, in which case there's nothing to adapt from
+    if (last <= 0.0) {
+        out_luminance = target;
+        return;
+    }
+
+    out_luminance = last + (target - last) * (1.0 - exp(-parameters.x * parameters.y));
+}
@@ -1,9 +1,233 @@
-#version 460
-
-layout (location = 0) in vec4 fragment_color;
-
-layout (location = 0) out vec4 out_color;
-
-void main() {
-    out_color = fragment_color;
-}
+#version 460
+
+#extension GL_EXT_nonuniform_qualifier : require
+
+#define CASCADE_COUNT 4
+#define MAX_SHADOW_VIEWS 20
+#define MAX_LIGHTS 16
+#define MAX_TEXTURES 1024
+
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_SPOT 1
+#define LIGHT_POINT 2
+
+#define TEXTURE_BASE_COLOR 0
+#define TEXTURE_NORMAL 1
+#define TEXTURE_METALLIC_ROUGHNESS 2
+#define TEXTURE_OCCLUSION 3
+#define TEXTURE_EMISSIVE 4
+
+#define PI 3.14159265359
+
+struct Light {
+    vec4 position_range;
+    vec4 direction_type;
+    vec4 color_intensity;
+    // Cosines of the inner and outer cone angles, first shadow matrix (negative if the light
+    // has no shadows) and first shadow map layer
+    vec4 cone_shadow;
+};
+
+layout (set = 0, binding = 0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 ambient;
+    vec4 cascade_splits;
+    uvec4 light_info;
+    mat4 shadow_matrices[MAX_SHADOW_VIEWS];
+    Light lights[MAX_LIGHTS];
+} uniform_buffer;
+
+layout (set = 1, binding = 0) uniform sampler2DArrayShadow cascade_shadows;
+layout (set = 1, binding = 1) uniform sampler2DArrayShadow spot_shadows;
+layout (set = 1, binding = 2) uniform sampler2DArrayShadow point_shadows;
+
+// Indices are into the texture array, maps a material doesn't have point at plain white or a
+// flat normal so they can be sampled the same way
+struct Material {
+    vec4 base_color;
+    // Emissive color and normal map scale
+    vec4 emissive_normal_scale;
+    // Metallic, roughness and occlusion strength
+    vec4 metallic_roughness_occlusion;
+    uint textures[8];
+};
+
+layout (std430, set = 2, binding = 0) readonly buffer material_buffer {
+    Material materials[];
+};
+layout (set = 2, binding = 1) uniform sampler2D textures[MAX_TEXTURES];
+
+layout (location = 0) in vec3 fragment_position;
+layout (location = 1) in vec3 fragment_normal;
+layout (location = 2) in vec2 fragment_texture_coordinate;
+layout (location = 3) in float fragment_view_depth;
+layout (location = 4) flat in uint fragment_material;
+
+layout (location = 0) out vec4 out_color;
+
+// Averages a grid of hardware filtered comparisons around the sample point
+float filter_shadow(sampler2DArrayShadow shadow_map, uint matrix, float layer) {
+    vec4 shadow_position = uniform_buffer.shadow_matrices[matrix] * vec4(fragment_position, 1);
+    shadow_position /= shadow_position.w;
+    vec2 uv = shadow_position.xy * 0.5 + 0.5;
+    if (shadow_position.z > 1.0) {
+        return 1.0;
+    }
+
+    int radius = int(uniform_buffer.light_info.y);
+    vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0).xy);
+    float total = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            total += texture(shadow_map, vec4(uv + vec2(x, y) * texel, layer, shadow_position.z));
+        }
+    }
+
+    float width = float(radius * 2 + 1);
+    return total / (width * width);
+}
+
+float shadow_factor(Light light, vec3 to_light) {
+    if (light.cone_shadow.z < 0.0) {
+        return 1.0;
+    }
+
+    uint first_matrix = uint(light.cone_shadow.z);
+    float first_layer = light.cone_shadow.w;
+    int type = int(light.direction_type.w);
+    if (type == LIGHT_DIRECTIONAL) {
+        uint cascade = uint(CASCADE_COUNT - 1);
+        for (uint i = 0u; i < uint(CASCADE_COUNT); i++) {
+            if (fragment_view_depth < uniform_buffer.cascade_splits[i]) {
+                cascade = i;
+                break;
+            }
+        }
+        return filter_shadow(cascade_shadows, first_matrix + cascade, first_layer + float(cascade));
+    } else if (type == LIGHT_SPOT) {
+        return filter_shadow(spot_shadows, first_matrix, first_layer);
+    } else {
+        // Same face order as the CPU side: +X, -X, +Y, -Y, +Z, -Z
+        vec3 direction = -to_light;
+        vec3 magnitude = abs(direction);
+        uint face;
+        if (magnitude.x >= magnitude.y && magnitude.x >= magnitude.z) {
+            face = direction.x > 0.0 ? 0u : 1u;
+        } else if (magnitude.y >= magnitude.z) {
+            face = direction.y > 0.0 ? 2u : 3u;
+        } else {
+            face = direction.z > 0.0 ? 4u : 5u;
+        }
+        return filter_shadow(point_shadows, first_matrix + face, first_layer + float(face));
+    }
+}
+
+vec4 sample_material(Material material, uint slot) {
+    return texture(textures[nonuniformEXT(material.textures[slot])], fragment_texture_coordinate);
+}
+
+// Builds a tangent frame from screen space derivatives, so meshes don't need tangents
+vec3 perturb_normal(vec3 normal, vec3 map_normal) {
+    vec3 position_dx = dFdx(fragment_position);
+    vec3 position_dy = dFdy(fragment_position);
+    vec2 uv_dx = dFdx(fragment_texture_coordinate);
+    vec2 uv_dy = dFdy(fragment_texture_coordinate);
+
+    vec3 dy_perpendicular = cross(position_dy, normal);
+    vec3 dx_perpendicular = cross(normal, position_dx);
+    vec3 tangent = dy_perpendicular * uv_dx.x + dx_perpendicular * uv_dy.x;
+    vec3 bitangent = dy_perpendicular * uv_dx.y + dx_perpendicular * uv_dy.y;
+    float length_squared = max(dot(tangent, tangent), dot(bitangent, bitangent));
+    if (length_squared <= 0.0) {
+        return normal;
+    }
+
+    float scale = inversesqrt(length_squared);
+    return normalize(mat3(tangent * scale, bitangent * scale, normal) * map_normal);
+}
+
+float distribution_ggx(float n_dot_h, float alpha) {
+    float alpha_squared = alpha * alpha;
+    float denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+    return alpha_squared / (PI * denominator * denominator);
+}
+
+// Height correlated Smith, with the 4 * n_dot_l * n_dot_v of the specular BRDF folded in
+float visibility_smith(float n_dot_v, float n_dot_l, float alpha) {
+    float alpha_squared = alpha * alpha;
+    float view = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha_squared) + alpha_squared);
+    float light = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha_squared) + alpha_squared);
+    return 0.5 / max(view + light, 1e-5);
+}
+
+vec3 fresnel_schlick(float cosine, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - cosine, 5.0);
+}
+
+void main() {
+    Material material = materials[fragment_material];
+
+    vec4 base_color = material.base_color * sample_material(material, TEXTURE_BASE_COLOR);
+    // Roughness is in green and metalness in blue, like glTF
+    vec4 metallic_roughness = sample_material(material, TEXTURE_METALLIC_ROUGHNESS);
+    float metallic = clamp(material.metallic_roughness_occlusion.x * metallic_roughness.b, 0.0, 1.0);
+    float roughness = clamp(material.metallic_roughness_occlusion.y * metallic_roughness.g, 0.04, 1.0);
+    float occlusion = mix(
+        1.0,
+        sample_material(material, TEXTURE_OCCLUSION).r,
+        material.metallic_roughness_occlusion.z
+    );
+    vec3 emissive = material.emissive_normal_scale.rgb * sample_material(material, TEXTURE_EMISSIVE).rgb;
+
+    vec3 map_normal = sample_material(material, TEXTURE_NORMAL).xyz * 2.0 - 1.0;
+    map_normal.xy *= material.emissive_normal_scale.w;
+    vec3 normal = perturb_normal(normalize(fragment_normal), normalize(map_normal));
+    vec3 to_camera = normalize(uniform_buffer.camera_position.xyz - fragment_position);
+    float n_dot_v = max(dot(normal, to_camera), 1e-4);
+
+    vec3 diffuse_color = base_color.rgb * (1.0 - metallic);
+    vec3 f0 = mix(vec3(0.04), base_color.rgb, metallic);
+    float alpha = roughness * roughness;
+
+    vec3 color = uniform_buffer.ambient.rgb * (diffuse_color + f0) * occlusion + emissive;
+    for (uint i = 0u; i < min(uniform_buffer.light_info.x, uint(MAX_LIGHTS)); i++) {
+        Light light = uniform_buffer.lights[i];
+        int type = int(light.direction_type.w);
+
+        vec3 to_light;
+        float attenuation = 1.0;
+        if (type == LIGHT_DIRECTIONAL) {
+            to_light = -light.direction_type.xyz;
+        } else {
+            to_light = light.position_range.xyz - fragment_position;
+            float light_distance = length(to_light);
+            to_light /= light_distance;
+            float range = light.position_range.w;
+            float falloff = clamp(1.0 - pow(light_distance / range, 4.0), 0.0, 1.0);
+            attenuation = falloff * falloff / (light_distance * light_distance + 1.0);
+
+            if (type == LIGHT_SPOT) {
+                float cosine = dot(-to_light, light.direction_type.xyz);
+                attenuation *= smoothstep(light.cone_shadow.y, light.cone_shadow.x, cosine);
+            }
+        }
+
+        float n_dot_l = dot(normal, to_light);
+        if (n_dot_l <= 0.0 || attenuation <= 0.0) {
+            continue;
+        }
+
+        vec3 halfway = normalize(to_light + to_camera);
+        float n_dot_h = max(dot(normal, halfway), 0.0);
+        vec3 fresnel = fresnel_schlick(max(dot(halfway, to_camera), 0.0), f0);
+        vec3 specular = fresnel * distribution_ggx(n_dot_h, alpha) * visibility_smith(n_dot_v, n_dot_l, alpha);
+        vec3 diffuse = (1.0 - fresnel) * diffuse_color / PI;
+
+        vec3 radiance = light.color_intensity.rgb * light.color_intensity.a * attenuation;
+        color += (diffuse + specular) * radiance * n_dot_l * shadow_factor(light, to_light);
+    }
+
+    out_color = vec4(color, 1.0);
+}
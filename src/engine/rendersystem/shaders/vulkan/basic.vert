@@ -1,17 +1,51 @@
-#version 460
-
-layout (binding = 0) uniform ubo {
-    mat4 model;
-    mat4 view;
-    mat4 projection;
-} uniform_buffer;
-
-layout (location = 0) in vec3 in_position;
-
-layout (location = 0) out vec4 fragment_color;
-
-void main() {
-    mat4 mvp = uniform_buffer.projection * uniform_buffer.view * uniform_buffer.model;
-    gl_Position = mvp * vec4(in_position, 1);
-    fragment_color = vec4(1.0, 1.0, 1.0, 1.0);
-}
+#version 460
+
+#define CASCADE_COUNT 4
+#define MAX_SHADOW_VIEWS 20
+#define MAX_LIGHTS 16
+
+struct Light {
+    vec4 position_range;
+    vec4 direction_type;
+    vec4 color_intensity;
+    vec4 cone_shadow;
+};
+
+layout (set = 0, binding = 0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 ambient;
+    vec4 cascade_splits;
+    uvec4 light_info;
+    mat4 shadow_matrices[MAX_SHADOW_VIEWS];
+    Light lights[MAX_LIGHTS];
+} uniform_buffer;
+
+layout (push_constant) uniform constants {
+    mat4 view_projection;
+} push_constants;
+
+layout (location = 0) in vec3 in_position;
+layout (location = 1) in vec2 in_texture_coordinate;
+layout (location = 2) in vec3 in_normal;
+layout (location = 3) in mat4 in_model;
+layout (location = 7) in uint in_material;
+
+layout (location = 0) out vec3 fragment_position;
+layout (location = 1) out vec3 fragment_normal;
+layout (location = 2) out vec2 fragment_texture_coordinate;
+layout (location = 3) out float fragment_view_depth;
+layout (location = 4) flat out uint fragment_material;
+
+void main() {
+    vec4 world_position = in_model * vec4(in_position, 1);
+    gl_Position = push_constants.view_projection * world_position;
+
+    fragment_position = world_position.xyz;
+    // Inverse transpose so non-uniform scaling doesn't bend the normals
+    fragment_normal = transpose(inverse(mat3(in_model))) * in_normal;
+    fragment_texture_coordinate = in_texture_coordinate;
+    fragment_view_depth = -(uniform_buffer.view * world_position).z;
+    fragment_material = in_material;
+}
@@ -0,0 +1,62 @@
+#version 460
+
+layout (set = 1, binding = 0) uniform sampler2D source;
+layout (set = 1, binding = 1) uniform sampler2D adapted_luminance;
+
+// Whether this is the first level, threshold, whether there's auto exposure and exposure
+// compensation in stops
+layout (push_constant) uniform constants {
+    vec4 parameters[4];
+} push_constants;
+
+layout (location = 0) in vec2 fragment_texture_coordinate;
+
+layout (location = 0) out vec4 out_color;
+
+// Same as the tonemap shader, so the threshold is in terms of what ends up on screen
+float exposure() {
+    vec4 parameters = push_constants.parameters[0];
+    float scale = exp2(parameters.w);
+    if (parameters.z > 0.5) {
+        scale *= 0.18 / max(texelFetch(adapted_luminance, ivec2(0), 0).r, 1e-4);
+    }
+    return scale;
+}
+
+// 13 overlapping bilinear taps, which keeps things from flickering as they move across texels
+vec3 downsample(vec2 uv) {
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+    vec3 a = texture(source, uv + texel * vec2(-2.0, 2.0)).rgb;
+    vec3 b = texture(source, uv + texel * vec2(0.0, 2.0)).rgb;
+    vec3 c = texture(source, uv + texel * vec2(2.0, 2.0)).rgb;
+    vec3 d = texture(source, uv + texel * vec2(-2.0, 0.0)).rgb;
+    vec3 e = texture(source, uv).rgb;
+    vec3 f = texture(source, uv + texel * vec2(2.0, 0.0)).rgb;
+    vec3 g = texture(source, uv + texel * vec2(-2.0, -2.0)).rgb;
+    vec3 h = texture(source, uv + texel * vec2(0.0, -2.0)).rgb;
+    vec3 i = texture(source, uv + texel * vec2(2.0, -2.0)).rgb;
+    vec3 j = texture(source, uv + texel * vec2(-1.0, 1.0)).rgb;
+    vec3 k = texture(source, uv + texel * vec2(1.0, 1.0)).rgb;
+    vec3 l = texture(source, uv + texel * vec2(-1.0, -1.0)).rgb;
+    vec3 m = texture(source, uv + texel * vec2(1.0, -1.0)).rgb;
+
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+}
+
+void main() {
+    vec3 color = downsample(fragment_texture_coordinate);
+
+    // The first level only keeps what's over the threshold, with a soft knee so it fades in
+    vec4 parameters = push_constants.parameters[0];
+    if (parameters.x > 0.5) {
+        color *= exposure();
+        float threshold = parameters.y;
+        float knee = threshold * 0.5;
+        float brightness = max(color.r, max(color.g, color.b));
+        float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+        soft = soft * soft / (4.0 * knee + 1e-5);
+        color *= max(soft, brightness - threshold) / max(brightness, 1e-5);
+    }
+
+    out_color = vec4(color, 1.0);
+}
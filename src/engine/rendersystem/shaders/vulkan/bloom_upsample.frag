@@ -0,0 +1,26 @@
+#version 460
+
+layout (set = 1, binding = 0) uniform sampler2D current_level;
+layout (set = 1, binding = 1) uniform sampler2D lower_level;
+
+layout (location = 0) in vec2 fragment_texture_coordinate;
+
+layout (location = 0) out vec4 out_color;
+
+// 3x3 tent filter on the smaller level, added onto this one
+void main() {
+    vec2 uv = fragment_texture_coordinate;
+    vec2 texel = 1.0 / vec2(textureSize(lower_level, 0));
+
+    vec3 sum = texture(lower_level, uv).rgb * 4.0;
+    sum += (texture(lower_level, uv + texel * vec2(0.0, -1.0)).rgb
+        + texture(lower_level, uv + texel * vec2(-1.0, 0.0)).rgb
+        + texture(lower_level, uv + texel * vec2(1.0, 0.0)).rgb
+        + texture(lower_level, uv + texel * vec2(0.0, 1.0)).rgb) * 2.0;
+    sum += texture(lower_level, uv + texel * vec2(-1.0, -1.0)).rgb
+        + texture(lower_level, uv + texel * vec2(1.0, -1.0)).rgb
+        + texture(lower_level, uv + texel * vec2(-1.0, 1.0)).rgb
+        + texture(lower_level, uv + texel * vec2(1.0, 1.0)).rgb;
+
+    out_color = vec4(texture(current_level, uv).rgb + sum / 16.0, 1.0);
+}
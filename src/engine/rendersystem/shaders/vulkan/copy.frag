@@ -0,0 +1,12 @@
+#version 460
+
+layout (set = 1, binding = 0) uniform sampler2D source;
+
+layout (location = 0) in vec2 fragment_texture_coordinate;
+
+layout (location = 0) out vec4 out_color;
+
+// Also a 2x2 box filter when the target is half the size, since it's a bilinear tap
+void main() {
+    out_color = texture(source, fragment_texture_coordinate);
+}
@@ -0,0 +1,12 @@
+#version 460
+
+layout (location = 0) in vec4 position;
+layout (location = 1) in vec4 color;
+
+layout (location = 0) out vec4 fragment_color;
+
+// Positions are already in clip space
+void main() {
+    gl_Position = position;
+    fragment_color = color;
+}
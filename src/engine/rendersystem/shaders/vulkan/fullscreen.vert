@@ -0,0 +1,11 @@
+#version 460
+
+layout (location = 0) out vec2 fragment_texture_coordinate;
+
+// One triangle that covers the whole screen, made from the vertex index so nothing needs to be
+// bound. The texture coordinates go from 0 to 1 across the visible part.
+void main() {
+    vec2 texture_coordinate = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(texture_coordinate * 2.0 - 1.0, 0.0, 1.0);
+    fragment_texture_coordinate = texture_coordinate;
+}
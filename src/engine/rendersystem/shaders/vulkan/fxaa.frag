@@ -0,0 +1,155 @@
+#version 460
+
+layout (set = 1, binding = 0) uniform sampler2D source;
+
+// Whether the backbuffer is sRGB
+layout (push_constant) uniform constants {
+    vec4 parameters[4];
+} push_constants;
+
+layout (location = 0) in vec2 fragment_texture_coordinate;
+
+layout (location = 0) out vec4 out_color;
+
+#define EDGE_THRESHOLD_MIN 0.0312
+#define EDGE_THRESHOLD_MAX 0.125
+#define SUBPIXEL_QUALITY 0.75
+#define SEARCH_STEPS 12
+
+const float SEARCH_QUALITY[SEARCH_STEPS] = float[](1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0);
+
+vec3 srgb_decode(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(color, vec3(0.04045)));
+}
+
+// Works on sRGB encoded colors, which is closer to how bright edges look
+float luma(vec2 uv) {
+    return dot(textureLod(source, uv, 0.0).rgb, vec3(0.299, 0.587, 0.114));
+}
+
+float luma_offset(vec2 uv, vec2 texel, vec2 offset) {
+    return luma(uv + offset * texel);
+}
+
+// FXAA 3.11 quality, more or less: find edges from the luma contrast, walk along them to find
+// where they end, and blend across them based on how far along the edge the pixel is
+vec3 fxaa(vec2 uv) {
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+    vec3 color = textureLod(source, uv, 0.0).rgb;
+
+    float luma_center = luma(uv);
+    float luma_down = luma_offset(uv, texel, vec2(0.0, 1.0));
+    float luma_up = luma_offset(uv, texel, vec2(0.0, -1.0));
+    float luma_left = luma_offset(uv, texel, vec2(-1.0, 0.0));
+    float luma_right = luma_offset(uv, texel, vec2(1.0, 0.0));
+
+    float luma_min = min(luma_center, min(min(luma_down, luma_up), min(luma_left, luma_right)));
+    float luma_max = max(luma_center, max(max(luma_down, luma_up), max(luma_left, luma_right)));
+    float range = luma_max - luma_min;
+    if (range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX)) {
+        return color;
+    }
+
+    float luma_down_left = luma_offset(uv, texel, vec2(-1.0, 1.0));
+    float luma_up_right = luma_offset(uv, texel, vec2(1.0, -1.0));
+    float luma_up_left = luma_offset(uv, texel, vec2(-1.0, -1.0));
+    float luma_down_right = luma_offset(uv, texel, vec2(1.0, 1.0));
+
+    float luma_down_up = luma_down + luma_up;
+    float luma_left_right = luma_left + luma_right;
+    float luma_left_corners = luma_down_left + luma_up_left;
+    float luma_down_corners = luma_down_left + luma_down_right;
+    float luma_right_corners = luma_down_right + luma_up_right;
+    float luma_up_corners = luma_up_right + luma_up_left;
+
+    float edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_down_up) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    float edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    bool horizontal = edge_horizontal >= edge_vertical;
+
+    // Which side of the pixel the edge is on
+    float luma_1 = horizontal ? luma_up : luma_left;
+    float luma_2 = horizontal ? luma_down : luma_right;
+    float gradient_1 = luma_1 - luma_center;
+    float gradient_2 = luma_2 - luma_center;
+    bool steepest_1 = abs(gradient_1) >= abs(gradient_2);
+    float gradient_scaled = 0.25 * max(abs(gradient_1), abs(gradient_2));
+
+    float step_length = horizontal ? texel.y : texel.x;
+    float luma_local_average;
+    if (steepest_1) {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_1 + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma_2 + luma_center);
+    }
+
+    vec2 current_uv = uv;
+    if (horizontal) {
+        current_uv.y += step_length * 0.5;
+    } else {
+        current_uv.x += step_length * 0.5;
+    }
+
+    // Walk both ways along the edge until the luma changes enough
+    vec2 offset = horizontal ? vec2(texel.x, 0.0) : vec2(0.0, texel.y);
+    vec2 uv_1 = current_uv - offset;
+    vec2 uv_2 = current_uv + offset;
+    float luma_end_1 = luma(uv_1) - luma_local_average;
+    float luma_end_2 = luma(uv_2) - luma_local_average;
+    bool reached_1 = abs(luma_end_1) >= gradient_scaled;
+    bool reached_2 = abs(luma_end_2) >= gradient_scaled;
+
+    for (int i = 1; i < SEARCH_STEPS && !(reached_1 && reached_2); i++) {
+        if (!reached_1) {
+            uv_1 -= offset * SEARCH_QUALITY[i];
+            luma_end_1 = luma(uv_1) - luma_local_average;
+            reached_1 = abs(luma_end_1) >= gradient_scaled;
+        }
+        if (!reached_2) {
+            uv_2 += offset * SEARCH_QUALITY[i];
+            luma_end_2 = luma(uv_2) - luma_local_average;
+            reached_2 = abs(luma_end_2) >= gradient_scaled;
+        }
+    }
+
+    float distance_1 = horizontal ? uv.x - uv_1.x : uv.y - uv_1.y;
+    float distance_2 = horizontal ? uv_2.x - uv.x : uv_2.y - uv.y;
+    bool direction_1 = distance_1 < distance_2;
+    float distance_final = min(distance_1, distance_2);
+    float edge_length = distance_1 + distance_2;
+    float pixel_offset = -distance_final / edge_length + 0.5;
+
+    // Only blend if the end that's closer agrees with the center about which side is brighter
+    bool center_smaller = luma_center < luma_local_average;
+    bool correct_variation = ((direction_1 ? luma_end_1 : luma_end_2) < 0.0) != center_smaller;
+    float final_offset = correct_variation ? pixel_offset : 0.0;
+
+    // Single pixel features get blended by how different they are from their surroundings
+    float luma_average = (1.0 / 12.0) * (2.0 * (luma_down_up + luma_left_right) + luma_left_corners + luma_right_corners);
+    float subpixel_1 = clamp(abs(luma_average - luma_center) / range, 0.0, 1.0);
+    float subpixel_2 = (-2.0 * subpixel_1 + 3.0) * subpixel_1 * subpixel_1;
+    float subpixel_offset = subpixel_2 * subpixel_2 * SUBPIXEL_QUALITY;
+    final_offset = max(final_offset, subpixel_offset);
+
+    vec2 final_uv = uv;
+    if (horizontal) {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+    return textureLod(source, final_uv, 0.0).rgb;
+}
+
+void main() {
+    vec3 color = fxaa(fragment_texture_coordinate);
+    if (push_constants.parameters[0].x > 0.5) {
+        color = srgb_decode(color);
+    }
+    out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,14 @@
+#version 460
+
+layout (set = 1, binding = 0) uniform sampler2D scene;
+
+layout (location = 0) in vec2 fragment_texture_coordinate;
+
+layout (location = 0) out float out_luminance;
+
+void main() {
+    vec3 color = texture(scene, fragment_texture_coordinate).rgb;
+    float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    // Averaging logs gives the geometric mean, which a few bright spots don't throw off
+    out_luminance = log2(max(luminance, 1e-4));
+}
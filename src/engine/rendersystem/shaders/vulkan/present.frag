@@ -0,0 +1,28 @@
+#version 460
+
+layout (set = 1, binding = 0) uniform sampler2D source;
+
+// Whether the backbuffer is sRGB
+layout (push_constant) uniform constants {
+    vec4 parameters[4];
+} push_constants;
+
+layout (location = 0) in vec2 fragment_texture_coordinate;
+
+layout (location = 0) out vec4 out_color;
+
+vec3 srgb_decode(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(color, vec3(0.04045)));
+}
+
+// The source is already sRGB encoded, so it only has to be decoded if the hardware is going to
+// encode it again
+void main() {
+    vec3 color = texture(source, fragment_texture_coordinate).rgb;
+    if (push_constants.parameters[0].x > 0.5) {
+        color = srgb_decode(color);
+    }
+    out_color = vec4(color, 1.0);
+}
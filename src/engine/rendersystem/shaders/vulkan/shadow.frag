@@ -0,0 +1,5 @@
+#version 460
+
+// Depth only, nothing to write
+void main() {
+}
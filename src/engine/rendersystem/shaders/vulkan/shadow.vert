@@ -0,0 +1,12 @@
+#version 460
+
+layout (push_constant) uniform constants {
+    mat4 view_projection;
+} push_constants;
+
+layout (location = 0) in vec3 in_position;
+layout (location = 3) in mat4 in_model;
+
+void main() {
+    gl_Position = push_constants.view_projection * in_model * vec4(in_position, 1);
+}
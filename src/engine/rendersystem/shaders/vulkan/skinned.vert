@@ -0,0 +1,65 @@
+#version 460
+
+#define CASCADE_COUNT 4
+#define MAX_SHADOW_VIEWS 20
+#define MAX_LIGHTS 16
+
+struct Light {
+    vec4 position_range;
+    vec4 direction_type;
+    vec4 color_intensity;
+    vec4 cone_shadow;
+};
+
+layout (set = 0, binding = 0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 ambient;
+    vec4 cascade_splits;
+    uvec4 light_info;
+    mat4 shadow_matrices[MAX_SHADOW_VIEWS];
+    Light lights[MAX_LIGHTS];
+} uniform_buffer;
+
+// Every skinned instance's joints for the frame, each instance starts at its first joint
+layout (std430, set = 2, binding = 2) readonly buffer joint_buffer {
+    mat4 joints[];
+};
+
+layout (push_constant) uniform constants {
+    mat4 view_projection;
+} push_constants;
+
+layout (location = 0) in vec3 in_position;
+layout (location = 1) in vec2 in_texture_coordinate;
+layout (location = 2) in vec3 in_normal;
+layout (location = 3) in mat4 in_model;
+layout (location = 7) in uint in_material;
+layout (location = 8) in uint in_first_joint;
+layout (location = 9) in uvec4 in_joints;
+layout (location = 10) in vec4 in_weights;
+
+layout (location = 0) out vec3 fragment_position;
+layout (location = 1) out vec3 fragment_normal;
+layout (location = 2) out vec2 fragment_texture_coordinate;
+layout (location = 3) out float fragment_view_depth;
+layout (location = 4) flat out uint fragment_material;
+
+void main() {
+    mat4 skin = in_weights.x * joints[in_first_joint + in_joints.x] +
+                in_weights.y * joints[in_first_joint + in_joints.y] +
+                in_weights.z * joints[in_first_joint + in_joints.z] +
+                in_weights.w * joints[in_first_joint + in_joints.w];
+    mat4 model = in_model * skin;
+
+    vec4 world_position = model * vec4(in_position, 1);
+    gl_Position = push_constants.view_projection * world_position;
+
+    fragment_position = world_position.xyz;
+    // Inverse transpose so non-uniform scaling doesn't bend the normals
+    fragment_normal = transpose(inverse(mat3(model))) * in_normal;
+    fragment_texture_coordinate = in_texture_coordinate;
+    fragment_view_depth = -(uniform_buffer.view * world_position).z;
+    fragment_material = in_material;
+}
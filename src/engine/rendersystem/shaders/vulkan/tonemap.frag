@@ -0,0 +1,106 @@
+#version 460
+
+#extension GL_EXT_nonuniform_qualifier : require
+
+#define MAX_TEXTURES 1024
+
+#define TONE_MAPPER_NONE 0
+#define TONE_MAPPER_REINHARD 1
+#define TONE_MAPPER_ACES 2
+#define TONE_MAPPER_UNCHARTED2 3
+
+layout (set = 1, binding = 0) uniform sampler2D scene;
+layout (set = 1, binding = 1) uniform sampler2D bloom;
+layout (set = 1, binding = 2) uniform sampler2D adapted_luminance;
+
+// Color grading tables come from the same array as material textures
+layout (set = 2, binding = 1) uniform sampler2D textures[MAX_TEXTURES];
+
+// Exposure compensation in stops, whether there's auto exposure, bloom intensity, tone mapper,
+// then whether there's color grading, the table's texture and its size
+layout (push_constant) uniform constants {
+    vec4 parameters[4];
+} push_constants;
+
+layout (location = 0) in vec2 fragment_texture_coordinate;
+
+layout (location = 0) out vec4 out_color;
+
+float exposure() {
+    vec4 parameters = push_constants.parameters[0];
+    float scale = exp2(parameters.x);
+    if (parameters.y > 0.5) {
+        // Puts the average scene luminance at middle grey
+        scale *= 0.18 / max(texelFetch(adapted_luminance, ivec2(0), 0).r, 1e-4);
+    }
+    return scale;
+}
+
+// Narkowicz's fit of the ACES reference rendering transform
+vec3 aces(vec3 color) {
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 uncharted2_curve(vec3 x) {
+    const float A = 0.15;
+    const float B = 0.50;
+    const float C = 0.10;
+    const float D = 0.20;
+    const float E = 0.02;
+    const float F = 0.30;
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+vec3 uncharted2(vec3 color) {
+    const float WHITE_POINT = 11.2;
+    return clamp(uncharted2_curve(color * 2.0) / uncharted2_curve(vec3(WHITE_POINT)), 0.0, 1.0);
+}
+
+vec3 tone_map(vec3 color, int tone_mapper) {
+    if (tone_mapper == TONE_MAPPER_REINHARD) {
+        return color / (1.0 + color);
+    } else if (tone_mapper == TONE_MAPPER_ACES) {
+        return aces(color);
+    } else if (tone_mapper == TONE_MAPPER_UNCHARTED2) {
+        return uncharted2(color);
+    } else {
+        return clamp(color, 0.0, 1.0);
+    }
+}
+
+vec3 srgb_encode(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+// The table is a row of blue slices, each with red across and green down. Two slices get
+// sampled and blended, since filtering only happens within one.
+vec3 color_grade(vec3 color, uint table, float size) {
+    float blue = color.b * (size - 1.0);
+    float slice = floor(blue);
+    vec2 texel = 1.0 / vec2(size * size, size);
+    vec2 inside = color.rg * (size - 1.0) + 0.5;
+    vec2 low = vec2(inside.x + slice * size, inside.y) * texel;
+    vec2 high = vec2(inside.x + min(slice + 1.0, size - 1.0) * size, inside.y) * texel;
+
+    vec3 low_color = textureLod(textures[nonuniformEXT(table)], low, 0.0).rgb;
+    vec3 high_color = textureLod(textures[nonuniformEXT(table)], high, 0.0).rgb;
+    return mix(low_color, high_color, blue - slice);
+}
+
+void main() {
+    vec4 parameters = push_constants.parameters[0];
+    vec4 grading = push_constants.parameters[1];
+
+    // Bloom has already had exposure applied
+    vec3 color = texture(scene, fragment_texture_coordinate).rgb * exposure();
+    color += texture(bloom, fragment_texture_coordinate).rgb * parameters.z;
+
+    color = srgb_encode(tone_map(max(color, vec3(0.0)), int(parameters.w)));
+    if (grading.x > 0.5) {
+        color = color_grade(color, uint(grading.y), grading.z);
+    }
+
+    out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,35 @@
+#version 460
+
+#extension GL_EXT_nonuniform_qualifier : require
+
+#define MAX_TEXTURES 1024
+
+// UI textures come from the same array as material textures
+layout (set = 2, binding = 1) uniform sampler2D textures[MAX_TEXTURES];
+
+// Screen size for the vertex shader, then the texture and whether the backbuffer is sRGB
+layout (push_constant) uniform constants {
+    vec4 parameters[4];
+} push_constants;
+
+layout (location = 0) in vec2 fragment_texture_coordinate;
+layout (location = 1) in vec4 fragment_color;
+
+layout (location = 0) out vec4 out_color;
+
+vec3 srgb_decode(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(color, vec3(0.04045)));
+}
+
+// Colors and textures are sRGB with premultiplied alpha, so they get blended as they are
+// unless the hardware is going to encode them again
+void main() {
+    uint texture_index = uint(push_constants.parameters[0].z);
+    vec4 color = fragment_color * texture(textures[nonuniformEXT(texture_index)], fragment_texture_coordinate);
+    if (push_constants.parameters[0].w > 0.5 && color.a > 0.0) {
+        color.rgb = srgb_decode(color.rgb / color.a) * color.a;
+    }
+    out_color = color;
+}
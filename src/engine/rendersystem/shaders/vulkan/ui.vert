@@ -0,0 +1,21 @@
+#version 460
+
+// Screen size, then the texture and whether the backbuffer is sRGB for the fragment shader
+layout (push_constant) uniform constants {
+    vec4 parameters[4];
+} push_constants;
+
+layout (location = 0) in vec2 position;
+layout (location = 1) in vec2 texture_coordinate;
+layout (location = 2) in vec4 color;
+
+layout (location = 0) out vec2 fragment_texture_coordinate;
+layout (location = 1) out vec4 fragment_color;
+
+// Positions are in pixels from the top left
+void main() {
+    vec2 screen_size = push_constants.parameters[0].xy;
+    gl_Position = vec4(position / screen_size * 2.0 - 1.0, 0.0, 1.0);
+    fragment_texture_coordinate = texture_coordinate;
+    fragment_color = color;
+}
@@ -0,0 +1,234 @@
+use nalgebra::{Matrix4, Point3, Vector3};
+
+pub const CASCADE_COUNT: usize = 4;
+pub const MAX_SPOT_SHADOWS: usize = 4;
+pub const MAX_POINT_SHADOWS: usize = 2;
+// Cascades first, then spot lights, then six faces for each point light
+pub const MAX_SHADOW_VIEWS: usize = CASCADE_COUNT + MAX_SPOT_SHADOWS + MAX_POINT_SHADOWS * 6;
+
+const SHADOW_NEAR: f32 = 0.05;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    pub cascade_resolution: u32,
+    pub spot_resolution: u32,
+    pub point_resolution: u32,
+    // 0 is a single hardware filtered sample, n samples a (2n + 1)^2 grid
+    pub pcf_radius: u32,
+    // Blend between uniform (0) and logarithmic (1) cascade splits
+    pub split_lambda: f32,
+    // The sun's shadows stop this far from the camera
+    pub max_distance: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            cascade_resolution: 2048,
+            spot_resolution: 1024,
+            point_resolution: 512,
+            pcf_radius: 1,
+            split_lambda: 0.75,
+            max_distance: 100.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowMap {
+    Cascades,
+    Spot,
+    Point,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowView {
+    pub map: ShadowMap,
+    pub layer: u32,
+    pub view_projection: Matrix4<f32>,
+}
+
+// Right handed with depth from 0 to 1, which is what Vulkan wants
+pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Matrix4<f32> {
+    let focal_length = 1.0 / (fov_y / 2.0).tan();
+    Matrix4::new(
+        focal_length / aspect,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        focal_length,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        far / (near - far),
+        near * far / (near - far),
+        0.0,
+        0.0,
+        -1.0,
+        0.0,
+    )
+}
+
+pub fn orthographic(
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    near: f32,
+    far: f32,
+) -> Matrix4<f32> {
+    Matrix4::new(
+        2.0 / (right - left),
+        0.0,
+        0.0,
+        -(right + left) / (right - left),
+        0.0,
+        2.0 / (top - bottom),
+        0.0,
+        -(top + bottom) / (top - bottom),
+        0.0,
+        0.0,
+        -1.0 / (far - near),
+        -near / (far - near),
+        0.0,
+        0.0,
+        0.0,
+        1.0,
+    )
+}
+
+fn up_for(direction: &Vector3<f32>) -> Vector3<f32> {
+    if direction.y.abs() > 0.99 {
+        Vector3::z()
+    } else {
+        Vector3::y()
+    }
+}
+
+// Splits the camera frustum and fits an orthographic projection around each slice. Returns
+// the view space depth each cascade ends at along with the matrices.
+pub fn cascades(
+    view: &Matrix4<f32>,
+    projection: &Matrix4<f32>,
+    direction: &Vector3<f32>,
+    settings: &ShadowSettings,
+) -> ([f32; CASCADE_COUNT], [Matrix4<f32>; CASCADE_COUNT]) {
+    let inverse = (projection * view)
+        .try_inverse()
+        .unwrap_or_else(Matrix4::identity);
+    let camera = view
+        .try_inverse()
+        .unwrap_or_else(Matrix4::identity)
+        .transform_point(&Point3::origin());
+
+    let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+    let near_corners = corners.map(|(x, y)| inverse.transform_point(&Point3::new(x, y, 0.0)));
+    let far_corners = corners.map(|(x, y)| inverse.transform_point(&Point3::new(x, y, 1.0)));
+    let center = |points: &[Point3<f32>; 4]| {
+        Point3::from(
+            points
+                .iter()
+                .map(|point| point.coords)
+                .sum::<Vector3<f32>>()
+                / 4.0,
+        )
+    };
+    let near = (center(&near_corners) - camera).norm().max(SHADOW_NEAR);
+    let frustum_far = (center(&far_corners) - camera).norm().max(near + 1.0);
+    let far = frustum_far.min(settings.max_distance);
+
+    let rotation = Matrix4::look_at_rh(
+        &Point3::origin(),
+        &Point3::from(*direction),
+        &up_for(direction),
+    );
+
+    let mut splits = [0.0; CASCADE_COUNT];
+    let mut matrices = [Matrix4::identity(); CASCADE_COUNT];
+    let mut start = near;
+    for i in 0..CASCADE_COUNT {
+        let fraction = (i + 1) as f32 / CASCADE_COUNT as f32;
+        let logarithmic = near * (far / near).powf(fraction);
+        let uniform = near + (far - near) * fraction;
+        let end = settings.split_lambda * logarithmic + (1.0 - settings.split_lambda) * uniform;
+
+        let slice: Vec<Point3<f32>> = [start, end]
+            .iter()
+            .flat_map(|distance| {
+                let t = (distance - near) / (frustum_far - near);
+                (0..4).map(move |corner| {
+                    near_corners[corner] + (far_corners[corner] - near_corners[corner]) * t
+                })
+            })
+            .collect();
+        let slice_center = Point3::from(
+            slice.iter().map(|point| point.coords).sum::<Vector3<f32>>() / slice.len() as f32,
+        );
+        // A bounding sphere keeps the projection the same size as the camera turns, and
+        // snapping to texels stops the edges from crawling as it moves
+        let radius = slice
+            .iter()
+            .map(|point| (point - slice_center).norm())
+            .fold(0.0, f32::max);
+        let radius = (radius * 16.0).ceil() / 16.0;
+        let texel = radius * 2.0 / settings.cascade_resolution as f32;
+        let light_center = rotation.transform_point(&slice_center);
+        let x = (light_center.x / texel).floor() * texel;
+        let y = (light_center.y / texel).floor() * texel;
+
+        // Pulled back towards the light so casters outside the slice aren't clipped
+        let projection = orthographic(
+            x - radius,
+            x + radius,
+            y - radius,
+            y + radius,
+            -light_center.z - radius * 4.0,
+            -light_center.z + radius,
+        );
+
+        splits[i] = end;
+        matrices[i] = projection * rotation;
+        start = end;
+    }
+
+    (splits, matrices)
+}
+
+pub fn spot(
+    position: &Point3<f32>,
+    direction: &Vector3<f32>,
+    outer_angle: f32,
+    range: f32,
+) -> Matrix4<f32> {
+    let view = Matrix4::look_at_rh(position, &(position + direction), &up_for(direction));
+    perspective(
+        (outer_angle * 2.0).min(179f32.to_radians()),
+        1.0,
+        SHADOW_NEAR,
+        range.max(SHADOW_NEAR * 2.0),
+    ) * view
+}
+
+// In the order the shaders pick faces: +X, -X, +Y, -Y, +Z, -Z
+pub fn point_faces(position: &Point3<f32>, range: f32) -> [Matrix4<f32>; 6] {
+    let faces = [
+        (Vector3::x(), -Vector3::y()),
+        (-Vector3::x(), -Vector3::y()),
+        (Vector3::y(), Vector3::z()),
+        (-Vector3::y(), -Vector3::z()),
+        (Vector3::z(), -Vector3::y()),
+        (-Vector3::z(), -Vector3::y()),
+    ];
+    let projection = perspective(
+        90f32.to_radians(),
+        1.0,
+        SHADOW_NEAR,
+        range.max(SHADOW_NEAR * 2.0),
+    );
+
+    faces.map(|(direction, up)| {
+        projection * Matrix4::look_at_rh(position, &(position + direction), &up)
+    })
+}
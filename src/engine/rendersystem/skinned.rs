@@ -0,0 +1,355 @@
+use super::{as_bytes, bounds, Material, MeshHandle, Model, State};
+use crate::engine::animation::{self, Clip, Skeleton};
+use log::{debug, error, info, warn};
+use nalgebra::{Matrix4, Point3, Vector2, Vector3};
+use std::{mem, sync::Arc};
+
+// How many skinning matrices can be drawn in a frame, across every instance
+pub const MAX_JOINT_MATRICES: usize = 16384;
+
+#[derive(PartialEq)]
+#[repr(C)]
+pub struct SkinnedVertex {
+    position: Vector3<f32>,
+    texture_coordinate: Vector2<f32>,
+    normal: Vector3<f32>,
+    // Up to four joints that move the vertex and how much each one does
+    joints: [u16; 4],
+    weights: [f32; 4],
+}
+
+// One mesh per material, None is the default material
+type SkinnedMeshes = Vec<(MeshHandle, Option<usize>)>;
+
+pub struct SkinnedModel {
+    name: String,
+    meshes: SkinnedMeshes,
+    materials: Vec<Material>,
+    skeleton: Arc<Skeleton>,
+    // Around the bind pose vertices each joint moves the most, which follow the joint
+    // closely enough for culling
+    joint_bounds: Vec<Option<bounds::Aabb>>,
+    // In the bind pose
+    bounds: bounds::Bounds,
+    clips: Vec<Arc<Clip>>,
+}
+
+impl SkinnedModel {
+    // Loads name.gltf from the game's model directory, using its first skin and every mesh
+    // skinned to it. Materials are loaded by the names the file gives them like Model::load,
+    // and any animations in the file are loaded as clips.
+    pub fn load(state: &mut crate::engine::State, name: &str) -> Result<Self, String> {
+        let path = format!("{}{name}.gltf", crate::engine::GameDirs::models());
+        let (document, buffers) = animation::import::open(&path).map_err(|err| {
+            error!("Failed to load skinned model {path}: {err}");
+            err
+        })?;
+        let (skeleton, remap) =
+            animation::import::skeleton(&document, &buffers).map_err(|err| {
+                error!("Failed to load skeleton from {path}: {err}");
+                err
+            })?;
+        let clips = animation::import::clips(&document, &buffers, &skeleton)
+            .into_iter()
+            .map(Arc::new)
+            .collect();
+
+        // Materials are loaded in the file's order, so a primitive's material index works
+        // for both
+        let mut materials = Vec::new();
+        for material in document.materials() {
+            let material_name = material.name().map_or_else(
+                || format!("{name} {}", material.index().unwrap_or(0)),
+                String::from,
+            );
+            match Material::load(state, &material_name)
+                .or_else(|err| Material::builtin(state.render_state()).ok_or(err))
+            {
+                Ok(material) => materials.push(material),
+                Err(err) => {
+                    for material in materials {
+                        material.destroy(state.render_state());
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let mut groups: Vec<(Option<usize>, Vec<SkinnedVertex>, Vec<u32>)> = Vec::new();
+        let skinned_nodes = document
+            .nodes()
+            .filter(|node| node.skin().is_some_and(|skin| skin.index() == 0));
+        for node in skinned_nodes {
+            let Some(mesh) = node.mesh() else {
+                continue;
+            };
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    warn!(
+                        "Skipping {:?} primitive in skinned model {name}, only triangles are supported",
+                        primitive.mode()
+                    );
+                    continue;
+                }
+
+                let material = primitive.material().index();
+                let group = match groups.iter().position(|group| group.0 == material) {
+                    Some(group) => group,
+                    None => {
+                        groups.push((material, Vec::new(), Vec::new()));
+                        groups.len() - 1
+                    }
+                };
+                let (_, all_vertices, all_indices) = &mut groups[group];
+                let base_vertex = all_vertices.len() as u32;
+                if let Err(err) =
+                    Self::read_primitive(&primitive, &buffers, &remap, all_vertices, all_indices)
+                {
+                    error!("Failed to load skinned model {name}: {err}");
+                    for material in materials {
+                        material.destroy(state.render_state());
+                    }
+                    return Err(err);
+                }
+                debug!(
+                    "Loaded {} vertices for mesh {} in skinned model {name}",
+                    all_vertices.len() as u32 - base_vertex,
+                    mesh.name().unwrap_or("")
+                );
+            }
+        }
+
+        Self::new(
+            state.render_state(),
+            name,
+            &groups,
+            materials,
+            skeleton,
+            clips,
+        )
+    }
+
+    // Appends the primitive's vertices and indices, with joints going to the skeleton's order
+    fn read_primitive(
+        primitive: &gltf::Primitive,
+        buffers: &[gltf::buffer::Data],
+        remap: &[usize],
+        vertices: &mut Vec<SkinnedVertex>,
+        indices: &mut Vec<u32>,
+    ) -> Result<(), String> {
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<f32> = reader
+            .read_positions()
+            .ok_or_else(|| String::from("primitive has no positions"))?
+            .flatten()
+            .collect();
+        let vertex_count = positions.len() / 3;
+        let joints: Vec<[u16; 4]> = reader
+            .read_joints(0)
+            .ok_or_else(|| String::from("primitive has no joints"))?
+            .into_u16()
+            .collect();
+        let weights: Vec<[f32; 4]> = reader
+            .read_weights(0)
+            .ok_or_else(|| String::from("primitive has no weights"))?
+            .into_f32()
+            .collect();
+        if joints.len() != vertex_count || weights.len() != vertex_count {
+            return Err(String::from(
+                "primitive has a different number of joints or weights than positions",
+            ));
+        }
+        let texture_coordinates: Vec<[f32; 2]> = reader
+            .read_tex_coords(0)
+            .map(|coordinates| coordinates.into_f32().collect())
+            .unwrap_or_default();
+        let primitive_indices: Vec<u32> = match reader.read_indices() {
+            Some(primitive_indices) => primitive_indices.into_u32().collect(),
+            None => (0..vertex_count as u32).collect(),
+        };
+        let normals: Vec<f32> = match reader.read_normals() {
+            Some(normals) => normals.flatten().collect(),
+            None => Model::generate_normals(&positions, &primitive_indices),
+        };
+
+        let base_vertex = vertices.len() as u32;
+        for i in 0..vertex_count {
+            let joints = joints[i].map(|joint| match remap.get(joint as usize) {
+                Some(joint) => *joint as u16,
+                None => 0,
+            });
+            // Weights are meant to add up to 1, but exporters don't always manage it
+            let total: f32 = weights[i].iter().sum();
+            let weights = if total > 0.0 {
+                weights[i].map(|weight| weight / total)
+            } else {
+                [1.0, 0.0, 0.0, 0.0]
+            };
+            vertices.push(SkinnedVertex {
+                position: Vector3::new(
+                    positions[i * 3],
+                    positions[i * 3 + 1],
+                    positions[i * 3 + 2],
+                ),
+                texture_coordinate: texture_coordinates
+                    .get(i)
+                    .map_or_else(Vector2::zeros, |coordinate| Vector2::from(*coordinate)),
+                normal: Vector3::new(normals[i * 3], normals[i * 3 + 1], normals[i * 3 + 2]),
+                joints,
+                weights,
+            });
+        }
+        indices.extend(primitive_indices.iter().map(|index| index + base_vertex));
+
+        Ok(())
+    }
+
+    // Each group is a mesh with the material at its index, the materials belong to the model
+    // afterwards. Vertices should be in the skeleton's bind pose.
+    fn new(
+        state: &mut State,
+        name: &str,
+        groups: &[(Option<usize>, Vec<SkinnedVertex>, Vec<u32>)],
+        materials: Vec<Material>,
+        skeleton: Skeleton,
+        clips: Vec<Arc<Clip>>,
+    ) -> Result<Self, String> {
+        if !state.backend.is_initialized() {
+            error!(
+                "Not creating skinned model {name} because the render backend isn't initialized"
+            );
+            for material in materials {
+                material.destroy(state);
+            }
+            return Err(String::from("render backend not initialized"));
+        }
+
+        info!(
+            "Creating skinned model {name} with {} joint(s) and {} clip(s)",
+            skeleton.len(),
+            clips.len()
+        );
+
+        let mut meshes = Vec::with_capacity(groups.len());
+        let mut joint_points: Vec<Vec<Point3<f32>>> = vec![Vec::new(); skeleton.len()];
+        for (material, vertices, indices) in groups {
+            for vertex in vertices {
+                let strongest = (0..4)
+                    .max_by(|a, b| vertex.weights[*a].total_cmp(&vertex.weights[*b]))
+                    .unwrap();
+                if let Some(points) = joint_points.get_mut(vertex.joints[strongest] as usize) {
+                    points.push(Point3::from(vertex.position));
+                }
+            }
+
+            let material = material.filter(|material| *material < materials.len());
+            match state.backend.create_mesh(
+                as_bytes(vertices),
+                mem::size_of::<SkinnedVertex>(),
+                indices,
+            ) {
+                Ok(mesh) => meshes.push((mesh, material)),
+                Err(err) => {
+                    for (mesh, _) in meshes {
+                        state.backend.destroy_mesh(mesh);
+                    }
+                    for material in materials {
+                        material.destroy(state);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let points: Vec<Point3<f32>> = joint_points.iter().flatten().copied().collect();
+        let joint_bounds = joint_points
+            .iter()
+            .map(|points| (!points.is_empty()).then(|| bounds::Aabb::from_points(points)))
+            .collect();
+        Ok(Self {
+            name: String::from(name),
+            meshes,
+            materials,
+            skeleton: Arc::new(skeleton),
+            joint_bounds,
+            bounds: bounds::Bounds::from_points(&points),
+            clips,
+        })
+    }
+
+    pub fn skeleton(&self) -> &Arc<Skeleton> {
+        &self.skeleton
+    }
+
+    // The animations that came with the model
+    pub fn clips(&self) -> &[Arc<Clip>] {
+        &self.clips
+    }
+
+    pub fn clip(&self, name: &str) -> Option<Arc<Clip>> {
+        self.clips.iter().find(|clip| clip.name() == name).cloned()
+    }
+
+    // In model space, in the bind pose
+    pub fn bounds(&self) -> bounds::Bounds {
+        self.bounds
+    }
+
+    // Around the model in a pose, from the same matrices render takes
+    pub fn posed_bounds(&self, skinning: &[Matrix4<f32>]) -> bounds::Bounds {
+        let aabb = self
+            .joint_bounds
+            .iter()
+            .zip(skinning)
+            .filter_map(|(aabb, matrix)| aabb.map(|aabb| aabb.transform(matrix)))
+            .reduce(|all, aabb| all.union(&aabb));
+        match aabb {
+            Some(aabb) => bounds::Bounds {
+                aabb,
+                sphere: bounds::Sphere::new(aabb.center(), aabb.extents().norm()),
+            },
+            None => self.bounds,
+        }
+    }
+
+    // Draws the model posed by skinning matrices for its skeleton, usually from
+    // Animator::skinning_matrices
+    pub fn render(&self, state: &mut State, transform: &Matrix4<f32>, skinning: &[Matrix4<f32>]) {
+        if !state.backend.is_in_frame() {
+            return;
+        }
+        if skinning.len() != self.skeleton.len() {
+            error!(
+                "Not drawing skinned model {} with {} joint matrices for {} joints",
+                self.name,
+                skinning.len(),
+                self.skeleton.len()
+            );
+            return;
+        }
+        let Some(first_joint) = state.queue_joints(skinning) else {
+            return;
+        };
+
+        let bounds = self.posed_bounds(skinning).transform(transform);
+        for (mesh, material) in &self.meshes {
+            let material = match material {
+                Some(material) => Some(self.materials[*material].handle),
+                None => state.default_material,
+            };
+            if let Some(material) = material {
+                state.queue_skinned_draw(*mesh, material, *transform, Some(bounds), first_joint);
+            }
+        }
+    }
+
+    pub fn destroy(self, state: &mut State) {
+        info!("Destroying skinned model {}", self.name);
+        for (mesh, _) in self.meshes {
+            state.backend.destroy_mesh(mesh);
+        }
+        for material in self.materials {
+            material.destroy(state);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use super::TextureHandle;
+
+// Colors are sRGB with premultiplied alpha, which is what most UI libraries make
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct UiVertex {
+    // In pixels from the top left of the screen
+    pub position: [f32; 2],
+    pub texture_coordinate: [f32; 2],
+    pub color: [u8; 4],
+}
+
+// Triangles drawn over everything else with one texture, in the order they're queued
+pub struct UiMesh {
+    pub vertices: Vec<UiVertex>,
+    pub indices: Vec<u32>,
+    pub texture: TextureHandle,
+    // Min x, min y, max x and max y in pixels, nothing outside this gets drawn
+    pub clip: [f32; 4],
+}
+
+// Where a UiMesh ended up in the frame's buffers
+#[derive(Clone, Copy, Debug)]
+pub struct UiDraw {
+    pub texture: TextureHandle,
+    // X, Y, width and height in pixels
+    pub scissor: [u32; 4],
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+}
+
+// Puts every mesh in the same vertex and index buffer. Meshes that are clipped out completely
+// are skipped.
+pub fn batch(meshes: &[UiMesh], screen_size: (u32, u32)) -> (Vec<UiVertex>, Vec<u32>, Vec<UiDraw>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut draws = Vec::new();
+    for mesh in meshes {
+        let clamp = |value: f32, max: u32| (value.round().max(0.0) as u32).min(max);
+        let min_x = clamp(mesh.clip[0], screen_size.0);
+        let min_y = clamp(mesh.clip[1], screen_size.1);
+        let max_x = clamp(mesh.clip[2], screen_size.0);
+        let max_y = clamp(mesh.clip[3], screen_size.1);
+        if max_x <= min_x || max_y <= min_y || mesh.indices.is_empty() {
+            continue;
+        }
+
+        draws.push(UiDraw {
+            texture: mesh.texture,
+            scissor: [min_x, min_y, max_x - min_x, max_y - min_y],
+            first_index: indices.len() as u32,
+            index_count: mesh.indices.len() as u32,
+            vertex_offset: vertices.len() as i32,
+        });
+        vertices.extend_from_slice(&mesh.vertices);
+        indices.extend_from_slice(&mesh.indices);
+    }
+
+    (vertices, indices, draws)
+}
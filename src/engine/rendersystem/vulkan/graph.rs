@@ -0,0 +1,879 @@
+use super::{Buffer, Image, State, FRAME_COUNT, MAX_PASS_TEXTURES};
+use crate::engine::rendersystem::graph::{
+    Attachment, CompiledGraph, CompiledPass, LoadOp, ResourceDesc, ResourceHandle, ResourceState,
+    TextureDesc, TextureFormat, Transition,
+};
+use ash::vk;
+use log::{debug, error, trace};
+use std::{mem, ptr};
+
+// The acquire semaphore is waited on at these stages, so the first barrier on the backbuffer
+// has to start from them too
+pub const BACKBUFFER_WAIT_STAGES: vk::PipelineStageFlags = vk::PipelineStageFlags::from_raw(
+    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT.as_raw()
+        | vk::PipelineStageFlags::TRANSFER.as_raw(),
+);
+
+pub struct GraphTexture {
+    desc: TextureDesc,
+    extent: vk::Extent2D,
+    image: Image,
+    // One for each layer of array textures, so passes can render to them separately
+    layer_views: Vec<vk::ImageView>,
+    state: ResourceState,
+    // Last pass of this frame that uses it, None if nothing has claimed it yet
+    busy_until: Option<usize>,
+    last_frame: u64,
+    // Name of the history texture this belongs to, these are never shared
+    history: Option<String>,
+}
+
+pub struct GraphBuffer {
+    buffer: Buffer,
+    state: ResourceState,
+    busy_until: Option<usize>,
+    last_frame: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PhysicalResource {
+    Backbuffer,
+    Texture(usize),
+    Buffer(usize),
+}
+
+// Everything about a pass that a pipeline has to be created for
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PassFormats {
+    pub color_formats: Vec<vk::Format>,
+    pub depth_format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl Default for PassFormats {
+    fn default() -> Self {
+        Self {
+            color_formats: Vec::new(),
+            depth_format: vk::Format::UNDEFINED,
+            samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+}
+
+fn state_access(
+    state: ResourceState,
+) -> (vk::ImageLayout, vk::PipelineStageFlags, vk::AccessFlags) {
+    let shader_stages = vk::PipelineStageFlags::VERTEX_SHADER
+        | vk::PipelineStageFlags::FRAGMENT_SHADER
+        | vk::PipelineStageFlags::COMPUTE_SHADER;
+    let depth_stages =
+        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+
+    match state {
+        ResourceState::Undefined => (
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::AccessFlags::empty(),
+        ),
+        ResourceState::ColorAttachment => (
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        ),
+        ResourceState::DepthAttachment => (
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            depth_stages,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ),
+        ResourceState::DepthRead => (
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            depth_stages | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ,
+        ),
+        ResourceState::ShaderRead => (
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            shader_stages,
+            vk::AccessFlags::SHADER_READ,
+        ),
+        ResourceState::StorageRead => (
+            vk::ImageLayout::GENERAL,
+            shader_stages,
+            vk::AccessFlags::SHADER_READ,
+        ),
+        ResourceState::StorageWrite => (
+            vk::ImageLayout::GENERAL,
+            shader_stages,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        ),
+        ResourceState::IndirectRead => (
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::DRAW_INDIRECT,
+            vk::AccessFlags::INDIRECT_COMMAND_READ,
+        ),
+        ResourceState::TransferSrc => (
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_READ,
+        ),
+        ResourceState::TransferDst => (
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        ),
+        ResourceState::Present => (
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::AccessFlags::empty(),
+        ),
+    }
+}
+
+pub fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+fn attachment_load_op(load: LoadOp, depth: bool) -> (vk::AttachmentLoadOp, vk::ClearValue) {
+    match load {
+        LoadOp::Load => (vk::AttachmentLoadOp::LOAD, vk::ClearValue::default()),
+        LoadOp::Clear(value) => (
+            vk::AttachmentLoadOp::CLEAR,
+            if depth {
+                vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: value[0],
+                        stencil: 0,
+                    },
+                }
+            } else {
+                vk::ClearValue {
+                    color: vk::ClearColorValue { float32: value },
+                }
+            },
+        ),
+        LoadOp::DontCare => (vk::AttachmentLoadOp::DONT_CARE, vk::ClearValue::default()),
+    }
+}
+
+impl State {
+    pub(super) fn texture_format(&self, format: TextureFormat) -> vk::Format {
+        match format {
+            TextureFormat::Rgba8 => vk::Format::R8G8B8A8_UNORM,
+            TextureFormat::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+            TextureFormat::R16Float => vk::Format::R16_SFLOAT,
+            TextureFormat::R32Float => vk::Format::R32_SFLOAT,
+            TextureFormat::Depth => self.depth_format,
+            TextureFormat::Surface => self.surface_format.format,
+        }
+    }
+
+    fn create_graph_texture(
+        &self,
+        name: &str,
+        desc: TextureDesc,
+        extent: vk::Extent2D,
+    ) -> GraphTexture {
+        let format = self.texture_format(desc.format);
+        debug!(
+            "Creating {}x{} {format:#?} render graph texture for {name}",
+            extent.width, extent.height
+        );
+
+        let usage = if desc.format.is_depth() {
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+        } else {
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST
+        };
+        let image = vulkan_check!(Image::new(
+            &self.device,
+            &self.allocator,
+            format,
+            &mut vk::ImageCreateInfo {
+                extent: vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1
+                },
+                mip_levels: 1,
+                array_layers: desc.layers,
+                samples: vk::SampleCountFlags::from_raw(desc.samples),
+                usage,
+                image_type: vk::ImageType::TYPE_2D,
+                ..Default::default()
+            },
+            &mut vk::ImageViewCreateInfo {
+                view_type: if desc.layers > 1 {
+                    vk::ImageViewType::TYPE_2D_ARRAY
+                } else {
+                    vk::ImageViewType::TYPE_2D
+                },
+                subresource_range: vk::ImageSubresourceRange {
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: desc.layers,
+                    // Views can only have one aspect for sampling
+                    aspect_mask: if desc.format.is_depth() {
+                        vk::ImageAspectFlags::DEPTH
+                    } else {
+                        vk::ImageAspectFlags::COLOR
+                    },
+                },
+                ..Default::default()
+            },
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::AutoPreferDevice,
+                ..Default::default()
+            }
+        ));
+
+        let layer_views = if desc.layers > 1 {
+            (0..desc.layers)
+                .map(|layer| unsafe {
+                    vulkan_check!(self.device.create_image_view(
+                        &vk::ImageViewCreateInfo {
+                            image: *image.handle(),
+                            view_type: vk::ImageViewType::TYPE_2D,
+                            format,
+                            subresource_range: vk::ImageSubresourceRange {
+                                aspect_mask: aspect_mask(format),
+                                base_mip_level: 0,
+                                level_count: 1,
+                                base_array_layer: layer,
+                                layer_count: 1,
+                            },
+                            ..Default::default()
+                        },
+                        Some(&State::get_allocation_callbacks())
+                    ))
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        GraphTexture {
+            desc,
+            extent,
+            image,
+            layer_views,
+            state: ResourceState::Undefined,
+            busy_until: None,
+            last_frame: 0,
+            history: None,
+        }
+    }
+
+    // History textures get read before anything has written them, so they start out cleared
+    fn clear_graph_texture(&mut self, index: usize) {
+        let command_buffer = self.command_buffers[self.frame_index];
+        let texture = &mut self.graph_textures[index];
+        let aspect_mask = aspect_mask(texture.image.format());
+        let range = vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: texture.desc.layers,
+        };
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[vk::ImageMemoryBarrier {
+                    src_access_mask: vk::AccessFlags::empty(),
+                    dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                    old_layout: vk::ImageLayout::UNDEFINED,
+                    new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    image: *texture.image.handle(),
+                    subresource_range: range,
+                    ..Default::default()
+                }],
+            );
+            if aspect_mask.contains(vk::ImageAspectFlags::DEPTH) {
+                self.device.cmd_clear_depth_stencil_image(
+                    command_buffer,
+                    *texture.image.handle(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &vk::ClearDepthStencilValue::default(),
+                    &[range],
+                );
+            } else {
+                self.device.cmd_clear_color_image(
+                    command_buffer,
+                    *texture.image.handle(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &vk::ClearColorValue::default(),
+                    &[range],
+                );
+            }
+        }
+        texture.state = ResourceState::TransferDst;
+    }
+
+    fn destroy_graph_texture(&self, mut texture: GraphTexture) {
+        for view in texture.layer_views.drain(..) {
+            unsafe {
+                self.device
+                    .destroy_image_view(view, Some(&State::get_allocation_callbacks()))
+            };
+        }
+        texture.image.destroy(&self.device, &self.allocator);
+    }
+
+    // Picks an image or buffer for every resource the graph uses, reusing ones from earlier
+    // frames and ones that earlier passes in this frame are done with
+    pub(super) fn assign_graph_resources(&mut self, graph: &CompiledGraph) {
+        // Anything that wasn't used by any frame still in flight can go
+        let frame_number = self.frame_number;
+        let (textures, stale): (Vec<GraphTexture>, Vec<GraphTexture>) = self
+            .graph_textures
+            .drain(..)
+            .partition(|texture| texture.last_frame + FRAME_COUNT as u64 > frame_number);
+        for texture in stale {
+            debug!(
+                "Destroying unused render graph texture {:#?}",
+                texture.image.handle()
+            );
+            self.destroy_graph_texture(texture);
+        }
+        self.graph_textures = textures;
+        let (buffers, stale): (Vec<GraphBuffer>, Vec<GraphBuffer>) = self
+            .graph_buffers
+            .drain(..)
+            .partition(|buffer| buffer.last_frame + FRAME_COUNT as u64 > frame_number);
+        for buffer in stale {
+            debug!(
+                "Destroying unused render graph buffer {:#?}",
+                buffer.buffer.handle()
+            );
+            buffer.buffer.destroy(&self.allocator);
+        }
+        self.graph_buffers = buffers;
+
+        for texture in &mut self.graph_textures {
+            texture.busy_until = None;
+        }
+        for buffer in &mut self.graph_buffers {
+            buffer.busy_until = None;
+        }
+
+        let mut order: Vec<usize> = (0..graph.resources.len())
+            .filter(|index| graph.resources[*index].is_some())
+            .collect();
+        order.sort_by_key(|index| graph.resources[*index].as_ref().unwrap().first_pass);
+
+        let backbuffer_size = (self.swapchain_extent.width, self.swapchain_extent.height);
+        self.graph_resources = vec![None; graph.resources.len()];
+        for index in order {
+            let resource = graph.resources[index].as_ref().unwrap();
+            let free = |busy_until: Option<usize>| {
+                busy_until.map_or(true, |last_pass| last_pass < resource.first_pass)
+            };
+
+            self.graph_resources[index] = Some(match resource.desc {
+                ResourceDesc::Backbuffer => PhysicalResource::Backbuffer,
+                ResourceDesc::Texture(desc) => {
+                    let (width, height) = desc.size.resolve(backbuffer_size);
+                    let extent = vk::Extent2D { width, height };
+                    // History textures have to get the same image back every frame
+                    let history = resource.history.then(|| resource.name.clone());
+                    let texture_index = match self.graph_textures.iter().position(|texture| {
+                        texture.desc.format == desc.format
+                            && texture.desc.layers == desc.layers
+                            && texture.desc.samples == desc.samples
+                            && texture.extent == extent
+                            && texture.history == history
+                            && (history.is_some() || free(texture.busy_until))
+                    }) {
+                        Some(texture_index) => texture_index,
+                        None => {
+                            let mut texture =
+                                self.create_graph_texture(&resource.name, desc, extent);
+                            texture.history = history;
+                            self.graph_textures.push(texture);
+                            let texture_index = self.graph_textures.len() - 1;
+                            if resource.history {
+                                self.clear_graph_texture(texture_index);
+                            }
+                            texture_index
+                        }
+                    };
+
+                    let texture = &mut self.graph_textures[texture_index];
+                    texture.busy_until = Some(resource.last_pass);
+                    texture.last_frame = frame_number;
+                    trace!(
+                        "Render graph resource {} is texture {texture_index}",
+                        resource.name
+                    );
+                    PhysicalResource::Texture(texture_index)
+                }
+                ResourceDesc::Buffer(size) => {
+                    let buffer_index =
+                        match self.graph_buffers.iter().position(|buffer| {
+                            buffer.buffer.size() >= size && free(buffer.busy_until)
+                        }) {
+                            Some(buffer_index) => buffer_index,
+                            None => {
+                                debug!(
+                                    "Creating {size} byte render graph buffer for {}",
+                                    resource.name
+                                );
+                                let buffer = vulkan_check!(Buffer::new(
+                                    &self.allocator,
+                                    size,
+                                    vk::BufferUsageFlags::STORAGE_BUFFER
+                                        | vk::BufferUsageFlags::INDIRECT_BUFFER
+                                        | vk::BufferUsageFlags::TRANSFER_SRC
+                                        | vk::BufferUsageFlags::TRANSFER_DST,
+                                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                                ));
+                                self.graph_buffers.push(GraphBuffer {
+                                    buffer,
+                                    state: ResourceState::Undefined,
+                                    busy_until: None,
+                                    last_frame: 0,
+                                });
+                                self.graph_buffers.len() - 1
+                            }
+                        };
+
+                    let buffer = &mut self.graph_buffers[buffer_index];
+                    buffer.busy_until = Some(resource.last_pass);
+                    buffer.last_frame = frame_number;
+                    PhysicalResource::Buffer(buffer_index)
+                }
+            });
+        }
+
+        self.backbuffer_state = ResourceState::Undefined;
+    }
+
+    // Image, view, format, size and sample count of whatever the graph assigned to a resource
+    pub(super) fn graph_image(
+        &self,
+        resource: ResourceHandle,
+    ) -> Option<(
+        vk::Image,
+        vk::ImageView,
+        vk::Format,
+        vk::Extent2D,
+        vk::SampleCountFlags,
+    )> {
+        match self
+            .graph_resources
+            .get(resource.index())
+            .copied()
+            .flatten()
+        {
+            Some(PhysicalResource::Backbuffer) => Some((
+                self.swapchain_images[self.swapchain_index],
+                self.swapchain_views[self.swapchain_index],
+                self.surface_format.format,
+                self.swapchain_extent,
+                vk::SampleCountFlags::TYPE_1,
+            )),
+            Some(PhysicalResource::Texture(index)) => {
+                let texture = &self.graph_textures[index];
+                Some((
+                    *texture.image.handle(),
+                    *texture.image.view(),
+                    texture.image.format(),
+                    texture.extent,
+                    vk::SampleCountFlags::from_raw(texture.desc.samples),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn attachment_view(&self, attachment: &Attachment) -> vk::ImageView {
+        match (
+            attachment.layer,
+            self.graph_resources[attachment.resource.index()],
+        ) {
+            (Some(layer), Some(PhysicalResource::Texture(index))) => {
+                self.graph_textures[index].layer_views[layer as usize]
+            }
+            _ => self.graph_image(attachment.resource).unwrap().1,
+        }
+    }
+
+    // Color gets averaged, depth can't be so it takes the first sample, which every GPU that
+    // can resolve depth supports
+    fn attachment_resolve(&self, attachment: &Attachment) -> (vk::ResolveModeFlags, vk::ImageView) {
+        match attachment
+            .resolve
+            .and_then(|target| self.graph_image(target))
+        {
+            Some((_, view, format, _, _)) => (
+                if aspect_mask(format).contains(vk::ImageAspectFlags::DEPTH) {
+                    vk::ResolveModeFlags::SAMPLE_ZERO
+                } else {
+                    vk::ResolveModeFlags::AVERAGE
+                },
+                view,
+            ),
+            None => (vk::ResolveModeFlags::NONE, vk::ImageView::null()),
+        }
+    }
+
+    // Textures a pass samples go in a descriptor set of their own, since the frame's set is
+    // already bound by the time the graph knows which images it picked
+    fn bind_pass_textures(&mut self, pass: &CompiledPass) {
+        if pass.sampled.is_empty() {
+            return;
+        }
+        if pass.sampled.len() > MAX_PASS_TEXTURES {
+            error!(
+                "Render pass {} samples {} textures, only the first {MAX_PASS_TEXTURES} will be bound",
+                pass.name,
+                pass.sampled.len()
+            );
+        }
+
+        let set = unsafe {
+            vulkan_check!(self
+                .device
+                .allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo {
+                    descriptor_pool: self.descriptor_pool,
+                    descriptor_set_count: 1,
+                    p_set_layouts: ptr::addr_of!(self.texture_layout),
+                    ..Default::default()
+                }))
+        }[0];
+        self.pass_descriptor_sets[self.frame_index].push(set);
+
+        let image_infos: Vec<vk::DescriptorImageInfo> = pass
+            .sampled
+            .iter()
+            .take(MAX_PASS_TEXTURES)
+            .filter_map(|resource| self.graph_image(*resource))
+            .map(|(_, view, format, _, _)| vk::DescriptorImageInfo {
+                // Depth textures are only ever shadow maps for now
+                sampler: if aspect_mask(format).contains(vk::ImageAspectFlags::DEPTH) {
+                    self.shadow_sampler
+                } else {
+                    self.linear_sampler
+                },
+                image_view: view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            })
+            .collect();
+        let writes: Vec<vk::WriteDescriptorSet> = image_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, image_info)| vk::WriteDescriptorSet {
+                dst_set: set,
+                dst_binding: binding as u32,
+                dst_array_element: 0,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                p_image_info: image_info,
+                ..Default::default()
+            })
+            .collect();
+
+        unsafe {
+            self.device.update_descriptor_sets(&writes, &[]);
+            self.device.cmd_bind_descriptor_sets(
+                self.command_buffers[self.frame_index],
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                1,
+                &[set],
+                &[],
+            );
+        }
+    }
+
+    pub(super) fn transition_graph_resources(&mut self, transitions: &[Transition]) {
+        let mut src_stages = vk::PipelineStageFlags::empty();
+        let mut dst_stages = vk::PipelineStageFlags::empty();
+        let mut buffer_barriers = Vec::new();
+        let mut image_barriers = Vec::new();
+
+        for transition in transitions {
+            let Some(physical) = self
+                .graph_resources
+                .get(transition.resource.index())
+                .copied()
+                .flatten()
+            else {
+                continue;
+            };
+
+            let old_state = match physical {
+                PhysicalResource::Backbuffer => self.backbuffer_state,
+                PhysicalResource::Texture(index) => self.graph_textures[index].state,
+                PhysicalResource::Buffer(index) => self.graph_buffers[index].state,
+            };
+            let (old_layout, old_stages, old_access) = match physical {
+                PhysicalResource::Backbuffer if old_state == ResourceState::Undefined => (
+                    vk::ImageLayout::UNDEFINED,
+                    BACKBUFFER_WAIT_STAGES,
+                    vk::AccessFlags::empty(),
+                ),
+                _ => state_access(old_state),
+            };
+            let (new_layout, new_stages, new_access) = state_access(transition.state);
+            src_stages |= old_stages;
+            dst_stages |= new_stages;
+
+            match physical {
+                PhysicalResource::Buffer(index) => {
+                    let buffer = &mut self.graph_buffers[index];
+                    buffer_barriers.push(vk::BufferMemoryBarrier {
+                        src_access_mask: old_access,
+                        dst_access_mask: new_access,
+                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        buffer: *buffer.buffer.handle(),
+                        offset: 0,
+                        size: vk::WHOLE_SIZE,
+                        ..Default::default()
+                    });
+                    buffer.state = transition.state;
+                }
+                _ => {
+                    let (image, format, layers) = match physical {
+                        PhysicalResource::Texture(index) => {
+                            let texture = &mut self.graph_textures[index];
+                            texture.state = transition.state;
+                            (
+                                *texture.image.handle(),
+                                texture.image.format(),
+                                texture.desc.layers,
+                            )
+                        }
+                        _ => {
+                            self.backbuffer_state = transition.state;
+                            (
+                                self.swapchain_images[self.swapchain_index],
+                                self.surface_format.format,
+                                1,
+                            )
+                        }
+                    };
+
+                    image_barriers.push(vk::ImageMemoryBarrier {
+                        src_access_mask: old_access,
+                        dst_access_mask: new_access,
+                        // Going from undefined lets the driver throw away the old contents
+                        old_layout: if transition.discard {
+                            vk::ImageLayout::UNDEFINED
+                        } else {
+                            old_layout
+                        },
+                        new_layout,
+                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        image,
+                        subresource_range: vk::ImageSubresourceRange {
+                            aspect_mask: aspect_mask(format),
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: layers,
+                        },
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        if buffer_barriers.is_empty() && image_barriers.is_empty() {
+            return;
+        }
+
+        trace!(
+            "Recording {} buffer and {} image barrier(s) from {src_stages:?} to {dst_stages:?}",
+            buffer_barriers.len(),
+            image_barriers.len()
+        );
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                self.command_buffers[self.frame_index],
+                src_stages,
+                dst_stages,
+                vk::DependencyFlags::empty(),
+                &[],
+                &buffer_barriers,
+                &image_barriers,
+            )
+        };
+    }
+
+    pub(super) fn begin_graph_pass(&mut self, pass: &CompiledPass) {
+        trace!("Beginning render pass {}", pass.name);
+
+        self.transition_graph_resources(&pass.transitions);
+        self.bind_pass_textures(pass);
+        self.last_mesh_block = None;
+        self.last_pipeline = vk::Pipeline::null();
+
+        // Passes without attachments (copies, compute) don't need to start rendering
+        if pass.color_attachments.is_empty() && pass.depth_attachment.is_none() {
+            self.pass_formats = PassFormats::default();
+            return;
+        }
+
+        let mut extent = self.swapchain_extent;
+        let mut samples = vk::SampleCountFlags::TYPE_1;
+        let mut color_formats = Vec::new();
+        let color_attachments: Vec<vk::RenderingAttachmentInfo> = pass
+            .color_attachments
+            .iter()
+            .map(|attachment| {
+                let (_, _, format, image_extent, image_samples) =
+                    self.graph_image(attachment.resource).unwrap();
+                extent = image_extent;
+                samples = image_samples;
+                color_formats.push(format);
+
+                let (load_op, clear_value) = attachment_load_op(attachment.load, false);
+                let (resolve_mode, resolve_image_view) = self.attachment_resolve(attachment);
+                vk::RenderingAttachmentInfo {
+                    image_view: self.attachment_view(attachment),
+                    image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    resolve_mode,
+                    resolve_image_view,
+                    resolve_image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    load_op,
+                    store_op: vk::AttachmentStoreOp::STORE,
+                    clear_value,
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        let mut depth_format = vk::Format::UNDEFINED;
+        let depth_attachment = pass.depth_attachment.map(|attachment| {
+            let (_, _, format, image_extent, image_samples) =
+                self.graph_image(attachment.resource).unwrap();
+            extent = image_extent;
+            samples = image_samples;
+            depth_format = format;
+
+            let (load_op, clear_value) = attachment_load_op(attachment.load, true);
+            let (resolve_mode, resolve_image_view) = self.attachment_resolve(&attachment);
+            vk::RenderingAttachmentInfo {
+                image_view: self.attachment_view(&attachment),
+                resolve_mode,
+                resolve_image_view,
+                resolve_image_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                image_layout: if pass.depth_write {
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+                } else {
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+                },
+                load_op,
+                store_op: if pass.depth_write {
+                    vk::AttachmentStoreOp::STORE
+                } else {
+                    vk::AttachmentStoreOp::NONE
+                },
+                clear_value,
+                ..Default::default()
+            }
+        });
+
+        let rendering_info = vk::RenderingInfo {
+            color_attachment_count: color_attachments.len() as u32,
+            p_color_attachments: color_attachments.as_ptr(),
+            p_depth_attachment: match &depth_attachment {
+                Some(depth_attachment) => depth_attachment,
+                None => ptr::null(),
+            },
+            layer_count: 1,
+            render_area: vk::Rect2D {
+                extent,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        unsafe {
+            let command_buffer = self.command_buffers[self.frame_index];
+            self.device
+                .cmd_begin_rendering(command_buffer, &rendering_info);
+
+            self.device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            self.device.cmd_set_scissor(
+                command_buffer,
+                0,
+                &[vk::Rect2D {
+                    extent,
+                    ..Default::default()
+                }],
+            );
+            self.device.cmd_set_line_width(command_buffer, 1.0);
+            self.device
+                .cmd_set_primitive_topology(command_buffer, vk::PrimitiveTopology::TRIANGLE_LIST);
+        }
+
+        self.pass_formats = PassFormats {
+            color_formats,
+            depth_format,
+            samples,
+        };
+        self.rendering = true;
+    }
+
+    pub(super) fn end_graph_pass(&mut self) {
+        if self.rendering {
+            unsafe {
+                self.device
+                    .cmd_end_rendering(self.command_buffers[self.frame_index])
+            };
+            self.rendering = false;
+        }
+    }
+
+    pub(super) fn destroy_graph_resources(&mut self) {
+        debug!(
+            "Destroying {} render graph texture(s) and {} buffer(s)",
+            self.graph_textures.len(),
+            self.graph_buffers.len()
+        );
+        for texture in mem::take(&mut self.graph_textures) {
+            self.destroy_graph_texture(texture);
+        }
+        for buffer in self.graph_buffers.drain(..) {
+            buffer.buffer.destroy(&self.allocator);
+        }
+        self.graph_resources.clear();
+    }
+}
@@ -0,0 +1,596 @@
+use super::{HostBuffer, Image, State, FRAME_COUNT};
+use crate::engine::rendersystem::material::{MaterialData, MAX_MATERIALS, MAX_TEXTURES};
+use crate::engine::rendersystem::skinned::MAX_JOINT_MATRICES;
+use ash::vk;
+use log::{debug, error, trace};
+use nalgebra::Matrix4;
+use std::{mem, ptr};
+
+const MATERIAL_BUFFER_SIZE: vk::DeviceSize =
+    (MAX_MATERIALS * mem::size_of::<MaterialData>()) as vk::DeviceSize;
+const JOINT_BUFFER_SIZE: vk::DeviceSize =
+    (MAX_JOINT_MATRICES * mem::size_of::<Matrix4<f32>>()) as vk::DeviceSize;
+
+pub struct Texture {
+    image: Image,
+}
+
+impl State {
+    // Set 2 has every material and every texture, so the material can change per instance
+    // without rebinding anything. Textures are added to the array while frames using it are
+    // still in flight, which is fine as long as those frames don't use the new slot. The
+    // frame's skinning matrices are in it too, since they change at the same rate.
+    pub(super) fn create_material_layout(device: &ash::Device) -> vk::DescriptorSetLayout {
+        debug!("Creating material descriptor set layout");
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: MAX_TEXTURES as u32,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 2,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
+        ];
+        let binding_flags = [
+            vk::DescriptorBindingFlags::empty(),
+            vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+                | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING,
+            vk::DescriptorBindingFlags::empty(),
+        ];
+        let binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo {
+            binding_count: binding_flags.len() as u32,
+            p_binding_flags: binding_flags.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            vulkan_check!(device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo {
+                    flags: vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+                    p_bindings: bindings.as_ptr(),
+                    binding_count: bindings.len() as u32,
+                    p_next: ptr::addr_of!(binding_flags_info) as *const std::ffi::c_void,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        }
+    }
+
+    pub(super) fn allocate_material_buffers(allocator: &vk_mem::Allocator) -> Vec<HostBuffer> {
+        debug!("Allocating {FRAME_COUNT} {MATERIAL_BUFFER_SIZE} byte material buffers");
+        (0..FRAME_COUNT)
+            .map(|_| {
+                vulkan_check!(HostBuffer::new(
+                    allocator,
+                    MATERIAL_BUFFER_SIZE,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ))
+            })
+            .collect()
+    }
+
+    pub(super) fn allocate_joint_buffers(allocator: &vk_mem::Allocator) -> Vec<HostBuffer> {
+        debug!("Allocating {FRAME_COUNT} {JOINT_BUFFER_SIZE} byte joint buffers");
+        (0..FRAME_COUNT)
+            .map(|_| {
+                vulkan_check!(HostBuffer::new(
+                    allocator,
+                    JOINT_BUFFER_SIZE,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ))
+            })
+            .collect()
+    }
+
+    // Update after bind sets need their own pool
+    pub(super) fn allocate_material_sets(
+        device: &ash::Device,
+        layout: &vk::DescriptorSetLayout,
+        material_buffers: &[HostBuffer],
+        joint_buffers: &[HostBuffer],
+    ) -> (vk::DescriptorPool, Vec<vk::DescriptorSet>) {
+        debug!("Allocating {FRAME_COUNT} material descriptor sets");
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: (FRAME_COUNT * 2) as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: (MAX_TEXTURES * FRAME_COUNT) as u32,
+            },
+        ];
+        let pool = unsafe {
+            vulkan_check!(device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo {
+                    flags: vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
+                    pool_size_count: pool_sizes.len() as u32,
+                    p_pool_sizes: pool_sizes.as_ptr(),
+                    max_sets: FRAME_COUNT as u32,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        let layouts = [*layout; FRAME_COUNT];
+        let sets = unsafe {
+            vulkan_check!(
+                device.allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo {
+                    descriptor_pool: pool,
+                    descriptor_set_count: layouts.len() as u32,
+                    p_set_layouts: layouts.as_ptr(),
+                    ..Default::default()
+                })
+            )
+        };
+
+        let buffer_info = |buffer: &HostBuffer, range| vk::DescriptorBufferInfo {
+            buffer: *buffer.buffer().handle(),
+            offset: 0,
+            range,
+        };
+        let buffer_infos: Vec<(vk::DescriptorBufferInfo, vk::DescriptorBufferInfo)> =
+            material_buffers
+                .iter()
+                .zip(joint_buffers)
+                .map(|(materials, joints)| {
+                    (
+                        buffer_info(materials, MATERIAL_BUFFER_SIZE),
+                        buffer_info(joints, JOINT_BUFFER_SIZE),
+                    )
+                })
+                .collect();
+        let writes: Vec<vk::WriteDescriptorSet> = sets
+            .iter()
+            .zip(&buffer_infos)
+            .flat_map(|(set, (materials, joints))| {
+                [(0, materials), (2, joints)].map(|(binding, buffer_info)| vk::WriteDescriptorSet {
+                    dst_set: *set,
+                    dst_binding: binding,
+                    dst_array_element: 0,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 1,
+                    p_buffer_info: buffer_info,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+
+        (pool, sets)
+    }
+
+    pub(super) fn create_material_sampler(
+        device: &ash::Device,
+        gpu: &super::GpuInfo,
+    ) -> vk::Sampler {
+        let anisotropy = gpu.features.sampler_anisotropy == vk::TRUE;
+        unsafe {
+            vulkan_check!(device.create_sampler(
+                &vk::SamplerCreateInfo {
+                    mag_filter: vk::Filter::LINEAR,
+                    min_filter: vk::Filter::LINEAR,
+                    mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+                    address_mode_u: vk::SamplerAddressMode::REPEAT,
+                    address_mode_v: vk::SamplerAddressMode::REPEAT,
+                    address_mode_w: vk::SamplerAddressMode::REPEAT,
+                    anisotropy_enable: anisotropy as vk::Bool32,
+                    max_anisotropy: if anisotropy {
+                        gpu.properties.limits.max_sampler_anisotropy.min(16.0)
+                    } else {
+                        1.0
+                    },
+                    max_lod: vk::LOD_CLAMP_NONE,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        }
+    }
+
+    fn can_generate_mips(&self, format: vk::Format) -> bool {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.gpus[self.gpu].device, format)
+        };
+        properties.optimal_tiling_features.contains(
+            vk::FormatFeatureFlags::BLIT_SRC
+                | vk::FormatFeatureFlags::BLIT_DST
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+    }
+
+    // Copies the pixels in through the staging ring and blits down the mip chain, all in the
+    // upload command buffer so it's ready by the next frame
+    pub(super) fn upload_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        srgb: bool,
+    ) -> Result<Texture, vk::Result> {
+        let format = if srgb {
+            vk::Format::R8G8B8A8_SRGB
+        } else {
+            vk::Format::R8G8B8A8_UNORM
+        };
+        let mip_levels = if self.can_generate_mips(format) {
+            32 - width.max(height).leading_zeros()
+        } else {
+            1
+        };
+        trace!("Uploading {width}x{height} {format:?} texture with {mip_levels} mip level(s)");
+
+        let mut image = Image::new(
+            &self.device,
+            &self.allocator,
+            format,
+            &mut vk::ImageCreateInfo {
+                extent: vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                mip_levels,
+                array_layers: 1,
+                samples: vk::SampleCountFlags::TYPE_1,
+                usage: vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+                image_type: vk::ImageType::TYPE_2D,
+                ..Default::default()
+            },
+            &mut vk::ImageViewCreateInfo {
+                view_type: vk::ImageViewType::TYPE_2D,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: mip_levels,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                ..Default::default()
+            },
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::AutoPreferDevice,
+                ..Default::default()
+            },
+        )?;
+
+        let size = pixels.len() as vk::DeviceSize;
+        let mut offset = self.staging_ring.as_mut().unwrap().allocate(size, 16);
+        if offset.is_none() && size <= self.staging_ring.as_ref().unwrap().capacity() {
+            debug!("Staging ring is full, waiting for pending uploads");
+            self.flush_uploads();
+            offset = self.staging_ring.as_mut().unwrap().allocate(size, 16);
+        }
+        let (source, source_offset, temporary) = match offset {
+            Some(offset) => {
+                let staging_ring = self.staging_ring.as_ref().unwrap();
+                unsafe { staging_ring.write(offset, pixels) };
+                (*staging_ring.buffer().handle(), offset, None)
+            }
+            None => {
+                debug!("Upload of {size} byte(s) doesn't fit in the staging ring, using a temporary buffer");
+                let staging_buffer = match HostBuffer::new(
+                    &self.allocator,
+                    size,
+                    vk::BufferUsageFlags::TRANSFER_SRC,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ) {
+                    Ok(buffer) => buffer,
+                    Err(err) => {
+                        image.destroy(&self.device, &self.allocator);
+                        return Err(err);
+                    }
+                };
+                unsafe { staging_buffer.read(pixels, 0) };
+                (*staging_buffer.buffer().handle(), 0, Some(staging_buffer))
+            }
+        };
+
+        let command_buffer = self.begin_uploads();
+        let handle = *image.handle();
+        // Coming from undefined covers every level at once, after that they go one at a time
+        let barrier = |level: u32,
+                       old_layout: vk::ImageLayout,
+                       new_layout: vk::ImageLayout,
+                       src_access_mask: vk::AccessFlags,
+                       dst_access_mask: vk::AccessFlags| {
+            vk::ImageMemoryBarrier {
+                old_layout,
+                new_layout,
+                src_access_mask,
+                dst_access_mask,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: handle,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: level,
+                    level_count: if old_layout == vk::ImageLayout::UNDEFINED {
+                        mip_levels
+                    } else {
+                        1
+                    },
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                ..Default::default()
+            }
+        };
+        let mip_extent = |level: u32| vk::Offset3D {
+            x: (width >> level).max(1) as i32,
+            y: (height >> level).max(1) as i32,
+            z: 1,
+        };
+        let subresource = |level: u32| vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: level,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    0,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                )],
+            );
+            self.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                source,
+                handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::BufferImageCopy {
+                    buffer_offset: source_offset,
+                    image_subresource: subresource(0),
+                    image_extent: vk::Extent3D {
+                        width,
+                        height,
+                        depth: 1,
+                    },
+                    ..Default::default()
+                }],
+            );
+
+            // Each level is read to make the next one, then it's done
+            for level in 1..mip_levels {
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier(
+                        level - 1,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::TRANSFER_READ,
+                    )],
+                );
+                self.device.cmd_blit_image(
+                    command_buffer,
+                    handle,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    handle,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[vk::ImageBlit {
+                        src_subresource: subresource(level - 1),
+                        src_offsets: [vk::Offset3D::default(), mip_extent(level - 1)],
+                        dst_subresource: subresource(level),
+                        dst_offsets: [vk::Offset3D::default(), mip_extent(level)],
+                    }],
+                    vk::Filter::LINEAR,
+                );
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier(
+                        level - 1,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_READ,
+                        vk::AccessFlags::SHADER_READ,
+                    )],
+                );
+            }
+
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    mip_levels - 1,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                )],
+            );
+        }
+
+        // The temporary buffer has to outlive the copy
+        if let Some(staging_buffer) = temporary {
+            self.flush_uploads();
+            staging_buffer.destroy(&self.allocator);
+        }
+
+        Ok(Texture { image })
+    }
+
+    pub(super) fn add_texture(&mut self, texture: Texture) -> Option<usize> {
+        let index = match self.free_textures.pop() {
+            Some(index) => index,
+            None if self.textures.len() < MAX_TEXTURES => {
+                self.textures.push(None);
+                self.textures.len() - 1
+            }
+            None => {
+                error!("Texture array is full, there can only be {MAX_TEXTURES} textures");
+                let mut texture = texture;
+                texture.image.destroy(&self.device, &self.allocator);
+                return None;
+            }
+        };
+
+        let image_info = vk::DescriptorImageInfo {
+            sampler: self.material_sampler,
+            image_view: *texture.image.view(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let writes: Vec<vk::WriteDescriptorSet> = self
+            .material_sets
+            .iter()
+            .map(|set| vk::WriteDescriptorSet {
+                dst_set: *set,
+                dst_binding: 1,
+                dst_array_element: index as u32,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                p_image_info: ptr::addr_of!(image_info),
+                ..Default::default()
+            })
+            .collect();
+        unsafe { self.device.update_descriptor_sets(&writes, &[]) };
+
+        self.textures[index] = Some(texture);
+        Some(index)
+    }
+
+    // Frames in flight might still sample the texture, so it and its slot in the array are
+    // only freed once they're done
+    pub(super) fn remove_texture(&mut self, index: usize) {
+        if let Some(texture) = self
+            .textures
+            .get_mut(index)
+            .and_then(|texture| texture.take())
+        {
+            trace!(
+                "Destroying texture {index} after frame {}",
+                self.frame_number
+            );
+            self.pending_texture_frees
+                .push((self.frame_number, index, texture));
+        }
+    }
+
+    pub(super) fn free_pending_textures(&mut self, completed_frame: u64) {
+        let mut index = 0;
+        while index < self.pending_texture_frees.len() {
+            if self.pending_texture_frees[index].0 <= completed_frame {
+                let (_, slot, mut texture) = self.pending_texture_frees.swap_remove(index);
+                texture.image.destroy(&self.device, &self.allocator);
+                self.free_textures.push(slot);
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    pub(super) fn write_materials(&mut self, materials: &[MaterialData]) {
+        if materials.len() > MAX_MATERIALS {
+            error!(
+                "Only the first {MAX_MATERIALS} of {} materials will be used",
+                materials.len()
+            );
+        }
+
+        let count = materials.len().min(MAX_MATERIALS);
+        unsafe {
+            self.material_buffers[self.frame_index]
+                .read(super::super::as_bytes(&materials[..count]), 0)
+        };
+    }
+
+    pub(super) fn write_joints(&mut self, joints: &[Matrix4<f32>]) {
+        let count = joints.len().min(MAX_JOINT_MATRICES);
+        unsafe {
+            self.joint_buffers[self.frame_index].read(super::super::as_bytes(&joints[..count]), 0)
+        };
+    }
+
+    pub(super) fn destroy_materials(&mut self) {
+        debug!(
+            "Destroying {} texture(s)",
+            self.textures.iter().flatten().count() + self.pending_texture_frees.len()
+        );
+        self.free_pending_textures(u64::MAX);
+        for mut texture in self.textures.drain(..).flatten() {
+            texture.image.destroy(&self.device, &self.allocator);
+        }
+        self.free_textures.clear();
+
+        debug!("Freeing {FRAME_COUNT} material buffers");
+        for buffer in self.material_buffers.drain(..) {
+            buffer.destroy(&self.allocator);
+        }
+        debug!("Freeing {FRAME_COUNT} joint buffers");
+        for buffer in self.joint_buffers.drain(..) {
+            buffer.destroy(&self.allocator);
+        }
+
+        unsafe {
+            debug!(
+                "Destroying material descriptor pool {:#?}",
+                self.material_pool
+            );
+            self.device.destroy_descriptor_pool(
+                self.material_pool,
+                Some(&State::get_allocation_callbacks()),
+            );
+            debug!(
+                "Destroying material descriptor set layout {:#?}",
+                self.material_layout
+            );
+            self.device.destroy_descriptor_set_layout(
+                self.material_layout,
+                Some(&State::get_allocation_callbacks()),
+            );
+            self.device.destroy_sampler(
+                self.material_sampler,
+                Some(&State::get_allocation_callbacks()),
+            );
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use super::{Buffer, HostBuffer, FRAME_COUNT};
+use ash::vk;
+use log::{debug, trace};
+use once_cell::sync::Lazy;
+use std::{alloc::Layout, cmp::Reverse, collections::HashMap, sync::Mutex};
+
+const MESH_BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+pub const STAGING_RING_SIZE: vk::DeviceSize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryCategory {
+    Buffers,
+    Images,
+    Staging,
+    // What the driver allocated through the allocation callbacks
+    Host,
+}
+
+impl MemoryCategory {
+    pub const ALL: [Self; 4] = [Self::Buffers, Self::Images, Self::Staging, Self::Host];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Buffers => "buffers",
+            Self::Images => "images",
+            Self::Staging => "staging",
+            Self::Host => "host",
+        }
+    }
+}
+
+// Every live buffer and image by raw handle, so usage can be broken down and anything still
+// around at shutdown can be reported
+static DEVICE_OBJECTS: Lazy<Mutex<HashMap<u64, (MemoryCategory, vk::DeviceSize)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// Every host allocation by address, since freeing one needs the layout it was made with
+static HOST_ALLOCATIONS: Lazy<Mutex<HashMap<usize, Layout>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn track(handle: u64, category: MemoryCategory, size: vk::DeviceSize) {
+    if let Ok(mut objects) = DEVICE_OBJECTS.lock() {
+        objects.insert(handle, (category, size));
+    }
+}
+
+pub fn untrack(handle: u64) {
+    if let Ok(mut objects) = DEVICE_OBJECTS.lock() {
+        objects.remove(&handle);
+    }
+}
+
+pub fn track_host(address: usize, layout: Layout) {
+    if let Ok(mut allocations) = HOST_ALLOCATIONS.lock() {
+        allocations.insert(address, layout);
+    }
+}
+
+pub fn untrack_host(address: usize) -> Option<Layout> {
+    HOST_ALLOCATIONS.lock().ok()?.remove(&address)
+}
+
+// Bytes and object count in each category, in the order of MemoryCategory::ALL
+pub fn usage() -> Vec<(MemoryCategory, vk::DeviceSize, usize)> {
+    let mut usage: Vec<(MemoryCategory, vk::DeviceSize, usize)> = MemoryCategory::ALL
+        .iter()
+        .map(|category| (*category, 0, 0))
+        .collect();
+    if let Ok(objects) = DEVICE_OBJECTS.lock() {
+        for (category, size) in objects.values() {
+            let entry = &mut usage[*category as usize];
+            entry.1 += size;
+            entry.2 += 1;
+        }
+    }
+    if let Ok(allocations) = HOST_ALLOCATIONS.lock() {
+        let entry = &mut usage[MemoryCategory::Host as usize];
+        entry.1 = allocations
+            .values()
+            .map(|layout| layout.size() as u64)
+            .sum();
+        entry.2 = allocations.len();
+    }
+    usage
+}
+
+// Buffers and images nothing destroyed, biggest first
+pub fn live_objects() -> Vec<(u64, MemoryCategory, vk::DeviceSize)> {
+    let mut objects: Vec<(u64, MemoryCategory, vk::DeviceSize)> = DEVICE_OBJECTS
+        .lock()
+        .map(|objects| {
+            objects
+                .iter()
+                .map(|(handle, (category, size))| (*handle, *category, *size))
+                .collect()
+        })
+        .unwrap_or_default();
+    objects.sort_by_key(|(_, _, size)| Reverse(*size));
+    objects
+}
+
+// Host allocations nothing freed, as (count, bytes)
+pub fn live_host_allocations() -> (usize, usize) {
+    HOST_ALLOCATIONS
+        .lock()
+        .map(|allocations| {
+            (
+                allocations.len(),
+                allocations.values().map(Layout::size).sum(),
+            )
+        })
+        .unwrap_or_default()
+}
+
+pub fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    if alignment <= 1 {
+        value
+    } else {
+        (value + alignment - 1) / alignment * alignment
+    }
+}
+
+// First fit free list over a range of offsets, kept sorted so neighbours can be merged
+pub struct RangeAllocator {
+    size: vk::DeviceSize,
+    free: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+impl RangeAllocator {
+    pub fn new(size: vk::DeviceSize) -> Self {
+        Self {
+            size,
+            free: vec![(0, size)],
+        }
+    }
+
+    pub fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        for i in 0..self.free.len() {
+            let (offset, free_size) = self.free[i];
+            let aligned = align_up(offset, alignment);
+            let padding = aligned - offset;
+            if free_size < padding + size {
+                continue;
+            }
+
+            let end = offset + free_size;
+            let allocation_end = aligned + size;
+            self.free.remove(i);
+            let mut index = i;
+            if padding > 0 {
+                self.free.insert(index, (offset, padding));
+                index += 1;
+            }
+            if allocation_end < end {
+                self.free
+                    .insert(index, (allocation_end, end - allocation_end));
+            }
+
+            return Some(aligned);
+        }
+
+        None
+    }
+
+    pub fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let mut i = self
+            .free
+            .partition_point(|&(free_offset, _)| free_offset < offset);
+        self.free.insert(i, (offset, size));
+
+        if i + 1 < self.free.len() && self.free[i].0 + self.free[i].1 == self.free[i + 1].0 {
+            self.free[i].1 += self.free[i + 1].1;
+            self.free.remove(i + 1);
+        }
+        if i > 0 && self.free[i - 1].0 + self.free[i - 1].1 == self.free[i].0 {
+            self.free[i - 1].1 += self.free[i].1;
+            self.free.remove(i);
+            i -= 1;
+        }
+
+        trace!(
+            "Freed {size} byte(s) at offset {offset}, free range {i} is now {:?}",
+            self.free[i]
+        );
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    pub fn used(&self) -> vk::DeviceSize {
+        self.size
+            - self
+                .free
+                .iter()
+                .map(|(_, size)| size)
+                .sum::<vk::DeviceSize>()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshAllocation {
+    pub block: usize,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+}
+
+// Big device local buffers that meshes get suballocated from, so adding or removing one
+// doesn't need a new buffer or a rebind for every draw
+pub struct MeshHeap {
+    blocks: Vec<(Buffer, RangeAllocator)>,
+}
+
+impl MeshHeap {
+    pub fn new() -> Self {
+        Self { blocks: Vec::new() }
+    }
+
+    pub fn allocate(
+        &mut self,
+        allocator: &vk_mem::Allocator,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Result<MeshAllocation, vk::Result> {
+        for (block, (_, ranges)) in self.blocks.iter_mut().enumerate() {
+            if let Some(offset) = ranges.allocate(size, alignment) {
+                return Ok(MeshAllocation {
+                    block,
+                    offset,
+                    size,
+                });
+            }
+        }
+
+        let block_size = align_up(size, MESH_BLOCK_SIZE);
+        debug!(
+            "Creating {block_size} byte mesh heap block {}",
+            self.blocks.len()
+        );
+        let buffer = Buffer::new(
+            allocator,
+            block_size,
+            vk::BufferUsageFlags::VERTEX_BUFFER
+                | vk::BufferUsageFlags::INDEX_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let mut ranges = RangeAllocator::new(block_size);
+        let offset = ranges.allocate(size, alignment).unwrap();
+        self.blocks.push((buffer, ranges));
+
+        Ok(MeshAllocation {
+            block: self.blocks.len() - 1,
+            offset,
+            size,
+        })
+    }
+
+    pub fn free(&mut self, allocation: &MeshAllocation) {
+        self.blocks[allocation.block]
+            .1
+            .free(allocation.offset, allocation.size);
+    }
+
+    pub fn buffer(&self, block: usize) -> &Buffer {
+        &self.blocks[block].0
+    }
+
+    pub fn used(&self) -> vk::DeviceSize {
+        self.blocks.iter().map(|(_, ranges)| ranges.used()).sum()
+    }
+
+    pub fn capacity(&self) -> vk::DeviceSize {
+        self.blocks.iter().map(|(_, ranges)| ranges.size()).sum()
+    }
+
+    pub fn destroy(&mut self, allocator: &vk_mem::Allocator) {
+        debug!("Destroying {} mesh heap block(s)", self.blocks.len());
+        for (buffer, _) in self.blocks.drain(..) {
+            buffer.destroy(allocator);
+        }
+    }
+}
+
+// Host visible ring that uploads get copied through. Space used by a frame's uploads is
+// given back once that frame's fence has been waited on.
+pub struct StagingRing {
+    buffer: HostBuffer,
+    head: vk::DeviceSize,
+    used: vk::DeviceSize,
+    pending: vk::DeviceSize,
+    frame_usage: [vk::DeviceSize; FRAME_COUNT],
+}
+
+impl StagingRing {
+    pub fn new(allocator: &vk_mem::Allocator, size: vk::DeviceSize) -> Result<Self, vk::Result> {
+        debug!("Creating {size} byte staging ring");
+        Ok(Self {
+            buffer: HostBuffer::new(
+                allocator,
+                size,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?,
+            head: 0,
+            used: 0,
+            pending: 0,
+            frame_usage: [0; FRAME_COUNT],
+        })
+    }
+
+    pub fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        let capacity = self.buffer.buffer().size();
+        if size > capacity {
+            return None;
+        }
+
+        let mut offset = align_up(self.head, alignment);
+        let wasted = if offset + size > capacity {
+            offset = 0;
+            capacity - self.head
+        } else {
+            offset - self.head
+        };
+        if self.used + wasted + size > capacity {
+            return None;
+        }
+
+        self.head = offset + size;
+        self.used += wasted + size;
+        self.pending += wasted + size;
+
+        Some(offset)
+    }
+
+    pub unsafe fn write(&self, offset: vk::DeviceSize, data: &[u8]) {
+        self.buffer.read(data, offset);
+    }
+
+    // Everything allocated since the last submit belongs to this frame
+    pub fn submit(&mut self, frame_index: usize) {
+        self.frame_usage[frame_index] += self.pending;
+        self.pending = 0;
+    }
+
+    pub fn retire(&mut self, frame_index: usize) {
+        self.used -= self.frame_usage[frame_index];
+        self.frame_usage[frame_index] = 0;
+    }
+
+    pub fn retire_all(&mut self) {
+        for frame_index in 0..FRAME_COUNT {
+            self.retire(frame_index);
+        }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        self.buffer.buffer()
+    }
+
+    pub fn capacity(&self) -> vk::DeviceSize {
+        self.buffer.buffer().size()
+    }
+
+    pub fn destroy(self, allocator: &vk_mem::Allocator) {
+        debug!("Destroying staging ring");
+        self.buffer.destroy(allocator);
+    }
+}
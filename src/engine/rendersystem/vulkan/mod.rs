@@ -0,0 +1,3446 @@
+use super::ShaderKind;
+use crate::engine::{crash, profiler};
+use crate::platform;
+use ash::{extensions, vk};
+use graph::{GraphBuffer, GraphTexture, PassFormats, PhysicalResource};
+use log::{debug, error, log, trace, warn};
+use material::Texture;
+use memory::{MeshAllocation, MeshHeap, StagingRing};
+use nalgebra::Matrix4;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::{alloc, any::Any, cmp, ffi, mem, ptr};
+use vk_mem::*;
+
+macro_rules! vulkan_check {
+    ($call: expr) => {
+        $call.unwrap_or_else(|err| panic!("Vulkan call {} failed: {}", stringify!($call), err))
+    };
+}
+
+mod graph;
+mod material;
+mod memory;
+mod timing;
+
+// Vulkan only asks for power of two alignments, but this makes sure the layout is valid
+fn host_layout(size: usize, alignment: usize) -> Option<alloc::Layout> {
+    let alignment = alignment.max(1).next_power_of_two();
+    let size = if size == 0 { alignment } else { size };
+    alloc::Layout::from_size_align(size, alignment).ok()
+}
+
+// Every host allocation is tracked, since freeing it needs the layout it was made with
+extern "system" fn vulkan_alloc(
+    _p_user_data: *mut ffi::c_void,
+    size: usize,
+    alignment: usize,
+    _allocation_scope: vk::SystemAllocationScope,
+) -> *mut ffi::c_void {
+    let Some(layout) = host_layout(size, alignment) else {
+        return ptr::null_mut();
+    };
+    trace!(
+        "Allocating {} byte(s) aligned to {} for Vulkan",
+        layout.size(),
+        layout.align()
+    );
+    let memory = unsafe { alloc::alloc(layout) };
+    if !memory.is_null() {
+        memory::track_host(memory as usize, layout);
+    }
+    memory as *mut ffi::c_void
+}
+
+extern "system" fn vulkan_realloc(
+    p_user_data: *mut ffi::c_void,
+    p_original: *mut ffi::c_void,
+    size: usize,
+    alignment: usize,
+    allocation_scope: vk::SystemAllocationScope,
+) -> *mut ffi::c_void {
+    if p_original.is_null() {
+        return vulkan_alloc(p_user_data, size, alignment, allocation_scope);
+    }
+    if size == 0 {
+        vulkan_dealloc(p_user_data, p_original);
+        return ptr::null_mut();
+    }
+    let Some(layout) = host_layout(size, alignment) else {
+        return ptr::null_mut();
+    };
+    let Some(old_layout) = memory::untrack_host(p_original as usize) else {
+        error!(
+            "Vulkan tried to reallocate {:X}, which didn't come from vulkan_alloc",
+            p_original as usize
+        );
+        return ptr::null_mut();
+    };
+    trace!(
+        "Reallocating Vulkan allocation {:X} to {} byte(s) aligned to {}",
+        p_original as usize,
+        layout.size(),
+        layout.align()
+    );
+
+    let memory = unsafe {
+        if layout.align() == old_layout.align() {
+            alloc::realloc(p_original as *mut u8, old_layout, layout.size())
+        } else {
+            let memory = alloc::alloc(layout);
+            if !memory.is_null() {
+                memory.copy_from_nonoverlapping(
+                    p_original as *const u8,
+                    old_layout.size().min(layout.size()),
+                );
+                alloc::dealloc(p_original as *mut u8, old_layout);
+            }
+            memory
+        }
+    };
+    // The original is still there if this failed
+    if memory.is_null() {
+        memory::track_host(p_original as usize, old_layout);
+    } else {
+        memory::track_host(memory as usize, layout);
+    }
+    memory as *mut ffi::c_void
+}
+
+extern "system" fn vulkan_dealloc(_p_user_data: *mut ffi::c_void, p_memory: *mut ffi::c_void) {
+    if p_memory.is_null() {
+        return;
+    }
+    trace!("Freeing Vulkan allocation {:X}", p_memory as usize);
+    match memory::untrack_host(p_memory as usize) {
+        Some(layout) => unsafe { alloc::dealloc(p_memory as *mut u8, layout) },
+        None => error!(
+            "Vulkan tried to free {:X}, which didn't come from vulkan_alloc",
+            p_memory as usize
+        ),
+    }
+}
+
+const FRAME_COUNT: usize = 3;
+// Textures a pass samples get bound to set 1 in the order the pass declared them
+const MAX_PASS_TEXTURES: usize = 8;
+
+struct GpuInfo {
+    device: vk::PhysicalDevice,
+
+    properties: vk::PhysicalDeviceProperties,
+    features: vk::PhysicalDeviceFeatures,
+
+    surface_formats: Vec<vk::SurfaceFormatKHR>,
+    present_modes: Vec<vk::PresentModeKHR>,
+
+    graphics_family_index: u32,
+    compute_family_index: u32,
+    // How many bits of the graphics queue's timestamps count, 0 if it can't write them
+    timestamp_valid_bits: u32,
+
+    // Vague guess at how powerful the GPU is
+    performance_score: u32,
+}
+
+struct Image {
+    handle: vk::Image,
+    allocation: Option<vk_mem::Allocation>,
+    view: vk::ImageView,
+    format: vk::Format,
+}
+
+impl Image {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        format: vk::Format,
+        create_info: &mut vk::ImageCreateInfo,
+        view_info: &mut vk::ImageViewCreateInfo,
+        allocation_info: &vk_mem::AllocationCreateInfo,
+    ) -> Result<Self, vk::Result> {
+        create_info.format = format;
+        let result = unsafe { allocator.create_image(create_info, allocation_info) };
+        let (handle, allocation) = result?;
+        let size = unsafe { device.get_image_memory_requirements(handle) }.size;
+        memory::track(
+            vk::Handle::as_raw(handle),
+            memory::MemoryCategory::Images,
+            size,
+        );
+        view_info.image = handle;
+        view_info.format = format;
+
+        let view = unsafe {
+            device.create_image_view(view_info, Some(&State::get_allocation_callbacks()))?
+        };
+
+        Ok(Self {
+            handle,
+            allocation: Some(allocation),
+            view,
+            format,
+        })
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &vk_mem::Allocator) {
+        unsafe {
+            device.destroy_image_view(self.view, Some(&State::get_allocation_callbacks()));
+            allocator.destroy_image(self.handle, self.allocation.take().unwrap());
+        }
+        memory::untrack(vk::Handle::as_raw(self.handle));
+    }
+
+    pub fn choose_fmt(
+        instance: &ash::Instance,
+        gpu: &GpuInfo,
+        formats: &[vk::Format],
+        tiling: vk::ImageTiling,
+        required_features: vk::FormatFeatureFlags,
+    ) -> vk::Format {
+        debug!("Finding format with feature flags {:#?}", required_features);
+
+        let formats: Vec<&vk::Format> = formats
+            .iter()
+            .filter(|format| {
+                let props =
+                    unsafe { instance.get_physical_device_format_properties(gpu.device, **format) };
+                match tiling {
+                    vk::ImageTiling::LINEAR => {
+                        props.linear_tiling_features.contains(required_features)
+                    }
+                    vk::ImageTiling::OPTIMAL => {
+                        props.optimal_tiling_features.contains(required_features)
+                    }
+                    _ => false,
+                }
+            })
+            .collect();
+
+        if !formats.is_empty() {
+            *formats[0]
+        } else {
+            vk::Format::UNDEFINED
+        }
+    }
+
+    pub fn handle(&self) -> &vk::Image {
+        &self.handle
+    }
+
+    pub fn allocation(&self) -> &Option<vk_mem::Allocation> {
+        &self.allocation
+    }
+
+    pub fn allocation_mut(&mut self) -> &mut Option<vk_mem::Allocation> {
+        &mut self.allocation
+    }
+
+    pub fn view(&self) -> &vk::ImageView {
+        &self.view
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+}
+
+struct Buffer {
+    handle: vk::Buffer,
+    allocation: vk_mem::Allocation,
+    size: vk::DeviceSize,
+}
+
+impl Buffer {
+    pub fn new(
+        allocator: &vk_mem::Allocator,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        flags: vk::MemoryPropertyFlags,
+    ) -> Result<Self, vk::Result> {
+        let result = unsafe {
+            allocator.create_buffer(
+                &vk::BufferCreateInfo {
+                    size,
+                    usage,
+                    sharing_mode: vk::SharingMode::EXCLUSIVE,
+                    ..Default::default()
+                },
+                &vk_mem::AllocationCreateInfo {
+                    required_flags: flags,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let (handle, allocation) = result?;
+        // Buffers that are only ever copied from are for uploads
+        let category = if usage == vk::BufferUsageFlags::TRANSFER_SRC {
+            memory::MemoryCategory::Staging
+        } else {
+            memory::MemoryCategory::Buffers
+        };
+        memory::track(vk::Handle::as_raw(handle), category, size);
+        Ok(Self {
+            handle,
+            allocation,
+            size,
+        })
+    }
+
+    pub fn copy(
+        &self,
+        device: &ash::Device,
+        queue: &vk::Queue,
+        transfer_pool: &vk::CommandPool,
+        destination: &vk::Buffer,
+        destination_offset: vk::DeviceSize,
+    ) {
+        let transfer_buffer = unsafe {
+            vulkan_check!(
+                device.allocate_command_buffers(&vk::CommandBufferAllocateInfo {
+                    level: vk::CommandBufferLevel::PRIMARY,
+                    command_pool: *transfer_pool,
+                    command_buffer_count: 1,
+                    ..Default::default()
+                })
+            )
+        }[0];
+
+        unsafe {
+            vulkan_check!(device.begin_command_buffer(
+                transfer_buffer,
+                &vk::CommandBufferBeginInfo {
+                    flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                    ..Default::default()
+                }
+            ));
+
+            device.cmd_copy_buffer(
+                transfer_buffer,
+                self.handle,
+                *destination,
+                &[vk::BufferCopy {
+                    dst_offset: destination_offset,
+                    size: self.size,
+                    ..Default::default()
+                }],
+            );
+
+            vulkan_check!(device.end_command_buffer(transfer_buffer));
+            vulkan_check!(device.queue_submit(
+                *queue,
+                &[vk::SubmitInfo {
+                    command_buffer_count: 1,
+                    p_command_buffers: ptr::addr_of!(transfer_buffer),
+                    ..Default::default()
+                }],
+                vk::Fence::null()
+            ));
+            vulkan_check!(device.queue_wait_idle(*queue));
+
+            device.free_command_buffers(*transfer_pool, &[transfer_buffer]);
+        }
+    }
+
+    pub fn destroy(self, allocator: &vk_mem::Allocator) {
+        memory::untrack(vk::Handle::as_raw(self.handle));
+        unsafe { allocator.destroy_buffer(self.handle, self.allocation) };
+    }
+
+    pub fn handle(&self) -> &vk::Buffer {
+        &self.handle
+    }
+
+    pub fn allocation(&self) -> &vk_mem::Allocation {
+        &self.allocation
+    }
+
+    pub fn allocation_mut(&mut self) -> &mut vk_mem::Allocation {
+        &mut self.allocation
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+}
+
+struct HostBuffer {
+    buffer: Buffer,
+    address: u64,
+}
+
+impl HostBuffer {
+    pub fn new(
+        allocator: &vk_mem::Allocator,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        flags: vk::MemoryPropertyFlags,
+    ) -> Result<Self, vk::Result> {
+        Self::from_buffer(
+            allocator,
+            match Buffer::new(allocator, size, usage, flags) {
+                Ok(buffer) => buffer,
+                Err(err) => return Err(err),
+            },
+        )
+    }
+
+    pub fn from_buffer(allocator: &vk_mem::Allocator, buffer: Buffer) -> Result<Self, vk::Result> {
+        let mut self_ = Self { buffer, address: 0 };
+        self_.address = unsafe {
+            match allocator.map_memory(&mut self_.buffer.allocation) {
+                Ok(address) => address as u64,
+                Err(err) => return Err(err),
+            }
+        };
+        Ok(self_)
+    }
+
+    pub unsafe fn read(&self, source: &[u8], offset: vk::DeviceSize) -> usize {
+        let size = cmp::min(
+            self.buffer.size().saturating_sub(offset) as usize,
+            source.len(),
+        );
+
+        (self.address as *mut u8)
+            .add(offset as usize)
+            .copy_from(source.as_ptr(), size);
+
+        size
+    }
+
+    pub fn destroy(mut self, allocator: &vk_mem::Allocator) {
+        unsafe { allocator.unmap_memory(&mut self.buffer.allocation) };
+        self.buffer.destroy(allocator);
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut Buffer {
+        &mut self.buffer
+    }
+
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+}
+
+pub struct State {
+    entry: ash::Entry,
+    instance: ash::Instance,
+    device: ash::Device,
+    surface_loader: extensions::khr::Surface,
+    swapchain_loader: extensions::khr::Swapchain,
+    surface: vk::SurfaceKHR,
+
+    allocator: vk_mem::Allocator,
+
+    gpu: usize,
+    gpus: Vec<GpuInfo>,
+    graphics_queue: vk::Queue,
+    compute_queue: vk::Queue,
+
+    command_pool: vk::CommandPool,
+    transfer_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+
+    fences: Vec<vk::Fence>,
+    acquire_semaphores: Vec<vk::Semaphore>,
+    render_complete_semaphores: Vec<vk::Semaphore>,
+
+    swapchain: vk::SwapchainKHR,
+    swapchain_images: Vec<vk::Image>,
+    swapchain_views: Vec<vk::ImageView>,
+    swapchain_index: usize,
+    surface_format: vk::SurfaceFormatKHR,
+    present_mode: vk::PresentModeKHR,
+    swapchain_extent: vk::Extent2D,
+
+    depth_format: vk::Format,
+
+    graph_textures: Vec<GraphTexture>,
+    graph_buffers: Vec<GraphBuffer>,
+    graph_resources: Vec<Option<PhysicalResource>>,
+    backbuffer_state: super::graph::ResourceState,
+    pass_formats: PassFormats,
+    rendering: bool,
+
+    pipeline_layout: vk::PipelineLayout,
+
+    descriptor_layout: vk::DescriptorSetLayout,
+    texture_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    // Allocated as passes need them and freed once the frame is done
+    pass_descriptor_sets: Vec<Vec<vk::DescriptorSet>>,
+
+    linear_sampler: vk::Sampler,
+    shadow_sampler: vk::Sampler,
+
+    uniform_buffers: Vec<HostBuffer>,
+
+    material_layout: vk::DescriptorSetLayout,
+    material_pool: vk::DescriptorPool,
+    material_sets: Vec<vk::DescriptorSet>,
+    material_buffers: Vec<HostBuffer>,
+    joint_buffers: Vec<HostBuffer>,
+    material_sampler: vk::Sampler,
+    textures: Vec<Option<Texture>>,
+    free_textures: Vec<usize>,
+    pending_texture_frees: Vec<(u64, usize, Texture)>,
+
+    initialized: bool,
+    loaded: bool,
+
+    in_frame: bool,
+    frame_index: usize,
+    frame_number: u64,
+    // The last frame each slot submitted, None until it's submitted one
+    submitted_frames: [Option<u64>; FRAME_COUNT],
+    resized: bool,
+
+    mesh_heap: MeshHeap,
+    meshes: Vec<Option<Mesh>>,
+    // Bumped when a slot's mesh is destroyed
+    mesh_generations: Vec<u32>,
+    free_meshes: Vec<usize>,
+    pending_frees: Vec<(u64, MeshAllocation)>,
+
+    staging_ring: Option<StagingRing>,
+    upload_command_buffers: Vec<vk::CommandBuffer>,
+    uploading: bool,
+
+    instance_buffers: Vec<Option<HostBuffer>>,
+    indirect_buffers: Vec<Option<HostBuffer>>,
+    indirect: bool,
+    debug_buffers: Vec<Option<HostBuffer>>,
+    ui_vertex_buffers: Vec<Option<HostBuffer>>,
+    ui_index_buffers: Vec<Option<HostBuffer>>,
+
+    last_mesh_block: Option<usize>,
+    last_pipeline: vk::Pipeline,
+
+    timestamps: Option<timing::Timestamps>,
+    // Read from the frame that last used this frame's resources
+    gpu_timings: Option<(u64, Vec<profiler::GpuPass>)>,
+}
+
+struct Mesh {
+    allocation: MeshAllocation,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+impl State {
+    fn get_allocation_callbacks<'a>() -> vk::AllocationCallbacks<'a> {
+        vk::AllocationCallbacks {
+            pfn_allocation: Some(vulkan_alloc),
+            pfn_reallocation: Some(vulkan_realloc),
+            pfn_free: Some(vulkan_dealloc),
+            p_user_data: ptr::null_mut(),
+            pfn_internal_allocation: None,
+            pfn_internal_free: None,
+            ..Default::default()
+        }
+    }
+
+    unsafe extern "system" fn debug_log(
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        types: vk::DebugUtilsMessageTypeFlagsEXT,
+        callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+        #[allow(unused_variables)] user_data: *mut ffi::c_void,
+    ) -> u32 {
+        let log_level = match severity {
+            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => log::Level::Trace,
+            vk::DebugUtilsMessageSeverityFlagsEXT::INFO => log::Level::Debug,
+            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => log::Level::Info,
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => log::Level::Warn,
+            _ => log::Level::Debug,
+        };
+
+        let mut location = String::new();
+
+        if types.contains(vk::DebugUtilsMessageTypeFlagsEXT::GENERAL) {
+            location += "GENERAL ";
+        }
+        if types.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+            location += "PERFORMANCE ";
+        }
+        if types.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+            location += "VALIDATION ";
+        }
+
+        let message_ptr = (*callback_data).p_message as *const ffi::c_char;
+        let message_raw = unsafe { ffi::CStr::from_ptr(message_ptr) };
+        let message = message_raw.to_str().unwrap();
+        log!(log_level, "VULKAN {}MESSAGE: {}", location, message);
+
+        vk::TRUE
+    }
+
+    fn create_instance(entry: &ash::Entry) -> ash::Instance {
+        debug!("Creating Vulkan instance");
+
+        let app_name = ffi::CString::new(crate::GAME_NAME).unwrap();
+        let engine_name = ffi::CString::new("Purpl Engine").unwrap();
+        let app_info = vk::ApplicationInfo {
+            p_application_name: app_name.as_ptr() as *const ffi::c_char,
+            application_version: vk::make_api_version(
+                0,
+                crate::GAME_VERSION_MAJOR.into(),
+                crate::GAME_VERSION_MINOR.into(),
+                crate::GAME_VERSION_PATCH.into(),
+            ),
+            p_engine_name: engine_name.as_ptr() as *const ffi::c_char,
+            engine_version: 2,
+            api_version: vk::make_api_version(0, 1, 3, 0),
+            ..Default::default()
+        };
+
+        let extensions = [
+            #[cfg(feature = "graphics_debug")]
+            "VK_EXT_debug_utils",
+            "VK_KHR_surface",
+            #[cfg(windows)]
+            "VK_KHR_win32_surface",
+            #[cfg(unix)]
+            "VK_KHR_xcb_surface",
+        ];
+
+        let layers = ["VK_LAYER_KHRONOS_validation"];
+
+        let extensions_cstr: Vec<ffi::CString> = extensions
+            .iter()
+            .map(|extension_name| ffi::CString::new(*extension_name).unwrap())
+            .collect();
+        let extensions_raw: Vec<*const ffi::c_char> = extensions_cstr
+            .iter()
+            .map(|extension_name| extension_name.as_ptr())
+            .collect();
+        let layers_cstr: Vec<ffi::CString> = layers
+            .iter()
+            .map(|layer_name| ffi::CString::new(*layer_name).unwrap())
+            .collect();
+        let layers_raw: Vec<*const ffi::c_char> = layers_cstr
+            .iter()
+            .map(|layer_name| layer_name.as_ptr())
+            .collect();
+
+        let debug_messenger_info = vk::DebugUtilsMessengerCreateInfoEXT {
+            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            pfn_user_callback: Some(Self::debug_log),
+            ..Default::default()
+        };
+
+        let mut create_info = vk::InstanceCreateInfo {
+            p_application_info: ptr::addr_of!(app_info),
+            enabled_extension_count: extensions.len() as u32,
+            pp_enabled_extension_names: extensions_raw.as_ptr(),
+            #[cfg(feature = "graphics_debug")]
+            enabled_layer_count: layers_raw.len() as u32,
+            #[cfg(feature = "graphics_debug")]
+            pp_enabled_layer_names: layers_raw.as_ptr(),
+            #[cfg(feature = "graphics_debug")]
+            p_next: ptr::addr_of!(debug_messenger_info) as *const ffi::c_void,
+            ..Default::default()
+        };
+
+        let result = unsafe {
+            entry.create_instance(&create_info, Some(&State::get_allocation_callbacks()))
+        };
+        let instance = match result {
+            Ok(val) => val,
+            Err(err) if err == vk::Result::ERROR_LAYER_NOT_PRESENT => {
+                debug!("Validation layers not available, retrying with them disabled");
+                create_info.enabled_layer_count = 0;
+                unsafe {
+                    vulkan_check!(entry.create_instance(&create_info, Some(&State::get_allocation_callbacks())))
+                }
+            }
+            Err(err) =>
+                panic!("Vulkan call entry.create_instance(&create_info, Some(&State::get_allocation_callbacks())) failed: {err}")
+        };
+
+        debug!(
+            "Created Vulkan instance {:?} successfully",
+            instance.handle()
+        );
+        instance
+    }
+
+    const fn get_required_device_extensions() -> &'static [&'static str] {
+        const EXTENSIONS: &[&'static str] = &["VK_KHR_swapchain"];
+        EXTENSIONS
+    }
+
+    fn get_gpus(
+        instance: &ash::Instance,
+        surface_loader: &extensions::khr::Surface,
+        surface: &vk::SurfaceKHR,
+    ) -> Vec<GpuInfo> {
+        debug!("Enumerating devices (scores loosely based on memory, maximum viewport size, and discrete/integrated/CPU)");
+        let devices = unsafe { vulkan_check!(instance.enumerate_physical_devices()) };
+        let devices = devices
+            .into_iter()
+            .enumerate()
+            .map(|(i, device)| (i + 1, device));
+
+        debug!(
+            "Required extensions: {:#?}",
+            Self::get_required_device_extensions()
+        );
+
+        let mut gpus: Vec<GpuInfo> = Vec::new();
+        let mut usable_count = 0;
+        for (i, device) in devices {
+            debug!("Getting information for device {i}");
+            let queue_family_props =
+                unsafe { instance.get_physical_device_queue_family_properties(device) };
+            if queue_family_props.is_empty() {
+                error!("Ignoring GPU {i} because it has no queue families");
+                continue;
+            }
+
+            let Some((graphics_family_index, _)) = queue_family_props
+                .iter()
+                .enumerate()
+                .map(|(i, props)| (i as u32, props))
+                .find(|(_, props)| {
+                    props.queue_count >= 1
+                        && props.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+                }) else {
+                error!("Failed to get all required queue familiy indices for device {i}");
+                continue;
+            };
+
+            let Some((compute_family_index, _)) = queue_family_props
+                .iter()
+                .enumerate()
+                .map(|(i, props)| (i as u32, props))
+                .find(|(_, props)| {
+                    props.queue_count >= 1
+                        && props.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                }) else {
+                error!("Failed to get all required queue familiy indices for device {i}");
+                continue;
+            };
+
+            let extension_properties =
+                unsafe { instance.enumerate_device_extension_properties(device) };
+            match extension_properties {
+                Ok(val) if val.len() >= Self::get_required_device_extensions().len() => {
+                    debug!("Available extensions:");
+                    let mut required_found_count = 0;
+                    let extensions = Vec::from(Self::get_required_device_extensions());
+                    for properties in &val {
+                        let mut name_vec = Vec::from(unsafe {
+                            mem::transmute::<[i8; 256], [u8; 256]>(properties.extension_name)
+                        });
+                        name_vec.dedup_by(|a, b| *a == 0 && *b == 0);
+                        let name_raw = ffi::CString::from_vec_with_nul(name_vec).unwrap();
+                        let name = name_raw.into_string().unwrap();
+                        if extensions
+                            .split_at(required_found_count)
+                            .1
+                            .contains(&name.as_str())
+                        {
+                            debug!("\t{name} (required)");
+                            required_found_count += 1;
+                        } else {
+                            trace!("\t{name}");
+                        }
+                    }
+                    if required_found_count < extensions.len() {
+                        error!(
+                            "Ignoring device {} because it only has {} of the required {} extension(s)",
+                            i,
+                            required_found_count,
+                            extensions.len()
+                        );
+                        error!("Extensions: {extensions:?}");
+                        continue;
+                    }
+                    val
+                }
+                Ok(val) => {
+                    error!(
+                        "Ignoring device {} because it has {} extension(s) when {} are required",
+                        i,
+                        val.len(),
+                        Self::get_required_device_extensions().len()
+                    );
+                    continue;
+                }
+                Err(err) => {
+                    error!("Failed to get extension properties for device {i}: {err}");
+                    continue;
+                }
+            };
+
+            let surface_capabilities = unsafe {
+                surface_loader.get_physical_device_surface_capabilities(device, *surface)
+            };
+            let surface_caps = match surface_capabilities {
+                Ok(val) => val,
+                Err(err) => {
+                    error!("Failed to get surface capabilities for device {i}: {err}");
+                    continue;
+                }
+            };
+
+            let fmts =
+                unsafe { surface_loader.get_physical_device_surface_formats(device, *surface) };
+            let surface_formats = match fmts {
+                Ok(val) if !val.is_empty() => val,
+                Ok(_) => {
+                    error!("Ignoring device {i} because it has no surface formats");
+                    continue;
+                }
+                Err(err) => {
+                    error!("Failed to get surface formats for device {i}: {err}");
+                    continue;
+                }
+            };
+
+            let present_modes = unsafe {
+                surface_loader.get_physical_device_surface_present_modes(device, *surface)
+            };
+            let present_modes = match present_modes {
+                Ok(val) if !val.is_empty() => val,
+                Ok(_) => {
+                    error!("Ignoring device {i} because it has no present modes");
+                    continue;
+                }
+                Err(err) => {
+                    error!("Failed to get present modes for device {i}: {err}");
+                    continue;
+                }
+            };
+
+            let memory_properties =
+                unsafe { instance.get_physical_device_memory_properties(device) };
+            let properties = unsafe { instance.get_physical_device_properties(device) };
+            let features = unsafe { instance.get_physical_device_features(device) };
+
+            let mut score = (memory_properties.memory_heaps[0].size / 1_000) as u32
+                + (properties.limits.max_viewport_dimensions[0] as u64
+                    * properties.limits.max_viewport_dimensions[1] as u64
+                    / 1_000) as u32;
+            if [
+                vk::PhysicalDeviceType::DISCRETE_GPU,
+                vk::PhysicalDeviceType::VIRTUAL_GPU,
+            ]
+            .contains(&properties.device_type)
+            {
+                score *= 10;
+            } else if properties.device_type == vk::PhysicalDeviceType::INTEGRATED_GPU {
+                score *= 2;
+            }
+
+            let name = unsafe {
+                ffi::CStr::from_ptr(properties.device_name.as_ptr())
+                    .to_str()
+                    .unwrap()
+            };
+
+            // Doesn't work on my systems, but it should
+            let mut properties2 = vk::PhysicalDeviceProperties2::default();
+            unsafe { instance.get_physical_device_properties2(device, &mut properties2) };
+            let mut p_next = properties2.p_next;
+            let mut driver_info_ptr = ptr::null();
+            while !p_next.is_null() {
+                let tagged = unsafe { *(p_next as *mut vk::BaseOutStructure) };
+                if tagged.s_type == vk::StructureType::PHYSICAL_DEVICE_DRIVER_PROPERTIES_KHR {
+                    driver_info_ptr = p_next;
+                    break;
+                }
+
+                p_next = tagged.p_next as *mut ffi::c_void;
+            }
+
+            let driver_info = if !driver_info_ptr.is_null() {
+                unsafe { *(driver_info_ptr as *mut vk::PhysicalDeviceDriverProperties) }
+            } else {
+                vk::PhysicalDeviceDriverProperties::default()
+            };
+
+            debug!("Device {i}:");
+            debug!("\tName: {name}");
+            trace!("\tDriver information:\n{driver_info:#?}");
+            debug!("\tScore: {score}");
+            debug!("\tType: {:#?}", properties.device_type);
+            debug!("\tHandle: {device:#?}");
+
+            gpus.push(GpuInfo {
+                device,
+                properties,
+                features,
+                surface_formats,
+                present_modes,
+                graphics_family_index,
+                compute_family_index,
+                timestamp_valid_bits: queue_family_props[graphics_family_index as usize]
+                    .timestamp_valid_bits,
+                performance_score: score,
+            });
+
+            usable_count += 1;
+        }
+
+        debug!(
+            "Got information of {} device(s) (of which {} are usable)",
+            gpus.len(),
+            usable_count
+        );
+        if usable_count < 1 {
+            panic!("Could not find any usable Vulkan devices");
+        }
+
+        debug!("Sorting device(s)");
+        gpus.sort_by_key(|gpu| -(gpu.performance_score as i32));
+
+        gpus
+    }
+
+    fn create_device(
+        instance: &ash::Instance,
+
+        gpu: &GpuInfo,
+    ) -> (ash::Device, vk::Queue, vk::Queue) {
+        debug!("Creating logical device");
+
+        let queue_priority: f32 = 1.0;
+        let graphics_queue_info = vk::DeviceQueueCreateInfo {
+            queue_family_index: gpu.graphics_family_index,
+            p_queue_priorities: ptr::addr_of!(queue_priority),
+            queue_count: 1,
+            ..Default::default()
+        };
+        let present_queue_info = vk::DeviceQueueCreateInfo {
+            queue_family_index: gpu.compute_family_index,
+            p_queue_priorities: ptr::addr_of!(queue_priority),
+            queue_count: 1,
+            ..Default::default()
+        };
+        let queue_create_infos = if gpu.graphics_family_index != gpu.compute_family_index {
+            vec![graphics_queue_info, present_queue_info]
+        } else {
+            vec![graphics_queue_info]
+        };
+
+        // Indirect drawing gets turned off and textures aren't anisotropically filtered if
+        // these are missing
+        let device_features = vk::PhysicalDeviceFeatures {
+            multi_draw_indirect: gpu.features.multi_draw_indirect,
+            draw_indirect_first_instance: gpu.features.draw_indirect_first_instance,
+            sampler_anisotropy: gpu.features.sampler_anisotropy,
+            ..Default::default()
+        };
+
+        let shader_object_features = vk::PhysicalDeviceShaderObjectFeaturesEXT {
+            shader_object: vk::TRUE,
+            ..Default::default()
+        };
+        let device_13_features = vk::PhysicalDeviceVulkan13Features {
+            dynamic_rendering: vk::TRUE,
+            p_next: ptr::addr_of!(shader_object_features) as *mut ffi::c_void,
+            ..Default::default()
+        };
+        // For the material texture array
+        let device_12_features = vk::PhysicalDeviceVulkan12Features {
+            shader_sampled_image_array_non_uniform_indexing: vk::TRUE,
+            descriptor_binding_sampled_image_update_after_bind: vk::TRUE,
+            descriptor_binding_update_unused_while_pending: vk::TRUE,
+            descriptor_binding_partially_bound: vk::TRUE,
+            p_next: ptr::addr_of!(device_13_features) as *mut ffi::c_void,
+            ..Default::default()
+        };
+
+        let extensions_cstr: Vec<ffi::CString> = Self::get_required_device_extensions()
+            .iter()
+            .map(|extension_name| ffi::CString::new(*extension_name).unwrap())
+            .collect();
+        let extensions_raw: Vec<*const ffi::c_char> = extensions_cstr
+            .iter()
+            .map(|extension_name| extension_name.as_ptr())
+            .collect();
+
+        let device_info = vk::DeviceCreateInfo {
+            p_queue_create_infos: queue_create_infos.as_ptr(),
+            queue_create_info_count: queue_create_infos.len() as u32,
+            p_enabled_features: ptr::addr_of!(device_features),
+            pp_enabled_extension_names: extensions_raw.as_ptr(),
+            enabled_extension_count: extensions_raw.len() as u32,
+            p_next: ptr::addr_of!(device_12_features) as *const ffi::c_void,
+
+            ..Default::default()
+        };
+
+        let device = unsafe {
+            vulkan_check!(instance.create_device(
+                gpu.device,
+                &device_info,
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        debug!("Created logical device {:#?} successfully", device.handle());
+
+        debug!("Retrieving queues");
+        let graphics_queue = unsafe { device.get_device_queue(gpu.graphics_family_index, 0) };
+        let present_queue = unsafe { device.get_device_queue(gpu.compute_family_index, 0) };
+        debug!(
+            "Got graphics queue {:#?} and compute queue {:#?}",
+            graphics_queue, present_queue
+        );
+
+        (device, graphics_queue, present_queue)
+    }
+
+    fn create_fences(device: &ash::Device) -> Vec<vk::Fence> {
+        debug!("Creating {FRAME_COUNT} command fences");
+
+        let fence_create_info = vk::FenceCreateInfo {
+            flags: vk::FenceCreateFlags::SIGNALED,
+            ..Default::default()
+        };
+        let mut fences = Vec::new();
+        for _ in 0..FRAME_COUNT {
+            fences.push(unsafe {
+                vulkan_check!(device
+                    .create_fence(&fence_create_info, Some(&State::get_allocation_callbacks())))
+            })
+        }
+
+        fences
+    }
+
+    fn create_semaphores(device: &ash::Device) -> (Vec<vk::Semaphore>, Vec<vk::Semaphore>) {
+        debug!("Creating {} semaphores", FRAME_COUNT * 2);
+
+        let semaphore_create_info = vk::SemaphoreCreateInfo {
+            ..Default::default()
+        };
+        let mut acquire_semaphores = Vec::new();
+        let mut complete_semaphores = Vec::new();
+        acquire_semaphores.resize_with(FRAME_COUNT, || unsafe {
+            vulkan_check!(device.create_semaphore(
+                &semaphore_create_info,
+                Some(&State::get_allocation_callbacks())
+            ))
+        });
+        complete_semaphores.resize_with(FRAME_COUNT, || unsafe {
+            vulkan_check!(device.create_semaphore(
+                &semaphore_create_info,
+                Some(&State::get_allocation_callbacks())
+            ))
+        });
+
+        (acquire_semaphores, complete_semaphores)
+    }
+
+    fn create_command_pools(
+        device: &ash::Device,
+        gpu: &GpuInfo,
+    ) -> (vk::CommandPool, vk::CommandPool) {
+        debug!("Creating command pools");
+
+        let main_pool_info = vk::CommandPoolCreateInfo {
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            queue_family_index: gpu.graphics_family_index,
+            ..Default::default()
+        };
+        let transfer_pool_info = vk::CommandPoolCreateInfo {
+            flags: main_pool_info.flags | vk::CommandPoolCreateFlags::TRANSIENT,
+            ..main_pool_info
+        };
+
+        let main_pool = unsafe {
+            vulkan_check!(device
+                .create_command_pool(&main_pool_info, Some(&State::get_allocation_callbacks())))
+        };
+        let transfer_pool = unsafe {
+            vulkan_check!(device.create_command_pool(
+                &transfer_pool_info,
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        debug!(
+            "Created main command pool {main_pool:#?} and transfer command pool {transfer_pool:#?}"
+        );
+
+        (main_pool, transfer_pool)
+    }
+
+    fn allocate_command_buffers(
+        device: &ash::Device,
+        cmd_pool: &vk::CommandPool,
+    ) -> Vec<vk::CommandBuffer> {
+        debug!("Allocating {FRAME_COUNT} command buffers");
+
+        unsafe {
+            vulkan_check!(
+                device.allocate_command_buffers(&vk::CommandBufferAllocateInfo {
+                    level: vk::CommandBufferLevel::PRIMARY,
+                    command_pool: *cmd_pool,
+                    command_buffer_count: FRAME_COUNT as u32,
+                    ..Default::default()
+                })
+            )
+        }
+    }
+
+    // Buffers and images still around once everything that owns them is destroyed
+    fn report_leaked_objects() {
+        let leaked = memory::live_objects();
+        if leaked.is_empty() {
+            debug!("No buffers or images leaked");
+            return;
+        }
+        let bytes: vk::DeviceSize = leaked.iter().map(|(_, _, size)| size).sum();
+        warn!(
+            "{} buffer(s) and image(s) totalling {bytes} byte(s) weren't destroyed:",
+            leaked.len()
+        );
+        for (handle, category, size) in leaked {
+            warn!("    {handle:#x} in {}, {size} byte(s)", category.name());
+        }
+    }
+
+    fn create_allocator(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+    ) -> vk_mem::Allocator {
+        debug!("Creating Vulkan allocator");
+        vulkan_check!(vk_mem::Allocator::new(vk_mem::AllocatorCreateInfo::new(
+            Rc::from(instance),
+            Rc::from(device),
+            physical_device
+        )))
+    }
+
+    fn choose_surface_format(gpu: &GpuInfo) -> vk::SurfaceFormatKHR {
+        debug!("Choosing surface format");
+
+        if gpu.surface_formats.len() == 1 && gpu.surface_formats[0].format == vk::Format::UNDEFINED
+        {
+            return vk::SurfaceFormatKHR {
+                format: vk::Format::B8G8R8A8_UNORM,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            };
+        }
+
+        for &format in &gpu.surface_formats {
+            if format.format == vk::Format::B8G8R8A8_UNORM
+                && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            {
+                debug!("Chose format {format:#?}");
+                return format;
+            }
+        }
+
+        debug!("Chose format {:#?}", gpu.surface_formats[0]);
+        gpu.surface_formats[0]
+    }
+
+    fn choose_present_mode(gpu: &GpuInfo) -> vk::PresentModeKHR {
+        debug!("Choosing presentation mode");
+
+        for &mode in &gpu.present_modes {
+            if mode == vk::PresentModeKHR::MAILBOX {
+                debug!("Chose present mode {mode:#?}");
+                return mode;
+            }
+        }
+
+        debug!("Chose FIFO present mode");
+        vk::PresentModeKHR::FIFO
+    }
+
+    fn create_swapchain(
+        device: &ash::Device,
+        gpu: &GpuInfo,
+        surface: &vk::SurfaceKHR,
+        present_mode: &vk::PresentModeKHR,
+        surface_format: &vk::SurfaceFormatKHR,
+        image_extent: &vk::Extent2D,
+        loader: &extensions::khr::Swapchain,
+    ) -> (vk::SwapchainKHR, Vec<vk::Image>, Vec<vk::ImageView>) {
+        debug!("Creating swap chain");
+
+        let queue_family_indices = [gpu.graphics_family_index, gpu.compute_family_index];
+        let (image_sharing_mode, queue_family_index_count, p_queue_family_indices) =
+            if gpu.graphics_family_index != gpu.compute_family_index {
+                (
+                    vk::SharingMode::CONCURRENT,
+                    2,
+                    queue_family_indices.as_ptr(),
+                )
+            } else {
+                (vk::SharingMode::EXCLUSIVE, 0, ptr::null())
+            };
+
+        let swapchain_info = vk::SwapchainCreateInfoKHR {
+            surface: *surface,
+            min_image_count: FRAME_COUNT as u32,
+            image_format: surface_format.format,
+            image_color_space: surface_format.color_space,
+            image_extent: *image_extent,
+            image_array_layers: 1,
+
+            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+
+            image_sharing_mode,
+            queue_family_index_count,
+            p_queue_family_indices,
+
+            pre_transform: vk::SurfaceTransformFlagsKHR::IDENTITY,
+            composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+            present_mode: *present_mode,
+
+            clipped: vk::TRUE,
+
+            ..Default::default()
+        };
+
+        let swapchain = unsafe {
+            vulkan_check!(
+                loader.create_swapchain(&swapchain_info, Some(&State::get_allocation_callbacks()))
+            )
+        };
+        let images = unsafe { vulkan_check!(loader.get_swapchain_images(swapchain)) };
+
+        debug!("Creating swap chain image views");
+        let mut views = Vec::new();
+        let mut i = 0;
+        views.resize_with(FRAME_COUNT, || unsafe {
+            let image = images[i];
+            i += 1;
+            vulkan_check!(device.create_image_view(
+                &vk::ImageViewCreateInfo {
+                    image,
+
+                    view_type: vk::ImageViewType::TYPE_2D,
+
+                    format: surface_format.format,
+
+                    components: vk::ComponentMapping {
+                        r: vk::ComponentSwizzle::R,
+                        g: vk::ComponentSwizzle::G,
+                        b: vk::ComponentSwizzle::B,
+                        a: vk::ComponentSwizzle::A,
+                    },
+
+                    subresource_range: vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    },
+
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        });
+
+        debug!("Created swapchain {swapchain:#?}");
+
+        (swapchain, images, views)
+    }
+
+    fn destroy_swapchain(&mut self) {
+        debug!("Destroying {FRAME_COUNT} swap chain image views");
+        self.swapchain_views.iter().for_each(|view| unsafe {
+            self.device
+                .destroy_image_view(*view, Some(&State::get_allocation_callbacks()))
+        });
+
+        debug!("Destroying swap chain {:#?}", self.swapchain);
+        unsafe {
+            self.swapchain_loader
+                .destroy_swapchain(self.swapchain, Some(&State::get_allocation_callbacks()))
+        };
+    }
+
+    fn choose_depth_format(instance: &ash::Instance, gpu: &GpuInfo) -> vk::Format {
+        let depth_formats = vec![
+            vk::Format::D32_SFLOAT,
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D24_UNORM_S8_UINT,
+        ];
+
+        let depth_format = Image::choose_fmt(
+            instance,
+            gpu,
+            &depth_formats,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE,
+        );
+        if depth_format == vk::Format::UNDEFINED {
+            panic!("No supported depth formats found");
+        }
+        debug!("Using depth format {depth_format:#?}");
+
+        depth_format
+    }
+
+    fn create_descriptor_layout(device: &ash::Device) -> vk::DescriptorSetLayout {
+        debug!("Creating descriptor set layout");
+
+        let ubo_layout_binding = vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        };
+
+        let descriptor_layout_info = vk::DescriptorSetLayoutCreateInfo {
+            p_bindings: ptr::addr_of!(ubo_layout_binding),
+            binding_count: 1,
+            ..Default::default()
+        };
+
+        let layout = unsafe {
+            vulkan_check!(device.create_descriptor_set_layout(
+                &descriptor_layout_info,
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        debug!("Created descriptor set layout {layout:#?}");
+
+        layout
+    }
+
+    fn create_texture_layout(device: &ash::Device) -> vk::DescriptorSetLayout {
+        debug!("Creating pass texture descriptor set layout");
+
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = (0..MAX_PASS_TEXTURES)
+            .map(|binding| vk::DescriptorSetLayoutBinding {
+                binding: binding as u32,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            })
+            .collect();
+
+        unsafe {
+            vulkan_check!(device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo {
+                    p_bindings: bindings.as_ptr(),
+                    binding_count: bindings.len() as u32,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        }
+    }
+
+    fn create_samplers(device: &ash::Device) -> (vk::Sampler, vk::Sampler) {
+        debug!("Creating samplers");
+
+        let linear_sampler = unsafe {
+            vulkan_check!(device.create_sampler(
+                &vk::SamplerCreateInfo {
+                    mag_filter: vk::Filter::LINEAR,
+                    min_filter: vk::Filter::LINEAR,
+                    mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+                    address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    max_lod: vk::LOD_CLAMP_NONE,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+        // Compares in hardware and filters the results, which is the first step of PCF.
+        // Anything outside the map is lit.
+        let shadow_sampler = unsafe {
+            vulkan_check!(device.create_sampler(
+                &vk::SamplerCreateInfo {
+                    mag_filter: vk::Filter::LINEAR,
+                    min_filter: vk::Filter::LINEAR,
+                    mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                    address_mode_u: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+                    address_mode_v: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+                    address_mode_w: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+                    border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+                    compare_enable: vk::TRUE,
+                    compare_op: vk::CompareOp::LESS_OR_EQUAL,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        (linear_sampler, shadow_sampler)
+    }
+
+    fn create_pipeline_layout(
+        device: &ash::Device,
+        descriptor_layout: &vk::DescriptorSetLayout,
+        texture_layout: &vk::DescriptorSetLayout,
+        material_layout: &vk::DescriptorSetLayout,
+    ) -> vk::PipelineLayout {
+        let set_layouts = [*descriptor_layout, *texture_layout, *material_layout];
+        // Each pass pushes its own view projection matrix, fullscreen passes push parameters
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: mem::size_of::<Matrix4<f32>>() as u32,
+        };
+        let create_info = vk::PipelineLayoutCreateInfo {
+            p_set_layouts: set_layouts.as_ptr(),
+            set_layout_count: set_layouts.len() as u32,
+            p_push_constant_ranges: ptr::addr_of!(push_constant_range),
+            push_constant_range_count: 1,
+            ..Default::default()
+        };
+
+        unsafe {
+            vulkan_check!(device
+                .create_pipeline_layout(&create_info, Some(&Self::get_allocation_callbacks())))
+        }
+    }
+
+    fn resize(&mut self, video: &Box<dyn platform::video::VideoBackend>) {
+        debug!("Recreating swap chain");
+
+        debug!("Waiting for device idle");
+        unsafe { vulkan_check!(self.device.device_wait_idle()) };
+
+        // Everything the graph allocated is probably the wrong size now
+        self.destroy_graph_resources();
+        self.destroy_swapchain();
+        let (width, height) = video.get_size();
+        self.swapchain_extent = vk::Extent2D { width, height };
+        (self.swapchain, self.swapchain_images, self.swapchain_views) = Self::create_swapchain(
+            &self.device,
+            &self.gpus[self.gpu],
+            &self.surface,
+            &self.present_mode,
+            &self.surface_format,
+            &self.swapchain_extent,
+            &self.swapchain_loader,
+        );
+    }
+
+    fn create_descriptor_pool(device: &ash::Device) -> vk::DescriptorPool {
+        debug!("Creating descriptor pool");
+
+        const POOL_SIZES: [vk::DescriptorPoolSize; 11] = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLER,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::INPUT_ATTACHMENT,
+                descriptor_count: 1000,
+            },
+        ];
+
+        let pool = unsafe {
+            vulkan_check!(device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo {
+                    flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+                    pool_size_count: POOL_SIZES.len() as u32,
+                    p_pool_sizes: POOL_SIZES.as_ptr(),
+                    max_sets: 1000 * POOL_SIZES.len() as u32,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        debug!("Created descriptor pool {pool:#?}");
+
+        pool
+    }
+
+    fn allocate_uniform_buffers(allocator: &vk_mem::Allocator) -> Vec<HostBuffer> {
+        debug!("Allocating {FRAME_COUNT} uniform buffers");
+        let mut buffers = Vec::new();
+        buffers.resize_with(3, || {
+            vulkan_check!(HostBuffer::new(
+                allocator,
+                mem::size_of::<super::UniformData>() as u64,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ))
+        });
+
+        buffers
+    }
+
+    fn allocate_descriptor_sets(
+        device: &ash::Device,
+        layout: &vk::DescriptorSetLayout,
+        pool: &vk::DescriptorPool,
+        uniform_buffers: &[HostBuffer],
+    ) -> Vec<vk::DescriptorSet> {
+        debug!("Allocating {FRAME_COUNT} descriptor sets");
+
+        let mut layouts = Vec::new();
+        layouts.resize_with(3, || *layout);
+
+        let descriptor_sets = unsafe {
+            vulkan_check!(
+                device.allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo {
+                    descriptor_pool: *pool,
+                    descriptor_set_count: FRAME_COUNT as u32,
+                    p_set_layouts: layouts.as_ptr(),
+                    ..Default::default()
+                })
+            )
+        };
+
+        let mut i = 0;
+        let mut buffer_infos = Vec::new();
+        buffer_infos.resize_with(FRAME_COUNT, || {
+            let info = vk::DescriptorBufferInfo {
+                offset: 0,
+                range: mem::size_of::<super::UniformData>() as u64,
+                buffer: *uniform_buffers[i].buffer().handle(),
+            };
+            i += 1;
+
+            info
+        });
+        i = 0;
+        let mut write_infos = Vec::new();
+        write_infos.resize_with(FRAME_COUNT, || {
+            let write = vk::WriteDescriptorSet {
+                dst_binding: 0,
+                dst_array_element: 0,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+                p_buffer_info: ptr::addr_of!(buffer_infos[i]),
+                dst_set: descriptor_sets[i],
+                ..Default::default()
+            };
+            i += 1;
+            write
+        });
+
+        unsafe { device.update_descriptor_sets(write_infos.as_slice(), &[]) };
+
+        descriptor_sets
+    }
+
+    fn begin_uploads(&mut self) -> vk::CommandBuffer {
+        let command_buffer = self.upload_command_buffers[self.frame_index];
+
+        if !self.uploading {
+            unsafe {
+                // Outside of a frame, the last submission from this frame slot could still be
+                // using the command buffer and its part of the staging ring
+                if !self.in_frame {
+                    vulkan_check!(self.device.wait_for_fences(
+                        &[self.fences[self.frame_index]],
+                        true,
+                        u64::MAX
+                    ));
+                    self.staging_ring
+                        .as_mut()
+                        .unwrap()
+                        .retire(self.frame_index);
+                }
+
+                vulkan_check!(self
+                    .device
+                    .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty()));
+                vulkan_check!(self.device.begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo {
+                        flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                        ..Default::default()
+                    }
+                ));
+            }
+
+            self.uploading = true;
+        }
+
+        command_buffer
+    }
+
+    fn end_uploads(&mut self) -> Option<vk::CommandBuffer> {
+        if !self.uploading {
+            return None;
+        }
+
+        let command_buffer = self.upload_command_buffers[self.frame_index];
+        let barrier = vk::MemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                | vk::AccessFlags::INDEX_READ,
+            ..Default::default()
+        };
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+            vulkan_check!(self.device.end_command_buffer(command_buffer));
+        }
+
+        self.staging_ring
+            .as_mut()
+            .unwrap()
+            .submit(self.frame_index);
+        self.uploading = false;
+
+        Some(command_buffer)
+    }
+
+    fn flush_uploads(&mut self) {
+        if let Some(command_buffer) = self.end_uploads() {
+            debug!("Flushing pending uploads");
+            unsafe {
+                vulkan_check!(self.device.queue_submit(
+                    self.compute_queue,
+                    &[vk::SubmitInfo {
+                        command_buffer_count: 1,
+                        p_command_buffers: ptr::addr_of!(command_buffer),
+                        ..Default::default()
+                    }],
+                    vk::Fence::null()
+                ));
+                vulkan_check!(self.device.queue_wait_idle(self.compute_queue));
+            }
+
+            self.staging_ring.as_mut().unwrap().retire_all();
+        }
+    }
+
+    fn upload_buffer(
+        &mut self,
+        data: &[u8],
+        destination: vk::Buffer,
+        destination_offset: vk::DeviceSize,
+    ) {
+        if data.is_empty() {
+            return;
+        }
+
+        let size = data.len() as vk::DeviceSize;
+        let mut offset = self.staging_ring.as_mut().unwrap().allocate(size, 16);
+        if offset.is_none() && size <= self.staging_ring.as_ref().unwrap().capacity() {
+            debug!("Staging ring is full, waiting for pending uploads");
+            self.flush_uploads();
+            offset = self.staging_ring.as_mut().unwrap().allocate(size, 16);
+        }
+
+        match offset {
+            Some(offset) => {
+                let staging_ring = self.staging_ring.as_ref().unwrap();
+                unsafe { staging_ring.write(offset, data) };
+                let source = *staging_ring.buffer().handle();
+
+                let command_buffer = self.begin_uploads();
+                unsafe {
+                    self.device.cmd_copy_buffer(
+                        command_buffer,
+                        source,
+                        destination,
+                        &[vk::BufferCopy {
+                            src_offset: offset,
+                            dst_offset: destination_offset,
+                            size,
+                        }],
+                    )
+                };
+            }
+            None => {
+                debug!("Upload of {size} byte(s) doesn't fit in the staging ring, using a temporary buffer");
+
+                // Keep uploads in order in case this overwrites something still pending
+                self.flush_uploads();
+
+                let staging_buffer = vulkan_check!(HostBuffer::new(
+                    &self.allocator,
+                    size,
+                    vk::BufferUsageFlags::TRANSFER_SRC,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ));
+                unsafe { staging_buffer.read(data, 0) };
+                staging_buffer.buffer().copy(
+                    &self.device,
+                    &self.compute_queue,
+                    &self.transfer_pool,
+                    &destination,
+                    destination_offset,
+                );
+                staging_buffer.destroy(&self.allocator);
+            }
+        }
+    }
+
+    // Per frame buffers only get replaced after the frame's fence is waited on, so the old one
+    // can't be in use anymore
+    fn reserve_host_buffer(
+        allocator: &vk_mem::Allocator,
+        buffer: &mut Option<HostBuffer>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) {
+        if buffer
+            .as_ref()
+            .map_or(true, |buffer| buffer.buffer().size() < size)
+        {
+            if let Some(old_buffer) = buffer.take() {
+                old_buffer.destroy(allocator);
+            }
+
+            let size = size.next_power_of_two();
+            trace!("Allocating {size} byte host buffer for {usage:?}");
+            *buffer = Some(vulkan_check!(HostBuffer::new(
+                allocator,
+                size,
+                usage,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )));
+        }
+    }
+
+    fn bind_pipeline(&mut self, pipeline: vk::Pipeline) {
+        if self.last_pipeline != pipeline {
+            unsafe {
+                self.device.cmd_bind_pipeline(
+                    self.command_buffers[self.frame_index],
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline,
+                )
+            };
+            self.last_pipeline = pipeline;
+        }
+    }
+
+    fn bind_mesh_block(&mut self, block: usize) {
+        if self.last_mesh_block != Some(block) {
+            let buffer = *self.mesh_heap.buffer(block).handle();
+            unsafe {
+                self.device.cmd_bind_vertex_buffers(
+                    self.command_buffers[self.frame_index],
+                    0,
+                    &[buffer],
+                    &[0],
+                );
+                self.device.cmd_bind_index_buffer(
+                    self.command_buffers[self.frame_index],
+                    buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+            }
+            self.last_mesh_block = Some(block);
+        }
+    }
+
+    fn create_pipeline(
+        &self,
+        shader: &ShaderData,
+        formats: &PassFormats,
+    ) -> Result<vk::Pipeline, vk::Result> {
+        const DYNAMIC_STATES: [vk::DynamicState; 4] = [
+            vk::DynamicState::VIEWPORT,
+            vk::DynamicState::SCISSOR,
+            vk::DynamicState::LINE_WIDTH,
+            vk::DynamicState::PRIMITIVE_TOPOLOGY,
+        ];
+
+        let pipeline_dynamic_state = vk::PipelineDynamicStateCreateInfo {
+            dynamic_state_count: DYNAMIC_STATES.len() as u32,
+            p_dynamic_states: DYNAMIC_STATES.as_ptr(),
+            ..Default::default()
+        };
+
+        let vertex_stage_info = vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::VERTEX,
+            module: shader.vertex_module,
+            p_name: b"main\0".as_ptr() as *const ffi::c_char,
+            ..Default::default()
+        };
+        let fragment_stage_info = vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::FRAGMENT,
+            module: shader.fragment_module,
+            p_name: b"main\0".as_ptr() as *const ffi::c_char,
+            ..Default::default()
+        };
+
+        // Binding 0 is the mesh, binding 1 is the per instance model matrix and material
+        let vertex_binding_descriptions = [
+            vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: mem::size_of::<super::Vertex>() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            },
+            vk::VertexInputBindingDescription {
+                binding: 1,
+                stride: mem::size_of::<super::InstanceData>() as u32,
+                input_rate: vk::VertexInputRate::INSTANCE,
+            },
+        ];
+        let mut vertex_attribute_descriptions = vec![
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: mem::size_of::<[f32; 3]>() as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: mem::size_of::<[f32; 5]>() as u32,
+            },
+        ];
+        vertex_attribute_descriptions.extend((0..4).map(|column| {
+            vk::VertexInputAttributeDescription {
+                location: 3 + column,
+                binding: 1,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: column * mem::size_of::<[f32; 4]>() as u32,
+            }
+        }));
+        vertex_attribute_descriptions.push(vk::VertexInputAttributeDescription {
+            location: 7,
+            binding: 1,
+            format: vk::Format::R32_UINT,
+            offset: mem::size_of::<Matrix4<f32>>() as u32,
+        });
+
+        // Skinned meshes have joints and weights after the normal, and use the instance's
+        // first joint
+        let skinned_binding_descriptions = [
+            vk::VertexInputBindingDescription {
+                stride: mem::size_of::<super::skinned::SkinnedVertex>() as u32,
+                ..vertex_binding_descriptions[0]
+            },
+            vertex_binding_descriptions[1],
+        ];
+        let mut skinned_attribute_descriptions = vertex_attribute_descriptions.clone();
+        skinned_attribute_descriptions.extend([
+            vk::VertexInputAttributeDescription {
+                location: 8,
+                binding: 1,
+                format: vk::Format::R32_UINT,
+                offset: (mem::size_of::<Matrix4<f32>>() + mem::size_of::<u32>()) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 9,
+                binding: 0,
+                format: vk::Format::R16G16B16A16_UINT,
+                offset: mem::size_of::<[f32; 8]>() as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 10,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: (mem::size_of::<[f32; 8]>() + mem::size_of::<[u16; 4]>()) as u32,
+            },
+        ]);
+
+        // Debug geometry is already in clip space and just has a color
+        let debug_binding_description = vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: mem::size_of::<super::debug_draw::DebugVertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        };
+        let debug_attribute_descriptions =
+            [0, 1].map(|location| vk::VertexInputAttributeDescription {
+                location,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: location * mem::size_of::<[f32; 4]>() as u32,
+            });
+
+        // UI vertices are in pixels, with an 8 bit color
+        let ui_binding_description = vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: mem::size_of::<super::ui::UiVertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        };
+        let ui_attribute_descriptions = [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: mem::size_of::<[f32; 2]>() as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: vk::Format::R8G8B8A8_UNORM,
+                offset: mem::size_of::<[f32; 4]>() as u32,
+            },
+        ];
+
+        // Fullscreen shaders make their triangle from the vertex index
+        let vertex_input_state = match shader.kind {
+            ShaderKind::Mesh => vk::PipelineVertexInputStateCreateInfo {
+                vertex_binding_description_count: vertex_binding_descriptions.len() as u32,
+                p_vertex_binding_descriptions: vertex_binding_descriptions.as_ptr(),
+                vertex_attribute_description_count: vertex_attribute_descriptions.len() as u32,
+                p_vertex_attribute_descriptions: vertex_attribute_descriptions.as_ptr(),
+                ..Default::default()
+            },
+            ShaderKind::SkinnedMesh => vk::PipelineVertexInputStateCreateInfo {
+                vertex_binding_description_count: skinned_binding_descriptions.len() as u32,
+                p_vertex_binding_descriptions: skinned_binding_descriptions.as_ptr(),
+                vertex_attribute_description_count: skinned_attribute_descriptions.len() as u32,
+                p_vertex_attribute_descriptions: skinned_attribute_descriptions.as_ptr(),
+                ..Default::default()
+            },
+            ShaderKind::Fullscreen => vk::PipelineVertexInputStateCreateInfo::default(),
+            ShaderKind::Debug { .. } => vk::PipelineVertexInputStateCreateInfo {
+                vertex_binding_description_count: 1,
+                p_vertex_binding_descriptions: ptr::addr_of!(debug_binding_description),
+                vertex_attribute_description_count: debug_attribute_descriptions.len() as u32,
+                p_vertex_attribute_descriptions: debug_attribute_descriptions.as_ptr(),
+                ..Default::default()
+            },
+            ShaderKind::Ui => vk::PipelineVertexInputStateCreateInfo {
+                vertex_binding_description_count: 1,
+                p_vertex_binding_descriptions: ptr::addr_of!(ui_binding_description),
+                vertex_attribute_description_count: ui_attribute_descriptions.len() as u32,
+                p_vertex_attribute_descriptions: ui_attribute_descriptions.as_ptr(),
+                ..Default::default()
+            },
+        };
+        // Topology is dynamic, but only within the same class
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: match shader.kind {
+                ShaderKind::Debug { .. } => vk::PrimitiveTopology::LINE_LIST,
+                _ => vk::PrimitiveTopology::TRIANGLE_LIST,
+            },
+            ..Default::default()
+        };
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            viewport_count: 1,
+            scissor_count: 1,
+            ..Default::default()
+        };
+        // Depth only passes are for shadows, which want both sides of everything and a bit
+        // of bias to keep surfaces from shadowing themselves
+        let depth_only = formats.color_formats.is_empty();
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo {
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: if depth_only
+                || !matches!(shader.kind, ShaderKind::Mesh | ShaderKind::SkinnedMesh)
+            {
+                vk::CullModeFlags::NONE
+            } else {
+                vk::CullModeFlags::BACK
+            },
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            depth_bias_enable: depth_only as vk::Bool32,
+            depth_bias_constant_factor: 1.25,
+            depth_bias_slope_factor: 1.75,
+            ..Default::default()
+        };
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: formats.samples,
+            ..Default::default()
+        };
+        // Debug geometry goes over the scene without changing its depth
+        let (depth_test, depth_write) = match shader.kind {
+            ShaderKind::Mesh | ShaderKind::SkinnedMesh => (true, true),
+            ShaderKind::Fullscreen | ShaderKind::Ui => (false, false),
+            ShaderKind::Debug { depth_test } => (depth_test, false),
+        };
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo {
+            depth_test_enable: depth_test as vk::Bool32,
+            depth_write_enable: depth_write as vk::Bool32,
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+            ..Default::default()
+        };
+        // UI colors already have alpha multiplied in
+        let blend = matches!(shader.kind, ShaderKind::Debug { .. } | ShaderKind::Ui);
+        let (src_color_blend_factor, dst_alpha_blend_factor) = match shader.kind {
+            ShaderKind::Ui => (vk::BlendFactor::ONE, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+            _ => (vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ZERO),
+        };
+        let color_blend_attachments: Vec<vk::PipelineColorBlendAttachmentState> = formats
+            .color_formats
+            .iter()
+            .map(|_| vk::PipelineColorBlendAttachmentState {
+                blend_enable: blend as vk::Bool32,
+                src_color_blend_factor,
+                dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                color_blend_op: vk::BlendOp::ADD,
+                src_alpha_blend_factor: vk::BlendFactor::ONE,
+                dst_alpha_blend_factor,
+                alpha_blend_op: vk::BlendOp::ADD,
+                color_write_mask: vk::ColorComponentFlags::RGBA,
+            })
+            .collect();
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            attachment_count: color_blend_attachments.len() as u32,
+            p_attachments: color_blend_attachments.as_ptr(),
+            ..Default::default()
+        };
+
+        let rendering_info = vk::PipelineRenderingCreateInfo {
+            color_attachment_count: formats.color_formats.len() as u32,
+            p_color_attachment_formats: formats.color_formats.as_ptr(),
+            depth_attachment_format: formats.depth_format,
+            ..Default::default()
+        };
+
+        let stages = [vertex_stage_info, fragment_stage_info];
+        let pipeline_info = vk::GraphicsPipelineCreateInfo {
+            stage_count: stages.len() as u32,
+            p_stages: stages.as_ptr(),
+            p_vertex_input_state: ptr::addr_of!(vertex_input_state),
+            p_input_assembly_state: ptr::addr_of!(input_assembly_state),
+            p_viewport_state: ptr::addr_of!(viewport_state),
+            p_rasterization_state: ptr::addr_of!(rasterization_state),
+            p_multisample_state: ptr::addr_of!(multisample_state),
+            p_depth_stencil_state: ptr::addr_of!(depth_stencil_state),
+            p_color_blend_state: ptr::addr_of!(color_blend_state),
+            p_dynamic_state: ptr::addr_of!(pipeline_dynamic_state),
+            layout: self.pipeline_layout,
+            p_next: ptr::addr_of!(rendering_info) as *const ffi::c_void,
+            ..Default::default()
+        };
+
+        let result = unsafe {
+            self.device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &[pipeline_info],
+                Some(&Self::get_allocation_callbacks()),
+            )
+        };
+
+        match result {
+            Ok(pipelines) => Ok(pipelines[0]),
+            Err((_, err)) => Err(err),
+        }
+    }
+
+    // Pipelines depend on the formats of the pass they're used in, so they're made the first
+    // time a shader gets used in a pass with a new combination
+    fn get_pipeline(&self, shader: &ShaderData) -> vk::Pipeline {
+        if let Some(pipeline) = shader.pipelines.borrow().get(&self.pass_formats) {
+            return *pipeline;
+        }
+
+        let pipeline = match self.create_pipeline(shader, &self.pass_formats) {
+            Ok(pipeline) => {
+                debug!(
+                    "Created pipeline {pipeline:#?} for shader {} with formats {:?}",
+                    shader.name, self.pass_formats
+                );
+                pipeline
+            }
+            Err(err) => {
+                // Remembered as null so the error isn't repeated every frame
+                error!("Failed to create pipeline for shader {}: {err}", shader.name);
+                vk::Pipeline::null()
+            }
+        };
+        shader
+            .pipelines
+            .borrow_mut()
+            .insert(self.pass_formats.clone(), pipeline);
+
+        pipeline
+    }
+
+    fn free_pass_descriptor_sets(&mut self, frame_index: usize) {
+        let sets = mem::take(&mut self.pass_descriptor_sets[frame_index]);
+        if !sets.is_empty() {
+            unsafe {
+                vulkan_check!(self
+                    .device
+                    .free_descriptor_sets(self.descriptor_pool, &sets))
+            };
+        }
+    }
+
+    // None if the mesh was destroyed, even if something else is in its slot now
+    fn mesh(&self, mesh: super::MeshHandle) -> Option<&Mesh> {
+        if self.mesh_generations.get(mesh.index) != Some(&mesh.generation) {
+            return None;
+        }
+        self.meshes[mesh.index].as_ref()
+    }
+
+    fn free_pending_meshes(&mut self, completed_frame: u64) {
+        let mesh_heap = &mut self.mesh_heap;
+        self.pending_frees.retain(|(frame, allocation)| {
+            if *frame <= completed_frame {
+                mesh_heap.free(allocation);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn load_shader(
+        &self,
+        vertex_path: &str,
+        fragment_path: &str,
+        name: &String,
+        kind: ShaderKind,
+    ) -> Result<Box<dyn super::ShaderData>, String> {
+        let vertex_binary = match crate::engine::vfs::open(vertex_path) {
+            Ok(binary) => binary,
+            Err(err) => {
+                error!("Failed to read vertex shader {vertex_path}: {err}");
+                return Err(err);
+            }
+        };
+        let fragment_binary = match crate::engine::vfs::open(fragment_path) {
+            Ok(binary) => binary,
+            Err(err) => {
+                error!("Failed to read fragment shader {fragment_path}: {err}");
+                return Err(err);
+            }
+        };
+
+        let vertex_shader_info = vk::ShaderModuleCreateInfo {
+            code_size: vertex_binary.len(),
+            p_code: vertex_binary.as_ptr() as *const u32,
+            ..Default::default()
+        };
+        let fragment_shader_info = vk::ShaderModuleCreateInfo {
+            code_size: fragment_binary.len(),
+            p_code: fragment_binary.as_ptr() as *const u32,
+            ..Default::default()
+        };
+
+        let (vertex_module, fragment_module) = unsafe {
+            (
+                vulkan_check!(self.device.create_shader_module(
+                    &vertex_shader_info,
+                    Some(&Self::get_allocation_callbacks())
+                )),
+                vulkan_check!(self.device.create_shader_module(
+                    &fragment_shader_info,
+                    Some(&Self::get_allocation_callbacks())
+                )),
+            )
+        };
+
+        debug!("Loaded shader modules {vertex_module:#?} and {fragment_module:#?} for shader {name}");
+
+        Ok(Box::new(ShaderData {
+            name: name.clone(),
+            vertex_module,
+            fragment_module,
+            kind,
+            pipelines: RefCell::new(HashMap::new()),
+        }))
+    }
+}
+
+impl super::RenderBackend for State {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn init(video: &Box<dyn platform::video::VideoBackend>) -> Box<dyn super::RenderBackend> {
+        debug!("Vulkan initialization started");
+
+        debug!("Loading Vulkan library");
+        let entry = unsafe { vulkan_check!(ash::Entry::load()) };
+
+        let instance = Self::create_instance(&entry);
+        let surface_loader = extensions::khr::Surface::new(&entry, &instance);
+        let surface = video.create_vulkan_surface(
+            &entry,
+            &instance,
+            Some(&State::get_allocation_callbacks()),
+        );
+        let gpus = Self::get_gpus(&instance, &surface_loader, &surface);
+        let gpu = 0;
+        let (device, graphics_queue, compute_queue) = Self::create_device(&instance, &gpus[gpu]);
+        let (command_pool, transfer_pool) = Self::create_command_pools(&device, &gpus[gpu]);
+        let command_buffers = Self::allocate_command_buffers(&device, &command_pool);
+        let upload_command_buffers = Self::allocate_command_buffers(&device, &transfer_pool);
+        let allocator = Self::create_allocator(&instance, &device, gpus[gpu].device);
+        let fences = Self::create_fences(&device);
+        let (acquire_semaphores, render_complete_semaphores) = Self::create_semaphores(&device);
+        let surface_format = Self::choose_surface_format(&gpus[gpu]);
+        let present_mode = Self::choose_present_mode(&gpus[gpu]);
+        let video_size = video.get_size();
+        let swapchain_extent = vk::Extent2D {
+            width: video_size.0,
+            height: video_size.1,
+        };
+        let swapchain_loader = extensions::khr::Swapchain::new(&instance, &device);
+        let (swapchain, swapchain_images, swapchain_views) = Self::create_swapchain(
+            &device,
+            &gpus[gpu],
+            &surface,
+            &present_mode,
+            &surface_format,
+            &swapchain_extent,
+            &swapchain_loader,
+        );
+        let depth_format = Self::choose_depth_format(&instance, &gpus[gpu]);
+        let descriptor_layout = Self::create_descriptor_layout(&device);
+        let texture_layout = Self::create_texture_layout(&device);
+        let material_layout = Self::create_material_layout(&device);
+        let pipeline_layout = Self::create_pipeline_layout(
+            &device,
+            &descriptor_layout,
+            &texture_layout,
+            &material_layout,
+        );
+        let (linear_sampler, shadow_sampler) = Self::create_samplers(&device);
+        let descriptor_pool = Self::create_descriptor_pool(&device);
+        let uniform_buffers = Self::allocate_uniform_buffers(&allocator);
+        let descriptor_sets = Self::allocate_descriptor_sets(
+            &device,
+            &descriptor_layout,
+            &descriptor_pool,
+            &uniform_buffers,
+        );
+        let material_buffers = Self::allocate_material_buffers(&allocator);
+        let joint_buffers = Self::allocate_joint_buffers(&allocator);
+        let (material_pool, material_sets) = Self::allocate_material_sets(
+            &device,
+            &material_layout,
+            &material_buffers,
+            &joint_buffers,
+        );
+        let material_sampler = Self::create_material_sampler(&device, &gpus[gpu]);
+        let staging_ring = vulkan_check!(StagingRing::new(
+            &allocator,
+            memory::STAGING_RING_SIZE
+        ));
+        let timestamps = timing::Timestamps::new(
+            &device,
+            &gpus[gpu].properties,
+            gpus[gpu].timestamp_valid_bits,
+        );
+
+        debug!("Vulkan initialization succeeded");
+
+        let mut self_ = Box::new(Self {
+            entry,
+            instance,
+            device,
+            surface_loader,
+            swapchain_loader,
+            surface,
+            gpu,
+            gpus,
+            graphics_queue,
+            compute_queue,
+            command_pool,
+            transfer_pool,
+            command_buffers,
+            fences,
+            acquire_semaphores,
+            render_complete_semaphores,
+            allocator,
+            swapchain,
+            swapchain_images,
+            swapchain_views,
+            surface_format,
+            present_mode,
+            swapchain_extent,
+            depth_format,
+
+            graph_textures: Vec::new(),
+            graph_buffers: Vec::new(),
+            graph_resources: Vec::new(),
+            backbuffer_state: super::graph::ResourceState::Undefined,
+            pass_formats: PassFormats::default(),
+            rendering: false,
+
+            descriptor_layout,
+            texture_layout,
+            descriptor_pool,
+            descriptor_sets,
+            pass_descriptor_sets: (0..FRAME_COUNT).map(|_| Vec::new()).collect(),
+            linear_sampler,
+            shadow_sampler,
+            pipeline_layout,
+            uniform_buffers,
+
+            material_layout,
+            material_pool,
+            material_sets,
+            material_buffers,
+            joint_buffers,
+            material_sampler,
+            textures: Vec::new(),
+            free_textures: Vec::new(),
+            pending_texture_frees: Vec::new(),
+
+            initialized: true,
+            loaded: false,
+
+            in_frame: false,
+            frame_index: 0,
+            frame_number: 1,
+            submitted_frames: [None; FRAME_COUNT],
+            resized: false,
+            swapchain_index: 0,
+
+            mesh_heap: MeshHeap::new(),
+            meshes: Vec::new(),
+            mesh_generations: Vec::new(),
+            free_meshes: Vec::new(),
+            pending_frees: Vec::new(),
+
+            staging_ring: Some(staging_ring),
+            upload_command_buffers,
+            uploading: false,
+
+            instance_buffers: (0..FRAME_COUNT).map(|_| None).collect(),
+            indirect_buffers: (0..FRAME_COUNT).map(|_| None).collect(),
+            debug_buffers: (0..FRAME_COUNT).map(|_| None).collect(),
+            ui_vertex_buffers: (0..FRAME_COUNT).map(|_| None).collect(),
+            ui_index_buffers: (0..FRAME_COUNT).map(|_| None).collect(),
+            indirect: false,
+
+            last_mesh_block: None,
+            last_pipeline: vk::Pipeline::null(),
+
+            timestamps,
+            gpu_timings: None,
+        });
+        self_.set_gpu(self_.gpu);
+
+        self_
+    }
+
+    fn load_resources(&mut self) {
+        self.flush_uploads();
+        debug!(
+            "{} mesh(es) resident, using {} of {} byte(s) of mesh heap",
+            self.meshes.iter().filter(|mesh| mesh.is_some()).count(),
+            self.mesh_heap.used(),
+            self.mesh_heap.capacity()
+        );
+
+        self.loaded = true;
+    }
+
+    fn create_mesh(
+        &mut self,
+        vertices: &[u8],
+        vertex_stride: usize,
+        indices: &[u32],
+    ) -> Result<super::MeshHandle, String> {
+        assert!(vertex_stride % mem::size_of::<u32>() == 0);
+
+        let vertices_size = vertices.len() as vk::DeviceSize;
+        let indices_size = mem::size_of_val(indices) as vk::DeviceSize;
+        let allocation = match self.mesh_heap.allocate(
+            &self.allocator,
+            vertices_size + indices_size,
+            vertex_stride as vk::DeviceSize,
+        ) {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                error!(
+                    "Failed to allocate {} byte(s) of mesh heap: {err}",
+                    vertices_size + indices_size
+                );
+                return Err(err.to_string());
+            }
+        };
+
+        let buffer = *self.mesh_heap.buffer(allocation.block).handle();
+        self.upload_buffer(vertices, buffer, allocation.offset);
+        self.upload_buffer(
+            super::as_bytes(indices),
+            buffer,
+            allocation.offset + vertices_size,
+        );
+
+        let mesh = Mesh {
+            allocation,
+            first_index: ((allocation.offset + vertices_size) / mem::size_of::<u32>() as u64)
+                as u32,
+            index_count: indices.len() as u32,
+            vertex_offset: (allocation.offset / vertex_stride as u64) as i32,
+        };
+        let index = match self.free_meshes.pop() {
+            Some(index) => {
+                self.meshes[index] = Some(mesh);
+                index
+            }
+            None => {
+                self.meshes.push(Some(mesh));
+                self.mesh_generations.push(0);
+                self.meshes.len() - 1
+            }
+        };
+        let generation = self.mesh_generations[index];
+
+        trace!(
+            "Created mesh {index} generation {generation} in block {} at offset {} ({} byte(s))",
+            allocation.block,
+            allocation.offset,
+            allocation.size
+        );
+
+        Ok(super::MeshHandle { index, generation })
+    }
+
+    fn destroy_mesh(&mut self, mesh: super::MeshHandle) {
+        if self.mesh(mesh).is_none() {
+            warn!("Mesh {mesh:?} was already destroyed");
+            return;
+        }
+
+        let data = self.meshes[mesh.index].take().unwrap();
+        trace!("Destroying mesh {mesh:?} after frame {}", self.frame_number);
+        // The memory can only be reused once every frame that might draw the mesh is done
+        self.pending_frees
+            .push((self.frame_number, data.allocation));
+        self.mesh_generations[mesh.index] = mesh.generation.wrapping_add(1);
+        self.free_meshes.push(mesh.index);
+    }
+
+    fn create_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        srgb: bool,
+    ) -> Result<super::TextureHandle, String> {
+        if pixels.len() != width as usize * height as usize * 4 {
+            error!(
+                "Texture data is {} byte(s), expected {width}x{height} RGBA8",
+                pixels.len()
+            );
+            return Err(String::from("texture data is the wrong size"));
+        }
+
+        let texture = match self.upload_texture(width, height, pixels, srgb) {
+            Ok(texture) => texture,
+            Err(err) => {
+                error!("Failed to create {width}x{height} texture: {err}");
+                return Err(err.to_string());
+            }
+        };
+        match self.add_texture(texture) {
+            Some(index) => {
+                trace!("Created texture {index}");
+                Ok(super::TextureHandle(index))
+            }
+            None => Err(String::from("too many textures")),
+        }
+    }
+
+    fn destroy_texture(&mut self, texture: super::TextureHandle) {
+        self.remove_texture(texture.0);
+    }
+
+    fn begin_commands(&mut self, video: &Box<dyn platform::video::VideoBackend>) {
+        unsafe {
+            vulkan_check!(self.device.wait_for_fences(
+                &[self.fences[self.frame_index]],
+                true,
+                u64::MAX
+            ))
+        };
+
+        self.staging_ring
+            .as_mut()
+            .unwrap()
+            .retire(self.frame_index);
+        // Only frames up to the one this slot's fence was for are known to be done, and
+        // before the slot's first submission nothing is
+        let completed = self.submitted_frames[self.frame_index];
+        if let Some(completed) = completed {
+            self.free_pending_meshes(completed);
+            self.free_pending_textures(completed);
+        }
+        self.free_pass_descriptor_sets(self.frame_index);
+        self.last_mesh_block = None;
+        self.last_pipeline = vk::Pipeline::null();
+        if let (Some(timestamps), Some(completed)) = (&mut self.timestamps, completed) {
+            let frames_ago = self.frame_number - completed;
+            self.gpu_timings = timestamps
+                .read(&self.device, self.frame_index)
+                .map(|passes| (frames_ago, passes));
+        }
+
+        (self.swapchain_index, self.resized) = unsafe {
+            match self.swapchain_loader.acquire_next_image(
+                self.swapchain,
+                u64::MAX,
+                self.acquire_semaphores[self.frame_index],
+                vk::Fence::null(),
+            ) {
+                Ok(values) => (values.0 as usize, values.1),
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => (0, true),
+                Err(err) => {
+                    panic!("Failed to acquire next image: {err}");
+                }
+            }
+        };
+        if self.resized {
+            self.resize(video);
+            return;
+        }
+
+        unsafe {
+            vulkan_check!(self.device.reset_fences(&[self.fences[self.frame_index]]));
+            vulkan_check!(self.device.reset_command_buffer(
+                self.command_buffers[self.frame_index],
+                vk::CommandBufferResetFlags::empty()
+            ));
+            vulkan_check!(self.device.begin_command_buffer(
+                self.command_buffers[self.frame_index],
+                &vk::CommandBufferBeginInfo {
+                    flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                    ..Default::default()
+                }
+            ));
+        }
+        if let Some(timestamps) = &mut self.timestamps {
+            timestamps.reset(
+                &self.device,
+                self.command_buffers[self.frame_index],
+                self.frame_index,
+            );
+        }
+
+        self.in_frame = true;
+    }
+
+    fn begin_graph(&mut self, graph: &super::graph::CompiledGraph) {
+        self.assign_graph_resources(graph);
+    }
+
+    fn begin_pass(
+        &mut self,
+        _graph: &super::graph::CompiledGraph,
+        pass: &super::graph::CompiledPass,
+    ) {
+        if let Some(timestamps) = &mut self.timestamps {
+            timestamps.begin_pass(
+                &self.device,
+                self.command_buffers[self.frame_index],
+                self.frame_index,
+                &pass.name,
+            );
+        }
+        self.begin_graph_pass(pass);
+    }
+
+    fn end_pass(
+        &mut self,
+        _graph: &super::graph::CompiledGraph,
+        _pass: &super::graph::CompiledPass,
+    ) {
+        self.end_graph_pass();
+        if let Some(timestamps) = &mut self.timestamps {
+            timestamps.end_pass(
+                &self.device,
+                self.command_buffers[self.frame_index],
+                self.frame_index,
+            );
+        }
+    }
+
+    fn gpu_timings(&mut self) -> Option<(u64, Vec<profiler::GpuPass>)> {
+        self.gpu_timings.take()
+    }
+
+    fn end_graph(&mut self, graph: &super::graph::CompiledGraph) {
+        self.transition_graph_resources(&graph.final_transitions);
+    }
+
+    fn update_uniforms(&mut self, uniforms: &super::UniformData) {
+        unsafe {
+            self.uniform_buffers[self.frame_index]
+                .read(super::as_bytes(std::slice::from_ref(uniforms)), 0)
+        };
+    }
+
+    fn update_materials(&mut self, materials: &[super::material::MaterialData]) {
+        self.write_materials(materials);
+    }
+
+    fn update_joints(&mut self, joints: &[Matrix4<f32>]) {
+        self.write_joints(joints);
+    }
+
+    fn prepare_batches(&mut self, batches: &[super::DrawBatch], instances: &[super::InstanceData]) {
+        let instance_data = super::as_bytes(instances);
+        Self::reserve_host_buffer(
+            &self.allocator,
+            &mut self.instance_buffers[self.frame_index],
+            instance_data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+        if let Some(instance_buffer) = &self.instance_buffers[self.frame_index] {
+            unsafe { instance_buffer.read(instance_data, 0) };
+        }
+
+        if !self.indirect {
+            return;
+        }
+
+        // Every pass draws the same batches, so the commands only have to be written once.
+        // Destroyed meshes get an empty command to keep the indices lined up.
+        let commands: Vec<vk::DrawIndexedIndirectCommand> = batches
+            .iter()
+            .map(|batch| match self.mesh(batch.mesh) {
+                Some(mesh) => vk::DrawIndexedIndirectCommand {
+                    index_count: mesh.index_count,
+                    instance_count: batch.instance_count,
+                    first_index: mesh.first_index,
+                    vertex_offset: mesh.vertex_offset,
+                    first_instance: batch.first_instance,
+                },
+                None => vk::DrawIndexedIndirectCommand::default(),
+            })
+            .collect();
+        let command_data = super::as_bytes(&commands);
+        Self::reserve_host_buffer(
+            &self.allocator,
+            &mut self.indirect_buffers[self.frame_index],
+            command_data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::INDIRECT_BUFFER,
+        );
+        if let Some(indirect_buffer) = &self.indirect_buffers[self.frame_index] {
+            unsafe { indirect_buffer.read(command_data, 0) };
+        }
+    }
+
+    fn draw_batches(&mut self, batches: &[super::DrawBatch], view_projection: &Matrix4<f32>) {
+        if batches.is_empty() {
+            return;
+        }
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        let instance_buffer = self.instance_buffers[self.frame_index].as_ref().unwrap();
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_sets[self.frame_index]],
+                &[],
+            );
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                2,
+                &[self.material_sets[self.frame_index]],
+                &[],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                super::as_bytes(std::slice::from_ref(view_projection)),
+            );
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                1,
+                &[*instance_buffer.buffer().handle()],
+                &[0],
+            );
+        }
+
+        // Index into batches goes along with the draw so it can find its indirect command
+        let draws: Vec<(usize, vk::Pipeline, usize, vk::DrawIndexedIndirectCommand)> = batches
+            .iter()
+            .enumerate()
+            .filter_map(|(index, batch)| {
+                let Some(mesh) = self.mesh(batch.mesh) else {
+                    error!("Skipping draw of destroyed mesh {:?}", batch.mesh);
+                    return None;
+                };
+                let Some(shader) = batch.shader else {
+                    error!("Skipping draw with destroyed shader");
+                    return None;
+                };
+                let shader: &ShaderData = shader.as_any().downcast_ref().unwrap();
+                let pipeline = self.get_pipeline(shader);
+                if pipeline == vk::Pipeline::null() {
+                    return None;
+                }
+
+                Some((
+                    index,
+                    pipeline,
+                    mesh.allocation.block,
+                    vk::DrawIndexedIndirectCommand {
+                        index_count: mesh.index_count,
+                        instance_count: batch.instance_count,
+                        first_index: mesh.first_index,
+                        vertex_offset: mesh.vertex_offset,
+                        first_instance: batch.first_instance,
+                    },
+                ))
+            })
+            .collect();
+
+        let stride = mem::size_of::<vk::DrawIndexedIndirectCommand>();
+        let indirect_buffer = if self.indirect {
+            self.indirect_buffers[self.frame_index]
+                .as_ref()
+                .map(|buffer| *buffer.buffer().handle())
+        } else {
+            None
+        };
+
+        // Runs of draws that share a pipeline and mesh block only need one bind each, and can
+        // be a single indirect draw as long as none were skipped in between
+        let mut start = 0;
+        while start < draws.len() {
+            let (first, pipeline, block, _) = draws[start];
+            let mut end = start + 1;
+            while end < draws.len()
+                && draws[end].1 == pipeline
+                && draws[end].2 == block
+                && draws[end].0 == first + (end - start)
+            {
+                end += 1;
+            }
+
+            self.bind_pipeline(pipeline);
+            self.bind_mesh_block(block);
+
+            unsafe {
+                match indirect_buffer {
+                    Some(indirect_buffer)
+                        if self.gpus[self.gpu].features.multi_draw_indirect == vk::TRUE =>
+                    {
+                        self.device.cmd_draw_indexed_indirect(
+                            command_buffer,
+                            indirect_buffer,
+                            (first * stride) as vk::DeviceSize,
+                            (end - start) as u32,
+                            stride as u32,
+                        )
+                    }
+                    Some(indirect_buffer) => {
+                        for (index, _, _, _) in &draws[start..end] {
+                            self.device.cmd_draw_indexed_indirect(
+                                command_buffer,
+                                indirect_buffer,
+                                (index * stride) as vk::DeviceSize,
+                                1,
+                                stride as u32,
+                            )
+                        }
+                    }
+                    None => {
+                        for (_, _, _, command) in &draws[start..end] {
+                            self.device.cmd_draw_indexed(
+                                command_buffer,
+                                command.index_count,
+                                command.instance_count,
+                                command.first_index,
+                                command.vertex_offset,
+                                command.first_instance,
+                            )
+                        }
+                    }
+                }
+            }
+
+            start = end;
+        }
+    }
+
+    fn prepare_debug(&mut self, vertices: &[super::debug_draw::DebugVertex]) {
+        let vertex_data = super::as_bytes(vertices);
+        Self::reserve_host_buffer(
+            &self.allocator,
+            &mut self.debug_buffers[self.frame_index],
+            vertex_data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+        if let Some(debug_buffer) = &self.debug_buffers[self.frame_index] {
+            unsafe { debug_buffer.read(vertex_data, 0) };
+        }
+    }
+
+    fn draw_debug(&mut self, shader: &dyn super::ShaderData, first_vertex: u32, vertex_count: u32) {
+        let shader: &ShaderData = shader.as_any().downcast_ref().unwrap();
+        let pipeline = self.get_pipeline(shader);
+        let Some(debug_buffer) = &self.debug_buffers[self.frame_index] else {
+            return;
+        };
+        if pipeline == vk::Pipeline::null() || vertex_count == 0 {
+            return;
+        }
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        let buffer = *debug_buffer.buffer().handle();
+        self.bind_pipeline(pipeline);
+        unsafe {
+            self.device
+                .cmd_set_primitive_topology(command_buffer, vk::PrimitiveTopology::LINE_LIST);
+            self.device
+                .cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[0]);
+            self.device
+                .cmd_draw(command_buffer, vertex_count, 1, first_vertex, 0);
+        }
+        self.last_mesh_block = None;
+    }
+
+    fn draw_fullscreen(&mut self, shader: &dyn super::ShaderData, constants: &[f32; 16]) {
+        let shader: &ShaderData = shader.as_any().downcast_ref().unwrap();
+        let pipeline = self.get_pipeline(shader);
+        if pipeline == vk::Pipeline::null() {
+            return;
+        }
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        self.bind_pipeline(pipeline);
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_sets[self.frame_index]],
+                &[],
+            );
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                2,
+                &[self.material_sets[self.frame_index]],
+                &[],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                super::as_bytes(constants),
+            );
+            self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    fn present(&mut self) {
+        self.in_frame = false;
+
+        if self.resized {
+            self.resized = false;
+            return;
+        }
+
+        unsafe {
+            vulkan_check!(self
+                .device
+                .end_command_buffer(self.command_buffers[self.frame_index]));
+        };
+
+        // Uploads made since the last frame go first so this frame can use them
+        let command_buffers: Vec<vk::CommandBuffer> = self
+            .end_uploads()
+            .into_iter()
+            .chain([self.command_buffers[self.frame_index]])
+            .collect();
+
+        let wait_stage = graph::BACKBUFFER_WAIT_STAGES;
+        let submit_info = vk::SubmitInfo {
+            p_wait_dst_stage_mask: ptr::addr_of!(wait_stage),
+            wait_semaphore_count: 1,
+            p_wait_semaphores: ptr::addr_of!(self.acquire_semaphores[self.frame_index]),
+            signal_semaphore_count: 1,
+            p_signal_semaphores: ptr::addr_of!(self.render_complete_semaphores[self.frame_index]),
+            command_buffer_count: command_buffers.len() as u32,
+            p_command_buffers: command_buffers.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            vulkan_check!(self.device.queue_submit(
+                self.compute_queue,
+                &[submit_info],
+                self.fences[self.frame_index]
+            ))
+        }
+        self.submitted_frames[self.frame_index] = Some(self.frame_number);
+        self.frame_number += 1;
+
+        let index = self.swapchain_index as u32;
+        let present_info = vk::PresentInfoKHR {
+            p_swapchains: ptr::addr_of!(self.swapchain),
+            swapchain_count: 1,
+            p_wait_semaphores: ptr::addr_of!(self.render_complete_semaphores[self.frame_index]),
+            wait_semaphore_count: 1,
+            p_image_indices: ptr::addr_of!(index),
+            ..Default::default()
+        };
+
+        match unsafe {
+            self.swapchain_loader
+                .queue_present(self.compute_queue, &present_info)
+        } {
+            Ok(_) => {}
+            Err(err) if err == vk::Result::ERROR_OUT_OF_DATE_KHR => {}
+            Err(err) => {
+                panic!(
+                    "Failed to present frame {} (swapchain image {}): {err}",
+                    self.frame_index, self.swapchain_index
+                )
+            }
+        }
+
+        self.frame_index = (self.frame_index + 1) % FRAME_COUNT;
+    }
+
+    fn unload_resources(&mut self) {
+        debug!("Waiting for device idle");
+        unsafe { vulkan_check!(self.device.device_wait_idle()) };
+
+        self.flush_uploads();
+
+        debug!("Freeing {} mesh(es)", self.meshes.len() - self.free_meshes.len());
+        for mesh in self.meshes.drain(..).flatten() {
+            self.mesh_heap.free(&mesh.allocation);
+        }
+        self.mesh_generations.clear();
+        self.free_meshes.clear();
+        self.free_pending_meshes(u64::MAX);
+
+        self.loaded = false;
+    }
+
+    fn shutdown(&mut self) {
+        debug!("Vulkan shutdown started");
+
+        self.loaded = false;
+        self.initialized = false;
+
+        debug!("Waiting for device idle");
+        unsafe { vulkan_check!(self.device.device_wait_idle()) };
+
+        self.flush_uploads();
+        self.destroy_materials();
+        self.mesh_heap.destroy(&self.allocator);
+        self.staging_ring.take().unwrap().destroy(&self.allocator);
+
+        debug!("Freeing instance, indirect, debug and UI buffers");
+        for buffer in self
+            .instance_buffers
+            .drain(..)
+            .chain(self.indirect_buffers.drain(..))
+            .chain(self.debug_buffers.drain(..))
+            .chain(self.ui_vertex_buffers.drain(..))
+            .chain(self.ui_index_buffers.drain(..))
+            .flatten()
+        {
+            buffer.destroy(&self.allocator);
+        }
+
+        unsafe {
+            debug!("Freeing {FRAME_COUNT} uniform buffers");
+            for _ in 0..self.uniform_buffers.len() {
+                self.uniform_buffers.remove(0).destroy(&self.allocator)
+            }
+
+            for frame_index in 0..FRAME_COUNT {
+                self.free_pass_descriptor_sets(frame_index);
+            }
+
+            if let Some(timestamps) = self.timestamps.take() {
+                timestamps.destroy(&self.device);
+            }
+
+            debug!("Destroying samplers");
+            self.device
+                .destroy_sampler(self.linear_sampler, Some(&Self::get_allocation_callbacks()));
+            self.device
+                .destroy_sampler(self.shadow_sampler, Some(&Self::get_allocation_callbacks()));
+
+            debug!("Destroying descriptor pool {:#?}", self.descriptor_pool);
+            self.device.destroy_descriptor_pool(
+                self.descriptor_pool,
+                Some(&Self::get_allocation_callbacks()),
+            );
+
+            debug!("Destroying pipeline layout {:#?}", self.pipeline_layout);
+            self.device.destroy_pipeline_layout(
+                self.pipeline_layout,
+                Some(&Self::get_allocation_callbacks()),
+            );
+
+            debug!(
+                "Destroying descriptor set layout {:#?}",
+                self.descriptor_layout
+            );
+            self.device.destroy_descriptor_set_layout(
+                self.descriptor_layout,
+                Some(&Self::get_allocation_callbacks()),
+            );
+            debug!(
+                "Destroying pass texture descriptor set layout {:#?}",
+                self.texture_layout
+            );
+            self.device.destroy_descriptor_set_layout(
+                self.texture_layout,
+                Some(&Self::get_allocation_callbacks()),
+            );
+
+            self.destroy_graph_resources();
+            self.destroy_swapchain();
+
+            debug!("Destroying {} semaphores", FRAME_COUNT * 2);
+            self.acquire_semaphores.iter().for_each(|semaphore| {
+                self.device
+                    .destroy_semaphore(*semaphore, Some(&Self::get_allocation_callbacks()))
+            });
+            self.render_complete_semaphores
+                .iter()
+                .for_each(|semaphore| {
+                    self.device
+                        .destroy_semaphore(*semaphore, Some(&Self::get_allocation_callbacks()))
+                });
+
+            debug!("Destroying {FRAME_COUNT} fences");
+            self.fences.iter().for_each(|fence| {
+                self.device
+                    .destroy_fence(*fence, Some(&Self::get_allocation_callbacks()))
+            });
+            debug!("Destroying transfer command pool {:#?}", self.transfer_pool);
+            self.device
+                .destroy_command_pool(self.transfer_pool, Some(&Self::get_allocation_callbacks()));
+            debug!("Destroying command pool {:#?}", self.command_pool);
+            self.device
+                .destroy_command_pool(self.command_pool, Some(&Self::get_allocation_callbacks()));
+            Self::report_leaked_objects();
+            debug!("Destroying allocator");
+            ptr::drop_in_place(ptr::addr_of_mut!(self.allocator));
+            debug!("Destroying logical device {:#?}", self.device.handle());
+            self.device
+                .destroy_device(Some(&Self::get_allocation_callbacks()));
+            debug!("Destroying surface {:#?}", self.surface);
+            self.surface_loader
+                .destroy_surface(self.surface, Some(&Self::get_allocation_callbacks()));
+            debug!("Destroying instance {:#?}", self.instance.handle());
+            self.instance
+                .destroy_instance(Some(&Self::get_allocation_callbacks()));
+        }
+
+        let (count, bytes) = memory::live_host_allocations();
+        if count > 0 {
+            warn!("Vulkan didn't free {count} host allocation(s) totalling {bytes} byte(s)");
+        }
+
+        debug!("Vulkan shutdown succeeded");
+    }
+
+    fn set_gpu(&mut self, gpu_index: usize) -> usize {
+        let old_idx = self.gpu;
+        if gpu_index < self.gpus.len() {
+            self.gpu = gpu_index;
+            let gpu = &self.gpus[self.gpu];
+
+            let name = unsafe {
+                ffi::CStr::from_ptr(gpu.properties.device_name.as_ptr())
+                    .to_str()
+                    .unwrap()
+            };
+            debug!(
+                "Selected {:#?} device {}, {} [{:04x}:{:04x}] with score {}",
+                gpu.properties.device_type,
+                gpu_index,
+                name,
+                gpu.properties.vendor_id,
+                gpu.properties.device_id,
+                gpu.performance_score
+            );
+            crash::set_section(
+                "GPU",
+                format!(
+                    "{name} [{:04x}:{:04x}], {:?}, driver {:#x}, Vulkan {}.{}.{}",
+                    gpu.properties.vendor_id,
+                    gpu.properties.device_id,
+                    gpu.properties.device_type,
+                    gpu.properties.driver_version,
+                    vk::api_version_major(gpu.properties.api_version),
+                    vk::api_version_minor(gpu.properties.api_version),
+                    vk::api_version_patch(gpu.properties.api_version)
+                ),
+            );
+        }
+
+        old_idx
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    fn prepare_ui(&mut self, vertices: &[super::ui::UiVertex], indices: &[u32]) {
+        let vertex_data = super::as_bytes(vertices);
+        let index_data = super::as_bytes(indices);
+        Self::reserve_host_buffer(
+            &self.allocator,
+            &mut self.ui_vertex_buffers[self.frame_index],
+            vertex_data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+        Self::reserve_host_buffer(
+            &self.allocator,
+            &mut self.ui_index_buffers[self.frame_index],
+            index_data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        );
+        if let Some(vertex_buffer) = &self.ui_vertex_buffers[self.frame_index] {
+            unsafe { vertex_buffer.read(vertex_data, 0) };
+        }
+        if let Some(index_buffer) = &self.ui_index_buffers[self.frame_index] {
+            unsafe { index_buffer.read(index_data, 0) };
+        }
+    }
+
+    fn draw_ui(&mut self, shader: &dyn super::ShaderData, draws: &[super::ui::UiDraw]) {
+        let shader: &ShaderData = shader.as_any().downcast_ref().unwrap();
+        let pipeline = self.get_pipeline(shader);
+        let (Some(vertex_buffer), Some(index_buffer)) = (
+            &self.ui_vertex_buffers[self.frame_index],
+            &self.ui_index_buffers[self.frame_index],
+        ) else {
+            return;
+        };
+        if pipeline == vk::Pipeline::null() || draws.is_empty() {
+            return;
+        }
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        let vertex_buffer = *vertex_buffer.buffer().handle();
+        let index_buffer = *index_buffer.buffer().handle();
+        let extent = self.swapchain_extent;
+        let srgb = super::RenderBackend::surface_is_srgb(self);
+        self.bind_pipeline(pipeline);
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                2,
+                &[self.material_sets[self.frame_index]],
+                &[],
+            );
+            self.device
+                .cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+            self.device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+
+            for draw in draws {
+                self.device.cmd_set_scissor(
+                    command_buffer,
+                    0,
+                    &[vk::Rect2D {
+                        offset: vk::Offset2D {
+                            x: draw.scissor[0] as i32,
+                            y: draw.scissor[1] as i32,
+                        },
+                        extent: vk::Extent2D {
+                            width: draw.scissor[2],
+                            height: draw.scissor[3],
+                        },
+                    }],
+                );
+                let constants = [
+                    extent.width as f32,
+                    extent.height as f32,
+                    draw.texture.0 as f32,
+                    if srgb { 1.0 } else { 0.0 },
+                ];
+                self.device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    super::as_bytes(&constants),
+                );
+                self.device.cmd_draw_indexed(
+                    command_buffer,
+                    draw.index_count,
+                    1,
+                    draw.first_index,
+                    draw.vertex_offset,
+                    0,
+                );
+            }
+
+            // Anything after this in the pass expects the whole screen
+            self.device.cmd_set_scissor(
+                command_buffer,
+                0,
+                &[vk::Rect2D {
+                    extent,
+                    ..Default::default()
+                }],
+            );
+        }
+        self.last_mesh_block = None;
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    fn is_in_frame(&self) -> bool {
+        self.in_frame
+    }
+
+    fn backbuffer_size(&self) -> (u32, u32) {
+        (self.swapchain_extent.width, self.swapchain_extent.height)
+    }
+
+    fn sample_counts(&self) -> u32 {
+        let limits = &self.gpus[self.gpu].properties.limits;
+        (limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts).as_raw()
+    }
+
+    fn surface_is_srgb(&self) -> bool {
+        matches!(
+            self.surface_format.format,
+            vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_SRGB_PACK32
+        )
+    }
+
+    fn set_indirect_drawing(&mut self, enabled: bool) -> bool {
+        // Batches start at arbitrary instances, so this is needed for indirect draws to work
+        self.indirect =
+            enabled && self.gpus[self.gpu].features.draw_indirect_first_instance == vk::TRUE;
+        self.indirect
+    }
+
+    fn memory_stats(&self) -> super::MemoryStats {
+        let categories = memory::usage()
+            .into_iter()
+            .map(|(category, bytes, count)| super::MemoryUsage {
+                name: category.name(),
+                bytes,
+                count,
+            })
+            .collect();
+
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_memory_properties(self.gpus[self.gpu].device)
+        };
+        let budgets = unsafe { self.allocator.get_heap_budgets() }.unwrap_or_default();
+        let heaps = properties.memory_heaps[..properties.memory_heap_count as usize]
+            .iter()
+            .zip(&budgets)
+            .map(|(heap, budget)| super::HeapBudget {
+                size: heap.size,
+                usage: budget.usage,
+                budget: budget.budget,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+            })
+            .collect();
+
+        super::MemoryStats {
+            categories,
+            heaps,
+            allocated: budgets
+                .iter()
+                .map(|budget| budget.statistics.allocationBytes)
+                .sum(),
+            reserved: budgets
+                .iter()
+                .map(|budget| budget.statistics.blockBytes)
+                .sum(),
+            mesh_heap_used: self.mesh_heap.used(),
+            mesh_heap_capacity: self.mesh_heap.capacity(),
+            staging_capacity: self
+                .staging_ring
+                .as_ref()
+                .map_or(0, |staging_ring| staging_ring.capacity()),
+        }
+    }
+
+    fn create_shader(
+        &self,
+        shader_path: &String,
+        name: &String,
+    ) -> Result<Box<dyn super::ShaderData>, String> {
+        debug!("Loading Vulkan shader {name}");
+        self.load_shader(
+            &format!("{shader_path}.vert.spv"),
+            &format!("{shader_path}.frag.spv"),
+            name,
+            ShaderKind::Mesh,
+        )
+    }
+
+    fn create_fullscreen_shader(
+        &self,
+        shader_dir: &String,
+        name: &String,
+    ) -> Result<Box<dyn super::ShaderData>, String> {
+        debug!("Loading Vulkan fullscreen shader {name}");
+        self.load_shader(
+            &format!("{shader_dir}fullscreen.vert.spv"),
+            &format!("{shader_dir}{name}.frag.spv"),
+            name,
+            ShaderKind::Fullscreen,
+        )
+    }
+
+    fn create_ui_shader(&self, shader_dir: &String) -> Result<Box<dyn super::ShaderData>, String> {
+        debug!("Loading Vulkan UI shader");
+        self.load_shader(
+            &format!("{shader_dir}ui.vert.spv"),
+            &format!("{shader_dir}ui.frag.spv"),
+            &String::from("ui"),
+            ShaderKind::Ui,
+        )
+    }
+
+    fn create_skinned_shader(
+        &self,
+        shader_dir: &String,
+        name: &String,
+    ) -> Result<Box<dyn super::ShaderData>, String> {
+        debug!("Loading Vulkan skinned shader {name}");
+        self.load_shader(
+            &format!("{shader_dir}skinned.vert.spv"),
+            &format!("{shader_dir}{name}.frag.spv"),
+            &format!("{name} skinned"),
+            ShaderKind::SkinnedMesh,
+        )
+    }
+
+    fn create_debug_shader(
+        &self,
+        shader_dir: &String,
+        depth_test: bool,
+    ) -> Result<Box<dyn super::ShaderData>, String> {
+        debug!("Loading Vulkan debug shader with depth testing {depth_test}");
+        self.load_shader(
+            &format!("{shader_dir}debug.vert.spv"),
+            &format!("{shader_dir}debug.frag.spv"),
+            &String::from(if depth_test { "debug" } else { "debug overlay" }),
+            ShaderKind::Debug { depth_test },
+        )
+    }
+}
+
+pub struct ShaderData {
+    name: String,
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    kind: ShaderKind,
+    pipelines: RefCell<HashMap<PassFormats, vk::Pipeline>>,
+}
+
+impl super::ShaderData for ShaderData {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn destroy(&mut self, state: &Box<dyn super::RenderBackend>) {
+        let state: &State = state.as_any().downcast_ref().unwrap();
+        unsafe {
+            for pipeline in self.pipelines.get_mut().drain().map(|(_, pipeline)| pipeline) {
+                if pipeline != vk::Pipeline::null() {
+                    state
+                        .device
+                        .destroy_pipeline(pipeline, Some(&State::get_allocation_callbacks()));
+                }
+            }
+            state
+                .device
+                .destroy_shader_module(self.vertex_module, Some(&State::get_allocation_callbacks()));
+            state
+                .device
+                .destroy_shader_module(self.fragment_module, Some(&State::get_allocation_callbacks()));
+        }
+    }
+}
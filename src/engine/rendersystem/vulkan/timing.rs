@@ -0,0 +1,165 @@
+use super::{State, FRAME_COUNT};
+use crate::engine::profiler::GpuPass;
+use ash::vk;
+use log::{debug, warn};
+
+// Passes past this in one frame aren't timed
+const MAX_TIMED_PASSES: usize = 64;
+
+// Timestamp queries around each render graph pass. Every frame in flight has its own range of
+// the pool, which is read once its fence says the GPU is done with it.
+pub struct Timestamps {
+    pool: vk::QueryPool,
+    // Nanoseconds per tick
+    period: f64,
+    // The bits of a timestamp that count, the rest are garbage
+    mask: u64,
+    // The passes each frame wrote timestamps for, in order
+    passes: Vec<Vec<String>>,
+    // Whether the last pass started got a query
+    open: bool,
+}
+
+impl Timestamps {
+    // None if the GPU can't time graphics work. valid_bits is the graphics queue family's
+    // timestamp_valid_bits, which is all that matters for the queue passes run on.
+    pub fn new(
+        device: &ash::Device,
+        properties: &vk::PhysicalDeviceProperties,
+        valid_bits: u32,
+    ) -> Option<Self> {
+        if valid_bits == 0 || properties.limits.timestamp_period <= 0.0 {
+            warn!("GPU doesn't support timestamps, passes won't be timed");
+            return None;
+        }
+
+        debug!("Creating timestamp query pool");
+        let pool = unsafe {
+            vulkan_check!(device.create_query_pool(
+                &vk::QueryPoolCreateInfo {
+                    query_type: vk::QueryType::TIMESTAMP,
+                    query_count: (FRAME_COUNT * MAX_TIMED_PASSES * 2) as u32,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        Some(Self {
+            pool,
+            period: properties.limits.timestamp_period as f64,
+            mask: u64::MAX >> (64 - valid_bits.min(64)),
+            passes: (0..FRAME_COUNT).map(|_| Vec::new()).collect(),
+            open: false,
+        })
+    }
+
+    fn first_query(frame_index: usize) -> u32 {
+        (frame_index * MAX_TIMED_PASSES * 2) as u32
+    }
+
+    // What the frame that last used frame_index wrote, which has to be done on the GPU
+    pub fn read(&mut self, device: &ash::Device, frame_index: usize) -> Option<Vec<GpuPass>> {
+        let names = std::mem::take(&mut self.passes[frame_index]);
+        if names.is_empty() {
+            return None;
+        }
+
+        let mut ticks = vec![0u64; names.len() * 2];
+        unsafe {
+            device.get_query_pool_results(
+                self.pool,
+                Self::first_query(frame_index),
+                &mut ticks,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        }
+        .ok()?;
+
+        // Differences are masked too, so they're right when the counter wraps
+        let first = ticks[0];
+        let elapsed = |from: u64, to: u64| {
+            let ticks = (to & self.mask).wrapping_sub(from & self.mask) & self.mask;
+            ticks as f64 * self.period / 1_000_000.0
+        };
+        Some(
+            names
+                .into_iter()
+                .zip(ticks.chunks_exact(2))
+                .map(|(name, pair)| GpuPass {
+                    name,
+                    start: elapsed(first, pair[0]),
+                    duration: elapsed(pair[0], pair[1]),
+                })
+                .collect(),
+        )
+    }
+
+    // Has to be outside of rendering, at the start of the frame's commands
+    pub fn reset(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+    ) {
+        self.passes[frame_index].clear();
+        self.open = false;
+        unsafe {
+            device.cmd_reset_query_pool(
+                command_buffer,
+                self.pool,
+                Self::first_query(frame_index),
+                (MAX_TIMED_PASSES * 2) as u32,
+            )
+        };
+    }
+
+    pub fn begin_pass(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        name: &str,
+    ) {
+        let passes = &mut self.passes[frame_index];
+        self.open = passes.len() < MAX_TIMED_PASSES;
+        if !self.open {
+            return;
+        }
+        unsafe {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.pool,
+                Self::first_query(frame_index) + passes.len() as u32 * 2,
+            )
+        };
+        passes.push(String::from(name));
+    }
+
+    pub fn end_pass(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+    ) {
+        if !self.open {
+            return;
+        }
+        self.open = false;
+        let index = self.passes[frame_index].len() as u32 - 1;
+        unsafe {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.pool,
+                Self::first_query(frame_index) + index * 2 + 1,
+            )
+        };
+    }
+
+    pub fn destroy(self, device: &ash::Device) {
+        debug!("Destroying timestamp query pool");
+        unsafe { device.destroy_query_pool(self.pool, Some(&State::get_allocation_callbacks())) };
+    }
+}
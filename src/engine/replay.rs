@@ -0,0 +1,532 @@
+// Recording input and playing it back. A tick is one of the engine's fixed steps (see
+// engine::STEP), and with --record every tick's input events go to a file in
+// DataDirs::replays, along with a checksum of the world after the tick. --replay feeds a
+// recording back in place of the real input, one tick per step, and reports the first tick
+// where the world doesn't match what was recorded, which means something isn't deterministic
+// or the game changed since. With --headless it runs without a window or renderer, and
+// --max-speed doesn't wait for the steps to take as long as they did.
+//
+// Recordings start with MAGIC, the format version as a little endian u32, the game's version
+// as three u8s and the steps per second (u32), then the world from before the first tick as a
+// binary scene (u32 length). Each tick after that is its number (u32), a u8 of flags, the
+// number of events (u32) and the events, then the checksum (u64). Events are a u8 kind and
+// their fields, with keys and buttons as their index in Key::ALL and MouseButton::ALL.
+//
+// Frame lengths aren't recorded, since the simulation only ever sees STEP. Whatever happened
+// between two steps goes to the later one.
+
+use super::{gamelib, input, scene, script, DataDirs, State, STEP};
+use crate::platform::input::{Event, Key, MouseButton};
+use chrono::Local;
+use legion::World;
+use log::{error, info, warn};
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+pub const MAGIC: &[u8] = b"PURPLREC";
+// 2 changed how checksums are worked out, 3 made ticks fixed steps
+pub const FORMAT_VERSION: u32 = 3;
+pub const REPLAY_EXTENSION: &str = "rec";
+
+// The window lost focus before this tick, so everything held was let go
+const RELEASED: u8 = 1 << 0;
+const KEYBOARD_CAPTURED: u8 = 1 << 1;
+const MOUSE_CAPTURED: u8 = 1 << 2;
+
+const KEY: u8 = 0;
+const TEXT: u8 = 1;
+const MOUSE_MOVED: u8 = 2;
+const MOUSE_BUTTON: u8 = 3;
+const MOUSE_WHEEL: u8 = 4;
+
+fn steps_per_second() -> u32 {
+    (1.0 / STEP).round() as u32
+}
+
+// FNV-1a over each entity as a binary scene, so it covers whatever a save would. The entities'
+// hashes are added up rather than hashed in order, since the order legion iterates them in
+// depends on its archetypes and not on anything the game did.
+pub fn checksum(world: &World) -> u64 {
+    scene::Scene::from_world(world, 0)
+        .entities
+        .into_iter()
+        .map(|entity| {
+            scene::Scene {
+                format: scene::FORMAT_VERSION,
+                version: 0,
+                entities: vec![entity],
+            }
+            .to_binary()
+            .iter()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+            })
+        })
+        .fold(0, u64::wrapping_add)
+}
+
+// One step's worth of input
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tick {
+    pub released: bool,
+    // Whether the developer UI had the keyboard or mouse, which the game doesn't see
+    pub keyboard_captured: bool,
+    pub mouse_captured: bool,
+    pub events: Vec<Event>,
+    // The world after the tick
+    pub checksum: u64,
+}
+
+impl Tick {
+    // Starts input's next step with what the tick had
+    pub fn apply(&self, input: &mut input::State) {
+        if self.released {
+            input.release_all();
+        }
+        input.update(self.events.clone());
+        input.capture(self.keyboard_captured, self.mouse_captured);
+    }
+
+    fn write(&self, number: u32, data: &mut Vec<u8>) {
+        data.extend_from_slice(&number.to_le_bytes());
+        let mut flags = 0;
+        if self.released {
+            flags |= RELEASED;
+        }
+        if self.keyboard_captured {
+            flags |= KEYBOARD_CAPTURED;
+        }
+        if self.mouse_captured {
+            flags |= MOUSE_CAPTURED;
+        }
+        data.push(flags);
+
+        data.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+        for event in &self.events {
+            match *event {
+                Event::Key { key, pressed } => {
+                    let index = Key::ALL.iter().position(|other| *other == key).unwrap();
+                    data.extend_from_slice(&[KEY, index as u8, pressed as u8]);
+                }
+                Event::Text(character) => {
+                    data.push(TEXT);
+                    data.extend_from_slice(&(character as u32).to_le_bytes());
+                }
+                Event::MouseMoved { x, y } => {
+                    data.push(MOUSE_MOVED);
+                    data.extend_from_slice(&x.to_le_bytes());
+                    data.extend_from_slice(&y.to_le_bytes());
+                }
+                Event::MouseButton { button, pressed } => {
+                    let index = MouseButton::ALL
+                        .iter()
+                        .position(|other| *other == button)
+                        .unwrap();
+                    data.extend_from_slice(&[MOUSE_BUTTON, index as u8, pressed as u8]);
+                }
+                Event::MouseWheel(notches) => {
+                    data.push(MOUSE_WHEEL);
+                    data.extend_from_slice(&notches.to_le_bytes());
+                }
+            }
+        }
+
+        data.extend_from_slice(&self.checksum.to_le_bytes());
+    }
+
+    fn read(reader: &mut Reader, number: u32) -> Result<Self, String> {
+        let recorded = reader.u32()?;
+        if recorded != number {
+            return Err(format!("expected tick {number}, got {recorded}"));
+        }
+        let flags = reader.u8()?;
+
+        let count = reader.u32()?;
+        let mut events = Vec::new();
+        for _ in 0..count {
+            let event = match reader.u8()? {
+                KEY => {
+                    let index = reader.u8()? as usize;
+                    let key = *Key::ALL
+                        .get(index)
+                        .ok_or_else(|| format!("invalid key {index}"))?;
+                    Event::Key {
+                        key,
+                        pressed: reader.u8()? != 0,
+                    }
+                }
+                TEXT => {
+                    let value = reader.u32()?;
+                    Event::Text(
+                        char::from_u32(value)
+                            .ok_or_else(|| format!("invalid character {value:#x}"))?,
+                    )
+                }
+                MOUSE_MOVED => Event::MouseMoved {
+                    x: reader.f32()?,
+                    y: reader.f32()?,
+                },
+                MOUSE_BUTTON => {
+                    let index = reader.u8()? as usize;
+                    let button = *MouseButton::ALL
+                        .get(index)
+                        .ok_or_else(|| format!("invalid mouse button {index}"))?;
+                    Event::MouseButton {
+                        button,
+                        pressed: reader.u8()? != 0,
+                    }
+                }
+                MOUSE_WHEEL => Event::MouseWheel(reader.f32()?),
+                kind => return Err(format!("unknown event kind {kind}")),
+            };
+            events.push(event);
+        }
+
+        Ok(Self {
+            released: flags & RELEASED != 0,
+            keyboard_captured: flags & KEYBOARD_CAPTURED != 0,
+            mouse_captured: flags & MOUSE_CAPTURED != 0,
+            events,
+            checksum: reader.u64()?,
+        })
+    }
+}
+
+struct Reader {
+    data: Vec<u8>,
+    offset: usize,
+}
+
+impl Reader {
+    fn bytes(&mut self, count: usize) -> Result<&[u8], String> {
+        let end = self
+            .offset
+            .checked_add(count)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| format!("unexpected end of data at offset {}", self.offset))?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+}
+
+pub struct Recorder {
+    path: String,
+    file: BufWriter<fs::File>,
+    ticks: u32,
+}
+
+impl Recorder {
+    // Starts a new recording in DataDirs::replays, world is what it was before the first tick
+    pub fn create(world: &World) -> Result<Self, String> {
+        let date = Local::now().format("%Y-%m-%d_%H-%M-%S");
+        let path = format!(
+            "{}{}-{date}.{REPLAY_EXTENSION}",
+            DataDirs::replays(),
+            crate::GAME_EXECUTABLE_NAME
+        );
+        let file =
+            fs::File::create(&path).map_err(|err| format!("failed to create {path}: {err}"))?;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header.extend_from_slice(&[
+            crate::GAME_VERSION_MAJOR,
+            crate::GAME_VERSION_MINOR,
+            crate::GAME_VERSION_PATCH,
+        ]);
+        header.extend_from_slice(&steps_per_second().to_le_bytes());
+        let initial = scene::Scene::from_world(world, 0).to_binary();
+        header.extend_from_slice(&(initial.len() as u32).to_le_bytes());
+        header.extend_from_slice(&initial);
+
+        let mut self_ = Self {
+            path,
+            file: BufWriter::new(file),
+            ticks: 0,
+        };
+        self_.write(&header)?;
+        info!("Recording input to {}", self_.path);
+        Ok(self_)
+    }
+
+    pub fn record(&mut self, tick: &Tick) -> Result<(), String> {
+        let mut data = Vec::new();
+        tick.write(self.ticks, &mut data);
+        self.ticks += 1;
+        self.write(&data)
+    }
+
+    pub fn finish(mut self) {
+        match self.file.flush() {
+            Ok(()) => info!("Recorded {} ticks to {}", self.ticks, self.path),
+            Err(err) => error!("Failed to write {}: {err}", self.path),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.file
+            .write_all(data)
+            .map_err(|err| format!("failed to write {}: {err}", self.path))
+    }
+}
+
+pub struct Player {
+    path: String,
+    reader: Reader,
+    initial: scene::Scene,
+    started: bool,
+    ticks: u32,
+    diverged: Option<u32>,
+    mismatches: u32,
+    max_speed: bool,
+}
+
+impl Player {
+    // name is a path, or the name of a recording in DataDirs::replays with or without the
+    // extension
+    pub fn open(name: &str, max_speed: bool) -> Result<Self, String> {
+        let path = [
+            String::from(name),
+            DataDirs::replays() + name,
+            format!("{}{name}.{REPLAY_EXTENSION}", DataDirs::replays()),
+        ]
+        .into_iter()
+        .find(|path| Path::new(path).is_file())
+        .ok_or_else(|| format!("can't find recording {name}"))?;
+        let data = fs::read(&path).map_err(|err| format!("failed to read {path}: {err}"))?;
+
+        if !data.starts_with(MAGIC) {
+            return Err(format!("{path} isn't a recording"));
+        }
+        let mut reader = Reader {
+            data,
+            offset: MAGIC.len(),
+        };
+        let format = reader.u32()?;
+        if format != FORMAT_VERSION {
+            return Err(format!(
+                "{path} is format {format}, only {FORMAT_VERSION} can be played"
+            ));
+        }
+        let version = reader.bytes(3)?.to_vec();
+        let current = [
+            crate::GAME_VERSION_MAJOR,
+            crate::GAME_VERSION_MINOR,
+            crate::GAME_VERSION_PATCH,
+        ];
+        if version != current {
+            warn!(
+                "{path} was recorded with version {}.{}.{}, it might not play back the same",
+                version[0], version[1], version[2]
+            );
+        }
+        let rate = reader.u32()?;
+        if rate != steps_per_second() {
+            return Err(format!(
+                "{path} has {rate} steps per second instead of {}",
+                steps_per_second()
+            ));
+        }
+        let length = reader.u32()? as usize;
+        let initial = scene::Scene::load(reader.bytes(length)?, &scene::Migrations::default())
+            .map_err(|err| format!("failed to read the world in {path}: {err}"))?;
+
+        info!("Playing back {path}");
+        Ok(Self {
+            path,
+            reader,
+            initial,
+            started: false,
+            ticks: 0,
+            diverged: None,
+            mismatches: 0,
+            max_speed,
+        })
+    }
+
+    pub fn max_speed(&self) -> bool {
+        self.max_speed
+    }
+
+    // The input for the next tick, or None once the recording runs out. If world doesn't
+    // match the recording before the first tick, it's replaced with the recorded one.
+    pub fn next_tick(&mut self, world: &mut World) -> Option<Tick> {
+        if !self.started {
+            self.started = true;
+            let recorded = scene::Scene::from_world(world, 0).to_binary();
+            if recorded != self.initial.to_binary() {
+                warn!("The world doesn't match the one in the recording, replacing it");
+                world.clear();
+                self.initial.spawn(world);
+            }
+        }
+
+        if self.reader.is_empty() {
+            return None;
+        }
+        match Tick::read(&mut self.reader, self.ticks) {
+            Ok(tick) => Some(tick),
+            Err(err) => {
+                error!("Recording {} is cut off: {err}", self.path);
+                self.reader.offset = self.reader.data.len();
+                None
+            }
+        }
+    }
+
+    // Compares world to how it was after the tick when it was recorded, the first tick that's
+    // different is logged
+    pub fn check(&mut self, tick: &Tick, world: &World) {
+        if checksum(world) != tick.checksum {
+            self.mismatches += 1;
+            if self.diverged.is_none() {
+                error!(
+                    "World diverged from the recording at tick {}, checksum {:#018x} instead of {:#018x}",
+                    self.ticks,
+                    checksum(world),
+                    tick.checksum
+                );
+                self.diverged = Some(self.ticks);
+            }
+        }
+        self.ticks += 1;
+    }
+
+    // The tick where the world first didn't match
+    pub fn diverged(&self) -> Option<u32> {
+        self.diverged
+    }
+
+    pub fn report(&self) {
+        match self.diverged {
+            Some(tick) => error!(
+                "Played {} ticks of {}, {} didn't match starting at tick {tick}",
+                self.ticks, self.path, self.mismatches
+            ),
+            None => info!("Played {} ticks of {}, all matched", self.ticks, self.path),
+        }
+    }
+}
+
+// --replay with --headless: plays a recording with just the world, scripts and the game
+// library, one tick every STEP. Edits made with the developer UI while recording aren't
+// played back. Returns whether every tick matched.
+pub fn run_headless(args: &crate::Args, name: &str) -> bool {
+    let (game_dir, _mods) = State::init_common(args);
+
+    let mut player = match Player::open(name, args.max_speed) {
+        Ok(player) => player,
+        Err(err) => {
+            error!("Failed to open recording: {err}");
+            return false;
+        }
+    };
+
+    let mut world = World::default();
+    let mut input = input::State::default();
+    State::bind_ui_actions(&mut input);
+    let mut scripts = script::Scripts::new().hot_reload(args.reload_scripts);
+    let mut host = gamelib::EngineHost {
+        world: &mut world,
+        input: &input,
+        delta: 0.0,
+    };
+    let mut game = gamelib::GameLibrary::load(&game_dir, &mut host);
+
+    while let Some(tick) = player.next_tick(&mut world) {
+        tick.apply(&mut input);
+
+        scripts.update(&mut world, &mut input, STEP);
+        if let Some(game) = &mut game {
+            let mut host = gamelib::EngineHost {
+                world: &mut world,
+                input: &input,
+                delta: STEP,
+            };
+            game.tick(&mut host, STEP);
+        }
+        player.check(&tick, &world);
+
+        if !player.max_speed() {
+            thread::sleep(Duration::from_secs_f64(STEP));
+        }
+    }
+
+    if let Some(game) = game.take() {
+        let mut host = gamelib::EngineHost {
+            world: &mut world,
+            input: &input,
+            delta: 0.0,
+        };
+        game.shutdown(&mut host);
+    }
+
+    player.report();
+    player.diverged().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::components::{Name, Properties, Transform};
+    use nalgebra::Vector3;
+
+    #[test]
+    fn checksum_ignores_entity_order() {
+        // Different sets of components put the entities in different archetypes, which legion
+        // iterates in the order they were made
+        let named = || (Name("a".to_string()),);
+        let moved = || {
+            (
+                Name("b".to_string()),
+                Transform {
+                    position: Vector3::new(1.0, 2.0, 3.0),
+                    ..Default::default()
+                },
+            )
+        };
+
+        let mut first = World::default();
+        first.push(named());
+        first.push(moved());
+        let mut second = World::default();
+        second.push(moved());
+        second.push(named());
+        assert_eq!(checksum(&first), checksum(&second));
+
+        // But anything saved still counts
+        let entity = second.push(named());
+        assert_ne!(checksum(&first), checksum(&second));
+        second.remove(entity);
+        assert_eq!(checksum(&first), checksum(&second));
+        second.push((Properties([("k".to_string(), "v".to_string())].into()),));
+        assert_ne!(checksum(&first), checksum(&second));
+    }
+}
@@ -0,0 +1,183 @@
+use super::{EntityDesc, Scene};
+use crate::engine::{components::Transform, rendersystem::bounds::Aabb};
+use nalgebra::{Point3, Quaternion, UnitQuaternion, Vector3};
+
+// Saves start with this, then the format and game versions and the number of entities as
+// little endian u32s. Each entity is a u32 of flags for which parts it has, then each part it
+// has in the order of the flags. Strings are a u32 length then UTF-8, and vectors are f32s.
+pub const MAGIC: &[u8] = b"PURPLSAV";
+
+const HAS_NAME: u32 = 1 << 0;
+const HAS_TRANSFORM: u32 = 1 << 1;
+const HAS_BOUNDS: u32 = 1 << 2;
+const HAS_MODEL: u32 = 1 << 3;
+const HAS_PROPERTIES: u32 = 1 << 4;
+const KNOWN_FLAGS: u32 = HAS_NAME | HAS_TRANSFORM | HAS_BOUNDS | HAS_MODEL | HAS_PROPERTIES;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .offset
+            .checked_add(count)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| format!("unexpected end of data at offset {}", self.offset))?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn f32s<const N: usize>(&mut self) -> Result<[f32; N], String> {
+        let mut values = [0.0; N];
+        for value in &mut values {
+            *value = f32::from_le_bytes(self.bytes(4)?.try_into().unwrap());
+        }
+        Ok(values)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let offset = self.offset;
+        let length = self.u32()? as usize;
+        String::from_utf8(self.bytes(length)?.to_vec())
+            .map_err(|err| format!("invalid string at offset {offset}: {err}"))
+    }
+}
+
+pub fn read(data: &[u8]) -> Result<Scene, String> {
+    let mut reader = Reader {
+        data,
+        offset: MAGIC.len(),
+    };
+    let format = reader.u32()?;
+    let version = reader.u32()?;
+    let count = reader.u32()?;
+
+    // Not trusting the count for the capacity, since a bad one would allocate a lot
+    let mut entities = Vec::new();
+    for index in 0..count {
+        let flags = reader.u32()?;
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(format!("entity {index} has unknown flags {flags:#x}"));
+        }
+
+        let mut entity = EntityDesc::default();
+        if flags & HAS_NAME != 0 {
+            entity.name = Some(reader.string()?);
+        }
+        if flags & HAS_TRANSFORM != 0 {
+            let [px, py, pz, rx, ry, rz, rw, sx, sy, sz] = reader.f32s()?;
+            entity.transform = Some(Transform::new(
+                Vector3::new(px, py, pz),
+                UnitQuaternion::from_quaternion(Quaternion::new(rw, rx, ry, rz)),
+                Vector3::new(sx, sy, sz),
+            ));
+        }
+        if flags & HAS_BOUNDS != 0 {
+            let [min_x, min_y, min_z, max_x, max_y, max_z] = reader.f32s()?;
+            entity.bounds = Some(Aabb::new(
+                Point3::new(min_x, min_y, min_z),
+                Point3::new(max_x, max_y, max_z),
+            ));
+        }
+        if flags & HAS_MODEL != 0 {
+            entity.model = Some(reader.string()?);
+        }
+        if flags & HAS_PROPERTIES != 0 {
+            for _ in 0..reader.u32()? {
+                let key = reader.string()?;
+                let value = reader.string()?;
+                entity.properties.insert(key, value);
+            }
+        }
+        entities.push(entity);
+    }
+
+    if reader.offset != data.len() {
+        return Err(format!(
+            "{} bytes left over after {count} entities",
+            data.len() - reader.offset
+        ));
+    }
+
+    Ok(Scene {
+        format,
+        version,
+        entities,
+    })
+}
+
+fn write_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_f32s(data: &mut Vec<u8>, values: &[f32]) {
+    for value in values {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn write_string(data: &mut Vec<u8>, value: &str) {
+    write_u32(data, value.len() as u32);
+    data.extend_from_slice(value.as_bytes());
+}
+
+pub fn write(scene: &Scene) -> Vec<u8> {
+    let mut data = Vec::from(MAGIC);
+    write_u32(&mut data, scene.format);
+    write_u32(&mut data, scene.version);
+    write_u32(&mut data, scene.entities.len() as u32);
+
+    for entity in &scene.entities {
+        let mut flags = 0;
+        if entity.name.is_some() {
+            flags |= HAS_NAME;
+        }
+        if entity.transform.is_some() {
+            flags |= HAS_TRANSFORM;
+        }
+        if entity.bounds.is_some() {
+            flags |= HAS_BOUNDS;
+        }
+        if entity.model.is_some() {
+            flags |= HAS_MODEL;
+        }
+        if !entity.properties.is_empty() {
+            flags |= HAS_PROPERTIES;
+        }
+        write_u32(&mut data, flags);
+
+        if let Some(name) = &entity.name {
+            write_string(&mut data, name);
+        }
+        if let Some(transform) = &entity.transform {
+            let rotation = transform.rotation.coords;
+            write_f32s(&mut data, transform.position.as_slice());
+            write_f32s(&mut data, &[rotation.x, rotation.y, rotation.z, rotation.w]);
+            write_f32s(&mut data, transform.scale.as_slice());
+        }
+        if let Some(bounds) = &entity.bounds {
+            write_f32s(&mut data, bounds.min.coords.as_slice());
+            write_f32s(&mut data, bounds.max.coords.as_slice());
+        }
+        if let Some(model) = &entity.model {
+            write_string(&mut data, model);
+        }
+        if !entity.properties.is_empty() {
+            write_u32(&mut data, entity.properties.len() as u32);
+            for (key, value) in &entity.properties {
+                write_string(&mut data, key);
+                write_string(&mut data, value);
+            }
+        }
+    }
+
+    data
+}
@@ -0,0 +1,304 @@
+use crate::engine::components;
+use crate::engine::rendersystem::bounds::Aabb;
+use legion::IntoQuery;
+use log::{error, info};
+use std::{collections::BTreeMap, fs};
+
+mod binary;
+mod text;
+
+// Bumped whenever the layout of either format changes, see migrate_format
+pub const FORMAT_VERSION: u32 = 1;
+pub const LEVEL_EXTENSION: &str = "level";
+pub const SAVE_EXTENSION: &str = "sav";
+
+// Everything about an entity that gets saved
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntityDesc {
+    pub name: Option<String>,
+    pub transform: Option<components::Transform>,
+    pub bounds: Option<Aabb>,
+    pub model: Option<String>,
+    pub properties: BTreeMap<String, String>,
+}
+
+impl EntityDesc {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// A world on its way to or from disk. Levels are text so they can be edited and merged, and
+// saves are binary so they're smaller and quicker to read. Both hold the same things.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scene {
+    // The engine's layout, which is always FORMAT_VERSION once loaded
+    pub format: u32,
+    // The game's, for Migrations
+    pub version: u32,
+    pub entities: Vec<EntityDesc>,
+}
+
+// The game's migration hooks take a scene from one version to the next
+pub type Migration = fn(&mut Scene) -> Result<(), String>;
+
+// How to bring scenes from older versions of the game up to date, so old saves keep working
+// after the game changes what it keeps in them:
+//
+//     let migrations = Migrations::new(3)
+//         .add(1, rename_health_property)
+//         .add(2, add_missing_bounds);
+//
+// Scenes from newer versions than the game's are refused.
+#[derive(Clone, Debug, Default)]
+pub struct Migrations {
+    version: u32,
+    hooks: BTreeMap<u32, Migration>,
+}
+
+impl Migrations {
+    // What scenes get saved as
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            hooks: BTreeMap::new(),
+        }
+    }
+
+    // Scenes at from get hook run on them to bring them to from + 1. Versions without a hook
+    // are assumed to need no changes.
+    pub fn add(mut self, from: u32, hook: Migration) -> Self {
+        self.hooks.insert(from, hook);
+        self
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn apply(&self, scene: &mut Scene) -> Result<(), String> {
+        if scene.version > self.version {
+            return Err(format!(
+                "scene is from version {} of the game, which is newer than {}",
+                scene.version, self.version
+            ));
+        }
+
+        while scene.version < self.version {
+            if let Some(hook) = self.hooks.get(&scene.version) {
+                info!(
+                    "Migrating scene from version {} to {}",
+                    scene.version,
+                    scene.version + 1
+                );
+                hook(scene)
+                    .map_err(|err| format!("migrating from version {}: {err}", scene.version))?;
+            }
+            scene.version += 1;
+        }
+
+        Ok(())
+    }
+}
+
+// Changes to the engine's own layout go here, each one from its version to the next. The
+// parsers read older layouts as well as they can and leave the rest to this.
+fn migrate_format(scene: &mut Scene) -> Result<(), String> {
+    if scene.format > FORMAT_VERSION {
+        return Err(format!(
+            "scene format {} is newer than {FORMAT_VERSION}",
+            scene.format
+        ));
+    }
+
+    // Nothing has changed since the first layout. Changes get a step here that handles one
+    // version and moves it to the next.
+    scene.format = FORMAT_VERSION;
+
+    Ok(())
+}
+
+// What loading a scene made
+pub struct LoadedScene {
+    pub entities: Vec<legion::Entity>,
+    // Every model the entities use, which the game should load with Model::load
+    pub models: Vec<String>,
+}
+
+impl Scene {
+    // Every entity with something worth saving
+    pub fn from_world(world: &legion::World, version: u32) -> Self {
+        let mut query = <(
+            Option<&components::Name>,
+            Option<&components::Transform>,
+            Option<&components::Bounds>,
+            Option<&components::ModelRef>,
+            Option<&components::Properties>,
+        )>::query();
+        let entities = query
+            .iter(world)
+            .map(|(name, transform, bounds, model, properties)| EntityDesc {
+                name: name.map(|name| name.0.clone()),
+                transform: transform.copied(),
+                bounds: bounds.map(|bounds| bounds.0),
+                model: model.map(|model| model.0.clone()),
+                properties: properties
+                    .map(|properties| properties.0.clone())
+                    .unwrap_or_default(),
+            })
+            .filter(|entity| !entity.is_empty())
+            .collect();
+
+        Self {
+            format: FORMAT_VERSION,
+            version,
+            entities,
+        }
+    }
+
+    // Reads either format, telling them apart by the binary one's magic number, and brings
+    // it up to date
+    pub fn load(data: &[u8], migrations: &Migrations) -> Result<Self, String> {
+        let mut scene = if data.starts_with(binary::MAGIC) {
+            binary::read(data)?
+        } else {
+            let text = std::str::from_utf8(data).map_err(|err| err.to_string())?;
+            text::parse(text)?
+        };
+        migrate_format(&mut scene)?;
+        migrations.apply(&mut scene)?;
+        Ok(scene)
+    }
+
+    pub fn to_text(&self) -> String {
+        text::write(self)
+    }
+
+    pub fn to_binary(&self) -> Vec<u8> {
+        binary::write(self)
+    }
+
+    // Adds every entity to world
+    pub fn spawn(&self, world: &mut legion::World) -> LoadedScene {
+        let mut entities = Vec::with_capacity(self.entities.len());
+        let mut models: Vec<String> = Vec::new();
+        for desc in &self.entities {
+            let entity = world.push(());
+            let mut entry = world.entry(entity).unwrap();
+            if let Some(name) = &desc.name {
+                entry.add_component(components::Name(name.clone()));
+            }
+            if let Some(transform) = desc.transform {
+                entry.add_component(transform);
+            }
+            if let Some(bounds) = desc.bounds {
+                entry.add_component(components::Bounds(bounds));
+            }
+            if let Some(model) = &desc.model {
+                entry.add_component(components::ModelRef(model.clone()));
+                if !models.contains(model) {
+                    models.push(model.clone());
+                }
+            }
+            if !desc.properties.is_empty() {
+                entry.add_component(components::Properties(desc.properties.clone()));
+            }
+            entities.push(entity);
+        }
+
+        LoadedScene { entities, models }
+    }
+}
+
+fn write_scene(path: &str, data: &[u8]) -> Result<(), String> {
+    // Written next to the old one and renamed over it, so a crash can't leave half a file
+    let temporary = format!("{path}.tmp");
+    fs::write(&temporary, data)
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|err| format!("failed to write {path}: {err}"))
+}
+
+impl crate::engine::State {
+    // Replaces the world with name.level from the game's level directory
+    pub fn load_level(
+        &mut self,
+        name: &str,
+        migrations: &Migrations,
+    ) -> Result<LoadedScene, String> {
+        let path = format!(
+            "{}{name}.{LEVEL_EXTENSION}",
+            crate::engine::GameDirs::levels()
+        );
+        let data = crate::engine::vfs::open(&path);
+        self.replace_world(&path, data, migrations)
+    }
+
+    // For editors, writes the world to name.level in the game directory on disk, rather than
+    // whichever mount the level was loaded from
+    pub fn save_level(&mut self, name: &str, migrations: &Migrations) -> Result<(), String> {
+        let path = format!(
+            "{}/{}{name}.{LEVEL_EXTENSION}",
+            self.game_dir(),
+            crate::engine::GameDirs::levels()
+        );
+        if let Some((directory, _)) = path.rsplit_once('/') {
+            let _ = fs::create_dir_all(directory);
+        }
+        let scene = Scene::from_world(self.world(), migrations.version());
+        info!("Saving {} entities to {path}", scene.entities.len());
+        write_scene(&path, scene.to_text().as_bytes()).map_err(|err| {
+            error!("Failed to save level {name}: {err}");
+            err
+        })
+    }
+
+    // Replaces the world with the save in slot
+    pub fn load_game(
+        &mut self,
+        slot: &str,
+        migrations: &Migrations,
+    ) -> Result<LoadedScene, String> {
+        let path = format!(
+            "{}{slot}.{SAVE_EXTENSION}",
+            crate::engine::DataDirs::saves()
+        );
+        let data = fs::read(&path).map_err(|err| err.to_string());
+        self.replace_world(&path, data, migrations)
+    }
+
+    pub fn save_game(&mut self, slot: &str, migrations: &Migrations) -> Result<(), String> {
+        let path = format!(
+            "{}{slot}.{SAVE_EXTENSION}",
+            crate::engine::DataDirs::saves()
+        );
+        let scene = Scene::from_world(self.world(), migrations.version());
+        info!("Saving {} entities to {path}", scene.entities.len());
+        write_scene(&path, &scene.to_binary()).map_err(|err| {
+            error!("Failed to save game to slot {slot}: {err}");
+            err
+        })
+    }
+
+    // The world is only cleared once the scene has loaded, so a bad file leaves it alone
+    fn replace_world(
+        &mut self,
+        path: &str,
+        data: Result<Vec<u8>, String>,
+        migrations: &Migrations,
+    ) -> Result<LoadedScene, String> {
+        let scene = data
+            .map_err(|err| format!("failed to read {path}: {err}"))
+            .and_then(|data| {
+                Scene::load(&data, migrations)
+                    .map_err(|err| format!("failed to load {path}: {err}"))
+            })
+            .map_err(|err| {
+                error!("{err}");
+                err
+            })?;
+        info!("Loading {} entities from {path}", scene.entities.len());
+        let world = self.world();
+        world.clear();
+        Ok(scene.spawn(world))
+    }
+}
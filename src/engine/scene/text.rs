@@ -0,0 +1,189 @@
+use super::{EntityDesc, Scene};
+use crate::engine::{components::Transform, rendersystem::bounds::Aabb};
+use nalgebra::{Point3, Quaternion, UnitQuaternion, Vector3};
+use std::fmt::Write;
+
+// Levels look like this:
+//
+//     # Comments take up a whole line, since names and properties can have hashes in them
+//     format = 1
+//     version = 3
+//
+//     [entity]
+//     name = Crate
+//     position = 0 1 -5
+//     rotation = 0 0 0 1
+//     scale = 1 1 1
+//     bounds = -0.5 -0.5 -0.5 0.5 0.5 0.5
+//     model = crate
+//     property.health = 100
+//
+// Rotations are x y z w, and bounds are the minimum then the maximum. Anything an entity
+// doesn't have is left out. Strings are the rest of the line, with \n for newlines and \\ for
+// backslashes.
+pub fn parse(text: &str) -> Result<Scene, String> {
+    // Levels from before versions were written are the first of both
+    let mut scene = Scene {
+        format: 1,
+        version: 1,
+        entities: Vec::new(),
+    };
+    let mut in_entity = false;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |message: &str| format!("line {}: {message}", number + 1);
+        if let Some(section) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            match section.trim() {
+                "entity" => {
+                    scene.entities.push(EntityDesc::default());
+                    in_entity = true;
+                }
+                section => return Err(error(&format!("unknown section {section}"))),
+            }
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(error("expected key = value or [entity]"));
+        };
+        let (key, value) = (key.trim(), value.trim());
+
+        let numbers = |count: usize| {
+            let numbers = value
+                .split_whitespace()
+                .map(|number| number.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|err| error(&format!("invalid number in {key}: {err}")))?;
+            if numbers.len() == count {
+                Ok(numbers)
+            } else {
+                Err(error(&format!("{key} takes {count} numbers")))
+            }
+        };
+        let integer = || {
+            value
+                .parse::<u32>()
+                .map_err(|err| error(&format!("invalid {key}: {err}")))
+        };
+
+        if !in_entity {
+            match key {
+                "format" => scene.format = integer()?,
+                "version" => scene.version = integer()?,
+                _ => return Err(error(&format!("unknown key {key}"))),
+            }
+            continue;
+        }
+
+        let entity = scene.entities.last_mut().unwrap();
+        match key {
+            "name" => entity.name = Some(unescape(value)),
+            "position" => transform(entity).position = Vector3::from_column_slice(&numbers(3)?),
+            "rotation" => {
+                let rotation = numbers(4)?;
+                transform(entity).rotation = UnitQuaternion::from_quaternion(Quaternion::new(
+                    rotation[3],
+                    rotation[0],
+                    rotation[1],
+                    rotation[2],
+                ));
+            }
+            "scale" => transform(entity).scale = Vector3::from_column_slice(&numbers(3)?),
+            "bounds" => {
+                let bounds = numbers(6)?;
+                entity.bounds = Some(Aabb::new(
+                    Point3::new(bounds[0], bounds[1], bounds[2]),
+                    Point3::new(bounds[3], bounds[4], bounds[5]),
+                ));
+            }
+            "model" => entity.model = Some(unescape(value)),
+            _ => match key.strip_prefix("property.") {
+                Some(property) if !property.is_empty() => {
+                    entity
+                        .properties
+                        .insert(String::from(property), unescape(value));
+                }
+                _ => return Err(error(&format!("unknown key {key}"))),
+            },
+        }
+    }
+
+    Ok(scene)
+}
+
+// Entities only get a transform once a key sets part of one
+fn transform(entity: &mut EntityDesc) -> &mut Transform {
+    entity.transform.get_or_insert_with(Transform::default)
+}
+
+pub fn write(scene: &Scene) -> String {
+    let mut text = format!("format = {}\nversion = {}\n", scene.format, scene.version);
+    for entity in &scene.entities {
+        text += "\n[entity]\n";
+        if let Some(name) = &entity.name {
+            writeln!(text, "name = {}", escape(name)).unwrap();
+        }
+        if let Some(transform) = &entity.transform {
+            let position = transform.position;
+            let rotation = transform.rotation.coords;
+            let scale = transform.scale;
+            writeln!(
+                text,
+                "position = {} {} {}",
+                position.x, position.y, position.z
+            )
+            .unwrap();
+            writeln!(
+                text,
+                "rotation = {} {} {} {}",
+                rotation.x, rotation.y, rotation.z, rotation.w
+            )
+            .unwrap();
+            writeln!(text, "scale = {} {} {}", scale.x, scale.y, scale.z).unwrap();
+        }
+        if let Some(bounds) = &entity.bounds {
+            writeln!(
+                text,
+                "bounds = {} {} {} {} {} {}",
+                bounds.min.x, bounds.min.y, bounds.min.z, bounds.max.x, bounds.max.y, bounds.max.z
+            )
+            .unwrap();
+        }
+        if let Some(model) = &entity.model {
+            writeln!(text, "model = {}", escape(model)).unwrap();
+        }
+        for (key, value) in &entity.properties {
+            writeln!(text, "property.{key} = {}", escape(value)).unwrap();
+        }
+    }
+    text
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
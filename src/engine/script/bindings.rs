@@ -0,0 +1,360 @@
+// What scripts can call. Entities are an Entity type, vectors are arrays of numbers ([x, y, z]
+// and [x, y, z, w] for rotations), and anything that might not be there returns () instead.
+//
+//     spawn() -> Entity                  despawn(entity) -> bool
+//     exists(entity) -> bool             find(name) -> Entity or ()
+//     get_name(entity)                   set_name(entity, name)
+//     get_position(entity)               set_position(entity, [x, y, z])
+//     get_rotation(entity)               set_rotation(entity, [x, y, z, w])
+//     get_scale(entity)                  set_scale(entity, [x, y, z])
+//     get_model(entity)                  set_model(entity, name)
+//     get_property(entity, key)          set_property(entity, key, value)
+//     remove_property(entity, key)
+//     action_down(action)                action_pressed(action)
+//     action_released(action)            mouse_position()    mouse_delta()
+//     time()    delta()                  play_sound(name)
+//     after(seconds, "function") -> id   every(seconds, "function") -> id
+//     cancel(id) -> bool
+
+use super::{SharedContext, Timer, SCRIPT_EXTENSION};
+use crate::engine::{components, vfs, GameDirs};
+use legion::{Entity, IntoQuery};
+use nalgebra::{Quaternion, UnitQuaternion, Vector3};
+use rhai::{
+    Array, Dynamic, Engine, EvalAltResult, Module, ModuleResolver, Position, Scope, Shared, FLOAT,
+    INT,
+};
+
+type Result<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+// import "foo" reads foo.rhai from the script directory through the VFS, and nothing else
+pub struct VfsModuleResolver;
+
+impl ModuleResolver for VfsModuleResolver {
+    fn resolve(
+        &self,
+        engine: &Engine,
+        _source: Option<&str>,
+        path: &str,
+        position: Position,
+    ) -> Result<Shared<Module>> {
+        let file = format!("{}{path}.{SCRIPT_EXTENSION}", GameDirs::scripts());
+        let in_module = |err: Box<EvalAltResult>| {
+            Box::new(EvalAltResult::ErrorInModule(
+                String::from(path),
+                err,
+                position,
+            ))
+        };
+        if !vfs::exists(&file) {
+            return Err(Box::new(EvalAltResult::ErrorModuleNotFound(
+                String::from(path),
+                position,
+            )));
+        }
+
+        let source = vfs::read_to_string(&file).map_err(|err| in_module(err.into()))?;
+        let mut ast = engine
+            .compile(source)
+            .map_err(|err| in_module(err.into()))?;
+        ast.set_source(file);
+        Module::eval_ast_as_new(Scope::new(), &ast, engine)
+            .map(Shared::new)
+            .map_err(in_module)
+    }
+}
+
+fn to_array(values: &[f32]) -> Array {
+    values
+        .iter()
+        .map(|value| Dynamic::from_float(*value as FLOAT))
+        .collect()
+}
+
+fn from_array<const N: usize>(array: Array) -> Result<[f32; N]> {
+    if array.len() != N {
+        return Err(format!("expected {N} numbers but got {}", array.len()).into());
+    }
+    let mut values = [0.0; N];
+    for (value, element) in values.iter_mut().zip(array) {
+        *value = match element.as_float() {
+            Ok(float) => float as f32,
+            Err(_) => element
+                .as_int()
+                .map_err(|type_name| format!("expected a number but got {type_name}"))?
+                as f32,
+        };
+    }
+    Ok(values)
+}
+
+// Runs f on the entity's entry, which fails if it doesn't exist
+fn with_entry<T>(
+    context: &SharedContext,
+    entity: Entity,
+    f: impl FnOnce(&mut legion::world::Entry) -> T,
+) -> Result<T> {
+    let mut context = context.borrow_mut();
+    let mut entry = context
+        .world
+        .entry(entity)
+        .ok_or_else(|| format!("entity {entity:?} doesn't exist"))?;
+    Ok(f(&mut entry))
+}
+
+fn get_transform(context: &SharedContext, entity: Entity) -> Result<Option<components::Transform>> {
+    with_entry(context, entity, |entry| {
+        entry.get_component::<components::Transform>().ok().copied()
+    })
+}
+
+// Entities without a Transform get the default one first
+fn set_transform(
+    context: &SharedContext,
+    entity: Entity,
+    f: impl FnOnce(&mut components::Transform),
+) -> Result<()> {
+    with_entry(context, entity, |entry| {
+        let mut transform = entry
+            .get_component::<components::Transform>()
+            .ok()
+            .copied()
+            .unwrap_or_default();
+        f(&mut transform);
+        entry.add_component(transform);
+    })
+}
+
+fn set_timer(
+    context: &SharedContext,
+    seconds: FLOAT,
+    function: &str,
+    interval: Option<f64>,
+) -> INT {
+    let mut context = context.borrow_mut();
+    let id = context.next_timer;
+    context.next_timer += 1;
+    let timer = Timer {
+        id,
+        script: context.current.clone(),
+        function: String::from(function),
+        remaining: seconds,
+        interval,
+    };
+    context.timers.push(timer);
+    id
+}
+
+pub fn register(engine: &mut Engine, context: &SharedContext) {
+    engine
+        .register_type_with_name::<Entity>("Entity")
+        .register_fn("==", |a: &mut Entity, b: Entity| *a == b)
+        .register_fn("!=", |a: &mut Entity, b: Entity| *a != b)
+        .register_fn("to_string", |entity: &mut Entity| format!("{entity:?}"))
+        .register_fn("to_debug", |entity: &mut Entity| format!("{entity:?}"));
+
+    register_entities(engine, context);
+    register_transforms(engine, context);
+    register_components(engine, context);
+    register_input(engine, context);
+    register_time(engine, context);
+
+    let shared = context.clone();
+    engine.register_fn("play_sound", move |name: &str| {
+        shared.borrow_mut().sounds.push(String::from(name));
+    });
+}
+
+fn register_entities(engine: &mut Engine, context: &SharedContext) {
+    let shared = context.clone();
+    engine.register_fn("spawn", move || shared.borrow_mut().world.push(()));
+    let shared = context.clone();
+    engine.register_fn("despawn", move |entity: Entity| {
+        shared.borrow_mut().world.remove(entity)
+    });
+    let shared = context.clone();
+    engine.register_fn("exists", move |entity: Entity| {
+        shared.borrow().world.contains(entity)
+    });
+    let shared = context.clone();
+    engine.register_fn("find", move |name: &str| {
+        let context = shared.borrow();
+        let mut query = <(Entity, &components::Name)>::query();
+        query
+            .iter(&context.world)
+            .find(|(_, entity_name)| entity_name.0 == name)
+            .map_or(Dynamic::UNIT, |(entity, _)| Dynamic::from(*entity))
+    });
+}
+
+fn register_transforms(engine: &mut Engine, context: &SharedContext) {
+    let shared = context.clone();
+    engine.register_fn("get_position", move |entity: Entity| -> Result<Dynamic> {
+        Ok(
+            get_transform(&shared, entity)?.map_or(Dynamic::UNIT, |transform| {
+                to_array(transform.position.as_slice()).into()
+            }),
+        )
+    });
+    let shared = context.clone();
+    engine.register_fn(
+        "set_position",
+        move |entity: Entity, position: Array| -> Result<()> {
+            let position = Vector3::from(from_array::<3>(position)?);
+            set_transform(&shared, entity, |transform| transform.position = position)
+        },
+    );
+
+    let shared = context.clone();
+    engine.register_fn("get_rotation", move |entity: Entity| -> Result<Dynamic> {
+        Ok(
+            get_transform(&shared, entity)?.map_or(Dynamic::UNIT, |transform| {
+                to_array(transform.rotation.coords.as_slice()).into()
+            }),
+        )
+    });
+    let shared = context.clone();
+    engine.register_fn(
+        "set_rotation",
+        move |entity: Entity, rotation: Array| -> Result<()> {
+            let [x, y, z, w] = from_array::<4>(rotation)?;
+            let rotation = UnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z));
+            set_transform(&shared, entity, |transform| transform.rotation = rotation)
+        },
+    );
+
+    let shared = context.clone();
+    engine.register_fn("get_scale", move |entity: Entity| -> Result<Dynamic> {
+        Ok(
+            get_transform(&shared, entity)?.map_or(Dynamic::UNIT, |transform| {
+                to_array(transform.scale.as_slice()).into()
+            }),
+        )
+    });
+    let shared = context.clone();
+    engine.register_fn(
+        "set_scale",
+        move |entity: Entity, scale: Array| -> Result<()> {
+            let scale = Vector3::from(from_array::<3>(scale)?);
+            set_transform(&shared, entity, |transform| transform.scale = scale)
+        },
+    );
+}
+
+fn register_components(engine: &mut Engine, context: &SharedContext) {
+    let shared = context.clone();
+    engine.register_fn("get_name", move |entity: Entity| {
+        with_entry(&shared, entity, |entry| {
+            entry
+                .get_component::<components::Name>()
+                .map_or(Dynamic::UNIT, |name| name.0.clone().into())
+        })
+    });
+    let shared = context.clone();
+    engine.register_fn("set_name", move |entity: Entity, name: &str| {
+        with_entry(&shared, entity, |entry| {
+            entry.add_component(components::Name(String::from(name)))
+        })
+    });
+
+    let shared = context.clone();
+    engine.register_fn("get_model", move |entity: Entity| {
+        with_entry(&shared, entity, |entry| {
+            entry
+                .get_component::<components::ModelRef>()
+                .map_or(Dynamic::UNIT, |model| model.0.clone().into())
+        })
+    });
+    let shared = context.clone();
+    engine.register_fn("set_model", move |entity: Entity, model: &str| {
+        with_entry(&shared, entity, |entry| {
+            entry.add_component(components::ModelRef(String::from(model)))
+        })
+    });
+
+    let shared = context.clone();
+    engine.register_fn("get_property", move |entity: Entity, key: &str| {
+        with_entry(&shared, entity, |entry| {
+            entry
+                .get_component::<components::Properties>()
+                .ok()
+                .and_then(|properties| properties.0.get(key))
+                .map_or(Dynamic::UNIT, |value| value.clone().into())
+        })
+    });
+    // Values are saved as strings, so they come back as them too
+    let shared = context.clone();
+    engine.register_fn(
+        "set_property",
+        move |entity: Entity, key: &str, value: Dynamic| {
+            with_entry(&shared, entity, |entry| {
+                let mut properties = entry
+                    .get_component::<components::Properties>()
+                    .ok()
+                    .cloned()
+                    .unwrap_or_default();
+                properties.0.insert(String::from(key), value.to_string());
+                entry.add_component(properties);
+            })
+        },
+    );
+    let shared = context.clone();
+    engine.register_fn("remove_property", move |entity: Entity, key: &str| {
+        with_entry(&shared, entity, |entry| {
+            entry
+                .get_component_mut::<components::Properties>()
+                .ok()
+                .and_then(|properties| properties.0.remove(key))
+                .is_some()
+        })
+    });
+}
+
+fn register_input(engine: &mut Engine, context: &SharedContext) {
+    let shared = context.clone();
+    engine.register_fn("action_down", move |action: &str| {
+        shared.borrow().input.action_down(action)
+    });
+    let shared = context.clone();
+    engine.register_fn("action_pressed", move |action: &str| {
+        shared.borrow().input.action_pressed(action)
+    });
+    let shared = context.clone();
+    engine.register_fn("action_released", move |action: &str| {
+        shared.borrow().input.action_released(action)
+    });
+    let shared = context.clone();
+    engine.register_fn("mouse_position", move || {
+        let (x, y) = shared.borrow().input.mouse_position();
+        to_array(&[x, y])
+    });
+    let shared = context.clone();
+    engine.register_fn("mouse_delta", move || {
+        let (x, y) = shared.borrow().input.mouse_delta();
+        to_array(&[x, y])
+    });
+}
+
+fn register_time(engine: &mut Engine, context: &SharedContext) {
+    let shared = context.clone();
+    engine.register_fn("time", move || shared.borrow().time as FLOAT);
+    let shared = context.clone();
+    engine.register_fn("delta", move || shared.borrow().delta as FLOAT);
+
+    let shared = context.clone();
+    engine.register_fn("after", move |seconds: FLOAT, function: &str| {
+        set_timer(&shared, seconds, function, None)
+    });
+    let shared = context.clone();
+    engine.register_fn("every", move |seconds: FLOAT, function: &str| {
+        // A zero interval would run every step, which update already does
+        set_timer(&shared, seconds, function, Some(seconds.max(0.001)))
+    });
+    let shared = context.clone();
+    engine.register_fn("cancel", move |id: INT| {
+        let mut context = shared.borrow_mut();
+        let count = context.timers.len();
+        context.timers.retain(|timer| timer.id != id);
+        context.timers.len() != count
+    });
+}
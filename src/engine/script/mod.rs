@@ -0,0 +1,306 @@
+use crate::engine::{input, vfs, GameDirs};
+use log::{debug, error, info, warn};
+use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Scope, AST};
+use std::{cell::RefCell, collections::BTreeMap, mem, rc::Rc};
+
+mod bindings;
+
+pub const SCRIPT_EXTENSION: &str = "rhai";
+// How often scripts are checked for changes, in seconds
+const RELOAD_INTERVAL: f64 = 1.0;
+
+// Script timers, which call a function in the script that made them
+struct Timer {
+    id: i64,
+    script: String,
+    function: String,
+    remaining: f64,
+    // Repeating timers start over with this
+    interval: Option<f64>,
+}
+
+// What the bindings work on. The world and input are moved in while scripts run and moved
+// back out after, so nothing else can see them half changed.
+#[derive(Default)]
+struct Context {
+    world: legion::World,
+    input: input::State,
+    // Seconds
+    time: f64,
+    delta: f64,
+    // Which script is running, for timers
+    current: String,
+    timers: Vec<Timer>,
+    next_timer: i64,
+    sounds: Vec<String>,
+}
+
+type SharedContext = Rc<RefCell<Context>>;
+
+struct Script {
+    source: String,
+    // Compared before reading the file again
+    stamp: Option<vfs::Stamp>,
+    ast: AST,
+    // The script's state, which its functions see as this and which survives reloads
+    this: Dynamic,
+}
+
+impl Script {
+    fn has_function(&self, name: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == name)
+    }
+}
+
+// Gameplay scripts, which are every .rhai file in the game's script directory. Each one can
+// define any of these, which get this as an object map to keep their state in:
+//
+//     fn init() { this.score = 0; }           // after the script is first loaded
+//     fn reload() { print("reloaded"); }      // after it's changed on disk and reloaded
+//     fn update(delta) { ... }                // every step, delta is in seconds
+//
+// Scripts can only read files through the VFS (with import), and have limits on how much
+// they can do in one call so a bad one can't hang the game. See bindings for what they can
+// call.
+pub struct Scripts {
+    engine: Engine,
+    context: SharedContext,
+    scripts: BTreeMap<String, Script>,
+    hot_reload: bool,
+    // Starts out due, so scripts are loaded on the first update
+    until_reload: f64,
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        let context = SharedContext::default();
+        let mut engine = Engine::new();
+
+        engine
+            .set_max_operations(1_000_000)
+            .set_max_call_levels(64)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(1 << 20)
+            .set_max_array_size(1 << 16)
+            .set_max_map_size(1 << 16)
+            .set_module_resolver(bindings::VfsModuleResolver);
+        // eval would get around import going through the VFS
+        engine.disable_symbol("eval");
+        engine.on_print(|text| info!("{text}"));
+        engine.on_debug(|text, source, position| {
+            debug!("{}:{position}: {text}", source.unwrap_or("script"))
+        });
+        bindings::register(&mut engine, &context);
+
+        Self {
+            engine,
+            context,
+            scripts: BTreeMap::new(),
+            hot_reload: true,
+            until_reload: 0.0,
+        }
+    }
+
+    // Whether scripts are reloaded when they change, which they are by default. Without it
+    // they're only loaded on the first update.
+    pub fn hot_reload(mut self, enabled: bool) -> Self {
+        self.hot_reload = enabled;
+        self
+    }
+
+    // Runs every script's update and any timers that are due. delta is in seconds.
+    pub fn update(&mut self, world: &mut legion::World, input: &mut input::State, delta: f64) {
+        {
+            let mut context = self.context.borrow_mut();
+            mem::swap(&mut context.world, world);
+            mem::swap(&mut context.input, input);
+            context.delta = delta;
+            context.time += delta;
+        }
+
+        self.until_reload -= delta;
+        if self.until_reload <= 0.0 {
+            self.until_reload = if self.hot_reload {
+                RELOAD_INTERVAL
+            } else {
+                f64::INFINITY
+            };
+            self.reload();
+        }
+
+        let names: Vec<String> = self.scripts.keys().cloned().collect();
+        for name in &names {
+            self.call(name, "update", (delta,));
+        }
+        self.run_timers(delta);
+
+        let mut context = self.context.borrow_mut();
+        mem::swap(&mut context.world, world);
+        mem::swap(&mut context.input, input);
+    }
+
+    // Sounds scripts asked for with play_sound since the last call. The engine doesn't have
+    // any audio yet, so playing them is up to the game.
+    pub fn take_sounds(&mut self) -> Vec<String> {
+        mem::take(&mut self.context.borrow_mut().sounds)
+    }
+
+    // Loads new scripts, reloads changed ones and drops ones that are gone. Only files with a
+    // new stamp are read.
+    fn reload(&mut self) {
+        let files: Vec<String> = vfs::list(&GameDirs::scripts())
+            .into_iter()
+            .filter(|file| file.ends_with(&format!(".{SCRIPT_EXTENSION}")))
+            .collect();
+
+        self.scripts.retain(|name, _| {
+            let exists = files.contains(name);
+            if !exists {
+                info!("Unloading script {name}");
+            }
+            exists
+        });
+        let context = &self.context;
+        context
+            .borrow_mut()
+            .timers
+            .retain(|timer| files.contains(&timer.script));
+
+        for file in files {
+            let stamp = vfs::stamp(&file);
+            if stamp.is_some()
+                && self
+                    .scripts
+                    .get(&file)
+                    .is_some_and(|script| script.stamp == stamp)
+            {
+                continue;
+            }
+
+            let source = match vfs::read_to_string(&file) {
+                Ok(source) => source,
+                Err(err) => {
+                    error!("Failed to read script {file}: {err}");
+                    continue;
+                }
+            };
+            // Saved without changing anything
+            if let Some(script) = self
+                .scripts
+                .get_mut(&file)
+                .filter(|script| script.source == source)
+            {
+                script.stamp = stamp;
+                continue;
+            }
+            let existing = self.scripts.get(&file);
+
+            let ast = match self.engine.compile(&source) {
+                Ok(ast) => ast,
+                Err(err) => {
+                    // A script that's being edited keeps running its last good version
+                    error!("Failed to compile script {file}: {err}");
+                    continue;
+                }
+            };
+            let reloading = existing.is_some();
+            let this = existing.map_or_else(
+                || Dynamic::from_map(rhai::Map::new()),
+                |script| script.this.clone(),
+            );
+            info!(
+                "{} script {file}",
+                if reloading { "Reloading" } else { "Loading" }
+            );
+            self.scripts.insert(
+                file.clone(),
+                Script {
+                    source,
+                    stamp,
+                    ast,
+                    this,
+                },
+            );
+
+            // Top level statements run once, like a module
+            self.context.borrow_mut().current = file.clone();
+            if let Err(err) = self.engine.run_ast(&self.scripts[&file].ast) {
+                error!("Script {file} failed: {err}");
+            }
+            self.call(&file, if reloading { "reload" } else { "init" }, ());
+        }
+    }
+
+    // Does nothing if the script doesn't define function
+    fn call(&mut self, name: &str, function: &str, args: impl FuncArgs) {
+        let Some(script) = self.scripts.get_mut(name) else {
+            return;
+        };
+        if !script.has_function(function) {
+            return;
+        }
+
+        self.context.borrow_mut().current = String::from(name);
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut script.this);
+        if let Err(err) = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &script.ast,
+            function,
+            args,
+        ) {
+            error!("Script {name} failed in {function}: {err}");
+        }
+    }
+
+    fn run_timers(&mut self, delta: f64) {
+        let mut due = Vec::new();
+        {
+            let mut context = self.context.borrow_mut();
+            context.timers.retain_mut(|timer| {
+                timer.remaining -= delta;
+                if timer.remaining > 0.0 {
+                    return true;
+                }
+                due.push((timer.id, timer.script.clone(), timer.function.clone()));
+                match timer.interval {
+                    Some(interval) => {
+                        // Not trying to catch up if the step was longer than the interval
+                        timer.remaining = (timer.remaining + interval).max(0.0);
+                        true
+                    }
+                    None => false,
+                }
+            });
+        }
+
+        for (id, script, function) in due {
+            if !self.scripts.contains_key(&script) {
+                continue;
+            }
+            if self
+                .scripts
+                .get(&script)
+                .is_some_and(|loaded| !loaded.has_function(&function))
+            {
+                warn!("Cancelling timer {id} in script {script}, it has no function {function}");
+                self.context
+                    .borrow_mut()
+                    .timers
+                    .retain(|timer| timer.id != id);
+                continue;
+            }
+            self.call(&script, &function, ());
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::engine::{rendersystem, vfs};
+use log::{debug, error, warn};
+use std::collections::HashMap;
+
+pub const ATLAS_SIZE: u32 = 1024;
+// A white block in the corner for things that are just a color
+const WHITE_SIZE: u32 = 4;
+// Space between glyphs so filtering doesn't pick up the neighbours
+const GLYPH_PADDING: u32 = 1;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Glyph {
+    // From the pen position on the baseline to the top left of the bitmap, in pixels
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+    // Min x, min y, max x and max y in the atlas
+    pub uv: [f32; 4],
+    pub advance: f32,
+}
+
+// TTF fonts from the game's font directory, with every glyph that gets used rasterized into
+// one texture. Glyphs are white with coverage in every channel, which is premultiplied alpha,
+// so the vertex color decides what color text is.
+pub struct FontAtlas {
+    fonts: Vec<fontdue::Font>,
+    // Fonts that failed to load are remembered as None
+    font_names: HashMap<String, Option<usize>>,
+    glyphs: HashMap<(usize, char, u32), Glyph>,
+    pixels: Vec<u8>,
+    cursor: (u32, u32),
+    shelf_height: u32,
+    dirty: bool,
+    full: bool,
+    texture: Option<rendersystem::TextureHandle>,
+}
+
+impl Default for FontAtlas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FontAtlas {
+    pub fn new() -> Self {
+        let mut self_ = Self {
+            fonts: Vec::new(),
+            font_names: HashMap::new(),
+            glyphs: HashMap::new(),
+            pixels: vec![0; (ATLAS_SIZE * ATLAS_SIZE * 4) as usize],
+            cursor: (0, 0),
+            shelf_height: 0,
+            dirty: true,
+            full: false,
+            texture: None,
+        };
+        self_.clear();
+        self_
+    }
+
+    fn clear(&mut self) {
+        self.pixels.fill(0);
+        for y in 0..WHITE_SIZE {
+            let start = (y * ATLAS_SIZE * 4) as usize;
+            self.pixels[start..start + (WHITE_SIZE * 4) as usize].fill(0xFF);
+        }
+        self.glyphs.clear();
+        self.cursor = (WHITE_SIZE + GLYPH_PADDING, 0);
+        self.shelf_height = WHITE_SIZE;
+        self.dirty = true;
+        self.full = false;
+    }
+
+    // Glyphs are only thrown out between frames, so anything laid out this frame stays valid
+    pub fn begin_frame(&mut self) {
+        if self.full {
+            warn!("UI font atlas is full, clearing it");
+            self.clear();
+        }
+    }
+
+    // The middle of the white block
+    pub fn white_uv(&self) -> [f32; 2] {
+        let center = WHITE_SIZE as f32 / 2.0 / ATLAS_SIZE as f32;
+        [center, center]
+    }
+
+    pub fn font(&mut self, font_dir: &str, name: &str) -> Option<usize> {
+        if let Some(font) = self.font_names.get(name) {
+            return *font;
+        }
+
+        let path = format!("{font_dir}{name}");
+        debug!("Loading font {path}");
+        let font = vfs::open(&path).and_then(|data| {
+            fontdue::Font::from_bytes(data, fontdue::FontSettings::default()).map_err(String::from)
+        });
+        let font = match font {
+            Ok(font) => {
+                self.fonts.push(font);
+                Some(self.fonts.len() - 1)
+            }
+            Err(err) => {
+                error!("Failed to load font {path}: {err}");
+                None
+            }
+        };
+        self.font_names.insert(String::from(name), font);
+        font
+    }
+
+    // Ascent and the distance between baselines
+    pub fn line_metrics(&self, font: usize, size: u32) -> (f32, f32) {
+        match self.fonts[font].horizontal_line_metrics(size as f32) {
+            Some(metrics) => (metrics.ascent, metrics.new_line_size),
+            None => (size as f32 * 0.8, size as f32 * 1.2),
+        }
+    }
+
+    pub fn glyph(&mut self, font: usize, character: char, size: u32) -> Glyph {
+        if let Some(glyph) = self.glyphs.get(&(font, character, size)) {
+            return *glyph;
+        }
+
+        let (metrics, coverage) = self.fonts[font].rasterize(character, size as f32);
+        let mut glyph = Glyph {
+            offset: [
+                metrics.xmin as f32,
+                -(metrics.height as f32 + metrics.ymin as f32),
+            ],
+            size: [metrics.width as f32, metrics.height as f32],
+            uv: [0.0; 4],
+            advance: metrics.advance_width,
+        };
+
+        let (width, height) = (metrics.width as u32, metrics.height as u32);
+        if width > 0 && height > 0 {
+            let Some((x, y)) = self.allocate(width, height) else {
+                // Drawn as nothing until the atlas gets cleared
+                glyph.size = [0.0; 2];
+                return glyph;
+            };
+            for row in 0..height {
+                for column in 0..width {
+                    let value = coverage[(row * width + column) as usize];
+                    let start = (((y + row) * ATLAS_SIZE + x + column) * 4) as usize;
+                    self.pixels[start..start + 4].fill(value);
+                }
+            }
+            glyph.uv = [
+                x as f32 / ATLAS_SIZE as f32,
+                y as f32 / ATLAS_SIZE as f32,
+                (x + width) as f32 / ATLAS_SIZE as f32,
+                (y + height) as f32 / ATLAS_SIZE as f32,
+            ];
+            self.dirty = true;
+        }
+
+        self.glyphs.insert((font, character, size), glyph);
+        glyph
+    }
+
+    // Rows of glyphs, starting a new row when one doesn't fit
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if self.cursor.0 + width > ATLAS_SIZE {
+            self.cursor = (0, self.cursor.1 + self.shelf_height + GLYPH_PADDING);
+            self.shelf_height = 0;
+        }
+        if width > ATLAS_SIZE || self.cursor.1 + height > ATLAS_SIZE {
+            self.full = true;
+            return None;
+        }
+
+        let position = self.cursor;
+        self.cursor.0 += width + GLYPH_PADDING;
+        self.shelf_height = self.shelf_height.max(height);
+        Some(position)
+    }
+
+    // Lines are split on newlines
+    pub fn measure(&mut self, font: usize, size: u32, text: &str) -> (f32, f32) {
+        let (_, line_height) = self.line_metrics(font, size);
+        let mut width: f32 = 0.0;
+        let mut lines = 0;
+        for line in text.split('\n') {
+            let line_width: f32 = line
+                .chars()
+                .map(|character| self.glyph(font, character, size).advance)
+                .sum();
+            width = width.max(line_width);
+            lines += 1;
+        }
+        (width, lines as f32 * line_height)
+    }
+
+    // Calls draw with each glyph and where its top left corner goes
+    pub fn layout<F>(&mut self, font: usize, size: u32, text: &str, origin: [f32; 2], mut draw: F)
+    where
+        F: FnMut(&Glyph, [f32; 2]),
+    {
+        let (ascent, line_height) = self.line_metrics(font, size);
+        let mut baseline = origin[1] + ascent;
+        for line in text.split('\n') {
+            let mut pen = origin[0];
+            for character in line.chars() {
+                let glyph = self.glyph(font, character, size);
+                if glyph.size[0] > 0.0 {
+                    draw(
+                        &glyph,
+                        [
+                            (pen + glyph.offset[0]).round(),
+                            (baseline + glyph.offset[1]).round(),
+                        ],
+                    );
+                }
+                pen += glyph.advance;
+            }
+            baseline += line_height;
+        }
+    }
+
+    // Uploads the atlas again if anything was added
+    pub fn texture(
+        &mut self,
+        render: &mut rendersystem::State,
+    ) -> Option<rendersystem::TextureHandle> {
+        if self.dirty || self.texture.is_none() {
+            if let Some(texture) = self.texture.take() {
+                render.destroy_texture(texture);
+            }
+            match render.create_texture(ATLAS_SIZE, ATLAS_SIZE, &self.pixels, false) {
+                Ok(texture) => self.texture = Some(texture),
+                Err(err) => error!("Failed to create UI font atlas: {err}"),
+            }
+            self.dirty = false;
+        }
+        self.texture
+    }
+
+    pub fn destroy(&mut self, render: &mut rendersystem::State) {
+        if let Some(texture) = self.texture.take() {
+            render.destroy_texture(texture);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    pub fn shrink(&self, amount: f32) -> Self {
+        Self::new(
+            self.x + amount,
+            self.y + amount,
+            (self.width - amount * 2.0).max(0.0),
+            (self.height - amount * 2.0).max(0.0),
+        )
+    }
+
+    pub fn intersect(&self, other: &Self) -> Self {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        Self::new(x, y, (right - x).max(0.0), (bottom - y).max(0.0))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Size {
+    // As big as the content
+    #[default]
+    Auto,
+    Pixels(f32),
+    // Of the parent's size inside its padding
+    Percent(f32),
+}
+
+impl Size {
+    pub fn resolve(&self, content: f32, parent: f32) -> f32 {
+        match *self {
+            Self::Auto => content,
+            Self::Pixels(pixels) => pixels,
+            Self::Percent(percent) => parent * percent / 100.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Direction {
+    Row,
+    #[default]
+    Column,
+}
+
+// Where children go along the direction when there's space left over
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Justify {
+    #[default]
+    Start,
+    Center,
+    End,
+    SpaceBetween,
+}
+
+// Where children go across the direction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Align {
+    Start,
+    Center,
+    End,
+    #[default]
+    Stretch,
+}
+
+// A subset of flexbox. Children are placed one after another along the direction, and ones
+// with grow share whatever space is left in proportion to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Layout {
+    pub direction: Direction,
+    pub justify: Justify,
+    pub align: Align,
+    pub gap: f32,
+    pub width: Size,
+    pub height: Size,
+    pub grow: f32,
+}
+
+impl Layout {
+    pub fn row() -> Self {
+        Self {
+            direction: Direction::Row,
+            ..Default::default()
+        }
+    }
+
+    pub fn column() -> Self {
+        Self::default()
+    }
+
+    pub fn justify(self, justify: Justify) -> Self {
+        Self { justify, ..self }
+    }
+
+    pub fn align(self, align: Align) -> Self {
+        Self { align, ..self }
+    }
+
+    pub fn gap(self, gap: f32) -> Self {
+        Self { gap, ..self }
+    }
+
+    pub fn width(self, width: Size) -> Self {
+        Self { width, ..self }
+    }
+
+    pub fn height(self, height: Size) -> Self {
+        Self { height, ..self }
+    }
+
+    pub fn grow(self, grow: f32) -> Self {
+        Self { grow, ..self }
+    }
+
+    // Splits a child's width and height into along and across this layout's direction
+    pub fn main_cross<T>(&self, size: (T, T)) -> (T, T) {
+        match self.direction {
+            Direction::Row => size,
+            Direction::Column => (size.1, size.0),
+        }
+    }
+
+    pub fn from_main_cross<T>(&self, main: T, cross: T) -> (T, T) {
+        match self.direction {
+            Direction::Row => (main, cross),
+            Direction::Column => (cross, main),
+        }
+    }
+}
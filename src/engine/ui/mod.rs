@@ -0,0 +1,987 @@
+pub mod font;
+pub mod layout;
+pub mod strings;
+pub mod style;
+
+use crate::engine::{input, rendersystem, vfs, GameDirs};
+use crate::platform::input::MouseButton;
+use layout::{Align, Justify, Layout, Rect, Size};
+use log::{debug, error, warn};
+use rendersystem::ui::{UiMesh, UiVertex};
+use strings::StringTable;
+use style::{Style, StyleSheet, WidgetState};
+
+// How much of a slider's range the left and right actions move it by
+const SLIDER_STEPS: f32 = 20.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WidgetId(usize);
+
+// Text starting with @ is looked up in the string table
+#[derive(Clone, Debug)]
+pub enum WidgetKind {
+    Panel,
+    Label {
+        text: String,
+    },
+    Button {
+        text: String,
+    },
+    Image {
+        texture: rendersystem::TextureHandle,
+        width: f32,
+        height: f32,
+    },
+    Slider {
+        min: f32,
+        max: f32,
+        value: f32,
+    },
+    List {
+        items: Vec<String>,
+        selected: Option<usize>,
+    },
+}
+
+impl WidgetKind {
+    // What style sheets call it
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Panel => "panel",
+            Self::Label { .. } => "label",
+            Self::Button { .. } => "button",
+            Self::Image { .. } => "image",
+            Self::Slider { .. } => "slider",
+            Self::List { .. } => "list",
+        }
+    }
+
+    pub fn focusable(&self) -> bool {
+        matches!(
+            self,
+            Self::Button { .. } | Self::Slider { .. } | Self::List { .. }
+        )
+    }
+}
+
+pub struct Widget {
+    kind: WidgetKind,
+    class: Option<String>,
+    layout: Layout,
+    visible: bool,
+    parent: Option<WidgetId>,
+    children: Vec<WidgetId>,
+
+    // From the last time the UI was rendered
+    rect: Rect,
+    style: Style,
+    font: Option<usize>,
+    line_height: f32,
+    size: (f32, f32),
+}
+
+impl Widget {
+    fn new(kind: WidgetKind) -> Self {
+        Self {
+            kind,
+            class: None,
+            layout: Layout::default(),
+            visible: true,
+            parent: None,
+            children: Vec::new(),
+            rect: Rect::default(),
+            style: Style::default(),
+            font: None,
+            line_height: 0.0,
+            size: (0.0, 0.0),
+        }
+    }
+
+    pub fn panel(layout: Layout) -> Self {
+        Self::new(WidgetKind::Panel).layout(layout)
+    }
+
+    pub fn label(text: &str) -> Self {
+        Self::new(WidgetKind::Label {
+            text: String::from(text),
+        })
+    }
+
+    pub fn button(text: &str) -> Self {
+        Self::new(WidgetKind::Button {
+            text: String::from(text),
+        })
+    }
+
+    // Drawn at this size unless the layout says otherwise
+    pub fn image(texture: rendersystem::TextureHandle, width: f32, height: f32) -> Self {
+        Self::new(WidgetKind::Image {
+            texture,
+            width,
+            height,
+        })
+    }
+
+    pub fn slider(min: f32, max: f32, value: f32) -> Self {
+        Self::new(WidgetKind::Slider {
+            min,
+            max,
+            value: value.clamp(min, max),
+        })
+    }
+
+    pub fn list(items: Vec<String>) -> Self {
+        Self::new(WidgetKind::List {
+            items,
+            selected: None,
+        })
+    }
+
+    pub fn class(self, class: &str) -> Self {
+        Self {
+            class: Some(String::from(class)),
+            ..self
+        }
+    }
+
+    pub fn layout(self, layout: Layout) -> Self {
+        Self { layout, ..self }
+    }
+
+    pub fn kind(&self) -> &WidgetKind {
+        &self.kind
+    }
+
+    pub fn kind_mut(&mut self) -> &mut WidgetKind {
+        &mut self.kind
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    // Hidden widgets and their children don't take up space or get input
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn parent(&self) -> Option<WidgetId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[WidgetId] {
+        &self.children
+    }
+
+    // Where the widget was last drawn, in pixels
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UiEvent {
+    Clicked(WidgetId),
+    ValueChanged(WidgetId, f32),
+    Selected(WidgetId, usize),
+    // The ui_back action, usually for closing a menu
+    Back,
+}
+
+// Quads that share a texture and clip rectangle. The font atlas is None, since its texture
+// isn't known until every glyph has been rasterized.
+struct Batch {
+    texture: Option<rendersystem::TextureHandle>,
+    clip: Rect,
+    vertices: Vec<UiVertex>,
+    indices: Vec<u32>,
+}
+
+// A tree of widgets for the game's menus and HUD. The game builds it once and changes it as
+// needed, calling update to handle input and render to draw it each frame. Input goes through
+// the ui_up, ui_down, ui_left, ui_right, ui_accept and ui_back actions and the mouse, and
+// anything the UI uses is captured so the game doesn't see it.
+pub struct Ui {
+    widgets: Vec<Option<Widget>>,
+    root: WidgetId,
+    styles: StyleSheet,
+    strings: StringTable,
+    fonts: font::FontAtlas,
+    font_dir: String,
+
+    hovered: Option<WidgetId>,
+    focused: Option<WidgetId>,
+    pressed: Option<WidgetId>,
+    events: Vec<UiEvent>,
+}
+
+impl Ui {
+    pub fn new(styles: StyleSheet, strings: StringTable, font_dir: &str) -> Self {
+        Self {
+            widgets: vec![Some(Widget::panel(Layout::column()))],
+            root: WidgetId(0),
+            styles,
+            strings,
+            fonts: font::FontAtlas::new(),
+            font_dir: String::from(font_dir),
+            hovered: None,
+            focused: None,
+            pressed: None,
+            events: Vec::new(),
+        }
+    }
+
+    // Loads {style_name}.style from the UI directory and {language}.strings from the string
+    // directory, using defaults for either one if it can't be loaded
+    pub fn load(style_name: &str, language: &str) -> Self {
+        let style_path = format!("{}{style_name}.{}", GameDirs::ui(), style::STYLE_EXTENSION);
+        let strings_path = format!(
+            "{}{language}.{}",
+            GameDirs::strings(),
+            strings::STRINGS_EXTENSION
+        );
+        Self::new(
+            Self::load_file(&style_path, StyleSheet::parse),
+            Self::load_file(&strings_path, StringTable::parse),
+            &GameDirs::fonts(),
+        )
+    }
+
+    fn load_file<T: Default>(path: &str, parse: fn(&str) -> Result<T, String>) -> T {
+        debug!("Loading UI file {path}");
+        match vfs::read_to_string(path) {
+            Ok(text) => parse(&text).unwrap_or_else(|err| {
+                error!("Failed to parse {path}: {err}");
+                T::default()
+            }),
+            Err(err) => {
+                warn!("Failed to read {path}: {err}");
+                T::default()
+            }
+        }
+    }
+
+    // For switching languages
+    pub fn set_strings(&mut self, strings: StringTable) {
+        self.strings = strings;
+    }
+
+    pub fn strings(&self) -> &StringTable {
+        &self.strings
+    }
+
+    // A panel covering the whole screen that everything else goes in
+    pub fn root(&self) -> WidgetId {
+        self.root
+    }
+
+    pub fn add(&mut self, parent: WidgetId, widget: Widget) -> WidgetId {
+        let id = WidgetId(self.widgets.len());
+        self.widgets.push(Some(Widget {
+            parent: Some(parent),
+            ..widget
+        }));
+        match self.widget_mut(parent) {
+            Some(parent) => parent.children.push(id),
+            None => error!("Adding UI widget {id:?} to nonexistent parent {parent:?}"),
+        }
+        id
+    }
+
+    // Also removes the widget's children
+    pub fn remove(&mut self, id: WidgetId) {
+        if id == self.root {
+            error!("Can't remove the root UI widget");
+            return;
+        }
+
+        let Some(widget) = self.widgets.get_mut(id.0).and_then(Option::take) else {
+            return;
+        };
+        if let Some(parent) = widget.parent.and_then(|parent| self.widget_mut(parent)) {
+            parent.children.retain(|child| *child != id);
+        }
+        for child in widget.children {
+            self.remove(child);
+        }
+
+        for state in [&mut self.hovered, &mut self.focused, &mut self.pressed] {
+            if *state == Some(id) {
+                *state = None;
+            }
+        }
+    }
+
+    pub fn widget(&self, id: WidgetId) -> Option<&Widget> {
+        self.widgets.get(id.0).and_then(Option::as_ref)
+    }
+
+    pub fn widget_mut(&mut self, id: WidgetId) -> Option<&mut Widget> {
+        self.widgets.get_mut(id.0).and_then(Option::as_mut)
+    }
+
+    // For labels and buttons
+    pub fn set_text(&mut self, id: WidgetId, new_text: &str) {
+        if let Some(WidgetKind::Label { text } | WidgetKind::Button { text }) =
+            self.widget_mut(id).map(Widget::kind_mut)
+        {
+            *text = String::from(new_text);
+        }
+    }
+
+    // For sliders
+    pub fn value(&self, id: WidgetId) -> Option<f32> {
+        match self.widget(id).map(Widget::kind) {
+            Some(WidgetKind::Slider { value, .. }) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn set_value(&mut self, id: WidgetId, new_value: f32) {
+        if let Some(WidgetKind::Slider { min, max, value }) =
+            self.widget_mut(id).map(Widget::kind_mut)
+        {
+            *value = new_value.clamp(*min, *max);
+        }
+    }
+
+    // For lists
+    pub fn selected(&self, id: WidgetId) -> Option<usize> {
+        match self.widget(id).map(Widget::kind) {
+            Some(WidgetKind::List { selected, .. }) => *selected,
+            _ => None,
+        }
+    }
+
+    pub fn focused(&self) -> Option<WidgetId> {
+        self.focused
+    }
+
+    // The keyboard is captured while something has focus, so menus should clear it when
+    // they close
+    pub fn set_focus(&mut self, id: Option<WidgetId>) {
+        self.focused = id;
+    }
+
+    // Everything that happened since the last call
+    pub fn take_events(&mut self) -> Vec<UiEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Uses where widgets were last rendered, so should be called before the game handles input
+    pub fn update(&mut self, input: &mut input::State) {
+        if self
+            .focused
+            .map_or(false, |focused| !self.reachable(focused))
+        {
+            self.focused = None;
+        }
+
+        let (mouse_x, mouse_y) = input.mouse_position();
+        let mut over_ui = false;
+        self.hovered = None;
+        if !input.mouse_captured() {
+            over_ui = self.blocks_mouse(self.root, mouse_x, mouse_y);
+            self.hovered = self.hit_test(self.root, mouse_x, mouse_y);
+        }
+
+        if input.button_pressed(MouseButton::Left) {
+            self.pressed = self.hovered;
+            if let Some(hovered) = self.hovered {
+                self.focused = Some(hovered);
+                self.press(hovered, mouse_x, mouse_y);
+            }
+        }
+        if let Some(pressed) = self.pressed {
+            if input.button_down(MouseButton::Left) {
+                self.drag(pressed, mouse_x);
+            } else {
+                let clicked = input.button_released(MouseButton::Left)
+                    && self.hovered == Some(pressed)
+                    && matches!(
+                        self.widget(pressed).map(Widget::kind),
+                        Some(WidgetKind::Button { .. })
+                    );
+                if clicked {
+                    self.events.push(UiEvent::Clicked(pressed));
+                }
+                self.pressed = None;
+            }
+        }
+
+        if input.action_pressed("ui_down") {
+            self.navigate(true);
+        }
+        if input.action_pressed("ui_up") {
+            self.navigate(false);
+        }
+        if input.action_pressed("ui_right") {
+            self.step_slider(1.0);
+        }
+        if input.action_pressed("ui_left") {
+            self.step_slider(-1.0);
+        }
+        if input.action_pressed("ui_accept") {
+            self.accept();
+        }
+        if input.action_pressed("ui_back") {
+            self.events.push(UiEvent::Back);
+        }
+
+        input.capture(self.focused.is_some(), over_ui || self.pressed.is_some());
+    }
+
+    // Whether the widget and everything above it is visible
+    fn reachable(&self, id: WidgetId) -> bool {
+        match self.widget(id) {
+            Some(widget) if widget.visible => {
+                widget.parent.map_or(true, |parent| self.reachable(parent))
+            }
+            _ => false,
+        }
+    }
+
+    // The topmost focusable widget under the mouse
+    fn hit_test(&self, id: WidgetId, x: f32, y: f32) -> Option<WidgetId> {
+        let widget = self.widget(id)?;
+        if !widget.visible || !widget.rect.contains(x, y) {
+            return None;
+        }
+        widget
+            .children
+            .iter()
+            .rev()
+            .find_map(|child| self.hit_test(*child, x, y))
+            .or_else(|| widget.kind.focusable().then_some(id))
+    }
+
+    // Whether the mouse is over something the game shouldn't see through, which is anything
+    // focusable or with a background
+    fn blocks_mouse(&self, id: WidgetId, x: f32, y: f32) -> bool {
+        let Some(widget) = self.widget(id) else {
+            return false;
+        };
+        if !widget.visible || !widget.rect.contains(x, y) {
+            return false;
+        }
+        (id != self.root && (widget.kind.focusable() || widget.style.background[3] > 0.0))
+            || widget
+                .children
+                .iter()
+                .any(|child| self.blocks_mouse(*child, x, y))
+    }
+
+    fn press(&mut self, id: WidgetId, x: f32, y: f32) {
+        if let Some(WidgetKind::Slider { .. }) = self.widget(id).map(Widget::kind) {
+            self.drag(id, x);
+            return;
+        }
+
+        let Some(widget) = self.widgets[id.0].as_mut() else {
+            return;
+        };
+        let inner = widget.rect.shrink(widget.style.padding);
+        if let WidgetKind::List { items, selected } = &mut widget.kind {
+            if widget.line_height <= 0.0 || y < inner.y {
+                return;
+            }
+            let item = ((y - inner.y) / widget.line_height) as usize;
+            if item < items.len() {
+                *selected = Some(item);
+                self.events.push(UiEvent::Selected(id, item));
+            }
+        }
+    }
+
+    fn drag(&mut self, id: WidgetId, x: f32) {
+        let Some(widget) = self.widgets[id.0].as_mut() else {
+            return;
+        };
+        let inner = widget.rect.shrink(widget.style.padding);
+        if let WidgetKind::Slider { min, max, value } = &mut widget.kind {
+            let t = if inner.width > 0.0 {
+                ((x - inner.x) / inner.width).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let new_value = *min + t * (*max - *min);
+            if new_value != *value {
+                *value = new_value;
+                self.events.push(UiEvent::ValueChanged(id, new_value));
+            }
+        }
+    }
+
+    // Visible focusable widgets in the order they're drawn
+    fn focus_order(&self, id: WidgetId, order: &mut Vec<WidgetId>) {
+        let Some(widget) = self.widget(id) else {
+            return;
+        };
+        if !widget.visible {
+            return;
+        }
+        if widget.kind.focusable() {
+            order.push(id);
+        }
+        for child in &widget.children {
+            self.focus_order(*child, order);
+        }
+    }
+
+    // Lists move their selection until it hits an end, then focus moves on
+    fn navigate(&mut self, forward: bool) {
+        if let Some(focused) = self.focused {
+            if let Some(WidgetKind::List { items, selected }) = self.widgets[focused.0]
+                .as_mut()
+                .map(|widget| &mut widget.kind)
+            {
+                let next = match *selected {
+                    None if !items.is_empty() => Some(0),
+                    Some(item) if forward && item + 1 < items.len() => Some(item + 1),
+                    Some(item) if !forward && item > 0 => Some(item - 1),
+                    _ => None,
+                };
+                if let Some(next) = next {
+                    *selected = Some(next);
+                    self.events.push(UiEvent::Selected(focused, next));
+                    return;
+                }
+            }
+        }
+
+        let mut order = Vec::new();
+        self.focus_order(self.root, &mut order);
+        if order.is_empty() {
+            return;
+        }
+        let current = self
+            .focused
+            .and_then(|focused| order.iter().position(|id| *id == focused));
+        let count = order.len();
+        self.focused = Some(match current {
+            Some(current) if forward => order[(current + 1) % count],
+            Some(current) => order[(current + count - 1) % count],
+            None if forward => order[0],
+            None => order[count - 1],
+        });
+    }
+
+    fn step_slider(&mut self, direction: f32) {
+        let Some(focused) = self.focused else {
+            return;
+        };
+        if let Some(WidgetKind::Slider { min, max, value }) =
+            self.widgets[focused.0].as_mut().map(Widget::kind_mut)
+        {
+            let new_value = (*value + direction * (*max - *min) / SLIDER_STEPS).clamp(*min, *max);
+            if new_value != *value {
+                *value = new_value;
+                self.events.push(UiEvent::ValueChanged(focused, new_value));
+            }
+        }
+    }
+
+    fn accept(&mut self) {
+        let Some(focused) = self.focused else {
+            return;
+        };
+        match self.widget(focused).map(Widget::kind) {
+            Some(WidgetKind::Button { .. }) => self.events.push(UiEvent::Clicked(focused)),
+            Some(WidgetKind::List {
+                selected: Some(selected),
+                ..
+            }) => self.events.push(UiEvent::Selected(focused, *selected)),
+            _ => {}
+        }
+    }
+
+    // Lays out the tree for the current screen size and queues it to be drawn
+    pub fn render(&mut self, render: &mut rendersystem::State) {
+        self.fonts.begin_frame();
+
+        let (width, height) = render.backbuffer_size();
+        let screen = Rect::new(0.0, 0.0, width as f32, height as f32);
+        self.measure(self.root);
+        self.arrange(self.root, screen);
+
+        let mut batches = Vec::new();
+        self.paint(self.root, screen, &mut batches);
+
+        let atlas = self.fonts.texture(render);
+        for batch in batches {
+            let Some(texture) = batch.texture.or(atlas) else {
+                continue;
+            };
+            render.queue_ui(UiMesh {
+                vertices: batch.vertices,
+                indices: batch.indices,
+                texture,
+                clip: [
+                    batch.clip.x,
+                    batch.clip.y,
+                    batch.clip.x + batch.clip.width,
+                    batch.clip.y + batch.clip.height,
+                ],
+            });
+        }
+    }
+
+    pub fn destroy(&mut self, render: &mut rendersystem::State) {
+        self.fonts.destroy(render);
+    }
+
+    // Works out how big each widget wants to be from the bottom up. Percentages depend on the
+    // parent, so they count as the content size until the widget is arranged.
+    fn measure(&mut self, id: WidgetId) -> (f32, f32) {
+        let Some(widget) = self.widgets[id.0].as_ref() else {
+            return (0.0, 0.0);
+        };
+        let style = self.styles.resolve(
+            widget.kind.name(),
+            widget.class.as_deref(),
+            WidgetState::Normal,
+        );
+        let layout = widget.layout;
+        let children = widget.children.clone();
+
+        let font = self.fonts.font(&self.font_dir, &style.font);
+        let line_height = match font {
+            Some(font) => self.fonts.line_metrics(font, style.font_size).1,
+            None => style.font_size as f32,
+        };
+        let widget = self.widgets[id.0].as_ref().unwrap();
+        let mut measure_text = |text: &str| match font {
+            Some(font) => self
+                .fonts
+                .measure(font, style.font_size, self.strings.get(text)),
+            None => (0.0, line_height),
+        };
+        let mut content = match &widget.kind {
+            WidgetKind::Panel => (0.0, 0.0),
+            WidgetKind::Label { text } | WidgetKind::Button { text } => measure_text(text),
+            WidgetKind::Image { width, height, .. } => (*width, *height),
+            WidgetKind::Slider { .. } => (style.font_size as f32 * 10.0, line_height),
+            WidgetKind::List { items, .. } => {
+                let width = items
+                    .iter()
+                    .map(|item| measure_text(item).0)
+                    .fold(0.0, f32::max);
+                (width, items.len() as f32 * line_height)
+            }
+        };
+
+        // Anything with children is as big as they are
+        if !children.is_empty() {
+            let (mut main, mut cross, mut count) = (0.0, 0.0_f32, 0);
+            for child in children {
+                if !self.widget(child).map_or(false, Widget::is_visible) {
+                    continue;
+                }
+                let (child_main, child_cross) = layout.main_cross(self.measure(child));
+                main += child_main;
+                cross = cross.max(child_cross);
+                count += 1;
+            }
+            main += layout.gap * (count.max(1) - 1) as f32;
+            content = layout.from_main_cross(main, cross);
+        }
+
+        let pixels = |size: Size, content: f32| match size {
+            Size::Pixels(pixels) => pixels,
+            _ => content + style.padding * 2.0,
+        };
+        let size = (
+            pixels(layout.width, content.0),
+            pixels(layout.height, content.1),
+        );
+
+        let widget = self.widgets[id.0].as_mut().unwrap();
+        widget.style = style;
+        widget.font = font;
+        widget.line_height = line_height;
+        widget.size = size;
+        size
+    }
+
+    // Places children inside the widget's padding from the top down
+    fn arrange(&mut self, id: WidgetId, rect: Rect) {
+        let Some(widget) = self.widgets[id.0].as_mut() else {
+            return;
+        };
+        widget.rect = rect;
+        let layout = widget.layout;
+        let inner = rect.shrink(widget.style.padding);
+        let children: Vec<WidgetId> = widget
+            .children
+            .clone()
+            .into_iter()
+            .filter(|child| self.widget(*child).map_or(false, Widget::is_visible))
+            .collect();
+        if children.is_empty() {
+            return;
+        }
+
+        let (inner_main, inner_cross) = layout.main_cross((inner.width, inner.height));
+        let mut sizes = Vec::with_capacity(children.len());
+        let mut used = layout.gap * (children.len() - 1) as f32;
+        let mut total_grow = 0.0;
+        for child in &children {
+            let child = self.widget(*child).unwrap();
+            let (main_size, cross_size) =
+                layout.main_cross((child.layout.width, child.layout.height));
+            let (main, cross) = layout.main_cross(child.size);
+            let main = match main_size {
+                Size::Percent(_) => main_size.resolve(main, inner_main),
+                _ => main,
+            };
+            let cross = match cross_size {
+                Size::Auto if layout.align == Align::Stretch => inner_cross,
+                _ => cross_size.resolve(cross, inner_cross),
+            };
+            used += main;
+            total_grow += child.layout.grow;
+            sizes.push((main, cross, child.layout.grow));
+        }
+
+        let mut left_over = (inner_main - used).max(0.0);
+        if total_grow > 0.0 {
+            for (main, _, grow) in &mut sizes {
+                *main += left_over * *grow / total_grow;
+            }
+            left_over = 0.0;
+        }
+
+        let (mut position, spacing) = match layout.justify {
+            Justify::Start => (0.0, 0.0),
+            Justify::Center => (left_over / 2.0, 0.0),
+            Justify::End => (left_over, 0.0),
+            Justify::SpaceBetween if children.len() > 1 => {
+                (0.0, left_over / (children.len() - 1) as f32)
+            }
+            Justify::SpaceBetween => (0.0, 0.0),
+        };
+        for (child, (main, cross, _)) in children.into_iter().zip(sizes) {
+            let cross_position = match layout.align {
+                Align::Start | Align::Stretch => 0.0,
+                Align::Center => (inner_cross - cross) / 2.0,
+                Align::End => inner_cross - cross,
+            };
+            let (x, y) = layout.from_main_cross(position, cross_position);
+            let (width, height) = layout.from_main_cross(main, cross);
+            self.arrange(child, Rect::new(inner.x + x, inner.y + y, width, height));
+            position += main + layout.gap + spacing;
+        }
+    }
+
+    fn paint(&mut self, id: WidgetId, clip: Rect, batches: &mut Vec<Batch>) {
+        let Some(widget) = self.widgets[id.0].as_ref() else {
+            return;
+        };
+        if !widget.visible {
+            return;
+        }
+
+        let state = if self.pressed == Some(id) {
+            WidgetState::Pressed
+        } else if self.hovered == Some(id) {
+            WidgetState::Hovered
+        } else if self.focused == Some(id) {
+            WidgetState::Focused
+        } else {
+            WidgetState::Normal
+        };
+        let style = match state {
+            WidgetState::Normal => widget.style.clone(),
+            _ => self
+                .styles
+                .resolve(widget.kind.name(), widget.class.as_deref(), state),
+        };
+
+        let rect = widget.rect;
+        let clip = clip.intersect(&rect);
+        let inner = rect.shrink(style.padding);
+        let white = self.fonts.white_uv();
+        let white = [white[0], white[1], white[0], white[1]];
+        let fill = |batches: &mut Vec<Batch>, rect: Rect, color: [f32; 4]| {
+            push_quad(batches, None, clip, rect, white, color)
+        };
+
+        fill(batches, rect, style.background);
+        let border = style.border_width;
+        if border > 0.0 {
+            let color = style.border_color;
+            fill(
+                batches,
+                Rect::new(rect.x, rect.y, rect.width, border),
+                color,
+            );
+            fill(
+                batches,
+                Rect::new(rect.x, rect.y + rect.height - border, rect.width, border),
+                color,
+            );
+            fill(
+                batches,
+                Rect::new(rect.x, rect.y, border, rect.height),
+                color,
+            );
+            fill(
+                batches,
+                Rect::new(rect.x + rect.width - border, rect.y, border, rect.height),
+                color,
+            );
+        }
+
+        let mut text = |batches: &mut Vec<Batch>, text: &str, origin: [f32; 2]| {
+            if let Some(font) = widget.font {
+                let text = self.strings.get(text);
+                self.fonts
+                    .layout(font, style.font_size, text, origin, |glyph, position| {
+                        let rect =
+                            Rect::new(position[0], position[1], glyph.size[0], glyph.size[1]);
+                        push_quad(batches, None, clip, rect, glyph.uv, style.color);
+                    });
+            }
+        };
+
+        match &widget.kind {
+            WidgetKind::Panel => {}
+            WidgetKind::Label { text: label } => text(batches, label, [inner.x, inner.y]),
+            WidgetKind::Button { text: label } => {
+                // Centered, using the text size from measuring
+                let content = widget.size;
+                let origin = [
+                    inner.x + (inner.width - (content.0 - style.padding * 2.0)) / 2.0,
+                    inner.y + (inner.height - (content.1 - style.padding * 2.0)) / 2.0,
+                ];
+                text(batches, label, origin);
+            }
+            WidgetKind::Image { texture, .. } => push_quad(
+                batches,
+                Some(*texture),
+                clip,
+                inner,
+                [0.0, 0.0, 1.0, 1.0],
+                style.color,
+            ),
+            WidgetKind::Slider { min, max, value } => {
+                let t = if max > min {
+                    (value - min) / (max - min)
+                } else {
+                    0.0
+                };
+                let track_height = (inner.height / 4.0).max(2.0);
+                let track = Rect::new(
+                    inner.x,
+                    inner.y + (inner.height - track_height) / 2.0,
+                    inner.width,
+                    track_height,
+                );
+                let mut track_color = style.color;
+                track_color[3] *= 0.25;
+                push_quad(batches, None, clip, track, white, track_color);
+                push_quad(
+                    batches,
+                    None,
+                    clip,
+                    Rect::new(track.x, track.y, track.width * t, track.height),
+                    white,
+                    style.accent,
+                );
+                let handle_width = track_height * 2.0;
+                push_quad(
+                    batches,
+                    None,
+                    clip,
+                    Rect::new(
+                        inner.x + (inner.width - handle_width) * t,
+                        inner.y,
+                        handle_width,
+                        inner.height,
+                    ),
+                    white,
+                    style.color,
+                );
+            }
+            WidgetKind::List { items, selected } => {
+                let line_height = widget.line_height;
+                for (index, item) in items.iter().enumerate() {
+                    let y = inner.y + index as f32 * line_height;
+                    if Some(index) == *selected {
+                        push_quad(
+                            batches,
+                            None,
+                            clip,
+                            Rect::new(inner.x, y, inner.width, line_height),
+                            white,
+                            style.accent,
+                        );
+                    }
+                    text(batches, item, [inner.x, y]);
+                }
+            }
+        }
+
+        for child in widget.children.clone() {
+            self.paint(child, clip, batches);
+        }
+    }
+}
+
+// Colors are sRGB and get premultiplied here
+fn push_quad(
+    batches: &mut Vec<Batch>,
+    texture: Option<rendersystem::TextureHandle>,
+    clip: Rect,
+    rect: Rect,
+    uv: [f32; 4],
+    color: [f32; 4],
+) {
+    if rect.width <= 0.0 || rect.height <= 0.0 || color[3] <= 0.0 {
+        return;
+    }
+
+    let same = batches.last().map_or(false, |batch| {
+        batch.texture == texture && batch.clip == clip
+    });
+    if !same {
+        batches.push(Batch {
+            texture,
+            clip,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        });
+    }
+    let batch = batches.last_mut().unwrap();
+
+    let alpha = color[3].clamp(0.0, 1.0);
+    let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let color = [
+        to_byte(color[0] * alpha),
+        to_byte(color[1] * alpha),
+        to_byte(color[2] * alpha),
+        to_byte(alpha),
+    ];
+    let base = batch.vertices.len() as u32;
+    let (left, top) = (rect.x, rect.y);
+    let (right, bottom) = (rect.x + rect.width, rect.y + rect.height);
+    for (position, texture_coordinate) in [
+        ([left, top], [uv[0], uv[1]]),
+        ([right, top], [uv[2], uv[1]]),
+        ([right, bottom], [uv[2], uv[3]]),
+        ([left, bottom], [uv[0], uv[3]]),
+    ] {
+        batch.vertices.push(UiVertex {
+            position,
+            texture_coordinate,
+            color,
+        });
+    }
+    batch
+        .indices
+        .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+}
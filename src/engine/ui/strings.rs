@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+pub const STRINGS_EXTENSION: &str = "strings";
+
+// Text for one language, read from a file in the game's string directory that looks like this:
+//
+//     # Lines starting with a hash are comments
+//     menu.play = Play
+//     menu.quit = Quit to desktop
+//     intro = First line\nSecond line
+//
+// Widget text starting with @ is looked up by the key after it, and anything else is shown as
+// it is. Keys that aren't in the table show up as the key so they're easy to spot.
+#[derive(Clone, Debug, Default)]
+pub struct StringTable {
+    strings: HashMap<String, String>,
+}
+
+impl StringTable {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut strings = HashMap::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected key = value", number + 1));
+            };
+            strings.insert(String::from(key.trim()), value.trim().replace("\\n", "\n"));
+        }
+
+        Ok(Self { strings })
+    }
+
+    pub fn get<'a>(&'a self, text: &'a str) -> &'a str {
+        match text.strip_prefix('@') {
+            Some(key) => self.strings.get(key).map_or(key, |value| value.as_str()),
+            None => text,
+        }
+    }
+}
@@ -0,0 +1,229 @@
+pub const STYLE_EXTENSION: &str = "style";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WidgetState {
+    Normal,
+    Hovered,
+    Focused,
+    Pressed,
+}
+
+// What a widget looks like once every rule that applies to it is combined. Colors are sRGB
+// and not premultiplied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Style {
+    pub background: [f32; 4],
+    pub color: [f32; 4],
+    // Slider fill and list selection
+    pub accent: [f32; 4],
+    pub border_color: [f32; 4],
+    pub border_width: f32,
+    pub padding: f32,
+    // Relative to the game's font directory
+    pub font: String,
+    pub font_size: u32,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            background: [0.0; 4],
+            color: [1.0; 4],
+            accent: [0.25, 0.5, 0.9, 1.0],
+            border_color: [0.0; 4],
+            border_width: 0.0,
+            padding: 0.0,
+            font: String::from("default.ttf"),
+            font_size: 20,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct StyleRule {
+    background: Option<[f32; 4]>,
+    color: Option<[f32; 4]>,
+    accent: Option<[f32; 4]>,
+    border_color: Option<[f32; 4]>,
+    border_width: Option<f32>,
+    padding: Option<f32>,
+    font: Option<String>,
+    font_size: Option<u32>,
+}
+
+impl StyleRule {
+    fn apply(&self, style: &mut Style) {
+        fn set<T: Clone>(value: &Option<T>, target: &mut T) {
+            if let Some(value) = value {
+                *target = value.clone();
+            }
+        }
+
+        set(&self.background, &mut style.background);
+        set(&self.color, &mut style.color);
+        set(&self.accent, &mut style.accent);
+        set(&self.border_color, &mut style.border_color);
+        set(&self.border_width, &mut style.border_width);
+        set(&self.padding, &mut style.padding);
+        set(&self.font, &mut style.font);
+        set(&self.font_size, &mut style.font_size);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Target {
+    All,
+    Kind(String),
+    Class(String),
+}
+
+#[derive(Clone, Debug)]
+struct Selector {
+    target: Target,
+    state: Option<WidgetState>,
+}
+
+impl Selector {
+    // Higher goes on top
+    fn specificity(&self) -> u32 {
+        let target = match self.target {
+            Target::All => 0,
+            Target::Kind(_) => 2,
+            Target::Class(_) => 4,
+        };
+        target + self.state.is_some() as u32
+    }
+}
+
+// Rules for how widgets look, read from a file in the game's UI directory that looks like
+// this:
+//
+//     # Comments start with a hash, and anything before the first section applies to all
+//     font = regular.ttf
+//     font_size = 20
+//
+//     [button]
+//     background = 0.2 0.2 0.2 0.9
+//     padding = 8
+//
+//     [button:hover]
+//     background = 0.3 0.3 0.3 0.9
+//
+//     [.title]
+//     font_size = 48
+//
+// Sections are a widget kind (panel, label, button, image, slider or list), a class starting
+// with a dot, or * for everything, optionally followed by :hover, :focus or :pressed. Classes
+// beat kinds, and rules for a state beat ones without, like CSS.
+#[derive(Clone, Debug, Default)]
+pub struct StyleSheet {
+    rules: Vec<(Selector, StyleRule)>,
+}
+
+impl StyleSheet {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rules = vec![(
+            Selector {
+                target: Target::All,
+                state: None,
+            },
+            StyleRule::default(),
+        )];
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {message}", number + 1);
+            if let Some(selector) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                let (target, state) = match selector.split_once(':') {
+                    Some((target, state)) => (target.trim(), Some(state.trim())),
+                    None => (selector.trim(), None),
+                };
+                let target = match target {
+                    "*" => Target::All,
+                    _ => match target.strip_prefix('.') {
+                        Some(class) => Target::Class(String::from(class)),
+                        None => Target::Kind(String::from(target)),
+                    },
+                };
+                let state = match state {
+                    None => None,
+                    Some("hover") => Some(WidgetState::Hovered),
+                    Some("focus") => Some(WidgetState::Focused),
+                    Some("pressed") => Some(WidgetState::Pressed),
+                    Some(state) => return Err(error(&format!("unknown state {state}"))),
+                };
+                rules.push((Selector { target, state }, StyleRule::default()));
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected key = value or [selector]"));
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            let numbers = || {
+                value
+                    .split_whitespace()
+                    .map(|number| number.parse::<f32>())
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map_err(|err| error(&format!("invalid number in {key}: {err}")))
+            };
+            let scalar = || match numbers()?.as_slice() {
+                [value] => Ok(*value),
+                _ => Err(error(&format!("{key} takes one number"))),
+            };
+            let color = || match numbers()?.as_slice() {
+                [r, g, b] => Ok(Some([*r, *g, *b, 1.0])),
+                [r, g, b, a] => Ok(Some([*r, *g, *b, *a])),
+                _ => Err(error(&format!("{key} takes three or four numbers"))),
+            };
+
+            let rule = &mut rules.last_mut().unwrap().1;
+            match key {
+                "background" => rule.background = color()?,
+                "color" => rule.color = color()?,
+                "accent" => rule.accent = color()?,
+                "border_color" => rule.border_color = color()?,
+                "border_width" => rule.border_width = Some(scalar()?),
+                "padding" => rule.padding = Some(scalar()?),
+                "font" => rule.font = Some(String::from(value)),
+                "font_size" => rule.font_size = Some(scalar()?.max(1.0) as u32),
+                _ => return Err(error(&format!("unknown key {key}"))),
+            }
+        }
+
+        // Stable, so rules with the same specificity stay in file order
+        rules.sort_by_key(|(selector, _)| selector.specificity());
+        Ok(Self { rules })
+    }
+
+    // Each state also gets the rules for the states it implies, pressed things are hovered
+    pub fn resolve(&self, kind: &str, class: Option<&str>, state: WidgetState) -> Style {
+        let mut style = Style::default();
+        for (selector, rule) in &self.rules {
+            let target = match &selector.target {
+                Target::All => true,
+                Target::Kind(target) => target == kind,
+                Target::Class(target) => Some(target.as_str()) == class,
+            };
+            let state = match selector.state {
+                None => true,
+                Some(WidgetState::Hovered) => {
+                    matches!(state, WidgetState::Hovered | WidgetState::Pressed)
+                }
+                Some(selector_state) => selector_state == state,
+            };
+            if target && state {
+                rule.apply(&mut style);
+            }
+        }
+        style
+    }
+}
@@ -0,0 +1,262 @@
+// Where the engine reads game files from. Directories and pak archives are mounted on top of
+// each other, and a path is read from the last mount that has it, so later mounts override
+// earlier ones like Quake's search path. Paths are relative to the game directory and always
+// use forward slashes, like models/test.obj.
+
+pub mod pak;
+
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use std::{collections::BTreeMap, fs, path::Path, sync::RwLock, time::SystemTime};
+
+enum Mount {
+    Directory(String),
+    Pak(pak::Pak),
+}
+
+impl Mount {
+    fn name(&self) -> &str {
+        match self {
+            Self::Directory(path) => path,
+            Self::Pak(pak) => pak.path(),
+        }
+    }
+
+    // None if the mount doesn't have path
+    fn read(&self, path: &str) -> Option<Result<Vec<u8>, String>> {
+        match self {
+            Self::Directory(directory) => {
+                let full_path = format!("{directory}{path}");
+                if !Path::new(&full_path).is_file() {
+                    return None;
+                }
+                Some(fs::read(&full_path).map_err(|err| err.to_string()))
+            }
+            Self::Pak(pak) => pak.read(path),
+        }
+    }
+
+    // Every path the mount has that starts with prefix, which is either empty or a directory
+    // ending in a slash
+    fn files(&self, prefix: &str) -> Vec<String> {
+        fn walk(root: &str, directory: &Path, files: &mut Vec<String>) {
+            let Ok(entries) = fs::read_dir(directory) else {
+                return;
+            };
+            for path in entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+            {
+                if path.is_dir() {
+                    walk(root, &path, files);
+                } else if let Some(path) = path.to_str() {
+                    let path = path.replace('\\', "/");
+                    if let Some(relative) = path.strip_prefix(root) {
+                        files.push(String::from(relative));
+                    }
+                }
+            }
+        }
+
+        match self {
+            Self::Directory(directory) => {
+                let mut files = Vec::new();
+                walk(
+                    directory,
+                    Path::new(&format!("{directory}{prefix}")),
+                    &mut files,
+                );
+                files
+            }
+            Self::Pak(pak) => pak
+                .entries()
+                .map(|(name, _)| name)
+                .filter(|name| name.starts_with(prefix))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    // None if the mount doesn't have path
+    fn stamp(&self, path: &str) -> Option<(Option<SystemTime>, u64)> {
+        match self {
+            Self::Directory(directory) => fs::metadata(format!("{directory}{path}"))
+                .ok()
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| (metadata.modified().ok(), metadata.len())),
+            Self::Pak(pak) => pak.entry(path).map(|entry| (None, entry.size)),
+        }
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        match self {
+            Self::Directory(directory) => Path::new(&format!("{directory}{path}")).is_file(),
+            Self::Pak(pak) => pak.contains(path),
+        }
+    }
+}
+
+// When a file was last changed and how big it is, to tell whether it changed without reading
+// it. Paks only read their directory when they're mounted, so nothing in them changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stamp {
+    mount: usize,
+    modified: Option<SystemTime>,
+    size: u64,
+}
+
+static MOUNTS: Lazy<RwLock<Vec<Mount>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+// Takes out empty and . components and turns backslashes into slashes, so the same file
+// always has the same path. Paths can't go above the game directory.
+pub fn normalize(path: &str) -> Result<String, String> {
+    let mut components = Vec::new();
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => {}
+            ".." => return Err(format!("{path} goes outside the game directory")),
+            component => components.push(component),
+        }
+    }
+    Ok(components.join("/"))
+}
+
+// Mounts a directory on disk over everything mounted so far
+pub fn mount_directory(path: &str) -> Result<(), String> {
+    if !Path::new(path).is_dir() {
+        return Err(format!("{path} is not a directory"));
+    }
+    let path = path.replace('\\', "/");
+    let path = if path.ends_with('/') {
+        path
+    } else {
+        path + "/"
+    };
+
+    info!("Mounting directory {path}");
+    MOUNTS.write().unwrap().push(Mount::Directory(path));
+    Ok(())
+}
+
+// Mounts a pak archive over everything mounted so far
+pub fn mount_pak(path: &str) -> Result<(), String> {
+    let pak = pak::Pak::open(path).map_err(|err| format!("failed to open {path}: {err}"))?;
+    info!(
+        "Mounting pak archive {path} with {} file(s)",
+        pak.entries().count()
+    );
+    MOUNTS.write().unwrap().push(Mount::Pak(pak));
+    Ok(())
+}
+
+// Mounts the paks in a directory in name order (so pak1.pak beats pak0.pak), then the
+// directory itself, so loose files override archived ones while working on them
+pub fn mount_root(path: &str) -> Result<(), String> {
+    let mut paks: Vec<String> = fs::read_dir(path)
+        .map_err(|err| format!("failed to list {path}: {err}"))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|extension| extension == pak::PAK_EXTENSION)
+        })
+        .filter_map(|path| path.to_str().map(|path| path.replace('\\', "/")))
+        .collect();
+    paks.sort();
+
+    for pak in paks {
+        if let Err(err) = mount_pak(&pak) {
+            warn!("Skipping pak archive: {err}");
+        }
+    }
+    mount_directory(path)
+}
+
+// Takes everything off, for switching games
+pub fn unmount_all() {
+    info!("Unmounting everything");
+    MOUNTS.write().unwrap().clear();
+}
+
+// Reads the whole file at path from the last mount that has it
+pub fn open(path: &str) -> Result<Vec<u8>, String> {
+    let path = normalize(path)?;
+    let mounts = MOUNTS.read().unwrap();
+    for mount in mounts.iter().rev() {
+        if let Some(data) = mount.read(&path) {
+            debug!("Reading {path} from {}", mount.name());
+            return data
+                .map_err(|err| format!("failed to read {path} from {}: {err}", mount.name()));
+        }
+    }
+    Err(format!("{path} not found"))
+}
+
+pub fn read_to_string(path: &str) -> Result<String, String> {
+    String::from_utf8(open(path)?).map_err(|err| format!("{path} is not UTF-8: {err}"))
+}
+
+// Every file under directory in any mount, sorted
+pub fn list(directory: &str) -> Vec<String> {
+    let Ok(directory) = normalize(directory) else {
+        return Vec::new();
+    };
+    let prefix = format!("{directory}/");
+    let mut files: Vec<String> = MOUNTS
+        .read()
+        .unwrap()
+        .iter()
+        .flat_map(|mount| mount.files(&prefix))
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+// The stamp of path in the last mount that has it
+pub fn stamp(path: &str) -> Option<Stamp> {
+    let path = normalize(path).ok()?;
+    MOUNTS
+        .read()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(index, mount)| {
+            let (modified, size) = mount.stamp(&path)?;
+            Some(Stamp {
+                mount: index,
+                modified,
+                size,
+            })
+        })
+}
+
+pub fn exists(path: &str) -> bool {
+    let Ok(path) = normalize(path) else {
+        return false;
+    };
+    MOUNTS
+        .read()
+        .unwrap()
+        .iter()
+        .any(|mount| mount.contains(&path))
+}
+
+// Every path more than one mount has, with the mounts that have it from the one that's used
+// down, for seeing what mods replace
+pub fn overrides() -> BTreeMap<String, Vec<String>> {
+    let mut sources: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for mount in MOUNTS.read().unwrap().iter().rev() {
+        for path in mount.files("") {
+            sources
+                .entry(path)
+                .or_default()
+                .push(String::from(mount.name()));
+        }
+    }
+    sources.retain(|_, mounts| mounts.len() > 1);
+    sources
+}
@@ -0,0 +1,322 @@
+// Pak archives hold a game's files compressed in one file. They start with MAGIC, then the
+// format version, the number of files and where the directory starts as little endian u32,
+// u32 and u64. The directory is at the end so files can be written as they're compressed,
+// and each entry in it is a u32 length and UTF-8 path, then the method, offset, stored size
+// and real size of the file as a u32 and three u64s.
+//
+// paktool also uses this file, so it only depends on std and flate2.
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+pub const MAGIC: &[u8] = b"PURPLPAK";
+pub const VERSION: u32 = 1;
+pub const PAK_EXTENSION: &str = "pak";
+
+const HEADER_SIZE: u64 = 24;
+// zlib can't make anything smaller than about this many times, so sizes in the directory that
+// need more than that are wrong
+const MAX_ZLIB_RATIO: u64 = 1032;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    // For files that don't get any smaller, like most images
+    Stored,
+    Zlib,
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub method: Method,
+    offset: u64,
+    pub stored_size: u64,
+    pub size: u64,
+}
+
+impl Entry {
+    // The most the stored data could be once it's read
+    fn max_size(&self) -> u64 {
+        match self.method {
+            Method::Stored => self.stored_size,
+            Method::Zlib => self.stored_size.saturating_mul(MAX_ZLIB_RATIO),
+        }
+    }
+}
+
+// An archive's directory, files are read from it as they're asked for
+#[derive(Debug)]
+pub struct Pak {
+    path: String,
+    entries: BTreeMap<String, Entry>,
+}
+
+fn read_u32(file: &mut impl Read) -> Result<u32, String> {
+    let mut bytes = [0; 4];
+    file.read_exact(&mut bytes).map_err(|err| err.to_string())?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(file: &mut impl Read) -> Result<u64, String> {
+    let mut bytes = [0; 8];
+    file.read_exact(&mut bytes).map_err(|err| err.to_string())?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl Pak {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let mut file = fs::File::open(path).map_err(|err| err.to_string())?;
+        let length = file.metadata().map_err(|err| err.to_string())?.len();
+
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)
+            .map_err(|_| String::from("not a pak archive"))?;
+        if magic != MAGIC {
+            return Err(String::from("not a pak archive"));
+        }
+        let version = read_u32(&mut file)?;
+        if version != VERSION {
+            return Err(format!("unsupported pak version {version}"));
+        }
+        let count = read_u32(&mut file)?;
+        let directory = read_u64(&mut file)?;
+        if directory < HEADER_SIZE || directory > length {
+            return Err(format!("directory offset {directory} is out of range"));
+        }
+
+        file.seek(SeekFrom::Start(directory))
+            .map_err(|err| err.to_string())?;
+        let mut file = std::io::BufReader::new(file);
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let path_length = read_u32(&mut file)? as u64;
+            if path_length > length {
+                return Err(format!("path length {path_length} is out of range"));
+            }
+            let mut name = vec![0; path_length as usize];
+            file.read_exact(&mut name).map_err(|err| err.to_string())?;
+            let name = String::from_utf8(name).map_err(|err| err.to_string())?;
+            let method = match read_u32(&mut file)? {
+                0 => Method::Stored,
+                1 => Method::Zlib,
+                method => return Err(format!("{name} has unknown method {method}")),
+            };
+            let entry = Entry {
+                method,
+                offset: read_u64(&mut file)?,
+                stored_size: read_u64(&mut file)?,
+                size: read_u64(&mut file)?,
+            };
+            if entry
+                .offset
+                .checked_add(entry.stored_size)
+                .is_none_or(|end| end > directory)
+            {
+                return Err(format!("{name} is out of range"));
+            }
+            if entry.size > entry.max_size() {
+                return Err(format!(
+                    "{name} is {} bytes, which can't be stored in {}",
+                    entry.size, entry.stored_size
+                ));
+            }
+            entries.insert(name, entry);
+        }
+
+        Ok(Self {
+            path: String::from(path),
+            entries,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Sorted by path
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.entries.iter()
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    // None if the archive doesn't have name
+    pub fn read(&self, name: &str) -> Option<Result<Vec<u8>, String>> {
+        let entry = self.entries.get(name)?;
+        Some(self.read_entry(entry))
+    }
+
+    fn read_entry(&self, entry: &Entry) -> Result<Vec<u8>, String> {
+        let mut file = fs::File::open(&self.path).map_err(|err| err.to_string())?;
+        file.seek(SeekFrom::Start(entry.offset))
+            .map_err(|err| err.to_string())?;
+        let stored = file.take(entry.stored_size);
+
+        // The directory could still be wrong about how much it inflates to, so this doesn't
+        // allocate more than it could be and reads a byte past the end to see if it's longer
+        let mut data = Vec::with_capacity(entry.size.min(entry.max_size()) as usize);
+        let limit = entry.size.saturating_add(1);
+        match entry.method {
+            Method::Stored => stored.take(limit).read_to_end(&mut data),
+            Method::Zlib => ZlibDecoder::new(stored).take(limit).read_to_end(&mut data),
+        }
+        .map_err(|err| err.to_string())?;
+        if data.len() as u64 > entry.size {
+            return Err(format!("expected {} bytes but got more", entry.size));
+        } else if data.len() as u64 != entry.size {
+            return Err(format!(
+                "expected {} bytes but got {}",
+                entry.size,
+                data.len()
+            ));
+        }
+        Ok(data)
+    }
+}
+
+// Builds an archive at a path, files are compressed and written as they're added
+pub struct PakWriter {
+    file: std::io::BufWriter<fs::File>,
+    offset: u64,
+    entries: BTreeMap<String, Entry>,
+}
+
+impl PakWriter {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let mut file =
+            std::io::BufWriter::new(fs::File::create(path).map_err(|err| err.to_string())?);
+        // Filled in by finish
+        file.write_all(&[0; HEADER_SIZE as usize])
+            .map_err(|err| err.to_string())?;
+        Ok(Self {
+            file,
+            offset: HEADER_SIZE,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    // name should be a path relative to the game directory with forward slashes, like
+    // models/test.obj
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<&Entry, String> {
+        if self.entries.contains_key(name) {
+            return Err(format!("{name} is already in the archive"));
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(data).map_err(|err| err.to_string())?;
+        let compressed = encoder.finish().map_err(|err| err.to_string())?;
+        let (method, stored) = if compressed.len() < data.len() {
+            (Method::Zlib, compressed.as_slice())
+        } else {
+            (Method::Stored, data)
+        };
+
+        self.file.write_all(stored).map_err(|err| err.to_string())?;
+        let entry = Entry {
+            method,
+            offset: self.offset,
+            stored_size: stored.len() as u64,
+            size: data.len() as u64,
+        };
+        self.offset += entry.stored_size;
+        Ok(self.entries.entry(String::from(name)).or_insert(entry))
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        let mut directory = Vec::new();
+        for (name, entry) in &self.entries {
+            directory.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+            let method: u32 = match entry.method {
+                Method::Stored => 0,
+                Method::Zlib => 1,
+            };
+            directory.extend_from_slice(&method.to_le_bytes());
+            directory.extend_from_slice(&entry.offset.to_le_bytes());
+            directory.extend_from_slice(&entry.stored_size.to_le_bytes());
+            directory.extend_from_slice(&entry.size.to_le_bytes());
+        }
+
+        let mut header = Vec::from(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        header.extend_from_slice(&self.offset.to_le_bytes());
+
+        let file = &mut self.file;
+        file.write_all(&directory)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(&header))
+            .and_then(|_| file.flush())
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A pak in the temporary directory with a stored file and a compressed one, which is
+    // last so its size is the end of the file
+    fn write_pak(name: &str) -> String {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "purpl-{}-{name}.{PAK_EXTENSION}",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned();
+        let mut writer = PakWriter::create(&path).unwrap();
+        let stored: Vec<u8> = (0..200u32).map(|i| (i * 7919 % 251) as u8).collect();
+        assert_eq!(writer.add("a.bin", &stored).unwrap().method, Method::Stored);
+        assert_eq!(
+            writer.add("z.txt", &[b'z'; 5000]).unwrap().method,
+            Method::Zlib
+        );
+        writer.finish().unwrap();
+        path
+    }
+
+    fn set_last_size(path: &str, size: u64) {
+        let mut data = fs::read(path).unwrap();
+        let end = data.len();
+        data[end - 8..].copy_from_slice(&size.to_le_bytes());
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn sizes_have_to_match() {
+        let path = write_pak("sizes");
+        let pak = Pak::open(&path).unwrap();
+        assert_eq!(pak.read("a.bin").unwrap().unwrap().len(), 200);
+        assert_eq!(pak.read("z.txt").unwrap().unwrap(), vec![b'z'; 5000]);
+
+        set_last_size(&path, 4999);
+        let pak = Pak::open(&path).unwrap();
+        assert_eq!(
+            pak.read("z.txt").unwrap(),
+            Err(String::from("expected 4999 bytes but got more"))
+        );
+
+        set_last_size(&path, 5001);
+        let pak = Pak::open(&path).unwrap();
+        assert_eq!(
+            pak.read("z.txt").unwrap(),
+            Err(String::from("expected 5001 bytes but got 5000"))
+        );
+
+        // Much more than the stored data could be isn't opened at all
+        set_last_size(&path, u64::MAX);
+        assert!(Pak::open(&path).unwrap_err().starts_with("z.txt is"));
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -13,6 +13,7 @@
 mod engine;
 mod game;
 mod platform;
+mod player;
 
 use engine::rendersystem::Renderable;
 
@@ -32,32 +33,114 @@ pub struct Args {
     wait_for_debugger: bool,
     #[cfg_attr(not(any(macos, ios)), arg(short, long, default_value_t = engine::rendersystem::RenderApi::Vulkan))]
     render_api: engine::rendersystem::RenderApi,
+    // Clamped to what the GPU supports, 1 turns it off
+    #[arg(short, long, default_value_t = 4)]
+    msaa: u32,
+    // Loaded after the ones in mods.cfg, can be given more than once
+    #[arg(long = "mod")]
+    mods: Vec<String>,
+    // Runs a server with no window or renderer instead, see engine::dedicated
+    #[arg(long, default_value_t = false)]
+    dedicated: bool,
+    #[arg(long, default_value_t = engine::net::DEFAULT_PORT)]
+    port: u16,
+    // Snapshots a second the dedicated server sends
+    #[arg(long, default_value_t = 30)]
+    tick_rate: u32,
+    #[arg(long, default_value_t = 16)]
+    max_clients: usize,
+    // The level the dedicated server starts on
+    #[arg(long)]
+    map: Option<String>,
+    // A server to play on, a host name or IP with a port
+    #[arg(long)]
+    connect: Option<String>,
+    // What the server calls this client
+    #[arg(long, default_value_t = String::from("player"))]
+    name: String,
+    // Writes input to a file so it can be played back, see engine::replay
+    #[arg(long, default_value_t = false)]
+    record: bool,
+    // A recording to play back, either a path or a name in the replays directory
+    #[arg(long)]
+    replay: Option<String>,
+    // With --replay, plays it back without a window or renderer
+    #[arg(long, default_value_t = false)]
+    headless: bool,
+    // With --replay, runs steps as fast as it can instead of in real time
+    #[arg(long, default_value_t = false)]
+    max_speed: bool,
+    // Reloads scripts when they change with --dedicated or --replay too
+    #[arg(long, default_value_t = false)]
+    reload_scripts: bool,
 
 }
 
 fn main() {
     platform::init();
-    let mut engine_state = engine::State::init(Args::parse());
-
-    let shader = engine::rendersystem::Shader::new(engine_state.render_state(), "basic").unwrap();
-    let mut image = image::RgbaImage::new(1, 1);
-    image.fill(0xFF);
-    let texture = engine::rendersystem::RenderTexture::new(engine_state.render_state(), "test", image).unwrap();
-    let material = engine::rendersystem::Material::new(engine_state.render_state(), "basic", &shader, &texture).unwrap();
-    let mut obj = tobj::load_obj("test.obj", &tobj::LoadOptions {
-        triangulate: true,
-        ..Default::default()
-    }).unwrap().0;
-    let model = engine::rendersystem::Model::new(engine_state.render_state(), "test", &mut obj, &material);
+    let args = Args::parse();
+    if args.dedicated {
+        engine::dedicated::Server::init(args).run();
+        platform::shutdown();
+        return;
+    }
+    if let (Some(replay), true) = (&args.replay, args.headless) {
+        let matched = engine::replay::run_headless(&args, replay);
+        platform::shutdown();
+        if !matched {
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mut engine_state = engine::State::init(args);
+
+    let model = engine::rendersystem::Model::load(&mut engine_state, "test").unwrap();
+    let model_entity = engine_state.world().push((
+        engine::components::Name(String::from("test model")),
+        engine::components::Transform::default(),
+        engine::components::Bounds(model.bounds().aabb),
+        engine::components::ModelRef(String::from("test")),
+    ));
+    let mut model_lod = 0;
+    let sun = engine::rendersystem::light::DirectionalLight::default();
+    let sun_transform = nalgebra::Matrix4::from_euler_angles(-0.9, 0.4, 0.0);
+
+    let mut ui = engine::ui::Ui::load("hud", "english");
+    let hud = ui.add(
+        ui.root(),
+        engine::ui::Widget::panel(engine::ui::layout::Layout::row()).class("hud"),
+    );
+    ui.add(hud, engine::ui::Widget::label("@hud.title"));
 
     engine_state.render_state().load_resources();
 
-    while engine_state.video_state().update() {
+    while engine_state.running() && engine_state.video_state().update() {
         engine_state.update(Some(for <'a> |state: &'a mut engine::State| -> () {
-            model.render(state.render_state());
+            sun.render(state.render_state(), &sun_transform);
+            let transform = state
+                .world()
+                .entry(model_entity)
+                .and_then(|entry| {
+                    entry
+                        .get_component::<engine::components::Transform>()
+                        .ok()
+                        .copied()
+                })
+                .unwrap_or_default();
+            model.render_lod(state.render_state(), &transform.matrix(), &mut model_lod);
+
+            ui.update(state.input_state_mut());
+            ui.render(state.render_state());
         }));
     }
 
+    ui.destroy(engine_state.render_state());
+    model.destroy(engine_state.render_state());
+
+    let diverged = engine_state.replay_diverged();
     engine_state.shutdown();
     platform::shutdown();
+    if diverged {
+        std::process::exit(1);
+    }
 }
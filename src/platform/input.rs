@@ -0,0 +1,171 @@
+// Keys by where they are on a US layout, text input comes separately as Event::Text
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    Num0,
+    Num1,
+    Num2,
+    Num3,
+    Num4,
+    Num5,
+    Num6,
+    Num7,
+    Num8,
+    Num9,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    Escape,
+    Enter,
+    Tab,
+    Backspace,
+    Space,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Left,
+    Right,
+    Up,
+    Down,
+    Minus,
+    Equals,
+    Grave,
+    Shift,
+    Control,
+    Alt,
+}
+
+impl Key {
+    // Every key in the order they're declared, for saving them as numbers
+    pub const ALL: [Self; 69] = [
+        Self::A,
+        Self::B,
+        Self::C,
+        Self::D,
+        Self::E,
+        Self::F,
+        Self::G,
+        Self::H,
+        Self::I,
+        Self::J,
+        Self::K,
+        Self::L,
+        Self::M,
+        Self::N,
+        Self::O,
+        Self::P,
+        Self::Q,
+        Self::R,
+        Self::S,
+        Self::T,
+        Self::U,
+        Self::V,
+        Self::W,
+        Self::X,
+        Self::Y,
+        Self::Z,
+        Self::Num0,
+        Self::Num1,
+        Self::Num2,
+        Self::Num3,
+        Self::Num4,
+        Self::Num5,
+        Self::Num6,
+        Self::Num7,
+        Self::Num8,
+        Self::Num9,
+        Self::F1,
+        Self::F2,
+        Self::F3,
+        Self::F4,
+        Self::F5,
+        Self::F6,
+        Self::F7,
+        Self::F8,
+        Self::F9,
+        Self::F10,
+        Self::F11,
+        Self::F12,
+        Self::Escape,
+        Self::Enter,
+        Self::Tab,
+        Self::Backspace,
+        Self::Space,
+        Self::Insert,
+        Self::Delete,
+        Self::Home,
+        Self::End,
+        Self::PageUp,
+        Self::PageDown,
+        Self::Left,
+        Self::Right,
+        Self::Up,
+        Self::Down,
+        Self::Minus,
+        Self::Equals,
+        Self::Grave,
+        Self::Shift,
+        Self::Control,
+        Self::Alt,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+impl MouseButton {
+    pub const ALL: [Self; 3] = [Self::Left, Self::Right, Self::Middle];
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Key { key: Key, pressed: bool },
+    // Already has the layout and shift applied
+    Text(char),
+    // In pixels from the top left of the window
+    MouseMoved { x: f32, y: f32 },
+    MouseButton { button: MouseButton, pressed: bool },
+    // In notches, positive is away from the user
+    MouseWheel(f32),
+}
@@ -1,5 +1,10 @@
+pub mod input;
 pub mod video;
 
+use once_cell::sync::OnceCell;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
 #[cfg(unix)]
 mod unix;
 #[cfg(any(windows, xbox))]
@@ -23,3 +28,58 @@ pub fn shutdown() {
 pub fn have_debugger() -> bool {
     unsafe { platform_impl::have_debugger() }
 }
+
+// Where a fatal signal or exception's report goes, with a null on the end, and its first line
+static CRASH_PATH: OnceCell<Vec<u8>> = OnceCell::new();
+static CRASH_HEADING: OnceCell<Vec<u8>> = OnceCell::new();
+// The rest of the report, formatted ahead of time
+static CRASH_TEXT: AtomicPtr<Vec<u8>> = AtomicPtr::new(ptr::null_mut());
+static CRASHED: AtomicBool = AtomicBool::new(false);
+
+// A fatal signal or exception writes a report to path with the heading, what happened, the
+// last text given to set_crash_text and the raw addresses on the stack, and prints a summary to
+// stderr. Whatever crashed might have been allocating or holding a lock, so the handler can't do
+// either and everything it writes has to be ready beforehand.
+pub fn install_crash_handler(path: &str, heading: &str) {
+    let mut path = Vec::from(path.as_bytes());
+    path.push(0);
+    let _ = CRASH_PATH.set(path);
+    let _ = CRASH_HEADING.set(Vec::from(heading.as_bytes()));
+    unsafe { platform_impl::install_crash_handler() }
+}
+
+pub fn set_crash_text(text: String) {
+    let text = Box::into_raw(Box::new(text.into_bytes()));
+    // The old text is leaked, since a handler could be writing it out
+    CRASH_TEXT.swap(text, Ordering::SeqCst);
+}
+
+// Only the first crash gets a report, so a panic that aborts doesn't get a second one from the
+// signal
+pub fn begin_crash_report() -> bool {
+    !CRASHED.swap(true, Ordering::SeqCst)
+}
+
+// The path with its null, the heading and the text, for the handlers
+fn crash_report() -> (&'static [u8], &'static [u8], &'static [u8]) {
+    let text = CRASH_TEXT.load(Ordering::SeqCst);
+    (
+        CRASH_PATH.get().map_or(&[], Vec::as_slice),
+        CRASH_HEADING.get().map_or(&[], Vec::as_slice),
+        if text.is_null() {
+            &[]
+        } else {
+            unsafe { (*text).as_slice() }
+        },
+    )
+}
+
+// Call at the start of threads the engine spawns, so a stack overflow on them gets reported
+pub fn init_thread() {
+    unsafe { platform_impl::init_thread() }
+}
+
+// A message box where there's a way to show one
+pub fn show_error(title: &str, message: &str) {
+    unsafe { platform_impl::show_error(title, message) }
+}
@@ -1,5 +1,12 @@
 pub mod video;
 
+use std::{mem, ptr};
+
+// Big enough for backtrace, which Rust's alternate signal stack isn't
+const SIGNAL_STACK_SIZE: usize = 64 * 1024;
+// Stack addresses in a crash report
+const MAX_FRAMES: usize = 64;
+
 pub unsafe fn init() {}
 
 pub unsafe fn shutdown() {}
@@ -7,3 +14,158 @@ pub unsafe fn shutdown() {}
 pub unsafe fn have_debugger() -> bool {
     false
 }
+
+// Everything from here to install_crash_handler runs in the signal handler, so it can only use
+// async-signal-safe calls
+
+fn write_all(fd: libc::c_int, mut data: &[u8]) {
+    while !data.is_empty() {
+        let written = unsafe { libc::write(fd, data.as_ptr() as *const libc::c_void, data.len()) };
+        if written <= 0 {
+            break;
+        }
+        data = &data[written as usize..];
+    }
+}
+
+fn write_address(fd: libc::c_int, address: usize) {
+    let mut text = *b"0x0000000000000000\n";
+    for (index, digit) in text[2..18].iter_mut().enumerate() {
+        *digit = b"0123456789abcdef"[(address >> ((15 - index) * 4)) & 0xF];
+    }
+    write_all(fd, &text);
+}
+
+#[cfg(any(all(target_os = "linux", target_env = "gnu"), target_os = "macos"))]
+fn write_stack(fd: libc::c_int) {
+    let mut frames = [ptr::null_mut(); MAX_FRAMES];
+    let count = unsafe { libc::backtrace(frames.as_mut_ptr(), MAX_FRAMES as libc::c_int) };
+    write_all(fd, b"\nStack:\n");
+    for frame in &frames[..count.max(0) as usize] {
+        write_address(fd, *frame as usize);
+    }
+}
+
+#[cfg(not(any(all(target_os = "linux", target_env = "gnu"), target_os = "macos")))]
+fn write_stack(_fd: libc::c_int) {}
+
+// Where everything was loaded, which the stack addresses need to be matched to symbols
+#[cfg(target_os = "linux")]
+fn write_memory_map(fd: libc::c_int) {
+    let maps = unsafe {
+        libc::open(
+            c"/proc/self/maps".as_ptr(),
+            libc::O_RDONLY | libc::O_CLOEXEC,
+        )
+    };
+    if maps < 0 {
+        return;
+    }
+    write_all(fd, b"\nMemory map:\n");
+    let mut buffer = [0u8; 1024];
+    loop {
+        let read =
+            unsafe { libc::read(maps, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+        if read <= 0 {
+            break;
+        }
+        write_all(fd, &buffer[..read as usize]);
+    }
+    unsafe { libc::close(maps) };
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_memory_map(_fd: libc::c_int) {}
+
+extern "C" fn fatal_signal(signal: libc::c_int) {
+    let description: &[u8] = match signal {
+        libc::SIGSEGV => b"segmentation fault",
+        libc::SIGBUS => b"bus error",
+        libc::SIGILL => b"illegal instruction",
+        libc::SIGFPE => b"floating point exception",
+        libc::SIGABRT => b"aborted",
+        _ => b"fatal signal",
+    };
+
+    if super::begin_crash_report() {
+        let (path, heading, text) = super::crash_report();
+        let fd = unsafe {
+            libc::open(
+                path.as_ptr() as *const libc::c_char,
+                libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC,
+                0o644,
+            )
+        };
+        if fd >= 0 {
+            write_all(fd, heading);
+            write_all(fd, b" crashed: ");
+            write_all(fd, description);
+            write_all(fd, b"\n\n");
+            write_all(fd, text);
+            write_stack(fd);
+            write_memory_map(fd);
+            unsafe { libc::close(fd) };
+        }
+
+        write_all(libc::STDERR_FILENO, heading);
+        write_all(libc::STDERR_FILENO, b" crashed: ");
+        write_all(libc::STDERR_FILENO, description);
+        if fd >= 0 {
+            write_all(libc::STDERR_FILENO, b"\n\nA report was saved to ");
+            write_all(libc::STDERR_FILENO, &path[..path.len().saturating_sub(1)]);
+        }
+        write_all(libc::STDERR_FILENO, b"\n");
+    }
+
+    // The default action still kills the process and dumps core
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+// Handlers run on an alternate stack so stack overflows can be reported too. It's leaked, since
+// it has to be there until the thread's last signal.
+unsafe fn install_signal_stack() {
+    let stack = Box::leak(vec![0u8; SIGNAL_STACK_SIZE].into_boxed_slice());
+    libc::sigaltstack(
+        &libc::stack_t {
+            ss_sp: stack.as_mut_ptr() as *mut libc::c_void,
+            ss_flags: 0,
+            ss_size: SIGNAL_STACK_SIZE,
+        },
+        ptr::null_mut(),
+    );
+}
+
+pub unsafe fn install_crash_handler() {
+    install_signal_stack();
+
+    // The first backtrace loads the unwinder, which can't happen in the handler
+    #[cfg(any(all(target_os = "linux", target_env = "gnu"), target_os = "macos"))]
+    {
+        let mut frames = [ptr::null_mut(); 1];
+        libc::backtrace(frames.as_mut_ptr(), 1);
+    }
+
+    let mut action: libc::sigaction = mem::zeroed();
+    action.sa_sigaction = fatal_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_ONSTACK;
+    libc::sigemptyset(&mut action.sa_mask);
+    for signal in [
+        libc::SIGSEGV,
+        libc::SIGBUS,
+        libc::SIGILL,
+        libc::SIGFPE,
+        libc::SIGABRT,
+    ] {
+        libc::sigaction(signal, &action, ptr::null_mut());
+    }
+}
+
+pub unsafe fn init_thread() {
+    install_signal_stack();
+}
+
+// There's nothing to show a message box with, so the summary on stderr has to do
+pub unsafe fn show_error(_title: &str, _message: &str) {}
@@ -1,3 +1,4 @@
+use super::super::input::{Event, Key, MouseButton};
 use ash::{extensions, vk};
 use log::{debug, info};
 use std::{ffi, mem};
@@ -14,6 +15,12 @@ pub struct State {
     resized: bool,
     focused: bool,
     closed: bool,
+
+    // Each keycode's keysyms, starting at min_keycode
+    keysyms: Vec<x::Keysym>,
+    keysyms_per_keycode: usize,
+    min_keycode: u8,
+    events: Vec<Event>,
 }
 
 fn get_xcb_atom(connection: &xcb::Connection, name: &str) -> x::Atom {
@@ -24,6 +31,148 @@ fn get_xcb_atom(connection: &xcb::Connection, name: &str) -> x::Atom {
     connection.wait_for_reply(reply).unwrap().atom()
 }
 
+fn keysym_to_key(keysym: x::Keysym) -> Option<Key> {
+    const LETTERS: [Key; 26] = [
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+        Key::G,
+        Key::H,
+        Key::I,
+        Key::J,
+        Key::K,
+        Key::L,
+        Key::M,
+        Key::N,
+        Key::O,
+        Key::P,
+        Key::Q,
+        Key::R,
+        Key::S,
+        Key::T,
+        Key::U,
+        Key::V,
+        Key::W,
+        Key::X,
+        Key::Y,
+        Key::Z,
+    ];
+    const DIGITS: [Key; 10] = [
+        Key::Num0,
+        Key::Num1,
+        Key::Num2,
+        Key::Num3,
+        Key::Num4,
+        Key::Num5,
+        Key::Num6,
+        Key::Num7,
+        Key::Num8,
+        Key::Num9,
+    ];
+    const FUNCTION_KEYS: [Key; 12] = [
+        Key::F1,
+        Key::F2,
+        Key::F3,
+        Key::F4,
+        Key::F5,
+        Key::F6,
+        Key::F7,
+        Key::F8,
+        Key::F9,
+        Key::F10,
+        Key::F11,
+        Key::F12,
+    ];
+
+    // From X11/keysymdef.h
+    Some(match keysym {
+        0x61..=0x7a => LETTERS[(keysym - 0x61) as usize],
+        0x41..=0x5a => LETTERS[(keysym - 0x41) as usize],
+        0x30..=0x39 => DIGITS[(keysym - 0x30) as usize],
+        0xffbe..=0xffc9 => FUNCTION_KEYS[(keysym - 0xffbe) as usize],
+        0xff1b => Key::Escape,
+        0xff0d | 0xff8d => Key::Enter,
+        0xff09 => Key::Tab,
+        0xff08 => Key::Backspace,
+        0x20 => Key::Space,
+        0xff63 => Key::Insert,
+        0xffff => Key::Delete,
+        0xff50 => Key::Home,
+        0xff57 => Key::End,
+        0xff55 => Key::PageUp,
+        0xff56 => Key::PageDown,
+        0xff51 => Key::Left,
+        0xff52 => Key::Up,
+        0xff53 => Key::Right,
+        0xff54 => Key::Down,
+        0x2d => Key::Minus,
+        0x3d => Key::Equals,
+        0x60 => Key::Grave,
+        0xffe1 | 0xffe2 => Key::Shift,
+        0xffe3 | 0xffe4 => Key::Control,
+        0xffe9 | 0xffea => Key::Alt,
+        _ => return None,
+    })
+}
+
+impl State {
+    fn keysym(&self, keycode: x::Keycode, column: usize) -> x::Keysym {
+        let index = (keycode - self.min_keycode) as usize * self.keysyms_per_keycode + column;
+        match self.keysyms.get(index) {
+            Some(keysym) if column < self.keysyms_per_keycode => *keysym,
+            _ => 0,
+        }
+    }
+
+    fn key_event(&mut self, keycode: x::Keycode, state: x::KeyButMask, pressed: bool) {
+        if let Some(key) = keysym_to_key(self.keysym(keycode, 0)) {
+            self.events.push(Event::Key { key, pressed });
+        }
+
+        // Text is only the printable Latin-1 range, which is the same in keysyms
+        if pressed && !state.contains(x::KeyButMask::CONTROL) {
+            let shift = state.contains(x::KeyButMask::SHIFT);
+            let mut keysym = self.keysym(keycode, shift as usize);
+            if keysym == 0 {
+                keysym = self.keysym(keycode, 0);
+            }
+            if let Some(mut character) = char::from_u32(keysym)
+                .filter(|character| matches!(*character as u32, 0x20..=0x7e | 0xa0..=0xff))
+            {
+                if state.contains(x::KeyButMask::LOCK) && character.is_ascii_alphabetic() {
+                    character = if shift {
+                        character.to_ascii_lowercase()
+                    } else {
+                        character.to_ascii_uppercase()
+                    };
+                }
+                self.events.push(Event::Text(character));
+            }
+        }
+    }
+
+    fn button_event(&mut self, button: x::Button, pressed: bool) {
+        // The wheel is buttons 4 and 5, which only matter when they're pressed
+        let button = match button {
+            1 => MouseButton::Left,
+            2 => MouseButton::Middle,
+            3 => MouseButton::Right,
+            4 | 5 => {
+                if pressed {
+                    self.events
+                        .push(Event::MouseWheel(if button == 4 { 1.0 } else { -1.0 }));
+                }
+                return;
+            }
+            _ => return,
+        };
+        self.events.push(Event::MouseButton { button, pressed });
+    }
+}
+
 impl super::super::video::VideoBackend for State {
     fn init() -> Box<dyn super::super::video::VideoBackend> {
         info!("XCB video initialization started");
@@ -59,7 +208,15 @@ impl super::super::video::VideoBackend for State {
             visual: screen.root_visual(),
             value_list: &[
                 x::Cw::BackPixel(screen.black_pixel()),
-                x::Cw::EventMask(x::EventMask::FOCUS_CHANGE | x::EventMask::STRUCTURE_NOTIFY),
+                x::Cw::EventMask(
+                    x::EventMask::FOCUS_CHANGE
+                        | x::EventMask::STRUCTURE_NOTIFY
+                        | x::EventMask::KEY_PRESS
+                        | x::EventMask::KEY_RELEASE
+                        | x::EventMask::BUTTON_PRESS
+                        | x::EventMask::BUTTON_RELEASE
+                        | x::EventMask::POINTER_MOTION,
+                ),
             ],
         });
         if connection.check_request(cookie).is_err() {
@@ -88,6 +245,17 @@ impl super::super::video::VideoBackend for State {
             data: &[delete_data],
         });
 
+        let setup = connection.get_setup();
+        let (min_keycode, max_keycode) = (setup.min_keycode(), setup.max_keycode());
+        let reply = connection.send_request(&x::GetKeyboardMapping {
+            first_keycode: min_keycode,
+            count: max_keycode - min_keycode + 1,
+        });
+        let mapping = match connection.wait_for_reply(reply) {
+            Ok(mapping) => mapping,
+            Err(err) => panic!("Failed to get keyboard mapping: {err}"),
+        };
+
         connection.send_request(&x::MapWindow { window });
 
         if connection.flush().is_err() {
@@ -105,11 +273,15 @@ impl super::super::video::VideoBackend for State {
             resized: false,
             focused: true,
             closed: false,
+            keysyms: mapping.keysyms().to_vec(),
+            keysyms_per_keycode: mapping.keysyms_per_keycode() as usize,
+            min_keycode,
+            events: Vec::new(),
         })
     }
 
     fn update(&mut self) -> bool {
-        if let Ok(Some(xcb::Event::X(event))) = self.connection.poll_for_event() {
+        while let Ok(Some(xcb::Event::X(event))) = self.connection.poll_for_event() {
             match event {
                 x::Event::ConfigureNotify(ev) => {
                     let new_width = ev.width() as u32;
@@ -133,6 +305,14 @@ impl super::super::video::VideoBackend for State {
                     info!("Window unfocused");
                     self.focused = false;
                 }
+                x::Event::KeyPress(ev) => self.key_event(ev.detail(), ev.state(), true),
+                x::Event::KeyRelease(ev) => self.key_event(ev.detail(), ev.state(), false),
+                x::Event::ButtonPress(ev) => self.button_event(ev.detail(), true),
+                x::Event::ButtonRelease(ev) => self.button_event(ev.detail(), false),
+                x::Event::MotionNotify(ev) => self.events.push(Event::MouseMoved {
+                    x: ev.event_x() as f32,
+                    y: ev.event_y() as f32,
+                }),
                 x::Event::ClientMessage(ev) => {
                     if let x::ClientMessageData::Data32(atom) = ev.data() {
                         let delete_atom = get_xcb_atom(&self.connection, "WM_DELETE_WINDOW");
@@ -172,6 +352,10 @@ impl super::super::video::VideoBackend for State {
         self.focused
     }
 
+    fn take_events(&mut self) -> Vec<Event> {
+        mem::take(&mut self.events)
+    }
+
     fn create_vulkan_surface(
         &self,
         entry: &ash::Entry,
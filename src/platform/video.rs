@@ -11,6 +11,8 @@ pub trait VideoBackend {
     fn get_size(&self) -> (u32, u32);
     fn focused(&self) -> bool;
     fn resized(&mut self) -> bool;
+    // Everything that happened since the last call
+    fn take_events(&mut self) -> Vec<super::input::Event>;
 
     #[cfg(any(windows, xbox))]
     fn get_handle(&self) -> usize;
@@ -1,6 +1,18 @@
 pub mod video;
 
+use std::{ffi, ptr};
+use windows_sys::Win32::Foundation::*;
+use windows_sys::Win32::Storage::FileSystem::*;
+use windows_sys::Win32::System::Console::*;
 use windows_sys::Win32::System::Diagnostics::Debug::*;
+use windows_sys::Win32::System::LibraryLoader::*;
+use windows_sys::Win32::System::Threading::*;
+use windows_sys::Win32::UI::WindowsAndMessaging::*;
+
+// Stack kept back for the exception filter when a thread overflows its stack
+const STACK_GUARANTEE: u32 = 64 * 1024;
+// Stack addresses in a crash report
+const MAX_FRAMES: usize = 62;
 
 pub unsafe fn init() {}
 
@@ -9,3 +21,120 @@ pub unsafe fn shutdown() {}
 pub unsafe fn have_debugger() -> bool {
     IsDebuggerPresent() != 0
 }
+
+// Everything from here to install_crash_handler runs in the exception filter, which can't
+// allocate or take locks since whatever crashed might have been doing that
+
+unsafe fn write_all(file: HANDLE, mut data: &[u8]) {
+    while !data.is_empty() {
+        let mut written = 0;
+        if WriteFile(
+            file,
+            data.as_ptr(),
+            data.len() as u32,
+            &mut written,
+            ptr::null_mut(),
+        ) == 0
+            || written == 0
+        {
+            break;
+        }
+        data = &data[written as usize..];
+    }
+}
+
+unsafe fn write_address(file: HANDLE, address: usize) {
+    let mut text = *b"0x0000000000000000\r\n";
+    for (index, digit) in text[2..18].iter_mut().enumerate() {
+        *digit = b"0123456789ABCDEF"[(address >> ((15 - index) * 4)) & 0xF];
+    }
+    write_all(file, &text);
+}
+
+unsafe extern "system" fn unhandled_exception(info: *const EXCEPTION_POINTERS) -> i32 {
+    let record = &*(*info).ExceptionRecord;
+    let description: &[u8] = match record.ExceptionCode {
+        EXCEPTION_ACCESS_VIOLATION => b"access violation",
+        EXCEPTION_STACK_OVERFLOW => b"stack overflow",
+        EXCEPTION_ILLEGAL_INSTRUCTION => b"illegal instruction",
+        EXCEPTION_INT_DIVIDE_BY_ZERO => b"integer divide by zero",
+        _ => b"unhandled exception",
+    };
+
+    if super::begin_crash_report() {
+        let (path, heading, text) = super::crash_report();
+        let file = CreateFileA(
+            path.as_ptr(),
+            FILE_GENERIC_WRITE,
+            FILE_SHARE_READ,
+            ptr::null(),
+            CREATE_ALWAYS,
+            FILE_ATTRIBUTE_NORMAL,
+            0,
+        );
+        if file != INVALID_HANDLE_VALUE {
+            write_all(file, heading);
+            write_all(file, b" crashed: ");
+            write_all(file, description);
+            write_all(file, b"\r\nCode: ");
+            write_address(file, record.ExceptionCode as u32 as usize);
+            write_all(file, b"Address: ");
+            write_address(file, record.ExceptionAddress as usize);
+            // The stack addresses need this to be matched to symbols
+            write_all(file, b"Executable base: ");
+            write_address(file, GetModuleHandleA(ptr::null()) as usize);
+            write_all(file, b"\r\n");
+            write_all(file, text);
+
+            let mut frames = [ptr::null_mut(); MAX_FRAMES];
+            let count = RtlCaptureStackBackTrace(
+                0,
+                MAX_FRAMES as u32,
+                frames.as_mut_ptr(),
+                ptr::null_mut(),
+            );
+            write_all(file, b"\r\nStack:\r\n");
+            for frame in &frames[..count as usize] {
+                write_address(file, *frame as usize);
+            }
+            CloseHandle(file);
+        }
+
+        let stderr = GetStdHandle(STD_ERROR_HANDLE);
+        if stderr != 0 && stderr != INVALID_HANDLE_VALUE {
+            write_all(stderr, heading);
+            write_all(stderr, b" crashed: ");
+            write_all(stderr, description);
+            if file != INVALID_HANDLE_VALUE {
+                write_all(stderr, b"\r\n\r\nA report was saved to ");
+                write_all(stderr, &path[..path.len().saturating_sub(1)]);
+            }
+            write_all(stderr, b"\r\n");
+        }
+    }
+
+    // EXCEPTION_CONTINUE_SEARCH, so Windows Error Reporting still gets it
+    0
+}
+
+pub unsafe fn install_crash_handler() {
+    init_thread();
+    SetUnhandledExceptionFilter(Some(unhandled_exception));
+}
+
+pub unsafe fn init_thread() {
+    let mut size = STACK_GUARANTEE;
+    SetThreadStackGuarantee(&mut size);
+}
+
+pub unsafe fn show_error(title: &str, message: &str) {
+    let (Ok(title), Ok(message)) = (ffi::CString::new(title), ffi::CString::new(message)) else {
+        return;
+    };
+    MessageBoxA(
+        0,
+        message.as_ptr() as *const u8,
+        title.as_ptr() as *const u8,
+        MB_OK | MB_ICONERROR,
+    );
+}
@@ -1,9 +1,11 @@
+use super::super::input::{Event, Key, MouseButton};
 #[cfg(not(xbox))]
 use ash::{extensions, vk};
 use log::{debug, info};
 use std::{ffi, mem, ptr};
 use windows_sys::Win32::Foundation::*;
 use windows_sys::Win32::System::LibraryLoader::*;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::*;
 use windows_sys::Win32::UI::WindowsAndMessaging::*;
 
 const IDI_ICON1: u32 = 103;
@@ -23,6 +25,93 @@ pub struct State {
     resized: bool,
     focused: bool,
     closed: bool,
+    events: Vec<Event>,
+}
+
+fn virtual_key_to_key(virtual_key: u16) -> Option<Key> {
+    const LETTERS: [Key; 26] = [
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+        Key::G,
+        Key::H,
+        Key::I,
+        Key::J,
+        Key::K,
+        Key::L,
+        Key::M,
+        Key::N,
+        Key::O,
+        Key::P,
+        Key::Q,
+        Key::R,
+        Key::S,
+        Key::T,
+        Key::U,
+        Key::V,
+        Key::W,
+        Key::X,
+        Key::Y,
+        Key::Z,
+    ];
+    const DIGITS: [Key; 10] = [
+        Key::Num0,
+        Key::Num1,
+        Key::Num2,
+        Key::Num3,
+        Key::Num4,
+        Key::Num5,
+        Key::Num6,
+        Key::Num7,
+        Key::Num8,
+        Key::Num9,
+    ];
+    const FUNCTION_KEYS: [Key; 12] = [
+        Key::F1,
+        Key::F2,
+        Key::F3,
+        Key::F4,
+        Key::F5,
+        Key::F6,
+        Key::F7,
+        Key::F8,
+        Key::F9,
+        Key::F10,
+        Key::F11,
+        Key::F12,
+    ];
+
+    // Letters and digits are their ASCII values
+    Some(match virtual_key {
+        0x41..=0x5A => LETTERS[(virtual_key - 0x41) as usize],
+        0x30..=0x39 => DIGITS[(virtual_key - 0x30) as usize],
+        VK_F1..=VK_F12 => FUNCTION_KEYS[(virtual_key - VK_F1) as usize],
+        VK_ESCAPE => Key::Escape,
+        VK_RETURN => Key::Enter,
+        VK_TAB => Key::Tab,
+        VK_BACK => Key::Backspace,
+        VK_SPACE => Key::Space,
+        VK_INSERT => Key::Insert,
+        VK_DELETE => Key::Delete,
+        VK_HOME => Key::Home,
+        VK_END => Key::End,
+        VK_PRIOR => Key::PageUp,
+        VK_NEXT => Key::PageDown,
+        VK_LEFT => Key::Left,
+        VK_RIGHT => Key::Right,
+        VK_UP => Key::Up,
+        VK_DOWN => Key::Down,
+        VK_OEM_MINUS => Key::Minus,
+        VK_OEM_PLUS => Key::Equals,
+        VK_OEM_3 => Key::Grave,
+        VK_SHIFT => Key::Shift,
+        VK_CONTROL => Key::Control,
+        VK_MENU => Key::Alt,
+        _ => return None,
+    })
 }
 
 impl State {
@@ -82,6 +171,58 @@ impl State {
                     self_.closed = true;
                     0
                 }
+                // System keys are still passed on so things like Alt+F4 work
+                WM_KEYDOWN | WM_KEYUP | WM_SYSKEYDOWN | WM_SYSKEYUP => {
+                    if let Some(key) = virtual_key_to_key(wparam as u16) {
+                        self_.events.push(Event::Key {
+                            key,
+                            pressed: message == WM_KEYDOWN || message == WM_SYSKEYDOWN,
+                        });
+                    }
+                    if message == WM_SYSKEYDOWN || message == WM_SYSKEYUP {
+                        DefWindowProcA(message_window, message, wparam, lparam)
+                    } else {
+                        0
+                    }
+                }
+                WM_CHAR => {
+                    if let Some(character) =
+                        char::from_u32(wparam as u32).filter(|character| !character.is_control())
+                    {
+                        self_.events.push(Event::Text(character));
+                    }
+                    0
+                }
+                WM_MOUSEMOVE => {
+                    self_.events.push(Event::MouseMoved {
+                        x: (lparam & 0xFFFF) as i16 as f32,
+                        y: ((lparam >> 16) & 0xFFFF) as i16 as f32,
+                    });
+                    0
+                }
+                WM_LBUTTONDOWN | WM_LBUTTONUP | WM_RBUTTONDOWN | WM_RBUTTONUP | WM_MBUTTONDOWN
+                | WM_MBUTTONUP => {
+                    let button = match message {
+                        WM_LBUTTONDOWN | WM_LBUTTONUP => MouseButton::Left,
+                        WM_RBUTTONDOWN | WM_RBUTTONUP => MouseButton::Right,
+                        _ => MouseButton::Middle,
+                    };
+                    self_.events.push(Event::MouseButton {
+                        button,
+                        pressed: matches!(
+                            message,
+                            WM_LBUTTONDOWN | WM_RBUTTONDOWN | WM_MBUTTONDOWN
+                        ),
+                    });
+                    0
+                }
+                WM_MOUSEWHEEL => {
+                    let delta = ((wparam >> 16) & 0xFFFF) as i16;
+                    self_
+                        .events
+                        .push(Event::MouseWheel(delta as f32 / WHEEL_DELTA as f32));
+                    0
+                }
                 _ => DefWindowProcA(message_window, message, wparam, lparam),
             }
         } else {
@@ -194,6 +335,7 @@ impl super::super::video::VideoBackend for State {
             resized: false,
             focused: true,
             closed: false,
+            events: Vec::new(),
         })
     }
 
@@ -247,6 +389,10 @@ impl super::super::video::VideoBackend for State {
         self.focused
     }
 
+    fn take_events(&mut self) -> Vec<Event> {
+        mem::take(&mut self.events)
+    }
+
     fn get_handle(&self) -> usize {
         self.window as usize
     }
@@ -0,0 +1,47 @@
+// How players move and which actions they have. This isn't in game.rs, since build.rs
+// includes that and can't see the engine.
+
+use crate::engine::{components, input, net};
+use crate::platform::input::Key;
+use nalgebra::Vector3;
+
+// Actions sent to the server in net::Command, in bit order
+pub const NET_ACTIONS: &[&str] = &["forward", "back", "left", "right", "up", "down"];
+// Units a second
+const PLAYER_SPEED: f32 = 5.0;
+
+// Default keys for NET_ACTIONS, which the player can rebind
+pub fn bind_actions(input: &mut input::State) {
+    let keys = [Key::W, Key::S, Key::A, Key::D, Key::Space, Key::Control];
+    for (action, key) in NET_ACTIONS.iter().zip(keys) {
+        input.bind(action, input::Binding::Key(key));
+    }
+}
+
+// How players move, which the server runs on their commands and clients predict with
+pub fn simulate(world: &mut legion::World, entity: legion::Entity, command: &net::Command) {
+    let Some(mut entry) = world.entry(entity) else {
+        return;
+    };
+    let Ok(transform) = entry.get_component_mut::<components::Transform>() else {
+        return;
+    };
+
+    let directions = [
+        -Vector3::z(),
+        Vector3::z(),
+        -Vector3::x(),
+        Vector3::x(),
+        Vector3::y(),
+        -Vector3::y(),
+    ];
+    let direction: Vector3<f32> = directions
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| command.action(*index))
+        .map(|(_, direction)| direction)
+        .sum();
+    if let Some(direction) = direction.try_normalize(f32::EPSILON) {
+        transform.position += transform.rotation * direction * PLAYER_SPEED * command.delta;
+    }
+}
@@ -0,0 +1,156 @@
+// Makes lower detail versions of a model for the engine's LOD system. Levels are written next
+// to the model as name.lod1.obj, name.lod2.obj and so on, which Model::load picks up. Artists
+// can also make those files themselves.
+
+mod simplify;
+
+use clap::Parser;
+use std::fmt::Write as _;
+use std::fs;
+
+#[derive(Parser, Debug)]
+struct Args {
+    // An OBJ file, normally in a game's model directory
+    model: String,
+    // How many levels to make after the original
+    #[arg(short, long, default_value_t = 3)]
+    levels: u32,
+    // How many triangles each level keeps compared to the one before it
+    #[arg(short, long, default_value_t = 0.5)]
+    ratio: f32,
+    // Collapses that move the surface further than this (in model units) are skipped, which
+    // can leave a level with more triangles than asked for
+    #[arg(short, long, default_value_t = 1.0)]
+    max_error: f32,
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(err) = run(&args) {
+        eprintln!("Failed to simplify {}: {err}", args.model);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let Some(stem) = args.model.strip_suffix(".obj") else {
+        return Err(String::from("expected an .obj file"));
+    };
+
+    let (models, library) = tobj::load_obj(
+        &args.model,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+    )
+    .map_err(|err| err.to_string())?;
+    let material_names: Vec<String> = library
+        .map(|materials| {
+            materials
+                .into_iter()
+                .map(|material| material.name)
+                .collect()
+        })
+        .unwrap_or_default();
+    // tobj doesn't say what the library was called, so it's copied from the original
+    let libraries: Vec<String> = fs::read_to_string(&args.model)
+        .map_err(|err| err.to_string())?
+        .lines()
+        .filter(|line| line.starts_with("mtllib"))
+        .map(String::from)
+        .collect();
+
+    let original: usize = models
+        .iter()
+        .map(|model| model.mesh.indices.len() / 3)
+        .sum();
+    println!("{}: {original} triangles", args.model);
+    for level in 1..=args.levels {
+        let ratio = args.ratio.powi(level as i32);
+        let mut obj = String::new();
+        for library in &libraries {
+            writeln!(obj, "{library}").unwrap();
+        }
+
+        let mut base_vertex = 1;
+        let mut triangles = 0;
+        for model in &models {
+            let mesh = &model.mesh;
+            let positions: Vec<[f32; 3]> = mesh
+                .positions
+                .chunks_exact(3)
+                .map(|position| [position[0], position[1], position[2]])
+                .collect();
+            let target = ((mesh.indices.len() / 3) as f32 * ratio) as usize;
+            let indices = simplify::simplify(&positions, &mesh.indices, target, args.max_error);
+            triangles += indices.len() / 3;
+            write_mesh(&mut obj, model, &material_names, &indices, &mut base_vertex);
+        }
+
+        let path = format!("{stem}.lod{level}.obj");
+        fs::write(&path, obj).map_err(|err| format!("failed to write {path}: {err}"))?;
+        println!("{path}: {triangles} triangles");
+    }
+
+    Ok(())
+}
+
+// Only the vertices the indices use are written. OBJ indices start at 1 and count from the
+// start of the file, which base_vertex keeps track of.
+fn write_mesh(
+    obj: &mut String,
+    model: &tobj::Model,
+    material_names: &[String],
+    indices: &[u32],
+    base_vertex: &mut usize,
+) {
+    let mesh = &model.mesh;
+    writeln!(obj, "o {}", model.name).unwrap();
+    if let Some(name) = mesh.material_id.and_then(|id| material_names.get(id)) {
+        writeln!(obj, "usemtl {name}").unwrap();
+    }
+
+    let mut remap = vec![None; mesh.positions.len() / 3];
+    let mut used = Vec::new();
+    for index in indices {
+        if remap[*index as usize].is_none() {
+            remap[*index as usize] = Some(*base_vertex + used.len());
+            used.push(*index as usize);
+        }
+    }
+
+    let has_texcoords = mesh.texcoords.len() >= mesh.positions.len() / 3 * 2;
+    let has_normals = mesh.normals.len() == mesh.positions.len();
+    for vertex in &used {
+        let p = &mesh.positions[vertex * 3..vertex * 3 + 3];
+        writeln!(obj, "v {} {} {}", p[0], p[1], p[2]).unwrap();
+        if has_texcoords {
+            let t = &mesh.texcoords[vertex * 2..vertex * 2 + 2];
+            writeln!(obj, "vt {} {}", t[0], t[1]).unwrap();
+        }
+        if has_normals {
+            let n = &mesh.normals[vertex * 3..vertex * 3 + 3];
+            writeln!(obj, "vn {} {} {}", n[0], n[1], n[2]).unwrap();
+        }
+    }
+
+    for triangle in indices.chunks_exact(3) {
+        let corners: Vec<String> = triangle
+            .iter()
+            .map(|index| {
+                let index = remap[*index as usize].unwrap();
+                match (has_texcoords, has_normals) {
+                    (true, true) => format!("{index}/{index}/{index}"),
+                    (true, false) => format!("{index}/{index}"),
+                    (false, true) => format!("{index}//{index}"),
+                    (false, false) => format!("{index}"),
+                }
+            })
+            .collect();
+        writeln!(obj, "f {}", corners.join(" ")).unwrap();
+    }
+
+    *base_vertex += used.len();
+}
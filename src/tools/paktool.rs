@@ -0,0 +1,110 @@
+// Builds and lists the pak archives the engine mounts from game and mod directories. Paths in
+// an archive are relative to the directory it was built from, so building a game directory
+// into pak0.pak and putting that in an empty game directory gives the engine the same files.
+
+// The engine's reader and writer, some of which only the engine uses
+#[allow(dead_code)]
+#[path = "../engine/vfs/pak.rs"]
+mod pak;
+
+use clap::{Parser, Subcommand};
+use std::{fs, path::Path};
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    // Puts every file under a directory into an archive, except other archives
+    Build { directory: String, output: String },
+    // Prints every file in an archive with its size
+    List { archive: String },
+}
+
+fn main() {
+    let args = Args::parse();
+    let result = match &args.command {
+        Command::Build { directory, output } => build(directory, output),
+        Command::List { archive } => list(archive),
+    };
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+// Relative paths of every file under directory, in a stable order so archives built from the
+// same files are the same
+fn collect_files(root: &Path, directory: &Path, files: &mut Vec<String>) -> Result<(), String> {
+    let mut entries: Vec<_> = fs::read_dir(directory)
+        .map_err(|err| format!("failed to list {}: {err}", directory.display()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if path
+            .extension()
+            .map_or(true, |extension| extension != pak::PAK_EXTENSION)
+        {
+            let relative = path.strip_prefix(root).unwrap();
+            let Some(relative) = relative.to_str() else {
+                eprintln!("Skipping {}, its path isn't UTF-8", path.display());
+                continue;
+            };
+            files.push(relative.replace('\\', "/"));
+        }
+    }
+    Ok(())
+}
+
+fn build(directory: &str, output: &str) -> Result<(), String> {
+    let root = Path::new(directory);
+    let mut files = Vec::new();
+    collect_files(root, root, &mut files)?;
+
+    let mut writer = pak::PakWriter::create(output)
+        .map_err(|err| format!("failed to create {output}: {err}"))?;
+    let mut total = 0;
+    let mut stored = 0;
+    for file in &files {
+        let data =
+            fs::read(root.join(file)).map_err(|err| format!("failed to read {file}: {err}"))?;
+        let entry = writer
+            .add(file, &data)
+            .map_err(|err| format!("failed to add {file}: {err}"))?;
+        total += entry.size;
+        stored += entry.stored_size;
+        println!("{file}: {} -> {} bytes", entry.size, entry.stored_size);
+    }
+    writer
+        .finish()
+        .map_err(|err| format!("failed to write {output}: {err}"))?;
+
+    println!(
+        "{output}: {} file(s), {total} -> {stored} bytes",
+        files.len()
+    );
+    Ok(())
+}
+
+fn list(archive: &str) -> Result<(), String> {
+    let pak = pak::Pak::open(archive).map_err(|err| format!("failed to open {archive}: {err}"))?;
+    for (name, entry) in pak.entries() {
+        let method = match entry.method {
+            pak::Method::Stored => "stored",
+            pak::Method::Zlib => "zlib",
+        };
+        println!(
+            "{name}: {} bytes, {} stored ({method})",
+            entry.size, entry.stored_size
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,242 @@
+use nalgebra::{Matrix4, Vector3, Vector4};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// Quadric error metrics, from Garland and Heckbert's "Surface Simplification Using Quadric
+// Error Metrics". Edges are collapsed into one of their vertices instead of a new position,
+// so the result only uses vertices (with their texture coordinates and normals) from the
+// original mesh. Vertices on open edges never move, which keeps the outline of the mesh and
+// texture seams (where vertices are split) where they are.
+
+type Quadric = Matrix4<f64>;
+
+fn plane_quadric(a: &Vector3<f64>, b: &Vector3<f64>, c: &Vector3<f64>) -> Quadric {
+    let Some(normal) = (b - a).cross(&(c - a)).try_normalize(f64::EPSILON) else {
+        return Quadric::zeros();
+    };
+    let plane = Vector4::new(normal.x, normal.y, normal.z, -normal.dot(a));
+    plane * plane.transpose()
+}
+
+// The sum of squared distances from the point to every plane in the quadric
+fn quadric_error(quadric: &Quadric, point: &Vector3<f64>) -> f64 {
+    let point = Vector4::new(point.x, point.y, point.z, 1.0);
+    point.dot(&(quadric * point)).max(0.0)
+}
+
+// Moving from onto to, valid as long as neither vertex has changed since
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost.total_cmp(&other.cost) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Backwards, so the cheapest collapse is at the top of the heap
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+struct Simplifier<'a> {
+    positions: &'a [[f32; 3]],
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    quadrics: Vec<Quadric>,
+    // Triangles around each vertex, which can include dead ones
+    adjacent: Vec<Vec<usize>>,
+    locked: Vec<bool>,
+    removed: Vec<bool>,
+    versions: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier<'_> {
+    fn position(&self, vertex: u32) -> Vector3<f64> {
+        let [x, y, z] = self.positions[vertex as usize];
+        Vector3::new(x as f64, y as f64, z as f64)
+    }
+
+    fn push(&mut self, from: u32, to: u32) {
+        let (from_index, to_index) = (from as usize, to as usize);
+        if self.locked[from_index] {
+            return;
+        }
+        let quadric = self.quadrics[from_index] + self.quadrics[to_index];
+        self.heap.push(Collapse {
+            cost: quadric_error(&quadric, &self.position(to)),
+            from,
+            to,
+            versions: (self.versions[from_index], self.versions[to_index]),
+        });
+    }
+
+    // Whether any triangle around from would turn over or collapse to nothing
+    fn flips(&self, from: u32, to: u32) -> bool {
+        let target = self.position(to);
+        self.adjacent[from as usize]
+            .iter()
+            .filter(|triangle| self.alive[**triangle])
+            .map(|triangle| self.triangles[*triangle])
+            .filter(|triangle| !triangle.contains(&to))
+            .any(|triangle| {
+                let corners = triangle.map(|vertex| self.position(vertex));
+                let moved = triangle.map(|vertex| {
+                    if vertex == from {
+                        target
+                    } else {
+                        self.position(vertex)
+                    }
+                });
+                let before = (corners[1] - corners[0]).cross(&(corners[2] - corners[0]));
+                let after = (moved[1] - moved[0]).cross(&(moved[2] - moved[0]));
+                after.norm_squared() <= f64::EPSILON || before.dot(&after) <= 0.0
+            })
+    }
+
+    fn collapse(&mut self, from: u32, to: u32) -> usize {
+        let (from_index, to_index) = (from as usize, to as usize);
+        self.removed[from_index] = true;
+        let quadric = self.quadrics[from_index];
+        self.quadrics[to_index] += quadric;
+        self.versions[to_index] += 1;
+
+        let mut dead = 0;
+        for triangle in std::mem::take(&mut self.adjacent[from_index]) {
+            if !self.alive[triangle] {
+                continue;
+            }
+            if self.triangles[triangle].contains(&to) {
+                self.alive[triangle] = false;
+                dead += 1;
+            } else {
+                for vertex in &mut self.triangles[triangle] {
+                    if *vertex == from {
+                        *vertex = to;
+                    }
+                }
+                self.adjacent[to_index].push(triangle);
+            }
+        }
+
+        let alive = &self.alive;
+        self.adjacent[to_index].retain(|triangle| alive[*triangle]);
+        let mut neighbours: Vec<u32> = self.adjacent[to_index]
+            .iter()
+            .flat_map(|triangle| self.triangles[*triangle])
+            .filter(|vertex| *vertex != to)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for neighbour in neighbours {
+            self.push(to, neighbour);
+            self.push(neighbour, to);
+        }
+
+        dead
+    }
+}
+
+// Returns indices into the same vertices for at most target_triangles triangles, or as close
+// as it can get without any collapse being off by more than max_error
+pub fn simplify(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    target_triangles: usize,
+    max_error: f32,
+) -> Vec<u32> {
+    let vertex_count = positions.len();
+    let mut simplifier = Simplifier {
+        positions,
+        triangles: indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect(),
+        alive: Vec::new(),
+        quadrics: vec![Quadric::zeros(); vertex_count],
+        adjacent: vec![Vec::new(); vertex_count],
+        locked: vec![false; vertex_count],
+        removed: vec![false; vertex_count],
+        versions: vec![0; vertex_count],
+        heap: BinaryHeap::new(),
+    };
+    simplifier.alive = vec![true; simplifier.triangles.len()];
+
+    let mut live_triangles = 0;
+    let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+    for (index, triangle) in simplifier.triangles.iter().enumerate() {
+        let [a, b, c] = *triangle;
+        if a == b || b == c || c == a {
+            simplifier.alive[index] = false;
+            continue;
+        }
+
+        let quadric = plane_quadric(
+            &simplifier.position(a),
+            &simplifier.position(b),
+            &simplifier.position(c),
+        );
+        for vertex in triangle {
+            simplifier.quadrics[*vertex as usize] += quadric;
+            simplifier.adjacent[*vertex as usize].push(index);
+        }
+        for (start, end) in [(a, b), (b, c), (c, a)] {
+            *edges.entry((start.min(end), start.max(end))).or_default() += 1;
+        }
+        live_triangles += 1;
+    }
+
+    for ((a, b), count) in &edges {
+        if *count == 1 {
+            simplifier.locked[*a as usize] = true;
+            simplifier.locked[*b as usize] = true;
+        }
+    }
+    for (a, b) in edges.keys() {
+        simplifier.push(*a, *b);
+        simplifier.push(*b, *a);
+    }
+
+    let max_error = max_error as f64 * max_error as f64;
+    while live_triangles > target_triangles {
+        let Some(collapse) = simplifier.heap.pop() else {
+            break;
+        };
+        if collapse.cost > max_error {
+            break;
+        }
+
+        let (from, to) = (collapse.from as usize, collapse.to as usize);
+        let stale = simplifier.removed[from]
+            || simplifier.removed[to]
+            || collapse.versions != (simplifier.versions[from], simplifier.versions[to]);
+        if stale || simplifier.flips(collapse.from, collapse.to) {
+            continue;
+        }
+
+        live_triangles -= simplifier.collapse(collapse.from, collapse.to);
+    }
+
+    simplifier
+        .triangles
+        .iter()
+        .zip(&simplifier.alive)
+        .filter(|(_, alive)| **alive)
+        .flat_map(|(triangle, _)| *triangle)
+        .collect()
+}